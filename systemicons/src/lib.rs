@@ -2,7 +2,9 @@
 //!
 //! With this lib you can retrieve the system icon which is associated
 //! to a certain file extension. The icon will be in the .png format.
-//! Windows and Linux (GTK) are supported.
+//! Windows, macOS and Linux are supported. On Linux, icons are looked up in the
+//! freedesktop icon theme by the MIME type of the file, and may be SVG icons of
+//! scalable directories.
 //!
 //! When you specify an absolute path to a .exe file, then the icon is loaded from resource, if the exe contains an icon resource.
 #[cfg(target_os = "windows")]
@@ -13,7 +15,6 @@ use std::{fmt, str::Utf8Error};
 pub enum InnerError {
     IoError(std::io::Error),
     Utf8Error(Utf8Error),
-    GtkInitError,
     #[cfg(target_os = "windows")]
     ImageError(ImageError),
 }
@@ -61,7 +62,6 @@ impl fmt::Debug for Error {
 impl fmt::Debug for InnerError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let res = match self {
-            &InnerError::GtkInitError => "GtkInitError".to_string(),
             &InnerError::Utf8Error(_) => "Utf8Error".to_string(),
             &InnerError::IoError(_) => "IoError".to_string(),
             #[cfg(target_os = "windows")]
@@ -78,11 +78,14 @@ mod macos;
 #[cfg(target_os = "windows")]
 mod windows;
 
+#[cfg(target_os = "linux")]
+pub use linux::request::IconResolver;
+
 /// Retrieving system icon. You have to specify the file extension and desired icon size (like 16, 32 or 64).
 /// Returns the icon formatted as png as byte buffer.
 #[cfg(target_os = "linux")]
-pub fn get_icon(ext: &str, size: i32, _dir: bool) -> Result<Vec<u8>, Error> {
-    linux::request::get_icon(ext, size)
+pub fn get_icon(ext: &str, size: i32, dir: bool) -> Result<Vec<u8>, Error> {
+    linux::request::get_icon(ext, size, dir)
 }
 #[cfg(target_os = "windows")]
 pub fn get_icon(ext: &str, size: i32, dir: bool) -> Result<Vec<u8>, Error> {
@@ -96,12 +99,6 @@ pub fn get_icon_as_file(ext: &str, size: i32) -> Result<String, Error> {
     linux::request::get_icon_as_file(ext, size)
 }
 
-/// In a non GTK program you have to initialize GTK when getting system icons (Linux)-
-#[cfg(target_os = "linux")]
-pub fn init() {
-    linux::request::init()
-}
-
 /// Retrieving system icon. You have to specify the file extension and desired icon size (like 16, 32 or 64).
 /// Returns the icon formatted as png as byte buffer.
 #[cfg(target_os = "macos")]
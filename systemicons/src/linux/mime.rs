@@ -0,0 +1,305 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// MIME type of files no glob matches.
+pub const DEFAULT_TYPE: &str = "application/octet-stream";
+
+struct Glob {
+    mime_type: String,
+    /// Lower case unless the glob is case sensitive.
+    pattern: String,
+    weight: u32,
+    case_sensitive: bool,
+}
+
+/// Globs, aliases and icon names of MIME types from the `mime` directories of
+/// shared-mime-info.
+pub struct MimeDatabase {
+    globs: Vec<Glob>,
+    aliases: HashMap<String, String>,
+    icons: HashMap<String, String>,
+    generic_icons: HashMap<String, String>,
+}
+
+impl MimeDatabase {
+    /// Loads the database from data directories, most important first.
+    pub fn load(data_dirs: &[PathBuf]) -> Self {
+        let mut database = MimeDatabase {
+            globs: Vec::new(),
+            aliases: HashMap::new(),
+            icons: HashMap::new(),
+            generic_icons: HashMap::new(),
+        };
+        // less important directories are read first, so the others override them
+        for dir in data_dirs.iter().rev().map(|dir| dir.join("mime")) {
+            database.load_globs(&dir.join("globs2"));
+            read_pairs(&dir.join("aliases"), ' ', &mut database.aliases);
+            read_pairs(&dir.join("icons"), ':', &mut database.icons);
+            read_pairs(&dir.join("generic-icons"), ':', &mut database.generic_icons);
+        }
+        database
+    }
+
+    fn load_globs(&mut self, path: &Path) {
+        let Ok(content) = fs::read_to_string(path) else {
+            return;
+        };
+        let mut globs = Vec::new();
+        // types whose globs of less important directories are dropped
+        let mut replaced = HashSet::new();
+        for line in content.lines().filter(|line| !line.starts_with('#')) {
+            let mut fields = line.split(':');
+            let (Some(weight), Some(mime_type), Some(pattern)) =
+                (fields.next(), fields.next(), fields.next())
+            else {
+                continue;
+            };
+            if pattern == "__NOGLOBS__" {
+                replaced.insert(mime_type.to_string());
+                continue;
+            }
+            let case_sensitive = fields
+                .next()
+                .is_some_and(|flags| flags.split(',').any(|flag| flag == "cs"));
+            globs.push(Glob {
+                mime_type: mime_type.to_string(),
+                pattern: if case_sensitive {
+                    pattern.to_string()
+                } else {
+                    pattern.to_lowercase()
+                },
+                weight: weight.parse().unwrap_or(50),
+                case_sensitive,
+            });
+        }
+        self.globs
+            .retain(|glob| !replaced.contains(&glob.mime_type));
+        self.globs.extend(globs);
+    }
+
+    /// Gets the MIME type of a file from its name. Literal names win over patterns,
+    /// then globs of higher weight, then longer globs.
+    pub fn get_type(&self, file_name: &str) -> &str {
+        let lower_name = file_name.to_lowercase();
+        let mime_type = self
+            .globs
+            .iter()
+            .filter(|glob| {
+                let name = if glob.case_sensitive {
+                    file_name
+                } else {
+                    &lower_name
+                };
+                glob.matches(name)
+            })
+            .max_by_key(|glob| {
+                (
+                    !has_wildcards(&glob.pattern),
+                    glob.weight,
+                    glob.pattern.len(),
+                )
+            })
+            .map_or(DEFAULT_TYPE, |glob| glob.mime_type.as_str());
+        self.aliases
+            .get(mime_type)
+            .map_or(mime_type, |mime_type| mime_type.as_str())
+    }
+
+    /// Gets the names of icons of the MIME type, most specific first, like GIO does.
+    pub fn get_icon_names(&self, mime_type: &str) -> Vec<String> {
+        let mime_type = self
+            .aliases
+            .get(mime_type)
+            .map_or(mime_type, |mime_type| mime_type.as_str());
+        let mut names = Vec::new();
+        if let Some(icon) = self.icons.get(mime_type) {
+            names.push(icon.clone());
+        }
+        names.push(mime_type.replace('/', "-"));
+        let generic_icon = match self.generic_icons.get(mime_type) {
+            Some(icon) => icon.clone(),
+            None => {
+                let media = mime_type.split('/').next().unwrap_or_default();
+                format!("{}-x-generic", media)
+            }
+        };
+        names.push(generic_icon);
+        let mut seen = HashSet::new();
+        names.retain(|name| seen.insert(name.clone()));
+        names
+    }
+}
+
+impl Glob {
+    fn matches(&self, name: &str) -> bool {
+        // most globs are extensions or literal names, which need no backtracking
+        match self.pattern.strip_prefix('*') {
+            Some(suffix) if !has_wildcards(suffix) => name.ends_with(suffix),
+            _ if !has_wildcards(&self.pattern) => name == self.pattern,
+            _ => {
+                let pattern: Vec<char> = self.pattern.chars().collect();
+                let name: Vec<char> = name.chars().collect();
+                matches(&pattern, &name)
+            }
+        }
+    }
+}
+
+fn has_wildcards(pattern: &str) -> bool {
+    pattern.contains(&['*', '?', '['][..])
+}
+
+/// Matches a name against a shell pattern with `*`, `?` and bracket expressions.
+fn matches(pattern: &[char], name: &[char]) -> bool {
+    match pattern.split_first() {
+        None => name.is_empty(),
+        Some(('*', rest)) => (0..=name.len()).any(|skipped| matches(rest, &name[skipped..])),
+        Some(('?', rest)) => !name.is_empty() && matches(rest, &name[1..]),
+        Some(('[', rest)) => {
+            // a closing bracket right after the opening one is part of the set
+            let end = rest
+                .iter()
+                .skip(1)
+                .position(|c| *c == ']')
+                .map(|index| index + 1);
+            let (Some(end), Some((c, name))) = (end, name.split_first()) else {
+                return name.first() == Some(&'[') && matches(rest, &name[1..]);
+            };
+            let (negated, set) = match rest[..end].split_first() {
+                Some(('!', set)) | Some(('^', set)) => (true, set),
+                _ => (false, &rest[..end]),
+            };
+            set_contains(set, *c) != negated && matches(&rest[end + 1..], name)
+        }
+        Some((c, rest)) => name.first() == Some(c) && matches(rest, &name[1..]),
+    }
+}
+
+fn set_contains(set: &[char], c: char) -> bool {
+    let mut index = 0;
+    while index < set.len() {
+        if index + 2 < set.len() && set[index + 1] == '-' {
+            if (set[index]..=set[index + 2]).contains(&c) {
+                return true;
+            }
+            index += 3;
+        } else {
+            if set[index] == c {
+                return true;
+            }
+            index += 1;
+        }
+    }
+    false
+}
+
+/// Reads lines of two fields, such as aliases and their MIME types.
+fn read_pairs(path: &Path, separator: char, pairs: &mut HashMap<String, String>) {
+    let Ok(content) = fs::read_to_string(path) else {
+        return;
+    };
+    for line in content.lines().filter(|line| !line.starts_with('#')) {
+        if let Some((key, value)) = line.split_once(separator) {
+            pairs.insert(key.to_string(), value.to_string());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load() -> MimeDatabase {
+        let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+        MimeDatabase::load(&[fixtures.join("local"), fixtures.join("share")])
+    }
+
+    fn glob_matches(pattern: &str, name: &str) -> bool {
+        let pattern: Vec<char> = pattern.chars().collect();
+        let name: Vec<char> = name.chars().collect();
+        matches(&pattern, &name)
+    }
+
+    #[test]
+    fn get_type_by_glob() {
+        let database = load();
+        assert_eq!(database.get_type("notes.txt"), "text/plain");
+        assert_eq!(database.get_type("NOTES.TXT"), "text/plain");
+        assert_eq!(database.get_type("main.c"), "text/x-csrc");
+        assert_eq!(database.get_type("main.C"), "text/x-c++src");
+        assert_eq!(database.get_type("image.fo7"), "image/x-foo");
+        assert_eq!(database.get_type("image.foo"), DEFAULT_TYPE);
+        assert_eq!(database.get_type("unknown"), DEFAULT_TYPE);
+    }
+
+    #[test]
+    fn get_type_prefers_literals_weights_and_length() {
+        let database = load();
+        assert_eq!(database.get_type("makefile"), "text/x-makefile");
+        assert_eq!(database.get_type("rules.mk"), "text/x-makefile");
+        assert_eq!(database.get_type("files.tar"), "application/x-tar");
+        assert_eq!(
+            database.get_type("files.tar.gz"),
+            "application/x-compressed-tar"
+        );
+    }
+
+    #[test]
+    fn get_type_resolves_aliases() {
+        assert_eq!(load().get_type("file.gz"), "application/gzip");
+    }
+
+    #[test]
+    fn get_type_drops_replaced_globs() {
+        let database = load();
+        assert_eq!(database.get_type("file.old"), DEFAULT_TYPE);
+        assert_eq!(database.get_type("file.new"), "text/new");
+    }
+
+    #[test]
+    fn get_icon_names_most_specific_first() {
+        let database = load();
+        assert_eq!(
+            database.get_icon_names("text/x-makefile"),
+            ["special-make", "text-x-makefile", "text-x-generic"]
+        );
+        assert_eq!(
+            database.get_icon_names("application/x-compressed-tar"),
+            ["application-x-compressed-tar", "package-x-generic"]
+        );
+        assert_eq!(
+            database.get_icon_names("application/x-gzip"),
+            ["application-gzip", "application-x-generic"]
+        );
+    }
+
+    #[test]
+    fn matches_wildcards() {
+        assert!(glob_matches("*.tar.*", "files.tar.gz"));
+        assert!(glob_matches("?.c", "a.c"));
+        assert!(!glob_matches("?.c", "ab.c"));
+        assert!(glob_matches("*", ""));
+    }
+
+    #[test]
+    fn matches_brackets() {
+        assert!(glob_matches("*.[ch]", "main.h"));
+        assert!(!glob_matches("*.[ch]", "main.o"));
+        assert!(glob_matches("file[0-9]", "file7"));
+        assert!(!glob_matches("file[0-9]", "filex"));
+        assert!(glob_matches("[a-cx]", "x"));
+        assert!(glob_matches("[!a]", "b"));
+        assert!(!glob_matches("[!a]", "a"));
+        assert!(!glob_matches("[^a]", "a"));
+        // a closing bracket right after the opening one is part of the set
+        assert!(glob_matches("[]]", "]"));
+        assert!(glob_matches("[]a]", "a"));
+        // an unclosed bracket is literal
+        assert!(glob_matches("a[b", "a[b"));
+        assert!(!glob_matches("a[b", "ab"));
+        assert!(!glob_matches("[ab]", ""));
+    }
+}
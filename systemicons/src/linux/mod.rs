@@ -1 +1,3 @@
+mod mime;
 pub mod request;
+mod theme;
@@ -1,69 +1,152 @@
-use gio_sys::GThemedIcon;
-use glib::{gobject_ffi::g_object_unref, object::GObject};
-use glib_sys::g_free;
-use gtk_sys::{
-    gtk_icon_info_get_filename, gtk_icon_theme_choose_icon, gtk_icon_theme_get_default,
-    GtkIconTheme, GTK_ICON_LOOKUP_NO_SVG,
-};
 use std::{
-    ffi::{c_void, CStr, CString},
-    fs::{self, File},
-    io::Read,
+    env, fs, io,
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+
+use super::{
+    mime::MimeDatabase,
+    theme::{self, IconTheme},
 };
+use crate::Error;
+
+/// Icon name of directories, which have no glob.
+const DIRECTORY_ICON: &str = "folder";
 
-use crate::{Error, InnerError};
+/// Theme GTK uses if none is set.
+const DEFAULT_THEME: &str = "Adwaita";
 
-static mut DEFAULT_THEME: Option<*mut GtkIconTheme> = None;
+static RESOLVER: OnceLock<IconResolver> = OnceLock::new();
 
-pub fn get_icon(ext: &str, size: i32) -> Result<Vec<u8>, Error> {
-    let filename = get_icon_as_file(ext, size)?;
-    let mut f = File::open(&filename)?;
-    let metadata = fs::metadata(&filename)?;
-    let mut buffer = vec![0; metadata.len() as usize];
-    f.read(&mut buffer)?;
-    Ok(buffer)
+/// Finds icons of files from their MIME types in shared-mime-info and an icon theme,
+/// without GTK. It can be shared between threads.
+pub struct IconResolver {
+    mime: MimeDatabase,
+    theme: IconTheme,
 }
 
-pub fn get_icon_as_file(ext: &str, size: i32) -> Result<String, Error> {
-    let result: String;
-    unsafe {
-        let filename = CString::new(ext).unwrap();
-        let null: u8 = 0;
-        let p_null = &null as *const u8;
-        let nullsize: usize = 0;
-        let mut res = 0;
-        let p_res = &mut res as *mut i32;
-        let p_res = gio_sys::g_content_type_guess(filename.as_ptr(), p_null, nullsize, p_res);
-        let icon = gio_sys::g_content_type_get_icon(p_res);
-        g_free(p_res as *mut c_void);
-        if DEFAULT_THEME.is_none() {
-            let theme = gtk_icon_theme_get_default();
-            if theme.is_null() {
-                println!("You have to initialize GTK!");
-                return Err(Error {
-                    message: "You have to initialize GTK!".to_string(),
-                    inner_error: InnerError::GtkInitError,
-                });
-            }
-            let theme = gtk_icon_theme_get_default();
-            DEFAULT_THEME = Some(theme);
+impl IconResolver {
+    /// Creates a resolver from the XDG data directories and the icon theme set in
+    /// the GTK or KDE settings, or Adwaita like GTK.
+    pub fn from_environment() -> Self {
+        let data_dirs = get_data_dirs();
+        let mut icon_dirs: Vec<PathBuf> = home_dir()
+            .map(|dir| dir.join(".icons"))
+            .into_iter()
+            .collect();
+        icon_dirs.extend(data_dirs.iter().map(|dir| dir.join("icons")));
+        Self::load(&get_theme_name(), &icon_dirs, &data_dirs)
+    }
+
+    /// Creates a resolver from data directories, most important first, which may
+    /// hold `mime` and `icons` directories and unthemed icons in `pixmaps`.
+    pub fn new(data_dirs: &[PathBuf], theme_name: &str) -> Self {
+        let icon_dirs: Vec<PathBuf> = data_dirs.iter().map(|dir| dir.join("icons")).collect();
+        Self::load(theme_name, &icon_dirs, data_dirs)
+    }
+
+    fn load(theme_name: &str, icon_dirs: &[PathBuf], data_dirs: &[PathBuf]) -> Self {
+        let fallback_dirs = data_dirs.iter().map(|dir| dir.join("pixmaps")).collect();
+        IconResolver {
+            mime: MimeDatabase::load(data_dirs),
+            theme: IconTheme::load(theme_name, icon_dirs, fallback_dirs),
         }
-        let icon_names =
-            gio_sys::g_themed_icon_get_names(icon as *mut GThemedIcon) as *mut *const i8;
-        let icon_info = gtk_icon_theme_choose_icon(
-            DEFAULT_THEME.unwrap(),
-            icon_names,
-            size,
-            GTK_ICON_LOOKUP_NO_SVG,
-        );
-        let filename = gtk_icon_info_get_filename(icon_info);
-        let res_str = CStr::from_ptr(filename);
-        result = res_str.to_str()?.to_string();
-        g_object_unref(icon as *mut GObject);
     }
-    Ok(result)
+
+    /// Gets the MIME type of a file from its name, such as `text/plain`.
+    pub fn get_mime_type(&self, file_name: &str) -> &str {
+        self.mime.get_type(file_name)
+    }
+
+    /// Gets the path of the icon of a file or directory at the size or the nearest
+    /// one. Only the name of the path is used.
+    pub fn get_icon_path(&self, path: &str, size: u32, dir: bool) -> Option<PathBuf> {
+        if dir {
+            return self.theme.find_icon(&[DIRECTORY_ICON], size);
+        }
+        let name = Path::new(path)
+            .file_name()
+            .map_or(path.into(), |name| name.to_string_lossy());
+        let names = self.mime.get_icon_names(self.mime.get_type(&name));
+        self.theme.find_icon(&names, size)
+    }
+}
+
+pub fn get_icon(ext: &str, size: i32, dir: bool) -> Result<Vec<u8>, Error> {
+    let filename = get_icon_path(ext, size, dir)?;
+    Ok(fs::read(filename)?)
+}
+
+pub fn get_icon_as_file(ext: &str, size: i32) -> Result<String, Error> {
+    let filename = get_icon_path(ext, size, false)?;
+    Ok(filename.to_string_lossy().to_string())
+}
+
+fn get_icon_path(ext: &str, size: i32, dir: bool) -> Result<PathBuf, Error> {
+    let resolver = RESOLVER.get_or_init(IconResolver::from_environment);
+    resolver
+        .get_icon_path(ext, size.max(1) as u32, dir)
+        .ok_or_else(|| {
+            let message = format!("there is no icon for {}", ext);
+            io::Error::new(io::ErrorKind::NotFound, message).into()
+        })
+}
+
+/// Gets the icon theme from the GTK settings or else the KDE settings, as there is
+/// no common setting.
+fn get_theme_name() -> String {
+    let config_home =
+        get_env_dir("XDG_CONFIG_HOME").or_else(|| home_dir().map(|dir| dir.join(".config")));
+    let config_dirs =
+        get_env_dirs("XDG_CONFIG_DIRS").unwrap_or_else(|| vec![PathBuf::from("/etc/xdg")]);
+    let mut dirs: Vec<PathBuf> = config_home.into_iter().collect();
+    dirs.extend(config_dirs);
+    dirs.push(PathBuf::from("/etc"));
+
+    let gtk_settings = dirs.iter().flat_map(|dir| {
+        ["gtk-4.0", "gtk-3.0"]
+            .iter()
+            .map(move |version| dir.join(version).join("settings.ini"))
+    });
+    let gtk_theme = gtk_settings.filter_map(|path| {
+        let ini = theme::read_ini(&path)?;
+        theme::get_value(&ini, "Settings", "gtk-icon-theme-name").map(str::to_string)
+    });
+    let kde_theme = dirs.iter().filter_map(|dir| {
+        let ini = theme::read_ini(&dir.join("kdeglobals"))?;
+        theme::get_value(&ini, "Icons", "Theme").map(str::to_string)
+    });
+    gtk_theme
+        .chain(kde_theme)
+        .find(|name| !name.is_empty())
+        .unwrap_or_else(|| DEFAULT_THEME.to_string())
+}
+
+/// Gets the XDG data directories, most important first.
+fn get_data_dirs() -> Vec<PathBuf> {
+    let data_home =
+        get_env_dir("XDG_DATA_HOME").or_else(|| home_dir().map(|dir| dir.join(".local/share")));
+    let data_dirs = get_env_dirs("XDG_DATA_DIRS").unwrap_or_else(|| {
+        vec![
+            PathBuf::from("/usr/local/share"),
+            PathBuf::from("/usr/share"),
+        ]
+    });
+    data_home.into_iter().chain(data_dirs).collect()
+}
+
+fn home_dir() -> Option<PathBuf> {
+    get_env_dir("HOME")
+}
+
+/// Gets a directory from an environment variable; empty values count as unset.
+fn get_env_dir(name: &str) -> Option<PathBuf> {
+    env::var_os(name)
+        .filter(|value| !value.is_empty())
+        .map(PathBuf::from)
 }
 
-pub fn init() {
-    gtk::init().unwrap();
+fn get_env_dirs(name: &str) -> Option<Vec<PathBuf>> {
+    let value = env::var_os(name).filter(|value| !value.is_empty())?;
+    Some(env::split_paths(&value).collect())
 }
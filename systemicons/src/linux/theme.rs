@@ -0,0 +1,351 @@
+use std::{
+    collections::HashMap,
+    ffi::OsStr,
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Theme all themes fall back to.
+const FALLBACK_THEME: &str = "hicolor";
+
+/// Extensions of icons, preferred in this order; XPM icons are left out.
+const EXTENSIONS: [&str; 2] = ["png", "svg"];
+
+/// Sections of an INI file like `index.theme`, with their keys and values.
+pub type Ini = HashMap<String, HashMap<String, String>>;
+
+enum DirectoryType {
+    Fixed,
+    Scalable,
+    Threshold,
+}
+
+/// Subdirectory of a theme with icons of one size.
+struct Directory {
+    kind: DirectoryType,
+    size: u32,
+    scale: u32,
+    min_size: u32,
+    max_size: u32,
+    threshold: u32,
+    /// Paths of the icons by name, from the first base directory that has them.
+    icons: HashMap<String, PathBuf>,
+}
+
+impl Directory {
+    fn matches_size(&self, size: u32) -> bool {
+        if self.scale != 1 {
+            return false;
+        }
+        match self.kind {
+            DirectoryType::Fixed => self.size == size,
+            DirectoryType::Scalable => (self.min_size..=self.max_size).contains(&size),
+            DirectoryType::Threshold => (self.size.saturating_sub(self.threshold)
+                ..=self.size + self.threshold)
+                .contains(&size),
+        }
+    }
+
+    fn size_distance(&self, size: u32) -> u32 {
+        let (min_size, max_size) = match self.kind {
+            DirectoryType::Fixed => (self.size, self.size),
+            DirectoryType::Scalable => (self.min_size, self.max_size),
+            DirectoryType::Threshold => (
+                self.size.saturating_sub(self.threshold),
+                self.size + self.threshold,
+            ),
+        };
+        if size < min_size * self.scale {
+            min_size * self.scale - size
+        } else {
+            size.saturating_sub(max_size * self.scale)
+        }
+    }
+}
+
+struct Theme {
+    directories: Vec<Directory>,
+}
+
+/// Icon theme of the freedesktop icon theme specification, with the themes it
+/// inherits from and `hicolor`. Icon files are indexed when the theme is loaded.
+pub struct IconTheme {
+    /// Themes in the order they are searched.
+    themes: Vec<Theme>,
+    /// Directories of unthemed icons, such as `/usr/share/pixmaps`.
+    fallback_dirs: Vec<PathBuf>,
+}
+
+impl IconTheme {
+    /// Loads the theme from base directories, most important first, which contain
+    /// a directory for each theme.
+    pub fn load(name: &str, base_dirs: &[PathBuf], fallback_dirs: Vec<PathBuf>) -> Self {
+        let mut names = Vec::new();
+        add_theme_names(name, base_dirs, &mut names);
+        add_theme_names(FALLBACK_THEME, base_dirs, &mut names);
+        let themes = names
+            .iter()
+            .filter_map(|(name, index)| load_theme(name, index, base_dirs))
+            .collect();
+        IconTheme {
+            themes,
+            fallback_dirs,
+        }
+    }
+
+    /// Finds the icon of the first name a theme has, at the size or the nearest one.
+    /// Each theme is searched for all names before the themes it inherits from.
+    pub fn find_icon<S: AsRef<str>>(&self, names: &[S], size: u32) -> Option<PathBuf> {
+        self.themes
+            .iter()
+            .find_map(|theme| {
+                names
+                    .iter()
+                    .find_map(|name| theme.find_icon(name.as_ref(), size))
+            })
+            .or_else(|| {
+                names.iter().find_map(|name| {
+                    self.fallback_dirs
+                        .iter()
+                        .flat_map(|dir| {
+                            EXTENSIONS
+                                .iter()
+                                .map(move |ext| dir.join(format!("{}.{}", name.as_ref(), ext)))
+                        })
+                        .find(|path| path.is_file())
+                })
+            })
+    }
+}
+
+impl Theme {
+    fn find_icon(&self, name: &str, size: u32) -> Option<PathBuf> {
+        let icons = self
+            .directories
+            .iter()
+            .filter_map(|directory| Some((directory, directory.icons.get(name)?)));
+        let exact = icons
+            .clone()
+            .find(|(directory, _)| directory.matches_size(size));
+        exact
+            .or_else(|| icons.min_by_key(|(directory, _)| directory.size_distance(size)))
+            .map(|(_, path)| path.clone())
+    }
+}
+
+/// Adds the theme and the themes it inherits from, depth first, with their
+/// `index.theme` files.
+fn add_theme_names(name: &str, base_dirs: &[PathBuf], names: &mut Vec<(String, Ini)>) {
+    if names.iter().any(|(added, _)| added == name) {
+        return;
+    }
+    let Some(index) = base_dirs
+        .iter()
+        .find_map(|dir| read_ini(&dir.join(name).join("index.theme")))
+    else {
+        return;
+    };
+    let inherits = get_value(&index, "Icon Theme", "Inherits")
+        .map(split_list)
+        .unwrap_or_default();
+    names.push((name.to_string(), index));
+    for parent in inherits {
+        add_theme_names(&parent, base_dirs, names);
+    }
+}
+
+fn load_theme(name: &str, index: &Ini, base_dirs: &[PathBuf]) -> Option<Theme> {
+    let section = index.get("Icon Theme")?;
+    let subdirs = ["Directories", "ScaledDirectories"]
+        .iter()
+        .filter_map(|key| section.get(*key))
+        .flat_map(|value| split_list(value));
+    let mut directories = Vec::new();
+    for subdir in subdirs {
+        let Some(section) = index.get(&subdir) else {
+            continue;
+        };
+        let get_number = |key: &str| section.get(key).and_then(|value| value.parse().ok());
+        let Some(size) = get_number("Size") else {
+            continue;
+        };
+        let kind = match section.get("Type").map(|kind| kind.as_str()) {
+            Some("Fixed") => DirectoryType::Fixed,
+            Some("Scalable") => DirectoryType::Scalable,
+            _ => DirectoryType::Threshold,
+        };
+        // icons are found in the theme directories of all base directories
+        let mut icons = HashMap::new();
+        for dir in base_dirs.iter().rev() {
+            icons.extend(read_icons(&dir.join(name).join(&subdir)));
+        }
+        directories.push(Directory {
+            kind,
+            size,
+            scale: get_number("Scale").unwrap_or(1),
+            min_size: get_number("MinSize").unwrap_or(size),
+            max_size: get_number("MaxSize").unwrap_or(size),
+            threshold: get_number("Threshold").unwrap_or(2),
+            icons,
+        });
+    }
+    Some(Theme { directories })
+}
+
+fn read_icons(dir: &Path) -> Vec<(String, PathBuf)> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut paths: Vec<(usize, PathBuf)> = entries
+        .filter_map(|entry| {
+            let path = entry.ok()?.path();
+            let rank = EXTENSIONS
+                .iter()
+                .position(|ext| path.extension() == Some(OsStr::new(ext)))?;
+            Some((rank, path))
+        })
+        .collect();
+    // preferred icons come last, so they replace others of the same name
+    paths.sort_by(|(rank, _), (other, _)| other.cmp(rank));
+    paths
+        .into_iter()
+        .filter_map(|(_, path)| Some((path.file_stem()?.to_str()?.to_string(), path)))
+        .collect()
+}
+
+/// Reads an INI file; keys before the first section and comments are ignored.
+pub fn read_ini(path: &Path) -> Option<Ini> {
+    let content = fs::read_to_string(path).ok()?;
+    let mut ini = Ini::new();
+    let mut section = None;
+    for line in content.lines().map(str::trim) {
+        if line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+        if let Some(name) = line
+            .strip_prefix('[')
+            .and_then(|line| line.strip_suffix(']'))
+        {
+            section = Some(name.to_string());
+        } else if let (Some(section), Some((key, value))) = (&section, line.split_once('=')) {
+            ini.entry(section.clone())
+                .or_default()
+                .insert(key.trim().to_string(), value.trim().to_string());
+        }
+    }
+    Some(ini)
+}
+
+pub fn get_value<'a>(ini: &'a Ini, section: &str, key: &str) -> Option<&'a str> {
+    ini.get(section)?.get(key).map(|value| value.as_str())
+}
+
+fn split_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_share_dir() -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/share")
+    }
+
+    fn load() -> IconTheme {
+        let share = get_share_dir();
+        IconTheme::load("Child", &[share.join("icons")], vec![share.join("pixmaps")])
+    }
+
+    fn get_icon(path: &str) -> Option<PathBuf> {
+        Some(get_share_dir().join(path))
+    }
+
+    #[test]
+    fn find_icon_by_size() {
+        let theme = load();
+        // fixed
+        assert_eq!(
+            theme.find_icon(&["text-plain"], 16),
+            get_icon("icons/Child/16x16/mimetypes/text-plain.png")
+        );
+        // threshold
+        assert_eq!(
+            theme.find_icon(&["text-plain"], 50),
+            get_icon("icons/Child/48x48/mimetypes/text-plain.png")
+        );
+        // scalable
+        assert_eq!(
+            theme.find_icon(&["text-html"], 256),
+            get_icon("icons/Child/scalable/mimetypes/text-html.png")
+        );
+    }
+
+    #[test]
+    fn find_icon_nearest_size() {
+        let theme = load();
+        assert_eq!(
+            theme.find_icon(&["text-plain"], 20),
+            get_icon("icons/Child/16x16/mimetypes/text-plain.png")
+        );
+        assert_eq!(
+            theme.find_icon(&["text-html"], 4),
+            get_icon("icons/Child/scalable/mimetypes/text-html.png")
+        );
+    }
+
+    #[test]
+    fn find_scalable_icons() {
+        let theme = load();
+        assert_eq!(
+            theme.find_icon(&["text-plain"], 256),
+            get_icon("icons/Child/scalable/mimetypes/text-plain.svg")
+        );
+        // PNG icons are preferred to SVG icons of the same directory
+        assert_eq!(
+            theme.find_icon(&["text-html"], 256),
+            get_icon("icons/Child/scalable/mimetypes/text-html.png")
+        );
+        assert_eq!(
+            theme.find_icon(&["application-pdf"], 16),
+            get_icon("pixmaps/application-pdf.svg")
+        );
+    }
+
+    #[test]
+    fn find_icon_in_inherited_themes() {
+        let theme = load();
+        assert_eq!(
+            theme.find_icon(&["folder"], 32),
+            get_icon("icons/Parent/32x32/places/folder.png")
+        );
+        // all names are searched in a theme before the themes it inherits from
+        assert_eq!(
+            theme.find_icon(&["text-x-csrc", "text-plain"], 24),
+            get_icon("icons/Child/16x16/mimetypes/text-plain.png")
+        );
+        assert_eq!(
+            theme.find_icon(&["text-x-csrc", "text-x-generic"], 24),
+            get_icon("icons/Parent/24x24/mimetypes/text-x-csrc.png")
+        );
+    }
+
+    #[test]
+    fn find_icon_in_fallbacks() {
+        let theme = load();
+        assert_eq!(
+            theme.find_icon(&["application-gzip"], 16),
+            get_icon("icons/hicolor/48x48/apps/application-gzip.png")
+        );
+        assert_eq!(
+            theme.find_icon(&["image-png", "image-x-generic"], 16),
+            get_icon("pixmaps/image-x-generic.png")
+        );
+        assert_eq!(theme.find_icon(&["missing"], 16), None);
+    }
+}
@@ -0,0 +1,675 @@
+use crate::{
+	checksum::checksum, compare::compare_directories, get_port, post_startup, rename, sync,
+	Capabilities, CreateChecksumFileRequest, CreateLinkRequest, CreateLinkResponse, CreateRequest,
+	CreateResponse, DeleteRequest, DirectorySizeRequest, ErrorCode, ErrorResponse, Job,
+	JobResponse, JobResultsRequest, JobResultsResponse, Jobs, ListRequest, ListResponse,
+	Middleware, PackRequest, ReadRequest, ReadResponse, Request, Response, RestoreRequest,
+	SearchRequest, StatRequest, StatResponse, TransferRequest, UndoRequest, UnpackRequest,
+	VerifyChecksumFileRequest, WatchEvent, WatchRequest, WriteRequest, WriteResponse,
+	END_OF_FILE_HEADER, FILE_SIZE_HEADER, WATCH_PATH,
+};
+use actix_web::{
+	error::{InternalError, JsonPayloadError},
+	http::header,
+	web, App, HttpRequest, HttpResponse, HttpServer, ResponseError,
+};
+use async_trait::async_trait;
+use futures_util::{stream::LocalBoxStream, StreamExt};
+use serde::Deserialize;
+use std::{error::Error, sync::Arc};
+use tracing_actix_web::TracingLogger;
+
+/// Operations of a file system provider. Operations that are not implemented
+/// are answered with an "unsupported" error.
+#[async_trait(?Send)]
+pub trait Backend: Send + Sync + 'static {
+	/// Describes the backend and the requests it implements.
+	fn capabilities(&self) -> Capabilities;
+
+	/// Retrieves all entries of a directory.
+	async fn list(&self, _request: ListRequest) -> Result<ListResponse, ErrorResponse> {
+		Err(ErrorResponse::unsupported())
+	}
+
+	/// Reads the contents of a file, or the range given by the offset and length.
+	async fn read(&self, _request: ReadRequest) -> Result<ReadResponse, ErrorResponse> {
+		Err(ErrorResponse::unsupported())
+	}
+
+	/// Writes data to a file, replacing its entire contents.
+	async fn write(&self, _request: WriteRequest) -> Result<WriteResponse, ErrorResponse> {
+		Err(ErrorResponse::unsupported())
+	}
+
+	/// Creates a directory.
+	async fn create(&self, _request: CreateRequest) -> Result<CreateResponse, ErrorResponse> {
+		Err(ErrorResponse::unsupported())
+	}
+
+	/// Creates a symbolic or hard link.
+	async fn create_link(
+		&self,
+		_request: CreateLinkRequest,
+	) -> Result<CreateLinkResponse, ErrorResponse> {
+		Err(ErrorResponse::unsupported())
+	}
+
+	/// Starts a job copying files or directories.
+	async fn copy(
+		&self,
+		_request: TransferRequest,
+		_jobs: &Jobs,
+	) -> Result<JobResponse, ErrorResponse> {
+		Err(ErrorResponse::unsupported())
+	}
+
+	/// Starts a job renaming or moving files or directories.
+	async fn rename(
+		&self,
+		_request: TransferRequest,
+		_jobs: &Jobs,
+	) -> Result<JobResponse, ErrorResponse> {
+		Err(ErrorResponse::unsupported())
+	}
+
+	/// Starts a job deleting files or directories.
+	async fn delete(
+		&self,
+		_request: DeleteRequest,
+		_jobs: &Jobs,
+	) -> Result<JobResponse, ErrorResponse> {
+		Err(ErrorResponse::unsupported())
+	}
+
+	/// Starts a job searching a directory tree.
+	async fn search(
+		&self,
+		_request: SearchRequest,
+		_jobs: &Jobs,
+	) -> Result<JobResponse, ErrorResponse> {
+		Err(ErrorResponse::unsupported())
+	}
+
+	/// Starts a job summing sizes of directory trees.
+	async fn directory_size(
+		&self,
+		_request: DirectorySizeRequest,
+		_jobs: &Jobs,
+	) -> Result<JobResponse, ErrorResponse> {
+		Err(ErrorResponse::unsupported())
+	}
+
+	/// Starts a job writing checksums of files to a checksum file.
+	async fn create_checksum_file(
+		&self,
+		_request: CreateChecksumFileRequest,
+		_jobs: &Jobs,
+	) -> Result<JobResponse, ErrorResponse> {
+		Err(ErrorResponse::unsupported())
+	}
+
+	/// Starts a job comparing files with the checksums listed in a checksum file.
+	async fn verify_checksum_file(
+		&self,
+		_request: VerifyChecksumFileRequest,
+		_jobs: &Jobs,
+	) -> Result<JobResponse, ErrorResponse> {
+		Err(ErrorResponse::unsupported())
+	}
+
+	/// Starts a job packing files into an archive.
+	async fn pack(
+		&self,
+		_request: PackRequest,
+		_jobs: &Jobs,
+	) -> Result<JobResponse, ErrorResponse> {
+		Err(ErrorResponse::unsupported())
+	}
+
+	/// Starts a job extracting entries of an archive.
+	async fn unpack(
+		&self,
+		_request: UnpackRequest,
+		_jobs: &Jobs,
+	) -> Result<JobResponse, ErrorResponse> {
+		Err(ErrorResponse::unsupported())
+	}
+
+	/// Starts a job reverting the most recent operations that changed files.
+	async fn undo(
+		&self,
+		_request: UndoRequest,
+		_jobs: &Jobs,
+	) -> Result<JobResponse, ErrorResponse> {
+		Err(ErrorResponse::unsupported())
+	}
+
+	/// Starts a job moving entries of the trash back to where they were deleted from.
+	async fn restore(
+		&self,
+		_request: RestoreRequest,
+		_jobs: &Jobs,
+	) -> Result<JobResponse, ErrorResponse> {
+		Err(ErrorResponse::unsupported())
+	}
+
+	/// Starts a job deleting all entries of the trash for good.
+	async fn empty_trash(&self, _jobs: &Jobs) -> Result<JobResponse, ErrorResponse> {
+		Err(ErrorResponse::unsupported())
+	}
+
+	/// Gets a single entry.
+	async fn stat(&self, _request: StatRequest) -> Result<StatResponse, ErrorResponse> {
+		Err(ErrorResponse::unsupported())
+	}
+
+	/// Watches a directory for changes of its entries; watching stops when the stream is dropped.
+	fn watch(&self, _request: WatchRequest) -> Result<WatchStream, ErrorResponse> {
+		Err(ErrorResponse::unsupported())
+	}
+
+	/// Gets the icon with a given name; `path` identifies the entry the icon is shown for.
+	fn icon(&self, _name: &str, _path: Option<&str>) -> Result<Option<Icon>, ErrorResponse> {
+		Ok(None)
+	}
+
+	/// Gets a preview of an image file fitting in a square of the size in pixels;
+	/// files that are not images have no preview.
+	async fn thumbnail(&self, _path: &str, _size: u32) -> Result<Option<Icon>, ErrorResponse> {
+		Ok(None)
+	}
+
+	/// Registers additional services of the backend.
+	fn configure(&self, _config: &mut web::ServiceConfig) {}
+}
+
+/// Changes of the entries of a watched directory.
+pub type WatchStream = LocalBoxStream<'static, Result<WatchEvent, ErrorResponse>>;
+
+pub struct Icon {
+	pub content_type: &'static str,
+	pub data: Vec<u8>,
+}
+
+impl Icon {
+	pub fn png(data: Vec<u8>) -> Self {
+		Icon {
+			content_type: "image/png",
+			data,
+		}
+	}
+
+	pub fn svg(data: Vec<u8>) -> Self {
+		Icon {
+			content_type: "image/svg+xml",
+			data,
+		}
+	}
+}
+
+/// Size of thumbnails if the request does not specify one.
+const DEFAULT_THUMBNAIL_SIZE: u32 = 128;
+
+/// Largest accepted request body; backends that cannot append receive whole files in
+/// one write request.
+const MAX_REQUEST_SIZE: usize = 64 * 1024 * 1024;
+
+/// Runs the HTTP server of the backend until the parent process exits.
+pub async fn serve<B: Backend>(backend: B) -> Result<(), Box<dyn Error>> {
+	let port = get_port()?;
+	let backend = web::Data::new(backend);
+	let jobs = web::Data::new(Jobs::default());
+
+	let capabilities = backend.capabilities();
+	let server = HttpServer::new(move || {
+		App::new()
+			.app_data(backend.clone())
+			.app_data(jobs.clone())
+			.app_data(
+				web::JsonConfig::default()
+					.limit(MAX_REQUEST_SIZE)
+					.error_handler(json_error),
+			)
+			.wrap(Middleware::cors())
+			.wrap(Middleware::token_auth())
+			.wrap(TracingLogger::default())
+			.configure(|config| backend.configure(config))
+			.route("/icons/{name}", web::get().to(icon::<B>))
+			.route("/thumbnails", web::get().to(thumbnail::<B>))
+			.route(WATCH_PATH, web::get().to(watch::<B>))
+			.route("/", web::post().to(enact::<B>))
+	})
+	.bind(("127.0.0.1", port))?
+	// TODO: use TLS when tauri adds support to trust self-signed certificates
+	// .bind_rustls_021(format!("127.0.0.1:{}", port), load_rustls_config()?)?
+	.run();
+
+	post_startup(&server, port, capabilities);
+
+	server.await?;
+	Ok(())
+}
+
+/// Answers requests that cannot be parsed with an error like the other ones, keeping
+/// the status of the payload error.
+fn json_error(error: JsonPayloadError, _request: &HttpRequest) -> actix_web::Error {
+	let message = match &error {
+		JsonPayloadError::OverflowKnownLength { .. } | JsonPayloadError::Overflow { .. } => {
+			format!(
+				"request is larger than {} MiB",
+				MAX_REQUEST_SIZE / 1024 / 1024
+			)
+		}
+		JsonPayloadError::ContentType => "request is not JSON".to_string(),
+		JsonPayloadError::Deserialize(err) => format!("invalid request: {}", err),
+		err => err.to_string(),
+	};
+	let body = Response::Error(ErrorResponse::new(ErrorCode::InvalidInput, message));
+	let response = HttpResponse::build(error.status_code())
+		.content_type("application/json")
+		.body(serde_json::to_string(&body).unwrap_or_default());
+	InternalError::from_response(error, response).into()
+}
+
+async fn enact<B: Backend>(
+	http_request: HttpRequest,
+	request: web::Json<Request>,
+	backend: web::Data<B>,
+	jobs: web::Data<Jobs>,
+) -> Result<HttpResponse, ErrorResponse> {
+	let response = match request.into_inner() {
+		Request::List(request) => Response::List(backend.list(request).await?),
+		Request::Create(request) => Response::Create(backend.create(request).await?),
+		Request::CreateLink(request) => Response::CreateLink(backend.create_link(request).await?),
+		Request::Read(request) => {
+			// a range header applies when the request does not specify a range
+			if let Some(range) = http_request.headers().get(header::RANGE) {
+				if request.offset.is_none() && request.length.is_none() {
+					let range = parse_range(range.to_str().unwrap_or_default())?;
+					return read_range(backend.get_ref(), request, range).await;
+				}
+			}
+			let is_ranged = request.offset.is_some() || request.length.is_some();
+			return Ok(read_response(backend.read(request).await?, is_ranged));
+		}
+		Request::Write(request) => Response::Write(backend.write(request).await?),
+		Request::Stat(request) => Response::Stat(backend.stat(request).await?),
+		Request::Copy(request) => Response::Job(backend.copy(request, &jobs).await?),
+		Request::Rename(request) => Response::Job(backend.rename(request, &jobs).await?),
+		Request::Delete(request) => Response::Job(backend.delete(request, &jobs).await?),
+		Request::JobStatus { id } => Response::Job(job_status(&jobs, id, |_| {})?),
+		Request::JobCancel { id } => Response::Job(job_status(&jobs, id, Job::cancel)?),
+		Request::JobPause { id } => Response::Job(job_status(&jobs, id, Job::pause)?),
+		Request::JobResume { id } => Response::Job(job_status(&jobs, id, Job::resume)?),
+		Request::JobResults(request) => Response::JobResults(job_results(&jobs, request)?),
+		Request::JobRemove { id } => Response::Job(remove_job(&jobs, id)?),
+		Request::Search(request) => Response::Job(backend.search(request, &jobs).await?),
+		Request::DirectorySize(request) => {
+			Response::Job(backend.directory_size(request, &jobs).await?)
+		}
+		Request::CompareDirectories(request) => {
+			Response::Job(compare_directories(backend.clone(), request, jobs.clone())?)
+		}
+		Request::Sync(request) => {
+			Response::Job(sync::sync(backend.clone(), request, jobs.clone())?)
+		}
+		Request::Checksum(request) => Response::Job(checksum(backend.clone(), request, &jobs)?),
+		Request::CreateChecksumFile(request) => {
+			Response::Job(backend.create_checksum_file(request, &jobs).await?)
+		}
+		Request::VerifyChecksumFile(request) => {
+			Response::Job(backend.verify_checksum_file(request, &jobs).await?)
+		}
+		Request::Pack(request) => Response::Job(backend.pack(request, &jobs).await?),
+		Request::Unpack(request) => Response::Job(backend.unpack(request, &jobs).await?),
+		Request::BatchRename(request) => {
+			rename::batch_rename(backend.clone(), request, jobs.clone()).await?
+		}
+		Request::Undo(request) => Response::Job(backend.undo(request, &jobs).await?),
+		Request::Restore(request) => Response::Job(backend.restore(request, &jobs).await?),
+		Request::EmptyTrash {} => Response::Job(backend.empty_trash(&jobs).await?),
+		Request::Capabilities {} => Response::Capabilities(backend.capabilities()),
+		Request::Join { .. } => return Err(ErrorResponse::unsupported()),
+	};
+	let body = serde_json::to_string(&response)?;
+	Ok(HttpResponse::Ok().body(body))
+}
+
+/// Range of the `Range` header.
+#[derive(Debug, PartialEq)]
+enum ByteRange {
+	/// Bytes from an offset, with the length if the range has an end.
+	From(u64, Option<u64>),
+	/// Last bytes of the file.
+	Suffix(u64),
+}
+
+/// Parses a single range of the `Range` header, for instance `bytes=0-1023`,
+/// `bytes=1024-` or `bytes=-512`.
+fn parse_range(value: &str) -> Result<ByteRange, ErrorResponse> {
+	let invalid = || {
+		ErrorResponse::new(
+			ErrorCode::InvalidInput,
+			format!("invalid range '{}'", value),
+		)
+	};
+	let (start, end) = value
+		.strip_prefix("bytes=")
+		.and_then(|range| range.split_once('-'))
+		.ok_or_else(invalid)?;
+	let parse = |number: &str| number.trim().parse::<u64>().map_err(|_| invalid());
+	if start.trim().is_empty() {
+		return Ok(ByteRange::Suffix(parse(end)?));
+	}
+	let start = parse(start)?;
+	let length = match end.trim() {
+		"" => None,
+		end => Some(
+			parse(end)?
+				.checked_sub(start)
+				.and_then(|length| length.checked_add(1))
+				.ok_or_else(invalid)?,
+		),
+	};
+	Ok(ByteRange::From(start, length))
+}
+
+/// Reads the range of the `Range` header. Ranges starting beyond the end of the file
+/// are answered with status 416 and the size of the file.
+async fn read_range<B: Backend>(
+	backend: &B,
+	request: ReadRequest,
+	range: ByteRange,
+) -> Result<HttpResponse, ErrorResponse> {
+	// the size is needed for ranges at the end and for checking the start
+	let probe = ReadRequest {
+		path: request.path.clone(),
+		key: request.key.clone(),
+		offset: Some(0),
+		length: Some(0),
+	};
+	let size = backend.read(probe).await?.size;
+	let (offset, length) = match range {
+		ByteRange::From(start, length) => (start, length),
+		ByteRange::Suffix(length) => (size.saturating_sub(length), Some(length)),
+	};
+	// ranges have to contain a byte of the file, unlike reads
+	let read = if offset < size && length != Some(0) {
+		let request = ReadRequest {
+			offset: Some(offset),
+			length,
+			..request
+		};
+		backend.read(request).await
+	} else {
+		Err(ErrorResponse::new(
+			ErrorCode::OutOfRange,
+			"range is beyond the end of the file",
+		))
+	};
+	match read {
+		Ok(response) => Ok(read_response(response, true)),
+		// also when the file was truncated since its size was read
+		Err(err) if err.code == ErrorCode::OutOfRange => {
+			let mut response = err.error_response();
+			let content_range = format!("bytes */{}", size);
+			if let Ok(value) = header::HeaderValue::from_str(&content_range) {
+				response.headers_mut().insert(header::CONTENT_RANGE, value);
+			}
+			Ok(response)
+		}
+		Err(err) => Err(err),
+	}
+}
+
+fn read_response(response: ReadResponse, is_ranged: bool) -> HttpResponse {
+	let end = response.offset + response.data.len() as u64;
+	let mut builder = if is_ranged {
+		HttpResponse::PartialContent()
+	} else {
+		HttpResponse::Ok()
+	};
+	builder
+		.append_header((header::ACCEPT_RANGES, "bytes"))
+		.append_header((FILE_SIZE_HEADER, response.size))
+		.append_header((END_OF_FILE_HEADER, (end >= response.size).to_string()));
+	if is_ranged {
+		let range = if response.data.is_empty() {
+			format!("bytes */{}", response.size)
+		} else {
+			format!("bytes {}-{}/{}", response.offset, end - 1, response.size)
+		};
+		builder.append_header((header::CONTENT_RANGE, range));
+	}
+	builder.body(response.data)
+}
+
+fn job_status(
+	jobs: &Jobs,
+	id: u64,
+	action: impl FnOnce(&Job),
+) -> Result<JobResponse, ErrorResponse> {
+	let job = get_job(jobs, id)?;
+	action(&job);
+	Ok(job.status())
+}
+
+fn job_results(
+	jobs: &Jobs,
+	request: JobResultsRequest,
+) -> Result<JobResultsResponse, ErrorResponse> {
+	let job = get_job(jobs, request.id)?;
+	let (offset, limit) = request.page()?;
+	// gets the status first, so no results are missed when the client stops at a finished job
+	let status = job.status();
+	let results = job.results(offset, limit);
+	let cursor = (offset + results.len()).to_string();
+	Ok(JobResultsResponse {
+		job: status,
+		results,
+		cursor,
+	})
+}
+
+fn remove_job(jobs: &Jobs, id: u64) -> Result<JobResponse, ErrorResponse> {
+	let job = jobs.remove(id).ok_or_else(|| job_not_found(id))?;
+	job.cancel();
+	Ok(job.status())
+}
+
+fn get_job(jobs: &Jobs, id: u64) -> Result<Arc<Job>, ErrorResponse> {
+	jobs.get(id).ok_or_else(|| job_not_found(id))
+}
+
+fn job_not_found(id: u64) -> ErrorResponse {
+	ErrorResponse::new(ErrorCode::NotFound, format!("job {} does not exist", id))
+}
+
+/// Sends changes of the watched directory as server-sent events; failures are
+/// sent as `error` events.
+async fn watch<B: Backend>(
+	query: web::Query<WatchRequest>,
+	backend: web::Data<B>,
+) -> Result<HttpResponse, ErrorResponse> {
+	let events = backend.watch(query.into_inner())?.map(|event| {
+		let message = match event {
+			Ok(event) => format!("data: {}\n\n", serde_json::to_string(&event)?),
+			Err(err) => format!("event: error\ndata: {}\n\n", serde_json::to_string(&err)?),
+		};
+		Ok::<_, ErrorResponse>(web::Bytes::from(message))
+	});
+	Ok(HttpResponse::Ok()
+		.content_type("text/event-stream")
+		.append_header(("Cache-Control", "no-cache"))
+		.streaming(events))
+}
+
+#[derive(Deserialize)]
+struct IconQuery {
+	path: Option<String>,
+}
+
+async fn icon<B: Backend>(
+	name: web::Path<String>,
+	query: web::Query<IconQuery>,
+	backend: web::Data<B>,
+) -> Result<HttpResponse, ErrorResponse> {
+	if let Some(icon) = backend.icon(&name, query.path.as_deref())? {
+		Ok(HttpResponse::Ok()
+			.content_type(icon.content_type)
+			.append_header(("Cache-Control", "public, max-age=86400"))
+			.body(icon.data))
+	} else {
+		Ok(HttpResponse::NotFound().body("".to_string()))
+	}
+}
+
+#[derive(Deserialize)]
+struct ThumbnailQuery {
+	path: String,
+	size: Option<u32>,
+}
+
+async fn thumbnail<B: Backend>(
+	query: web::Query<ThumbnailQuery>,
+	backend: web::Data<B>,
+) -> Result<HttpResponse, ErrorResponse> {
+	let size = query.size.unwrap_or(DEFAULT_THUMBNAIL_SIZE);
+	if let Some(thumbnail) = backend.thumbnail(&query.path, size).await? {
+		// the file may change at the same path
+		Ok(HttpResponse::Ok()
+			.content_type(thumbnail.content_type)
+			.append_header(("Cache-Control", "no-cache"))
+			.body(thumbnail.data))
+	} else {
+		Ok(HttpResponse::NotFound().body("".to_string()))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::testing::Files;
+	use crate::PROTOCOL_VERSION;
+	use actix_web::test::{call_service, init_service, read_body, TestRequest};
+	use serde_json::{json, Value};
+
+	/// Posts the body with the headers to a server of files; gets the response.
+	async fn call(body: &str, headers: &[(&str, &str)]) -> (u16, header::HeaderMap, Vec<u8>) {
+		let files = web::Data::new(Files::default());
+		files.add("dir/a.txt", "abc", 0);
+		let app = init_service(
+			App::new()
+				.app_data(files)
+				.app_data(web::Data::new(Jobs::default()))
+				.app_data(web::JsonConfig::default().error_handler(json_error))
+				.route("/", web::post().to(enact::<Files>)),
+		)
+		.await;
+		let mut request = TestRequest::post().uri("/").set_payload(body.to_string());
+		for header in headers {
+			request = request.insert_header(*header);
+		}
+		let response = call_service(&app, request.to_request()).await;
+		let status = response.status().as_u16();
+		let headers = response.headers().clone();
+		let body = read_body(response).await;
+		(status, headers, body.to_vec())
+	}
+
+	/// Posts the body to a server of files; gets the status and the response.
+	async fn post(body: &str, content_type: &str) -> (u16, Value) {
+		let (status, _, body) = call(body, &[("content-type", content_type)]).await;
+		(status, serde_json::from_slice(&body).unwrap())
+	}
+
+	async fn post_json(body: Value) -> (u16, Value) {
+		post(&body.to_string(), "application/json").await
+	}
+
+	#[test]
+	fn parse_ranges() {
+		assert_eq!(
+			parse_range("bytes=0-1023").unwrap(),
+			ByteRange::From(0, Some(1024))
+		);
+		assert_eq!(
+			parse_range("bytes=1024-").unwrap(),
+			ByteRange::From(1024, None)
+		);
+		assert_eq!(parse_range("bytes=-512").unwrap(), ByteRange::Suffix(512));
+		for value in [
+			"bytes=5-4",
+			"bytes=-",
+			"bytes=a-",
+			"items=0-1",
+			"bytes=0",
+			"bytes=0-18446744073709551615",
+		] {
+			assert!(parse_range(value).is_err(), "{}", value);
+		}
+	}
+
+	#[actix_web::test]
+	async fn answer_errors() {
+		let (status, body) = post_json(json!({ "list": { "path": "missing" } })).await;
+		assert_eq!(status, 404);
+		assert_eq!(body["error"]["code"], "notFound");
+		assert_eq!(body["error"]["path"], "missing");
+		assert_eq!(body["error"]["retryable"], false);
+
+		let (status, body) = post_json(json!({ "join": { "path": "/", "key": "a" } })).await;
+		assert_eq!(status, 501);
+		assert_eq!(body["error"]["code"], "unsupported");
+
+		let (status, body) = post_json(json!({ "list": { "path": "dir" } })).await;
+		assert_eq!(status, 200);
+		assert_eq!(body["list"]["files"][0]["key"], "a.txt");
+	}
+
+	#[actix_web::test]
+	async fn answer_invalid_requests() {
+		let (status, body) = post("{", "application/json").await;
+		assert_eq!(status, 400);
+		assert_eq!(body["error"]["code"], "invalidInput");
+		let (status, body) = post_json(json!({ "unknown": {} })).await;
+		assert_eq!(status, 400);
+		assert!(body["error"]["message"]
+			.as_str()
+			.unwrap()
+			.starts_with("invalid request: "));
+		let (status, body) = post("{}", "text/plain").await;
+		assert_eq!(status, 400);
+		assert_eq!(body["error"]["message"], "request is not JSON");
+	}
+
+	#[actix_web::test]
+	async fn dispatch_requests() {
+		// operations the backend does not implement are answered as unsupported
+		let copy = json!({ "copy": { "keys": ["a.txt"], "destination": "b" } });
+		for request in [copy, json!({ "search": { "path": "dir" } })] {
+			let (status, body) = post_json(request).await;
+			assert_eq!(status, 501);
+			assert_eq!(body["error"]["code"], "unsupported");
+		}
+		// jobs are kept by the server for all backends
+		let (status, body) = post_json(json!({ "jobStatus": { "id": 7 } })).await;
+		assert_eq!(status, 404);
+		assert_eq!(body["error"]["code"], "notFound");
+		let read = json!({ "read": { "path": "dir", "key": "a.txt", "offset": 1 } });
+		let json = [("content-type", "application/json")];
+		let (status, headers, body) = call(&read.to_string(), &json).await;
+		assert_eq!((status, body.as_slice()), (206, &b"bc"[..]));
+		assert_eq!(headers.get(FILE_SIZE_HEADER).unwrap(), "3");
+	}
+
+	#[actix_web::test]
+	async fn answer_capabilities() {
+		let (status, body) = post_json(json!({ "capabilities": {} })).await;
+		assert_eq!(status, 200);
+		let capabilities = &body["capabilities"];
+		assert_eq!(capabilities["protocolVersion"], PROTOCOL_VERSION);
+		assert_eq!(capabilities["pathSyntax"]["separator"], "/");
+		assert_eq!(capabilities["pathSyntax"]["caseSensitive"], true);
+	}
+}
@@ -0,0 +1,165 @@
+use crate::{
+	Backend, Checksum, ChecksumAlgorithm, ChecksumRequest, ErrorCode, ErrorResponse, FileInfo, Job,
+	JobKind, JobResponse, JobResult, Jobs, ReadRequest, StatRequest,
+};
+use actix_web::web;
+use sha1::Digest;
+use std::fmt::Write;
+
+/// Number of bytes requested from the backend at once.
+const CHUNK_SIZE: u64 = 1024 * 1024;
+
+/// Starts a job computing checksums of files with ranged reads, so it works with
+/// every backend that supports reading and files do not have to fit in memory.
+pub(crate) fn checksum<B: Backend>(
+	backend: web::Data<B>,
+	request: ChecksumRequest,
+	jobs: &Jobs,
+) -> Result<JobResponse, ErrorResponse> {
+	if request.algorithms.is_empty() {
+		return Err(ErrorResponse::new(
+			ErrorCode::InvalidInput,
+			"no checksum algorithm is selected",
+		));
+	}
+	let job = jobs.start_local(JobKind::Checksum, |job| async move {
+		job.update(|progress| progress.files_total = request.keys.len() as u64);
+		for key in &request.keys {
+			job.checkpoint_async().await?;
+			job.update(|progress| progress.current = Some(key.clone()));
+			let result = match checksum_file(backend.get_ref(), &request, key, &job).await {
+				Ok(result) => result,
+				Err(err) if err.code == ErrorCode::Cancelled => return Err(err),
+				Err(err) => JobResult {
+					file: FileInfo {
+						key: key.clone(),
+						name: key.clone(),
+						..Default::default()
+					},
+					error: Some(err),
+					..Default::default()
+				},
+			};
+			job.add_result(result);
+			job.update(|progress| progress.files_done += 1);
+		}
+		Ok(())
+	});
+	Ok(job.status())
+}
+
+async fn checksum_file<B: Backend>(
+	backend: &B,
+	request: &ChecksumRequest,
+	key: &str,
+	job: &Job,
+) -> Result<JobResult, ErrorResponse> {
+	let stat = backend
+		.stat(StatRequest {
+			path: request.path.clone(),
+			key: Some(key.to_string()),
+		})
+		.await?;
+	if stat.file.is_directory {
+		return Err(ErrorResponse::new(
+			ErrorCode::IsADirectory,
+			"cannot compute checksum of a directory",
+		)
+		.with_path(key));
+	}
+	job.update(|progress| progress.bytes_total += stat.file.size);
+
+	let mut hashers = request
+		.algorithms
+		.iter()
+		.map(|algorithm| Hasher::new(*algorithm))
+		.collect::<Vec<_>>();
+	let mut offset = 0;
+	loop {
+		job.checkpoint_async().await?;
+		let response = backend
+			.read(ReadRequest {
+				path: request.path.clone(),
+				key: Some(key.to_string()),
+				offset: Some(offset),
+				length: Some(CHUNK_SIZE),
+			})
+			.await?;
+		for hasher in &mut hashers {
+			hasher.update(&response.data);
+		}
+		offset += response.data.len() as u64;
+		job.update(|progress| progress.bytes_done += response.data.len() as u64);
+		// the size of the response is current, also when the file changed after the stat
+		if response.data.is_empty() || offset >= response.size {
+			break;
+		}
+	}
+
+	let checksums = request
+		.algorithms
+		.iter()
+		.zip(hashers)
+		.map(|(algorithm, hasher)| Checksum {
+			algorithm: *algorithm,
+			value: hasher.finish(),
+			expected: None,
+		})
+		.collect();
+	Ok(JobResult {
+		file: stat.file,
+		checksums,
+		..Default::default()
+	})
+}
+
+/// Incremental computation of a checksum, so files can be hashed chunk by chunk.
+pub enum Hasher {
+	Sha256(sha2::Sha256),
+	Sha1(sha1::Sha1),
+	Md5(md5::Md5),
+	Crc32(crc32fast::Hasher),
+	Blake3(Box<blake3::Hasher>),
+}
+
+impl Hasher {
+	pub fn new(algorithm: ChecksumAlgorithm) -> Self {
+		match algorithm {
+			ChecksumAlgorithm::Sha256 => Hasher::Sha256(sha2::Sha256::new()),
+			ChecksumAlgorithm::Sha1 => Hasher::Sha1(sha1::Sha1::new()),
+			ChecksumAlgorithm::Md5 => Hasher::Md5(md5::Md5::new()),
+			ChecksumAlgorithm::Crc32 => Hasher::Crc32(crc32fast::Hasher::new()),
+			ChecksumAlgorithm::Blake3 => Hasher::Blake3(Box::default()),
+		}
+	}
+
+	pub fn update(&mut self, data: &[u8]) {
+		match self {
+			Hasher::Sha256(hasher) => hasher.update(data),
+			Hasher::Sha1(hasher) => hasher.update(data),
+			Hasher::Md5(hasher) => hasher.update(data),
+			Hasher::Crc32(hasher) => hasher.update(data),
+			Hasher::Blake3(hasher) => {
+				hasher.update(data);
+			}
+		}
+	}
+
+	/// Gets the checksum as lowercase hexadecimal digits.
+	pub fn finish(self) -> String {
+		match self {
+			Hasher::Sha256(hasher) => to_hex(&hasher.finalize()),
+			Hasher::Sha1(hasher) => to_hex(&hasher.finalize()),
+			Hasher::Md5(hasher) => to_hex(&hasher.finalize()),
+			Hasher::Crc32(hasher) => format!("{:08x}", hasher.finalize()),
+			Hasher::Blake3(hasher) => hasher.finalize().to_hex().to_string(),
+		}
+	}
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+	bytes.iter().fold(String::new(), |mut hex, byte| {
+		write!(hex, "{:02x}", byte).ok();
+		hex
+	})
+}
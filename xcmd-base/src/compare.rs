@@ -0,0 +1,312 @@
+use crate::side::{get_name, Side};
+use crate::{
+	Backend, CompareDirectoriesRequest, CompareMethod, ComparisonStatus, EntryComparison,
+	ErrorCode, ErrorResponse, FileInfo, Job, JobKind, JobResponse, JobResult, Jobs,
+};
+use actix_web::web;
+use futures_util::future::try_join;
+use std::collections::BTreeMap;
+
+/// Number of bytes read from each side at once when comparing contents.
+const CHUNK_SIZE: u64 = 1024 * 1024;
+
+/// Default largest difference of dates taken as the same date, in milliseconds.
+pub(crate) const DATE_TOLERANCE: i64 = 2000;
+
+/// Starts a job comparing two directories. Directories on other backends are
+/// listed and read with requests to those backends.
+pub(crate) fn compare_directories<B: Backend>(
+	backend: web::Data<B>,
+	request: CompareDirectoriesRequest,
+	jobs: web::Data<Jobs>,
+) -> Result<JobResponse, ErrorResponse> {
+	let client = reqwest::Client::new();
+	let comparer = Comparer {
+		left: Side::new(backend.clone(), jobs.clone(), request.left, &client),
+		right: Side::new(backend, jobs.clone(), request.right, &client),
+		recursive: request.recursive,
+		method: request.method,
+		date_tolerance: request.date_tolerance.unwrap_or(DATE_TOLERANCE),
+	};
+	let job = jobs.start_local(JobKind::CompareDirectories, |job| async move {
+		comparer
+			.compare(&job, |result| job.add_result(result))
+			.await
+	});
+	Ok(job.status())
+}
+
+pub(crate) struct Comparer<B: Backend> {
+	pub left: Side<B>,
+	pub right: Side<B>,
+	pub recursive: bool,
+	pub method: CompareMethod,
+	pub date_tolerance: i64,
+}
+
+impl<B: Backend> Comparer<B> {
+	/// Compares the directories depth first, so entries of a directory are followed
+	/// by entries of its subdirectories. Directories found on both sides are not
+	/// results themselves.
+	pub async fn compare(
+		&self,
+		job: &Job,
+		mut on_result: impl FnMut(JobResult),
+	) -> Result<(), ErrorResponse> {
+		let mut directories = vec![String::new()];
+		while let Some(prefix) = directories.pop() {
+			job.checkpoint_async().await?;
+			job.update(|progress| progress.current = Some(prefix.clone()));
+			let listings = try_join(self.left.list(&prefix), self.right.list(&prefix)).await;
+			let (left_files, right_files) = match listings {
+				Ok(listings) => listings,
+				// the compared directories themselves must be readable
+				Err(err) if prefix.is_empty() => return Err(err),
+				Err(err) => {
+					on_result(JobResult {
+						file: get_file(&prefix, None),
+						error: Some(err),
+						..Default::default()
+					});
+					continue;
+				}
+			};
+
+			let mut entries = BTreeMap::<String, (Option<FileInfo>, Option<FileInfo>)>::new();
+			for file in left_files {
+				let name = get_name(&file);
+				entries.entry(name).or_default().0 = Some(file);
+			}
+			for file in right_files {
+				let name = get_name(&file);
+				entries.entry(name).or_default().1 = Some(file);
+			}
+
+			let mut subdirectories = Vec::new();
+			for (name, (left, right)) in entries {
+				job.checkpoint_async().await?;
+				let key = format!("{}{}", prefix, name);
+				let is_subdirectory = |file: &Option<FileInfo>| {
+					file.as_ref()
+						.is_some_and(|file| file.is_directory && !file.is_symlink)
+				};
+				if is_subdirectory(&left) && is_subdirectory(&right) {
+					if self.recursive {
+						subdirectories.push(format!("{}/", key));
+					}
+					continue;
+				}
+
+				let (status, error) = match self.get_status(&key, &left, &right, job).await {
+					Ok(status) => (status, None),
+					Err(err) if err.code == ErrorCode::Cancelled => return Err(err),
+					Err(err) => (ComparisonStatus::Different, Some(err)),
+				};
+				let left = left.map(|file| get_file(&key, Some(file)));
+				let right = right.map(|file| get_file(&key, Some(file)));
+				on_result(JobResult {
+					file: left.clone().or(right.clone()).unwrap_or_default(),
+					error,
+					comparison: Some(EntryComparison {
+						status,
+						left,
+						right,
+					}),
+					..Default::default()
+				});
+				job.update(|progress| progress.files_done += 1);
+			}
+			directories.extend(subdirectories.into_iter().rev());
+		}
+		Ok(())
+	}
+
+	async fn get_status(
+		&self,
+		key: &str,
+		left: &Option<FileInfo>,
+		right: &Option<FileInfo>,
+		job: &Job,
+	) -> Result<ComparisonStatus, ErrorResponse> {
+		let (left, right) = match (left, right) {
+			(Some(left), Some(right)) => (left, right),
+			(Some(_), None) => return Ok(ComparisonStatus::LeftOnly),
+			_ => return Ok(ComparisonStatus::RightOnly),
+		};
+		if left.is_directory != right.is_directory {
+			return Ok(ComparisonStatus::Different);
+		}
+
+		let is_same_date = (left.date - right.date).abs() <= self.date_tolerance;
+		let is_identical = left.size == right.size
+			&& match self.method {
+				CompareMethod::Date => is_same_date,
+				CompareMethod::Content => {
+					left.is_directory || self.is_same_content(key, job).await?
+				}
+			};
+		Ok(if is_identical {
+			ComparisonStatus::Identical
+		} else if !is_same_date && left.date > right.date {
+			ComparisonStatus::LeftNewer
+		} else if !is_same_date {
+			ComparisonStatus::RightNewer
+		} else if left.size > right.size {
+			ComparisonStatus::LeftLarger
+		} else if left.size < right.size {
+			ComparisonStatus::RightLarger
+		} else {
+			ComparisonStatus::Different
+		})
+	}
+
+	/// Reads the files from both sides; stops at the first difference.
+	async fn is_same_content(&self, key: &str, job: &Job) -> Result<bool, ErrorResponse> {
+		let mut offset = 0;
+		loop {
+			job.checkpoint_async().await?;
+			let (left, right) = try_join(
+				self.left.read(key, offset, CHUNK_SIZE),
+				self.right.read(key, offset, CHUNK_SIZE),
+			)
+			.await?;
+			if left.data != right.data {
+				return Ok(false);
+			}
+			if left.data.is_empty() {
+				return Ok(true);
+			}
+			offset += left.data.len() as u64;
+			job.update(|progress| progress.bytes_done += 2 * left.data.len() as u64);
+		}
+	}
+}
+
+/// Gets the entry with a key relative to the compared directories.
+fn get_file(key: &str, file: Option<FileInfo>) -> FileInfo {
+	let key = key.trim_end_matches('/').to_string();
+	match file {
+		Some(file) => FileInfo { key, ..file },
+		None => FileInfo {
+			name: key.clone(),
+			key,
+			is_directory: true,
+			..Default::default()
+		},
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::testing::{get_side, Files};
+
+	fn compare(
+		files: Files,
+		recursive: bool,
+		method: CompareMethod,
+	) -> Vec<(String, ComparisonStatus)> {
+		let files = web::Data::new(files);
+		let comparer = Comparer {
+			left: get_side(&files, "/left"),
+			right: get_side(&files, "/right"),
+			recursive,
+			method,
+			date_tolerance: DATE_TOLERANCE,
+		};
+		let job = Job::new(1, JobKind::CompareDirectories);
+		let mut results = Vec::new();
+		let compared = comparer.compare(&job, |result| {
+			let status = result.comparison.unwrap().status;
+			results.push((result.file.key, status));
+		});
+		actix_web::rt::System::new().block_on(compared).unwrap();
+		results
+	}
+
+	fn get_statuses(results: &[(&str, ComparisonStatus)]) -> Vec<(String, ComparisonStatus)> {
+		results
+			.iter()
+			.map(|(key, status)| (key.to_string(), *status))
+			.collect()
+	}
+
+	#[test]
+	fn compare_by_date() {
+		let files = Files::default();
+		files.add("left/same", "a", 10_000);
+		files.add("right/same", "a", 11_000);
+		files.add("left/newer", "a", 20_000);
+		files.add("right/newer", "a", 10_000);
+		files.add("left/older", "a", 10_000);
+		files.add("right/older", "a", 20_000);
+		files.add("left/larger", "ab", 10_000);
+		files.add("right/larger", "a", 10_000);
+		files.add("left/smaller", "a", 10_000);
+		files.add("right/smaller", "ab", 10_000);
+		files.add("left/left", "a", 0);
+		files.add("right/right", "a", 0);
+		files.add_directory("left/type");
+		files.add("right/type", "a", 0);
+		use ComparisonStatus::*;
+		assert_eq!(
+			compare(files, false, CompareMethod::Date),
+			get_statuses(&[
+				("larger", LeftLarger),
+				("left", LeftOnly),
+				("newer", LeftNewer),
+				("older", RightNewer),
+				("right", RightOnly),
+				("same", Identical),
+				("smaller", RightLarger),
+				("type", Different),
+			])
+		);
+	}
+
+	#[test]
+	fn compare_subdirectories() {
+		let files = Files::default();
+		files.add("left/a/x", "x", 0);
+		files.add("right/a/x", "x", 0);
+		files.add("left/a/b/y", "y", 0);
+		files.add_directory("right/a/b");
+		files.add("left/c", "c", 0);
+		files.add("right/d/z", "z", 0);
+		// directories on both sides are entered after the entries of their parent,
+		// those on one side are results
+		assert_eq!(
+			compare(files, true, CompareMethod::Date),
+			get_statuses(&[
+				("c", ComparisonStatus::LeftOnly),
+				("d", ComparisonStatus::RightOnly),
+				("a/x", ComparisonStatus::Identical),
+				("a/b/y", ComparisonStatus::LeftOnly),
+			])
+		);
+		let files = Files::default();
+		files.add("left/a/x", "x", 0);
+		files.add_directory("right/a");
+		assert!(compare(files, false, CompareMethod::Date).is_empty());
+	}
+
+	#[test]
+	fn compare_by_content() {
+		let files = Files::default();
+		let large = "a".repeat(CHUNK_SIZE as usize + 1);
+		files.add("left/same", &large, 0);
+		files.add("right/same", &large, 50_000);
+		files.add("left/changed", "ab", 0);
+		files.add("right/changed", "ac", 0);
+		files.add("left/changed-later", &format!("{}b", large), 0);
+		files.add("right/changed-later", &format!("{}c", large), 0);
+		assert_eq!(
+			compare(files, false, CompareMethod::Content),
+			get_statuses(&[
+				("changed", ComparisonStatus::Different),
+				("changed-later", ComparisonStatus::Different),
+				("same", ComparisonStatus::Identical),
+			])
+		);
+	}
+}
@@ -0,0 +1,245 @@
+use crate::{JobCancelled, Response};
+use actix_web::{http::StatusCode, HttpResponse, ResponseError};
+use serde::Deserialize;
+use serde_derive::Serialize;
+use std::{fmt, io, path::Path};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ErrorResponse {
+	/// Stable error code.
+	pub code: ErrorCode,
+	/// Message shown to the user.
+	pub message: String,
+	/// Path of the entry that caused the error.
+	pub path: Option<String>,
+	/// Indicates whether repeating the request may succeed.
+	pub retryable: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ErrorCode {
+	NotFound,
+	PermissionDenied,
+	AlreadyExists,
+	InvalidInput,
+	/// The read starts beyond the end of the file.
+	OutOfRange,
+	NotADirectory,
+	IsADirectory,
+	DirectoryNotEmpty,
+	/// The entry was changed by someone else.
+	Conflict,
+	/// The backend does not support the operation.
+	Unsupported,
+	Cancelled,
+	StorageFull,
+	Connection,
+	Timeout,
+	/// Any other input/output error.
+	Io,
+	Internal,
+}
+
+impl ErrorResponse {
+	pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+		ErrorResponse {
+			code,
+			message: message.into(),
+			path: None,
+			retryable: matches!(code, ErrorCode::Connection | ErrorCode::Timeout),
+		}
+	}
+
+	/// Error of an operation the backend does not implement.
+	pub fn unsupported() -> Self {
+		ErrorResponse::new(ErrorCode::Unsupported, "operation is not supported")
+	}
+
+	/// Sets the path of the entry that caused the error.
+	pub fn with_path(mut self, path: impl AsRef<Path>) -> Self {
+		self.path = Some(path.as_ref().to_string_lossy().to_string());
+		self
+	}
+
+	pub fn with_retryable(mut self, retryable: bool) -> Self {
+		self.retryable = retryable;
+		self
+	}
+}
+
+/// Checks that a read starts within a file of the size or at its end.
+pub fn check_offset(offset: u64, size: u64) -> Result<(), ErrorResponse> {
+	if offset > size {
+		return Err(ErrorResponse::new(
+			ErrorCode::OutOfRange,
+			"offset is beyond the end of the file",
+		));
+	}
+	Ok(())
+}
+
+impl fmt::Display for ErrorResponse {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match &self.path {
+			Some(path) => write!(f, "{}: {}", path, self.message),
+			None => write!(f, "{}", self.message),
+		}
+	}
+}
+
+impl std::error::Error for ErrorResponse {}
+
+impl ResponseError for ErrorResponse {
+	fn status_code(&self) -> StatusCode {
+		match self.code {
+			ErrorCode::NotFound => StatusCode::NOT_FOUND,
+			ErrorCode::PermissionDenied => StatusCode::FORBIDDEN,
+			ErrorCode::AlreadyExists | ErrorCode::Conflict | ErrorCode::DirectoryNotEmpty => {
+				StatusCode::CONFLICT
+			}
+			ErrorCode::InvalidInput | ErrorCode::NotADirectory | ErrorCode::IsADirectory => {
+				StatusCode::BAD_REQUEST
+			}
+			ErrorCode::OutOfRange => StatusCode::RANGE_NOT_SATISFIABLE,
+			ErrorCode::Unsupported => StatusCode::NOT_IMPLEMENTED,
+			ErrorCode::Connection => StatusCode::BAD_GATEWAY,
+			ErrorCode::Timeout => StatusCode::GATEWAY_TIMEOUT,
+			ErrorCode::StorageFull => StatusCode::INSUFFICIENT_STORAGE,
+			ErrorCode::Cancelled | ErrorCode::Io | ErrorCode::Internal => {
+				StatusCode::INTERNAL_SERVER_ERROR
+			}
+		}
+	}
+
+	fn error_response(&self) -> HttpResponse {
+		let body = serde_json::to_string(&Response::Error(self.clone())).unwrap_or_default();
+		HttpResponse::build(self.status_code())
+			.content_type("application/json")
+			.body(body)
+	}
+}
+
+impl From<io::Error> for ErrorResponse {
+	fn from(error: io::Error) -> Self {
+		let code = match error.kind() {
+			io::ErrorKind::NotFound => ErrorCode::NotFound,
+			io::ErrorKind::PermissionDenied | io::ErrorKind::ReadOnlyFilesystem => {
+				ErrorCode::PermissionDenied
+			}
+			io::ErrorKind::AlreadyExists => ErrorCode::AlreadyExists,
+			io::ErrorKind::InvalidInput
+			| io::ErrorKind::InvalidData
+			| io::ErrorKind::InvalidFilename => ErrorCode::InvalidInput,
+			io::ErrorKind::NotADirectory => ErrorCode::NotADirectory,
+			io::ErrorKind::IsADirectory => ErrorCode::IsADirectory,
+			io::ErrorKind::DirectoryNotEmpty => ErrorCode::DirectoryNotEmpty,
+			io::ErrorKind::Unsupported => ErrorCode::Unsupported,
+			io::ErrorKind::StorageFull | io::ErrorKind::QuotaExceeded => ErrorCode::StorageFull,
+			io::ErrorKind::TimedOut => ErrorCode::Timeout,
+			io::ErrorKind::ConnectionRefused
+			| io::ErrorKind::ConnectionReset
+			| io::ErrorKind::ConnectionAborted
+			| io::ErrorKind::NotConnected
+			| io::ErrorKind::BrokenPipe
+			| io::ErrorKind::HostUnreachable
+			| io::ErrorKind::NetworkUnreachable => ErrorCode::Connection,
+			_ => ErrorCode::Io,
+		};
+		let response = ErrorResponse::new(code, error.to_string());
+		match error.kind() {
+			io::ErrorKind::Interrupted
+			| io::ErrorKind::WouldBlock
+			| io::ErrorKind::ResourceBusy => response.with_retryable(true),
+			_ => response,
+		}
+	}
+}
+
+impl From<serde_json::Error> for ErrorResponse {
+	fn from(error: serde_json::Error) -> Self {
+		ErrorResponse::new(ErrorCode::InvalidInput, error.to_string())
+	}
+}
+
+impl From<reqwest::Error> for ErrorResponse {
+	fn from(error: reqwest::Error) -> Self {
+		let code = if error.is_timeout() {
+			ErrorCode::Timeout
+		} else if error.is_decode() {
+			ErrorCode::Io
+		} else {
+			ErrorCode::Connection
+		};
+		ErrorResponse::new(code, error.to_string())
+	}
+}
+
+impl From<base64::DecodeError> for ErrorResponse {
+	fn from(error: base64::DecodeError) -> Self {
+		ErrorResponse::new(ErrorCode::InvalidInput, error.to_string())
+	}
+}
+
+impl From<JobCancelled> for ErrorResponse {
+	fn from(error: JobCancelled) -> Self {
+		ErrorResponse::new(ErrorCode::Cancelled, error.to_string())
+	}
+}
+
+/// Attaches the path of the entry to errors converted into an [`ErrorResponse`].
+pub trait ErrorContext<T> {
+	fn with_path(self, path: impl AsRef<Path>) -> Result<T, ErrorResponse>;
+}
+
+impl<T, E: Into<ErrorResponse>> ErrorContext<T> for Result<T, E> {
+	fn with_path(self, path: impl AsRef<Path>) -> Result<T, ErrorResponse> {
+		self.map_err(|err| err.into().with_path(path))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn convert_io_errors() {
+		let convert = |kind| ErrorResponse::from(io::Error::new(kind, "message"));
+		let error = convert(io::ErrorKind::NotFound);
+		assert_eq!(
+			(error.code, error.message.as_str()),
+			(ErrorCode::NotFound, "message")
+		);
+		assert_eq!(error.status_code(), StatusCode::NOT_FOUND);
+		assert_eq!(
+			convert(io::ErrorKind::ReadOnlyFilesystem).code,
+			ErrorCode::PermissionDenied
+		);
+		assert_eq!(
+			convert(io::ErrorKind::QuotaExceeded).code,
+			ErrorCode::StorageFull
+		);
+		assert_eq!(
+			convert(io::ErrorKind::BrokenPipe).code,
+			ErrorCode::Connection
+		);
+		assert_eq!(convert(io::ErrorKind::Other).code, ErrorCode::Io);
+		// connection errors and busy resources may pass
+		assert!(convert(io::ErrorKind::ConnectionReset).retryable);
+		assert!(convert(io::ErrorKind::WouldBlock).retryable);
+		assert!(!convert(io::ErrorKind::NotFound).retryable);
+	}
+
+	#[test]
+	fn attach_paths() {
+		let result: Result<(), io::Error> = Err(io::ErrorKind::NotFound.into());
+		let error = result.with_path("/a/b").unwrap_err();
+		assert_eq!(error.path.as_deref(), Some("/a/b"));
+		assert_eq!(error.to_string(), format!("/a/b: {}", error.message));
+		assert_eq!(
+			ErrorResponse::new(ErrorCode::Conflict, "changed").status_code(),
+			StatusCode::CONFLICT
+		);
+	}
+}
@@ -0,0 +1,246 @@
+use crate::{ErrorResponse, JobKind, JobProgress, JobResponse, JobResult, JobState};
+use parking_lot::{Condvar, Mutex};
+use std::{
+	collections::HashMap,
+	error::Error,
+	fmt,
+	future::Future,
+	sync::{
+		atomic::{AtomicBool, AtomicU64, Ordering},
+		Arc,
+	},
+	thread,
+	time::{Duration, Instant},
+};
+
+/// Interval of checking whether a paused job was resumed, for jobs that cannot block.
+const PAUSE_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Time finished jobs are kept for clients to get their state and results.
+const FINISHED_JOB_TTL: Duration = Duration::from_secs(10 * 60);
+
+/// Number of results kept by a job; further results are only counted.
+const MAX_RESULTS: usize = 100_000;
+
+/// Registry of long-running jobs, shared by all request handlers.
+#[derive(Default)]
+pub struct Jobs {
+	jobs: Mutex<HashMap<u64, Arc<Job>>>,
+	last_id: AtomicU64,
+}
+
+impl Jobs {
+	/// Runs the work on a background thread and registers it as a new job.
+	pub fn start<F>(&self, kind: JobKind, work: F) -> Arc<Job>
+	where
+		F: FnOnce(&Job) -> Result<(), ErrorResponse> + Send + 'static,
+	{
+		let job = self.register(kind);
+		let worker = job.clone();
+		thread::spawn(move || {
+			let result = work(&worker);
+			worker.finish(result);
+		});
+		job
+	}
+
+	/// Runs the work on a background thread with a runtime of its own and registers it
+	/// as a new job; used by jobs built on requests to the backend, whose calls and
+	/// computations would otherwise hold up the server. The work uses
+	/// [`Job::checkpoint_async`].
+	pub fn start_local<F, R>(&self, kind: JobKind, work: F) -> Arc<Job>
+	where
+		F: FnOnce(Arc<Job>) -> R + Send + 'static,
+		R: Future<Output = Result<(), ErrorResponse>> + 'static,
+	{
+		let job = self.register(kind);
+		let worker = job.clone();
+		thread::spawn(move || {
+			let result = actix_web::rt::System::new().block_on(work(worker.clone()));
+			worker.finish(result);
+		});
+		job
+	}
+
+	/// Registers a new job; jobs that finished long ago are dropped.
+	fn register(&self, kind: JobKind) -> Arc<Job> {
+		let id = self.last_id.fetch_add(1, Ordering::Relaxed) + 1;
+		let job = Arc::new(Job::new(id, kind));
+		let mut jobs = self.jobs.lock();
+		jobs.retain(|_, job| !job.is_expired());
+		jobs.insert(id, job.clone());
+		job
+	}
+
+	/// Gets the job with a given id.
+	pub fn get(&self, id: u64) -> Option<Arc<Job>> {
+		self.jobs.lock().get(&id).cloned()
+	}
+
+	/// Removes the job with a given id together with its results.
+	pub fn remove(&self, id: u64) -> Option<Arc<Job>> {
+		self.jobs.lock().remove(&id)
+	}
+}
+
+pub struct Job {
+	id: u64,
+	kind: JobKind,
+	inner: Mutex<JobInner>,
+	resumed: Condvar,
+	cancelled: AtomicBool,
+}
+
+struct JobInner {
+	state: JobState,
+	progress: JobProgress,
+	error: Option<ErrorResponse>,
+	results: Vec<JobResult>,
+	/// Time the job finished at.
+	finished: Option<Instant>,
+}
+
+impl Job {
+	/// Creates a job; jobs that are not registered are not cancelled by clients.
+	pub fn new(id: u64, kind: JobKind) -> Self {
+		Job {
+			id,
+			kind,
+			inner: Mutex::new(JobInner {
+				state: JobState::Running,
+				progress: JobProgress::default(),
+				error: None,
+				results: Vec::new(),
+				finished: None,
+			}),
+			resumed: Condvar::new(),
+			cancelled: AtomicBool::new(false),
+		}
+	}
+
+	/// Blocks while the job is paused; fails when the job was cancelled.
+	/// Workers call this between units of work.
+	pub fn checkpoint(&self) -> Result<(), JobCancelled> {
+		let mut inner = self.inner.lock();
+		while inner.state == JobState::Paused && !self.is_cancelled() {
+			self.resumed.wait(&mut inner);
+		}
+		if self.is_cancelled() {
+			Err(JobCancelled)
+		} else {
+			Ok(())
+		}
+	}
+
+	/// Waits while the job is paused without blocking the thread; fails when the
+	/// job was cancelled.
+	pub async fn checkpoint_async(&self) -> Result<(), JobCancelled> {
+		while self.inner.lock().state == JobState::Paused && !self.is_cancelled() {
+			actix_web::rt::time::sleep(PAUSE_POLL_INTERVAL).await;
+		}
+		if self.is_cancelled() {
+			Err(JobCancelled)
+		} else {
+			Ok(())
+		}
+	}
+
+	/// Updates the reported progress.
+	pub fn update(&self, f: impl FnOnce(&mut JobProgress)) {
+		f(&mut self.inner.lock().progress);
+	}
+
+	/// Adds a result, available to clients while the job is running. Results beyond
+	/// the limit are dropped.
+	pub fn add_result(&self, result: JobResult) {
+		let mut inner = self.inner.lock();
+		if inner.results.len() < MAX_RESULTS {
+			inner.results.push(result);
+		} else {
+			inner.progress.results_dropped += 1;
+		}
+	}
+
+	/// Gets the results found so far, skipping `offset` results.
+	pub fn results(&self, offset: usize, limit: usize) -> Vec<JobResult> {
+		let inner = self.inner.lock();
+		inner
+			.results
+			.iter()
+			.skip(offset)
+			.take(limit)
+			.cloned()
+			.collect()
+	}
+
+	pub fn is_cancelled(&self) -> bool {
+		self.cancelled.load(Ordering::Relaxed)
+	}
+
+	/// Requests the job to stop at its next checkpoint.
+	pub fn cancel(&self) {
+		self.cancelled.store(true, Ordering::Relaxed);
+		let _inner = self.inner.lock();
+		self.resumed.notify_all();
+	}
+
+	pub fn pause(&self) {
+		let mut inner = self.inner.lock();
+		if inner.state == JobState::Running {
+			inner.state = JobState::Paused;
+		}
+	}
+
+	pub fn resume(&self) {
+		let mut inner = self.inner.lock();
+		if inner.state == JobState::Paused {
+			inner.state = JobState::Running;
+			self.resumed.notify_all();
+		}
+	}
+
+	/// Gets the current state of the job.
+	pub fn status(&self) -> JobResponse {
+		let inner = self.inner.lock();
+		JobResponse {
+			id: self.id,
+			kind: self.kind,
+			state: inner.state,
+			progress: inner.progress.clone(),
+			error: inner.error.clone(),
+		}
+	}
+
+	fn is_expired(&self) -> bool {
+		self.inner
+			.lock()
+			.finished
+			.is_some_and(|finished| finished.elapsed() > FINISHED_JOB_TTL)
+	}
+
+	fn finish(&self, result: Result<(), ErrorResponse>) {
+		let mut inner = self.inner.lock();
+		inner.progress.current = None;
+		inner.finished = Some(Instant::now());
+		inner.state = match result {
+			Ok(()) => JobState::Completed,
+			Err(_) if self.is_cancelled() => JobState::Cancelled,
+			Err(err) => {
+				inner.error = Some(err);
+				JobState::Failed
+			}
+		};
+	}
+}
+
+/// Error returned from a checkpoint of a cancelled job.
+#[derive(Debug)]
+pub struct JobCancelled;
+
+impl fmt::Display for JobCancelled {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "job was cancelled")
+	}
+}
+
+impl Error for JobCancelled {}
@@ -1,13 +1,31 @@
+mod backend;
+mod checksum;
+mod compare;
+mod error;
+mod jobs;
+mod listing;
+mod rename;
+mod side;
+mod sync;
 mod telemetry;
+#[cfg(test)]
+mod testing;
+
+pub use backend::{serve, Backend, Icon, WatchStream};
+pub use checksum::Hasher;
+pub use error::{check_offset, ErrorCode, ErrorContext, ErrorResponse};
+pub use jobs::{Job, JobCancelled, Jobs};
+pub use listing::{get_listing_page, ListingPage};
 
 use actix_cors::Cors;
 use actix_web::{
 	dev::{
 		forward_ready, Server, ServerHandle, Service, ServiceRequest, ServiceResponse, Transform,
 	},
-	http::header,
+	http::header::{self, HeaderName},
 	web::Data,
 };
+use base64::{engine::general_purpose::STANDARD, Engine};
 use futures_util::future::LocalBoxFuture;
 use parking_lot::Mutex;
 use rcgen::generate_simple_self_signed;
@@ -15,7 +33,13 @@ use rustls::{Certificate, PrivateKey, ServerConfig};
 use rustls_pemfile::{certs, pkcs8_private_keys};
 use serde::Deserialize;
 use serde_derive::Serialize;
-use std::{env, error::Error, net::TcpListener, thread, time::Duration};
+use std::{
+	env,
+	error::Error,
+	net::TcpListener,
+	thread,
+	time::{Duration, SystemTime, UNIX_EPOCH},
+};
 use std::{
 	future::{ready, Ready},
 	io::BufReader,
@@ -39,7 +63,17 @@ fn get_unused_port() -> Result<u16, std::io::Error> {
 	Ok(port)
 }
 
-pub fn post_startup(server: &Server, port: u16) {
+/// Version of the protocol spoken between the frontend and backends.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Response header with the total size of a read file.
+pub const FILE_SIZE_HEADER: &str = "x-file-size";
+/// Response header indicating whether a read reached the end of the file.
+pub const END_OF_FILE_HEADER: &str = "x-end-of-file";
+/// Path of the stream of changes of watched directories.
+pub(crate) const WATCH_PATH: &str = "/watch";
+
+pub fn post_startup(server: &Server, port: u16, capabilities: Capabilities) {
 	let stop_handle = Data::new(StopHandle::default());
 	stop_handle.register(server.handle());
 
@@ -48,7 +82,7 @@ pub fn post_startup(server: &Server, port: u16) {
 		stop_handle.stop(true);
 	});
 
-	let value = StartupResponse { port };
+	let value = StartupResponse { port, capabilities };
 	println!("{}", serde_json::to_string(&value).unwrap());
 }
 
@@ -75,6 +109,93 @@ fn loop_while_parent_process_exists() -> Result<(), Box<dyn Error>> {
 #[serde(rename_all = "camelCase")]
 pub struct StartupResponse {
 	pub port: u16,
+	pub capabilities: Capabilities,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Capabilities {
+	/// Version of the protocol spoken by the backend.
+	pub protocol_version: u32,
+	/// Backend name.
+	pub name: String,
+	/// Backend version.
+	pub version: String,
+	/// Requests the backend implements.
+	pub requests: Vec<RequestKind>,
+	/// Syntax of paths used by the backend.
+	pub path_syntax: PathSyntax,
+	/// Optional features of implemented requests.
+	pub features: Vec<Feature>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum RequestKind {
+	List,
+	Join,
+	Create,
+	CreateLink,
+	Read,
+	Write,
+	Copy,
+	Rename,
+	Delete,
+	Stat,
+	JobStatus,
+	JobCancel,
+	JobPause,
+	JobResume,
+	JobResults,
+	JobRemove,
+	Search,
+	DirectorySize,
+	Checksum,
+	CreateChecksumFile,
+	VerifyChecksumFile,
+	CompareDirectories,
+	Sync,
+	Pack,
+	Unpack,
+	BatchRename,
+	Undo,
+	Restore,
+	EmptyTrash,
+	Capabilities,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PathSyntax {
+	/// Separator of path components.
+	pub separator: String,
+	/// Indicates whether names differing only in case are different entries.
+	pub case_sensitive: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Feature {
+	/// Reading a part of a file.
+	RangedReads,
+	/// Watching a directory for changes; events are streamed from `GET /watch`.
+	Watch,
+	/// Listing a directory in pages.
+	PagedListing,
+	/// Searching the contents of files.
+	ContentSearch,
+	/// Appending to files with writes.
+	AppendWrites,
+	/// Writing files in parts with `part` in write requests; the file is created
+	/// when the last part is written.
+	MultipartWrites,
+	/// Moving deleted entries to the trash, which is listed at the path `trash:`.
+	Trash,
+	/// Listing mounted file systems, the home directory and user directories at the
+	/// path `computer:`, as a root above the root directory.
+	ComputerRoot,
+	/// Previews of images from `GET /thumbnails?path=...&size=...`.
+	Thumbnails,
 }
 
 #[derive(Default)]
@@ -103,16 +224,66 @@ pub enum Request {
 	Join { path: String, key: String },
 	/// Creates a directory.
 	Create(CreateRequest),
-	/// Reads the entire contents of a file.
+	/// Creates a symbolic or hard link.
+	CreateLink(CreateLinkRequest),
+	/// Reads the contents of a file, or a range of it.
 	Read(ReadRequest),
 	/// Writes data to a file, replacing its entire contents.
-	Write {},
-	/// Copies a file or directory.
-	Copy {},
-	/// Renames a file or directory.
-	Rename {},
-	/// Deletes a file or directory.
-	Delete {},
+	Write(WriteRequest),
+	/// Copies files or directories; runs as a job.
+	Copy(TransferRequest),
+	/// Renames or moves files or directories; runs as a job.
+	Rename(TransferRequest),
+	/// Deletes files or directories; runs as a job.
+	Delete(DeleteRequest),
+	/// Gets a single entry.
+	Stat(StatRequest),
+	/// Gets the state of a job.
+	JobStatus { id: u64 },
+	/// Cancels a job.
+	JobCancel { id: u64 },
+	/// Pauses a job.
+	JobPause { id: u64 },
+	/// Resumes a paused job.
+	JobResume { id: u64 },
+	/// Gets a page of the results produced by a job.
+	JobResults(JobResultsRequest),
+	/// Removes a job with its results, cancelling it if it is running. Finished jobs
+	/// are removed after a while anyway.
+	JobRemove { id: u64 },
+	/// Searches a directory tree for entries; runs as a job producing results.
+	Search(SearchRequest),
+	/// Sums sizes of directory trees; runs as a job producing the updated entries.
+	DirectorySize(DirectorySizeRequest),
+	/// Compares entries of two directories, possibly on different backends; runs as
+	/// a job producing the differences.
+	CompareDirectories(CompareDirectoriesRequest),
+	/// Synchronizes two directories, possibly on different backends; runs as a job
+	/// producing a log of the actions.
+	Sync(SyncRequest),
+	/// Computes checksums of files; runs as a job producing the checksums.
+	Checksum(ChecksumRequest),
+	/// Writes checksums of files to a checksum file; runs as a job.
+	CreateChecksumFile(CreateChecksumFileRequest),
+	/// Compares files with the checksums listed in a checksum file; runs as a job.
+	VerifyChecksumFile(VerifyChecksumFileRequest),
+	/// Packs files into an archive; runs as a job.
+	Pack(PackRequest),
+	/// Extracts entries of an archive; runs as a job.
+	Unpack(UnpackRequest),
+	/// Renames entries of a directory by templates; a preview gets the new names,
+	/// otherwise it runs as a job.
+	BatchRename(BatchRenameRequest),
+	/// Reverts the most recent operations that changed files; runs as a job with the
+	/// restored entries as results.
+	Undo(UndoRequest),
+	/// Moves entries of the trash back to where they were deleted from; runs as a job
+	/// with the restored entries as results.
+	Restore(RestoreRequest),
+	/// Deletes all entries of the trash for good; runs as a job.
+	EmptyTrash {},
+	/// Gets the operations and features supported by the backend.
+	Capabilities {},
 }
 
 #[derive(Debug, Serialize)]
@@ -120,18 +291,52 @@ pub enum Request {
 pub enum Response {
 	List(ListResponse),
 	Create(CreateResponse),
+	CreateLink(CreateLinkResponse),
+	Write(WriteResponse),
+	Stat(StatResponse),
+	Job(JobResponse),
+	JobResults(JobResultsResponse),
+	BatchRename(BatchRenameResponse),
+	Capabilities(Capabilities),
+	Error(ErrorResponse),
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct ListRequest {
 	/// Directory path.
 	pub path: Option<String>,
 	/// Optional subdirectory key.
 	pub key: Option<String>,
+	/// Position to continue listing from, as returned in the previous page.
+	pub cursor: Option<String>,
+	/// Maximum number of entries in the page; all entries are listed if not provided.
+	pub limit: Option<usize>,
 }
 
-#[derive(Debug, Serialize)]
+impl ListRequest {
+	/// Gets the offset and size of the requested page, for backends using
+	/// entry indexes as cursors.
+	pub fn page(&self) -> Result<(usize, usize), ErrorResponse> {
+		get_page(&self.cursor, self.limit)
+	}
+}
+
+fn get_page(
+	cursor: &Option<String>,
+	limit: Option<usize>,
+) -> Result<(usize, usize), ErrorResponse> {
+	let offset = match cursor {
+		Some(cursor) => cursor
+			.parse()
+			.map_err(|_| ErrorResponse::new(ErrorCode::InvalidInput, "invalid cursor"))?,
+		None => 0,
+	};
+	let limit = limit.filter(|limit| *limit > 0).unwrap_or(usize::MAX);
+	Ok((offset, limit))
+}
+
+#[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct ListResponse {
 	/// Directory path.
@@ -140,9 +345,13 @@ pub struct ListResponse {
 	pub name: String,
 	/// Files in the directory.
 	pub files: Vec<FileInfo>,
+	/// Cursor of the next page; not provided for the last page.
+	pub cursor: Option<String>,
+	/// Space available to the user on the file system of the directory.
+	pub free_space: Option<u64>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct CreateRequest {
 	/// Directory path.
@@ -162,15 +371,786 @@ pub struct CreateResponse {
 
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
+pub struct CreateLinkRequest {
+	/// Directory path.
+	pub path: Option<String>,
+	/// Name of the link.
+	pub name: String,
+	/// Path the link points to; relative paths are relative to the directory.
+	pub target: String,
+	#[serde(default)]
+	pub kind: LinkKind,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum LinkKind {
+	#[default]
+	Symbolic,
+	Hard,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateLinkResponse {
+	/// Directory path.
+	pub path: String,
+	/// Created link.
+	pub file: FileInfo,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct ReadRequest {
 	/// Directory path.
 	pub path: Option<String>,
 	/// Optional subdirectory key.
 	pub key: Option<String>,
+	/// Position of the first byte to read.
+	pub offset: Option<u64>,
+	/// Maximum number of bytes to read; the file is read to its end if not provided.
+	pub length: Option<u64>,
+}
+
+/// Contents of a file, sent as the raw body of the response.
+#[derive(Debug)]
+pub struct ReadResponse {
+	/// Bytes read.
+	pub data: Vec<u8>,
+	/// Position of the first byte read.
+	pub offset: u64,
+	/// Total size of the file.
+	pub size: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WriteRequest {
+	/// Directory path.
+	pub path: Option<String>,
+	/// File key within the directory.
+	pub key: Option<String>,
+	/// New contents of the file.
+	pub content: String,
+	/// Encoding of the content.
+	#[serde(default)]
+	pub encoding: ContentEncoding,
+	/// Indicates whether the previous contents are kept in a backup file.
+	#[serde(default)]
+	pub backup: bool,
+	/// Optional state the file must still be in; the write fails otherwise.
+	pub expected: Option<WritePrecondition>,
+	/// Appends the content to the file, which is created if missing; large files are
+	/// written in chunks this way. The precondition and backup do not apply.
+	#[serde(default)]
+	pub append: bool,
+	/// Modification date set on the written file.
+	pub date: Option<i64>,
+	/// Writes the content as a part of the file, for backends that cannot append.
+	/// The precondition and backup do not apply.
+	pub part: Option<WritePart>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WritePart {
+	/// Upload the part belongs to, as returned for the first part; missing for the
+	/// first part, which starts an upload.
+	pub upload: Option<String>,
+	/// Number of the part, starting at 1; parts are joined in this order.
+	pub number: u32,
+	/// Indicates whether this is the last part, which completes the file.
+	#[serde(default)]
+	pub last: bool,
+	/// Discards the upload and its parts instead of writing the content.
+	#[serde(default)]
+	pub abort: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WritePrecondition {
+	/// Expected file date.
+	pub date: Option<i64>,
+	/// Expected file size.
+	pub size: Option<u64>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ContentEncoding {
+	/// UTF-8 text.
+	#[default]
+	Text,
+	/// Binary data encoded as base64.
+	Base64,
+}
+
+impl ContentEncoding {
+	/// Decodes the content into raw bytes.
+	pub fn decode(&self, content: String) -> Result<Vec<u8>, base64::DecodeError> {
+		match self {
+			ContentEncoding::Text => Ok(content.into_bytes()),
+			ContentEncoding::Base64 => STANDARD.decode(content),
+		}
+	}
+
+	/// Encodes raw bytes as base64.
+	pub fn encode(data: &[u8]) -> String {
+		STANDARD.encode(data)
+	}
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WriteResponse {
+	/// Directory path.
+	pub path: String,
+	/// Written file.
+	pub file: FileInfo,
+	/// Upload of a written part, which the following parts are sent with.
+	pub upload: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StatRequest {
+	/// Directory path.
+	pub path: Option<String>,
+	/// Optional entry key.
+	pub key: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StatResponse {
+	/// Directory path.
+	pub path: String,
+	/// Requested entry.
+	pub file: FileInfo,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TransferRequest {
+	/// Source directory path.
+	pub path: Option<String>,
+	/// Keys of the source entries.
+	pub keys: Vec<String>,
+	/// Destination directory path.
+	pub destination: String,
+	/// Optional destination names, one for each key; source names are kept otherwise.
+	pub names: Option<Vec<String>>,
+	/// Indicates whether existing destination files are replaced.
+	#[serde(default)]
+	pub overwrite: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeleteRequest {
+	/// Directory path.
+	pub path: Option<String>,
+	/// Keys of the entries to delete.
+	pub keys: Vec<String>,
+	/// Deletes the entries for good rather than moving them to the trash; entries of
+	/// the trash are always deleted for good.
+	#[serde(default)]
+	pub permanent: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChecksumRequest {
+	/// Directory path.
+	pub path: Option<String>,
+	/// Keys of the files.
+	pub keys: Vec<String>,
+	/// Algorithms to compute checksums with; each file is read once for all of them.
+	pub algorithms: Vec<ChecksumAlgorithm>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateChecksumFileRequest {
+	/// Directory path.
+	pub path: Option<String>,
+	/// Keys of the files.
+	pub keys: Vec<String>,
+	#[serde(default)]
+	pub algorithm: ChecksumAlgorithm,
+	/// Name of the checksum file; for instance `SHA256SUMS`, or `name.sha256` for a
+	/// single file, if not provided.
+	pub name: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VerifyChecksumFileRequest {
+	/// Directory path.
+	pub path: Option<String>,
+	/// Key of the checksum file.
+	pub key: String,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ChecksumAlgorithm {
+	#[default]
+	Sha256,
+	Sha1,
+	Md5,
+	Crc32,
+	Blake3,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Checksum {
+	pub algorithm: ChecksumAlgorithm,
+	/// Checksum as lowercase hexadecimal digits.
+	pub value: String,
+	/// Checksum listed in a verified checksum file.
+	pub expected: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PackRequest {
+	/// Directory path; entries are stored with keys relative to it.
+	pub path: Option<String>,
+	/// Keys of the entries; directories are packed with their contents.
+	pub keys: Vec<String>,
+	/// Name of the created archive within the directory.
+	pub name: String,
+	/// Format of the archive; recognized by the extension of the name if not provided.
+	pub format: Option<ArchiveFormat>,
+	/// Compression level, 0 to 9, or 1 to 22 for Zstandard; the default of the
+	/// format if not provided.
+	pub level: Option<u32>,
+	/// Indicates whether permissions are stored; entries are stored as readable by
+	/// everyone otherwise.
+	#[serde(default)]
+	pub preserve_permissions: bool,
+	/// Indicates whether modification dates are stored; the time of packing is
+	/// stored otherwise.
+	#[serde(default)]
+	pub preserve_dates: bool,
+	/// Glob patterns of entries left out; patterns match names or keys of entries.
+	#[serde(default)]
+	pub exclude: Vec<String>,
+	/// Indicates whether an existing archive is replaced.
+	#[serde(default)]
+	pub overwrite: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ArchiveFormat {
+	Zip,
+	Tar,
+	TarGz,
+	TarXz,
+	TarZst,
+}
+
+impl ArchiveFormat {
+	/// Recognizes the format by the extension of a file name.
+	pub fn from_name(name: &str) -> Option<Self> {
+		let name = name.to_ascii_lowercase();
+		[
+			(".zip", ArchiveFormat::Zip),
+			(".tar", ArchiveFormat::Tar),
+			(".tar.gz", ArchiveFormat::TarGz),
+			(".tgz", ArchiveFormat::TarGz),
+			(".tar.xz", ArchiveFormat::TarXz),
+			(".txz", ArchiveFormat::TarXz),
+			(".tar.zst", ArchiveFormat::TarZst),
+			(".tzst", ArchiveFormat::TarZst),
+		]
+		.into_iter()
+		.find(|(extension, _)| name.len() > extension.len() && name.ends_with(extension))
+		.map(|(_, format)| format)
+	}
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UnpackRequest {
+	/// Path of the archive, or of a directory within it; a file compressed on its
+	/// own, such as `name.gz`, is extracted as a single file.
+	pub path: Option<String>,
+	/// Keys of the entries in the directory; directories are extracted with their
+	/// contents. All entries are extracted if empty.
+	#[serde(default)]
+	pub keys: Vec<String>,
+	/// Destination directory path; it is created if missing. Entries are extracted
+	/// with paths relative to the directory within the archive.
+	pub destination: String,
+	/// Handling of entries that exist in the destination.
+	#[serde(default)]
+	pub conflict: ConflictPolicy,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ConflictPolicy {
+	/// Replaces existing files; existing directories are merged.
+	Overwrite,
+	/// Keeps existing files; skipped entries are results with an error.
+	#[default]
+	Skip,
+	/// Extracts the entry with a free name such as `name (2).txt`; renamed entries
+	/// are results.
+	Rename,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CompareDirectoriesRequest {
+	pub left: Location,
+	pub right: Location,
+	/// Compares subdirectories found on both sides.
+	#[serde(default)]
+	pub recursive: bool,
+	#[serde(default)]
+	pub method: CompareMethod,
+	/// Largest difference of modification dates in milliseconds taken as the same date;
+	/// 2 seconds if not provided, as some file systems store dates with that precision.
+	pub date_tolerance: Option<i64>,
+}
+
+/// Directory on a backend.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Location {
+	/// Backend of the directory; the backend receiving the request if not provided.
+	pub backend: Option<Endpoint>,
+	/// Directory path.
+	pub path: Option<String>,
+}
+
+/// Another backend running on this computer.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Endpoint {
+	pub port: u16,
+	/// Token the backend was started with.
+	pub token: Option<String>,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum CompareMethod {
+	/// Files with the same size and date are identical.
+	#[default]
+	Date,
+	/// Files with the same size are read from both sides and compared.
+	Content,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchRenameRequest {
+	/// Directory path.
+	pub path: Option<String>,
+	/// Keys of the entries, in the order counters are assigned.
+	pub keys: Vec<String>,
+	/// Template of the new name without extension; `[N]` if not provided. `[N]` is
+	/// the current name and `[N2-5]` a range of its characters, `[E]` the extension,
+	/// `[P]` the name of the directory and `[C]` the counter. `[Y]`, `[M]`, `[D]`,
+	/// `[h]`, `[m]` and `[s]` are parts of the modification date in local time,
+	/// `[YMD]` and `[hms]` the whole date and time; `[[` and `]]` are brackets.
+	pub name: Option<String>,
+	/// Template of the extension with the same tokens; `[E]` if not provided.
+	pub extension: Option<String>,
+	/// Text replaced in the new name, extension included.
+	pub search: Option<String>,
+	#[serde(default)]
+	pub replace: String,
+	/// Indicates whether the search is a regular expression; the replacement may
+	/// refer to groups as `$1`.
+	#[serde(default)]
+	pub regex: bool,
+	/// Case conversion of the new name, applied last.
+	pub case: Option<CaseConversion>,
+	/// First value of the counter; 1 if not provided.
+	pub counter_start: Option<i64>,
+	/// Increment of the counter; 1 if not provided.
+	pub counter_step: Option<i64>,
+	/// Minimum number of digits of the counter, padded with zeros.
+	pub counter_digits: Option<usize>,
+	/// Only gets the new names; nothing is renamed.
+	#[serde(default)]
+	pub preview: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum CaseConversion {
+	Lower,
+	Upper,
+	/// First letter uppercase, the others lowercase.
+	Capitalize,
+	/// First letter of each word uppercase, the others lowercase.
+	Title,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchRenameResponse {
+	pub entries: Vec<RenamedEntry>,
+}
+
+/// New name of an entry of a batch rename.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RenamedEntry {
+	/// Current key.
+	pub key: String,
+	/// New name; the same as the current one if the entry is not renamed.
+	pub name: String,
+	/// Error preventing the rename, for instance a collision with another name.
+	pub error: Option<ErrorResponse>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UndoRequest {
+	/// Number of operations to revert, the most recent first; 1 if not provided.
+	pub count: Option<usize>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RestoreRequest {
+	/// Keys of the entries in the trash.
+	pub keys: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SyncRequest {
+	pub left: Location,
+	pub right: Location,
+	pub direction: SyncDirection,
+	/// Only plans the actions; the results show what would be done.
+	#[serde(default)]
+	pub dry_run: bool,
+	/// Deletes entries missing in the source of a mirror; bidirectional synchronizations
+	/// cannot tell deleted entries from created ones, so these never delete.
+	#[serde(default)]
+	pub delete: bool,
+	#[serde(default)]
+	pub method: CompareMethod,
+	/// Largest difference of modification dates in milliseconds taken as the same date.
+	pub date_tolerance: Option<i64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum SyncDirection {
+	/// Makes the right directory a copy of the left one.
+	LeftToRight,
+	/// Makes the left directory a copy of the right one.
+	RightToLeft,
+	/// Copies new and newer entries in both directions.
+	Bidirectional,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum SyncAction {
+	CopyToRight,
+	CopyToLeft,
+	DeleteLeft,
+	DeleteRight,
+	/// The entry is left as it is, for instance when deletions are not propagated.
+	Skip,
+	/// The entry was changed on both sides, or is a file on one side and a directory
+	/// on the other; it is left for the user to resolve.
+	Conflict,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DirectorySizeRequest {
+	/// Directory path.
+	pub path: Option<String>,
+	/// Keys of the entries to measure.
+	pub keys: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WatchRequest {
+	/// Directory path.
+	pub path: Option<String>,
+	/// Optional subdirectory key.
+	pub key: Option<String>,
+}
+
+/// Change of an entry in a watched directory.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WatchEvent {
+	pub kind: WatchEventKind,
+	/// Directory path.
+	pub path: String,
+	/// Key of the changed entry.
+	pub key: String,
+	/// Previous key of a renamed entry.
+	pub old_key: Option<String>,
+	/// Current state of the entry; not provided for removed entries.
+	pub file: Option<FileInfo>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum WatchEventKind {
+	Created,
+	Modified,
+	Removed,
+	Renamed,
 }
 
 #[derive(Debug, Serialize)]
 #[serde(rename_all = "camelCase")]
+pub struct JobResponse {
+	/// Job identifier.
+	pub id: u64,
+	/// Operation performed by the job.
+	pub kind: JobKind,
+	/// Current state.
+	pub state: JobState,
+	/// Progress of the operation.
+	pub progress: JobProgress,
+	/// Error of a failed job.
+	pub error: Option<ErrorResponse>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JobResultsRequest {
+	/// Job identifier.
+	pub id: u64,
+	/// Position to continue from, as returned in the previous page.
+	pub cursor: Option<String>,
+	/// Maximum number of results in the page; all available results are returned if not provided.
+	pub limit: Option<usize>,
+}
+
+impl JobResultsRequest {
+	/// Gets the offset and size of the requested page.
+	pub fn page(&self) -> Result<(usize, usize), ErrorResponse> {
+		get_page(&self.cursor, self.limit)
+	}
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JobResultsResponse {
+	/// State of the job; more results may follow while it is running.
+	pub job: JobResponse,
+	/// Results found so far, starting at the cursor of the request.
+	pub results: Vec<JobResult>,
+	/// Cursor of the following results.
+	pub cursor: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JobResult {
+	/// Entry produced by the job. Keys of search results are paths relative to
+	/// the search root, so results can be shown as a listing of the root.
+	pub file: FileInfo,
+	/// Lines matching the content pattern of a search.
+	pub matches: Vec<TextMatch>,
+	/// Checksums computed for the entry.
+	pub checksums: Vec<Checksum>,
+	/// Error of the entry; the job continues with other entries.
+	pub error: Option<ErrorResponse>,
+	/// Differences of an entry of compared directories.
+	pub comparison: Option<EntryComparison>,
+	/// Action of a synchronization taken on the entry, or planned in a dry run.
+	pub action: Option<SyncAction>,
+}
+
+/// Entry of compared directories; entries of both sides have keys relative to the
+/// compared directories.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EntryComparison {
+	pub status: ComparisonStatus,
+	/// Entry in the left directory.
+	pub left: Option<FileInfo>,
+	/// Entry in the right directory.
+	pub right: Option<FileInfo>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ComparisonStatus {
+	LeftOnly,
+	RightOnly,
+	Identical,
+	LeftNewer,
+	RightNewer,
+	/// Files have the same date, the left one is larger.
+	LeftLarger,
+	RightLarger,
+	/// Files have the same date and size but different contents, or one entry is
+	/// a directory and the other a file.
+	Different,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TextMatch {
+	/// Line number, starting at 1.
+	pub line_number: u64,
+	/// Matching line; long lines are shortened.
+	pub line: String,
+	/// Lines preceding the matching line.
+	pub before: Vec<String>,
+	/// Lines following the matching line.
+	pub after: Vec<String>,
+}
+
+/// Largest number of characters of lines of text matches.
+const MAX_LINE_LENGTH: usize = 500;
+
+impl TextMatch {
+	/// Shortens a line of a match to the largest length, marking the cut.
+	pub fn shorten(line: String) -> String {
+		match line.char_indices().nth(MAX_LINE_LENGTH) {
+			Some((index, _)) => format!("{}…", &line[..index]),
+			None => line,
+		}
+	}
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SearchRequest {
+	/// Path of the directory to search in.
+	pub path: Option<String>,
+	/// Pattern the entry names must match; all entries match if not provided.
+	pub pattern: Option<String>,
+	#[serde(default)]
+	pub syntax: PatternSyntax,
+	/// Indicates whether the pattern distinguishes letter case.
+	#[serde(default)]
+	pub case_sensitive: bool,
+	/// Type of entries to find; all types are found if not provided.
+	pub entry_type: Option<EntryType>,
+	/// Minimum file size.
+	pub min_size: Option<u64>,
+	/// Maximum file size.
+	pub max_size: Option<u64>,
+	/// Minimum file date, see [`get_timestamp`].
+	pub modified_after: Option<i64>,
+	/// Maximum file date, see [`get_timestamp`].
+	pub modified_before: Option<i64>,
+	/// Minimum depth of found entries; entries of the root have depth 1.
+	pub min_depth: Option<usize>,
+	/// Maximum depth of found entries.
+	pub max_depth: Option<usize>,
+	/// Indicates whether entries excluded by .gitignore files are skipped.
+	#[serde(default)]
+	pub respect_gitignore: bool,
+	/// Maximum number of results; the search stops when reached.
+	pub limit: Option<usize>,
+	/// Text the files must contain; only files are found when provided.
+	pub content: Option<ContentPattern>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ContentPattern {
+	/// Text to find in lines of the files.
+	pub pattern: String,
+	/// Indicates whether the pattern is a regular expression rather than literal text.
+	#[serde(default)]
+	pub is_regex: bool,
+	/// Indicates whether the pattern distinguishes letter case.
+	#[serde(default)]
+	pub case_sensitive: bool,
+	/// Number of lines returned before and after each matching line.
+	#[serde(default)]
+	pub context: usize,
+	/// Indicates whether binary files are searched too.
+	#[serde(default)]
+	pub include_binary: bool,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum PatternSyntax {
+	/// Wildcards `*`, `?`, `[...]` and `{a,b}`.
+	#[default]
+	Glob,
+	Regex,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum EntryType {
+	File,
+	Directory,
+	Symlink,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum JobKind {
+	Copy,
+	Rename,
+	Delete,
+	Search,
+	DirectorySize,
+	Checksum,
+	CreateChecksumFile,
+	VerifyChecksumFile,
+	CompareDirectories,
+	Sync,
+	Pack,
+	Unpack,
+	BatchRename,
+	Undo,
+	Restore,
+	EmptyTrash,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum JobState {
+	Running,
+	Paused,
+	Cancelled,
+	Completed,
+	Failed,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JobProgress {
+	/// Total number of bytes to process, if known.
+	pub bytes_total: u64,
+	/// Number of bytes processed.
+	pub bytes_done: u64,
+	/// Total number of files to process, if known.
+	pub files_total: u64,
+	/// Number of files processed.
+	pub files_done: u64,
+	/// Item being processed.
+	pub current: Option<String>,
+	/// Number of results that were not kept, as the job produced too many.
+	pub results_dropped: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
 pub struct FileInfo {
 	/// Even if two files have same name, the key must be unique within a directory.
 	/// Path consists of keys concatenated with "/".
@@ -197,14 +1177,99 @@ pub struct FileInfo {
 	/// File size.
 	pub size: u64,
 
-	/// File date.
-	pub date: u128,
+	/// File date, see [`get_timestamp`].
+	pub date: i64,
 
 	/// Attributes.
 	pub attributes: String,
 
 	/// Indicates whether the file is active.
 	pub is_active: bool,
+
+	/// Indicates whether the file is hidden by default.
+	pub is_hidden: bool,
+
+	/// Indicates whether it is an archive, which can be listed like a directory.
+	pub is_archive: bool,
+
+	/// Indicates whether it is a symbolic link; other fields describe the link itself,
+	/// except for `is_directory`, which describes the target.
+	pub is_symlink: bool,
+
+	/// Indicates whether it is a symbolic link to a missing target.
+	pub is_broken: bool,
+
+	/// Name of the owning user.
+	pub owner: Option<String>,
+
+	/// Name of the owning group.
+	pub group: Option<String>,
+
+	/// Id of the owning user.
+	pub uid: Option<u32>,
+
+	/// Id of the owning group.
+	pub gid: Option<u32>,
+
+	/// Creation date, see [`get_timestamp`].
+	pub created: Option<i64>,
+
+	/// Last access date, see [`get_timestamp`].
+	pub accessed: Option<i64>,
+
+	/// Inode number.
+	pub inode: Option<u64>,
+
+	/// Id of the device containing the file.
+	pub device: Option<u64>,
+
+	/// Number of hard links.
+	pub links: Option<u64>,
+
+	/// Target path of a symbolic link.
+	pub link_target: Option<String>,
+
+	/// MIME type guessed from the extension.
+	pub mime_type: Option<String>,
+
+	/// Size allocated on the storage; for directories, the total of all contained files.
+	pub allocated_size: Option<u64>,
+
+	/// Number of files in a directory tree, when computed.
+	pub file_count: Option<u64>,
+
+	/// Number of subdirectories in a directory tree, when computed.
+	pub directory_count: Option<u64>,
+
+	/// Path an entry of the trash was deleted from.
+	pub original_path: Option<String>,
+
+	/// Date an entry of the trash was deleted, see [`get_timestamp`].
+	pub deletion_date: Option<i64>,
+
+	/// Type of the file system of an entry of the computer root, such as `ext4`.
+	pub file_system: Option<String>,
+
+	/// Size of the file system of an entry of the computer root.
+	pub total_space: Option<u64>,
+
+	/// Space available to the user on the file system of an entry of the computer root.
+	pub free_space: Option<u64>,
+
+	/// Space used on the file system of an entry of the computer root.
+	pub used_space: Option<u64>,
+}
+
+/// Largest integer exactly representable in JavaScript numbers.
+const MAX_SAFE_INTEGER: i64 = (1 << 53) - 1;
+
+/// Converts the time to milliseconds since the Unix epoch; times out of the
+/// range exactly representable in JavaScript are clamped.
+pub fn get_timestamp(time: SystemTime) -> i64 {
+	match time.duration_since(UNIX_EPOCH) {
+		Ok(duration) => duration.as_millis().min(MAX_SAFE_INTEGER as u128) as i64,
+		Err(err) => -(err.duration().as_millis().min(MAX_SAFE_INTEGER as u128) as i64),
+	}
 }
 
 pub fn init_telemetry(app_name: &str) {
@@ -233,6 +1298,13 @@ impl Middleware {
 				header::AUTHORIZATION,
 				header::ACCEPT,
 				header::CONTENT_TYPE,
+				header::RANGE,
+			])
+			.expose_headers(vec![
+				header::ACCEPT_RANGES,
+				header::CONTENT_RANGE,
+				HeaderName::from_static(FILE_SIZE_HEADER),
+				HeaderName::from_static(END_OF_FILE_HEADER),
 			])
 			.max_age(3600)
 	}
@@ -287,14 +1359,7 @@ where
 
 	fn call(&self, req: ServiceRequest) -> Self::Future {
 		if let Some(token) = &self.token {
-			if let Some(auth_header) = req.headers().get("Authorization") {
-				let auth_value = auth_header.to_str().unwrap_or_default();
-				if auth_value.starts_with("Bearer ") && &auth_value["Bearer ".len()..] != token {
-					return Box::pin(async move {
-						Err(actix_web::error::ErrorUnauthorized("Unauthorized"))
-					});
-				}
-			} else {
+			if !is_authorized(&req, token) {
 				return Box::pin(async move {
 					Err(actix_web::error::ErrorUnauthorized("Unauthorized"))
 				});
@@ -308,6 +1373,23 @@ where
 	}
 }
 
+#[derive(Deserialize)]
+struct TokenQuery {
+	token: Option<String>,
+}
+
+/// Checks the token of the `Authorization` header. Event sources of browsers cannot
+/// send headers, so the event stream takes the token as a query parameter as well.
+fn is_authorized(req: &ServiceRequest, token: &str) -> bool {
+	if let Some(auth_header) = req.headers().get("Authorization") {
+		let auth_value = auth_header.to_str().unwrap_or_default();
+		return !(auth_value.starts_with("Bearer ") && &auth_value["Bearer ".len()..] != token);
+	}
+	req.path() == WATCH_PATH
+		&& actix_web::web::Query::<TokenQuery>::from_query(req.query_string())
+			.is_ok_and(|query| query.token.as_deref() == Some(token))
+}
+
 pub fn load_rustls_config() -> Result<ServerConfig, Box<dyn Error>> {
 	let config = ServerConfig::builder()
 		.with_safe_defaults()
@@ -343,3 +1425,47 @@ pub fn load_rustls_config() -> Result<ServerConfig, Box<dyn Error>> {
 
 	Ok(result)
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use actix_web::{test, web, App, HttpResponse};
+
+	async fn get_status(uri: &str, authorization: Option<&str>) -> u16 {
+		let app = test::init_service(
+			App::new()
+				.wrap(TokenAuth::new(Some("secret".to_string())))
+				.route(WATCH_PATH, web::get().to(HttpResponse::Ok))
+				.route("/icons/file", web::get().to(HttpResponse::Ok)),
+		)
+		.await;
+		let mut request = test::TestRequest::get().uri(uri);
+		if let Some(authorization) = authorization {
+			request = request.insert_header((header::AUTHORIZATION, authorization));
+		}
+		match test::try_call_service(&app, request.to_request()).await {
+			Ok(response) => response.status().as_u16(),
+			Err(err) => err.as_response_error().status_code().as_u16(),
+		}
+	}
+
+	#[actix_web::test]
+	async fn token_auth() {
+		assert_eq!(get_status("/icons/file", Some("Bearer secret")).await, 200);
+		assert_eq!(get_status("/icons/file", Some("Bearer wrong")).await, 401);
+		assert_eq!(get_status("/icons/file", None).await, 401);
+		assert_eq!(
+			get_status("/watch?path=/tmp", Some("Bearer secret")).await,
+			200
+		);
+	}
+
+	#[actix_web::test]
+	async fn token_auth_of_watch_query() {
+		assert_eq!(get_status("/watch?path=/tmp&token=secret", None).await, 200);
+		assert_eq!(get_status("/watch?token=wrong", None).await, 401);
+		assert_eq!(get_status("/watch?path=/tmp", None).await, 401);
+		// other requests take the token only from the header
+		assert_eq!(get_status("/icons/file?token=secret", None).await, 401);
+	}
+}
@@ -0,0 +1,167 @@
+use crate::{ErrorCode, ErrorResponse, ListRequest};
+use std::any::Any;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+/// Time a listing is kept after its last page was requested.
+const LISTING_TTL: Duration = Duration::from_secs(5 * 60);
+
+/// Entries of directories listed in pages by the token of their cursor, so later pages
+/// are taken from the same entries in the same order, also when the directory changes.
+static LISTINGS: Mutex<Option<HashMap<u64, Listing>>> = Mutex::new(None);
+
+static LAST_TOKEN: AtomicU64 = AtomicU64::new(0);
+
+struct Listing {
+	path: PathBuf,
+	entries: Arc<dyn Any + Send + Sync>,
+	used: Instant,
+}
+
+/// Page of the entries of a directory.
+pub struct ListingPage<T> {
+	pub entries: Vec<T>,
+	/// Cursor of the next page; not provided for the last page.
+	pub cursor: Option<String>,
+}
+
+/// Gets the page requested of the entries of the directory at the path. The entries
+/// are loaded for the first page and kept for the following ones while there are any.
+pub fn get_listing_page<T>(
+	path: &Path,
+	request: &ListRequest,
+	load: impl FnOnce() -> Result<Vec<T>, ErrorResponse>,
+) -> Result<ListingPage<T>, ErrorResponse>
+where
+	T: Clone + Send + Sync + 'static,
+{
+	let limit = request
+		.limit
+		.filter(|limit| *limit > 0)
+		.unwrap_or(usize::MAX);
+	let Some(cursor) = &request.cursor else {
+		let mut entries = load()?;
+		if entries.len() <= limit {
+			return Ok(ListingPage {
+				entries,
+				cursor: None,
+			});
+		}
+		let rest = Arc::new(entries.split_off(limit));
+		let token = LAST_TOKEN.fetch_add(1, Ordering::Relaxed) + 1;
+		let mut listings = lock();
+		let listings = listings.get_or_insert_with(HashMap::new);
+		listings.retain(|_, listing| listing.used.elapsed() < LISTING_TTL);
+		listings.insert(
+			token,
+			Listing {
+				path: path.to_path_buf(),
+				entries: rest,
+				used: Instant::now(),
+			},
+		);
+		return Ok(ListingPage {
+			entries,
+			cursor: Some(format!("{}:0", token)),
+		});
+	};
+
+	let invalid = || ErrorResponse::new(ErrorCode::InvalidInput, "invalid cursor");
+	let (token, offset) = cursor.split_once(':').ok_or_else(invalid)?;
+	let token = token.parse::<u64>().map_err(|_| invalid())?;
+	let offset = offset.parse::<usize>().map_err(|_| invalid())?;
+	let mut listings = lock();
+	let listings = listings.get_or_insert_with(HashMap::new);
+	let listing = listings
+		.get_mut(&token)
+		.filter(|listing| listing.path == path)
+		.ok_or_else(|| {
+			ErrorResponse::new(
+				ErrorCode::NotFound,
+				"listing has expired, the directory has to be listed again",
+			)
+		})?;
+	let entries = listing
+		.entries
+		.clone()
+		.downcast::<Vec<T>>()
+		.map_err(|_| invalid())?;
+	let end = offset.saturating_add(limit).min(entries.len());
+	let page = entries.get(offset..end).ok_or_else(invalid)?.to_vec();
+	let cursor = if end < entries.len() {
+		listing.used = Instant::now();
+		Some(format!("{}:{}", token, end))
+	} else {
+		listings.remove(&token);
+		None
+	};
+	Ok(ListingPage {
+		entries: page,
+		cursor,
+	})
+}
+
+fn lock() -> MutexGuard<'static, Option<HashMap<u64, Listing>>> {
+	LISTINGS.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn get(
+		path: &str,
+		cursor: Option<&str>,
+		entries: &[u32],
+	) -> Result<ListingPage<u32>, ErrorResponse> {
+		let request = ListRequest {
+			path: Some(path.to_string()),
+			key: None,
+			cursor: cursor.map(str::to_string),
+			limit: Some(2),
+		};
+		get_listing_page(Path::new(path), &request, || Ok(entries.to_vec()))
+	}
+
+	#[test]
+	fn page_listing() {
+		let first = get("/a", None, &[1, 2, 3, 4, 5]).unwrap();
+		assert_eq!(first.entries, [1, 2]);
+		let cursor = first.cursor.unwrap();
+		// later pages are taken from the entries of the first one
+		let second = get("/a", Some(&cursor), &[9]).unwrap();
+		assert_eq!(second.entries, [3, 4]);
+		let cursor_3 = second.cursor.unwrap();
+		let third = get("/a", Some(&cursor_3), &[]).unwrap();
+		assert_eq!(third.entries, [5]);
+		assert!(third.cursor.is_none());
+		// the listing is released after its last page
+		let err = get("/a", Some(&cursor), &[]).err().unwrap();
+		assert_eq!(err.code, ErrorCode::NotFound);
+	}
+
+	#[test]
+	fn page_listing_in_one_page() {
+		let page = get("/b", None, &[1, 2]).unwrap();
+		assert_eq!(page.entries, [1, 2]);
+		assert!(page.cursor.is_none());
+	}
+
+	#[test]
+	fn reject_cursors() {
+		let cursor = get("/c", None, &[1, 2, 3]).unwrap().cursor.unwrap();
+		let err = get("/other", Some(&cursor), &[]).err().unwrap();
+		assert_eq!(err.code, ErrorCode::NotFound);
+		for cursor in ["", "1", "x:0", "1:x"] {
+			let err = get("/c", Some(cursor), &[]).err().unwrap();
+			assert_eq!(err.code, ErrorCode::InvalidInput, "{}", cursor);
+		}
+		let (token, _) = cursor.split_once(':').unwrap();
+		let err = get("/c", Some(&format!("{}:9", token)), &[]).err().unwrap();
+		assert_eq!(err.code, ErrorCode::InvalidInput);
+		assert_eq!(get("/c", Some(&cursor), &[]).unwrap().entries, [3]);
+	}
+}
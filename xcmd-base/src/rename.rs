@@ -0,0 +1,701 @@
+use crate::side::{get_name, Side};
+use crate::{
+	Backend, BatchRenameRequest, BatchRenameResponse, CaseConversion, ErrorCode, ErrorResponse,
+	FileInfo, Job, JobKind, JobResult, Jobs, Location, RenamedEntry, Response,
+};
+use actix_web::web;
+use chrono::{Local, TimeZone};
+use regex::Regex;
+use std::collections::{HashMap, HashSet};
+use std::process;
+
+/// Computes new names of entries and renames them with the rename request of the
+/// backend. A preview gets the new names; otherwise the entries are renamed by a job,
+/// which fails before renaming anything if a new name is invalid or taken. Renamed
+/// entries are the results of the job.
+pub(crate) async fn batch_rename<B: Backend>(
+	backend: web::Data<B>,
+	request: BatchRenameRequest,
+	jobs: web::Data<Jobs>,
+) -> Result<Response, ErrorResponse> {
+	let renamer = Renamer::new(&request)?;
+	let case_sensitive = backend.capabilities().path_syntax.case_sensitive;
+	let side = Side::new(
+		backend,
+		jobs.clone(),
+		Location {
+			backend: None,
+			path: request.path.clone(),
+		},
+		&reqwest::Client::new(),
+	);
+	let files = side.list("").await?;
+	let parent = request
+		.path
+		.as_deref()
+		.unwrap_or_default()
+		.trim_end_matches(['/', '\\'])
+		.rsplit(['/', '\\'])
+		.next()
+		.unwrap_or_default()
+		.to_string();
+
+	// new names are planned for all entries first, so collisions are found before
+	// anything is renamed
+	let mut plan = Vec::new();
+	let mut entries = Vec::new();
+	for (index, key) in request.keys.iter().enumerate() {
+		let name = key.trim_end_matches('/');
+		let Some(file) = files.iter().find(|file| get_name(file) == name) else {
+			entries.push(RenamedEntry {
+				key: key.clone(),
+				name: name.to_string(),
+				error: Some(ErrorResponse::new(
+					ErrorCode::NotFound,
+					"entry does not exist",
+				)),
+			});
+			continue;
+		};
+		let new_name = renamer.get_name(file, index, &parent);
+		entries.push(RenamedEntry {
+			key: key.clone(),
+			error: check_name(&new_name).err(),
+			name: new_name.clone(),
+		});
+		plan.push((file.clone(), new_name));
+	}
+	find_collisions(&mut entries, &files, case_sensitive);
+
+	if request.preview {
+		return Ok(Response::BatchRename(BatchRenameResponse { entries }));
+	}
+	if let Some(error) = entries.into_iter().find_map(|entry| entry.error) {
+		return Err(error);
+	}
+	plan.retain(|(file, new_name)| get_name(file) != *new_name);
+	let job = jobs.start_local(JobKind::BatchRename, move |job| async move {
+		apply(&side, plan, case_sensitive, &job).await
+	});
+	Ok(Response::Job(job.status()))
+}
+
+/// Flags new names used twice or taken by entries that are not renamed.
+fn find_collisions(entries: &mut [RenamedEntry], files: &[FileInfo], case_sensitive: bool) {
+	let renamed = entries
+		.iter()
+		.filter(|entry| entry.error.is_none())
+		.map(|entry| entry.key.trim_end_matches('/'))
+		.collect::<HashSet<_>>();
+	let taken = files
+		.iter()
+		.map(get_name)
+		.filter(|name| !renamed.contains(name.as_str()))
+		.map(|name| fold(&name, case_sensitive))
+		.collect::<HashSet<_>>();
+	let mut counts = HashMap::new();
+	for entry in entries.iter().filter(|entry| entry.error.is_none()) {
+		*counts.entry(fold(&entry.name, case_sensitive)).or_insert(0) += 1;
+	}
+	for entry in entries.iter_mut().filter(|entry| entry.error.is_none()) {
+		let name = fold(&entry.name, case_sensitive);
+		if taken.contains(&name) {
+			entry.error = Some(ErrorResponse::new(
+				ErrorCode::AlreadyExists,
+				"an entry with the name already exists",
+			));
+		} else if counts[&name] > 1 {
+			entry.error = Some(ErrorResponse::new(
+				ErrorCode::AlreadyExists,
+				"the name is given to several entries",
+			));
+		}
+	}
+}
+
+/// Gets the form of a name compared for collisions.
+fn fold(name: &str, case_sensitive: bool) -> String {
+	if case_sensitive {
+		name.to_string()
+	} else {
+		name.to_lowercase()
+	}
+}
+
+fn check_name(name: &str) -> Result<(), ErrorResponse> {
+	let message = if name.is_empty() {
+		"name is empty"
+	} else if name == "." || name == ".." {
+		"name is reserved"
+	} else if name.contains(['/', '\\', '\0']) {
+		"name contains a separator"
+	} else {
+		return Ok(());
+	};
+	Err(ErrorResponse::new(ErrorCode::InvalidInput, message))
+}
+
+/// Renames the entries; entries whose new name is the current name of another entry
+/// are moved to a temporary name first, so names can be swapped. Renames are undone
+/// in reverse order when one fails or the job is cancelled.
+async fn apply<B: Backend>(
+	side: &Side<B>,
+	plan: Vec<(FileInfo, String)>,
+	case_sensitive: bool,
+	job: &Job,
+) -> Result<(), ErrorResponse> {
+	job.update(|progress| progress.files_total = plan.len() as u64);
+	let sources = plan
+		.iter()
+		.map(|(file, _)| fold(&get_name(file), case_sensitive))
+		.collect::<HashSet<_>>();
+
+	// temporary names are given first and replaced last, when all sources are free
+	let mut to_temp = Vec::new();
+	let mut direct = Vec::new();
+	let mut from_temp = Vec::new();
+	for (index, (file, new_name)) in plan.iter().enumerate() {
+		let name = get_name(file);
+		if sources.contains(&fold(new_name, case_sensitive)) {
+			let temp_name = format!(".{}.{}.xcmd-rename", process::id(), index);
+			to_temp.push((name, temp_name.clone(), false));
+			from_temp.push((temp_name, new_name.clone(), true));
+		} else {
+			direct.push((name, new_name.clone(), true));
+		}
+	}
+
+	let mut done = Vec::new();
+	for (name, new_name, is_last) in to_temp.into_iter().chain(direct).chain(from_temp) {
+		let renamed = match job.checkpoint_async().await {
+			Ok(()) => {
+				job.update(|progress| progress.current = Some(name.clone()));
+				side.rename(&name, &new_name, false, job).await
+			}
+			Err(cancelled) => Err(cancelled.into()),
+		};
+		if let Err(err) = renamed {
+			undo(side, &done, job).await;
+			return Err(err);
+		}
+		done.push((new_name, name));
+		if is_last {
+			job.update(|progress| progress.files_done += 1);
+		}
+	}
+
+	for (file, new_name) in plan {
+		job.add_result(JobResult {
+			file: rename_file(file, &new_name),
+			..Default::default()
+		});
+	}
+	Ok(())
+}
+
+/// Undoes renames in reverse order; renames that cannot be undone are results with
+/// an error.
+async fn undo<B: Backend>(side: &Side<B>, done: &[(String, String)], job: &Job) {
+	// the job may be cancelled, so the renames wait for a job that is not
+	let waiting = Job::new(0, JobKind::BatchRename);
+	for (name, previous_name) in done.iter().rev() {
+		if let Err(err) = side.rename(name, previous_name, false, &waiting).await {
+			job.add_result(JobResult {
+				file: FileInfo {
+					key: name.clone(),
+					name: name.clone(),
+					..Default::default()
+				},
+				error: Some(err),
+				..Default::default()
+			});
+		}
+	}
+}
+
+fn rename_file(mut file: FileInfo, new_name: &str) -> FileInfo {
+	let (name, extension) = split_name(new_name, file.is_directory);
+	file.key = if file.is_directory {
+		format!("{}/", new_name)
+	} else {
+		new_name.to_string()
+	};
+	file.name = name.to_string();
+	file.extension = extension.to_string();
+	file
+}
+
+/// Splits a name into the name without extension and the extension; directories and
+/// names starting with a dot only have no extension.
+fn split_name(name: &str, is_directory: bool) -> (&str, &str) {
+	match name.rsplit_once('.') {
+		Some((stem, extension)) if !is_directory && !stem.is_empty() => (stem, extension),
+		_ => (name, ""),
+	}
+}
+
+/// Part of a name template.
+#[derive(Debug, PartialEq)]
+enum Token {
+	Text(String),
+	/// Current name, or the characters in a range counted from 1.
+	Name(Option<(usize, Option<usize>)>),
+	Extension,
+	Parent,
+	Counter,
+	/// Part of the modification date, as a format of `chrono`.
+	Date(&'static str),
+}
+
+struct Renamer {
+	name: Vec<Token>,
+	extension: Vec<Token>,
+	search: Option<(Regex, String)>,
+	case: Option<CaseConversion>,
+	counter_start: i64,
+	counter_step: i64,
+	counter_digits: usize,
+}
+
+impl Renamer {
+	fn new(request: &BatchRenameRequest) -> Result<Self, ErrorResponse> {
+		let search = match request
+			.search
+			.as_deref()
+			.filter(|search| !search.is_empty())
+		{
+			Some(search) => {
+				let (pattern, replace) = if request.regex {
+					(search.to_string(), request.replace.clone())
+				} else {
+					(regex::escape(search), request.replace.replace('$', "$$"))
+				};
+				let regex = Regex::new(&pattern).map_err(|err| {
+					ErrorResponse::new(
+						ErrorCode::InvalidInput,
+						format!("invalid regular expression: {}", err),
+					)
+				})?;
+				Some((regex, replace))
+			}
+			None => None,
+		};
+		Ok(Renamer {
+			name: parse_template(request.name.as_deref().unwrap_or("[N]"))?,
+			extension: parse_template(request.extension.as_deref().unwrap_or("[E]"))?,
+			search,
+			case: request.case,
+			counter_start: request.counter_start.unwrap_or(1),
+			counter_step: request.counter_step.unwrap_or(1),
+			counter_digits: request.counter_digits.unwrap_or(1),
+		})
+	}
+
+	/// Gets the new name of the entry at the index of the renamed entries.
+	fn get_name(&self, file: &FileInfo, index: usize, parent: &str) -> String {
+		let current = get_name(file);
+		let (name, extension) = split_name(&current, file.is_directory);
+		let render = |template: &[Token]| {
+			let mut text = String::new();
+			for token in template {
+				match token {
+					Token::Text(value) => text.push_str(value),
+					Token::Name(None) => text.push_str(name),
+					Token::Name(Some((start, end))) => {
+						let count = end.map_or(usize::MAX, |end| end + 1 - start);
+						text.extend(name.chars().skip(start - 1).take(count));
+					}
+					Token::Extension => text.push_str(extension),
+					Token::Parent => text.push_str(parent),
+					Token::Counter => {
+						let value = self.counter_start + index as i64 * self.counter_step;
+						text.push_str(&format!("{:0width$}", value, width = self.counter_digits));
+					}
+					Token::Date(format) => {
+						if let Some(date) = Local.timestamp_millis_opt(file.date).single() {
+							text.push_str(&date.format(format).to_string());
+						}
+					}
+				}
+			}
+			text
+		};
+
+		let name = render(&self.name);
+		let extension = render(&self.extension);
+		let mut new_name = if extension.is_empty() {
+			name
+		} else {
+			format!("{}.{}", name, extension)
+		};
+		if let Some((regex, replace)) = &self.search {
+			new_name = regex.replace_all(&new_name, replace.as_str()).into_owned();
+		}
+		match self.case {
+			Some(CaseConversion::Lower) => new_name.to_lowercase(),
+			Some(CaseConversion::Upper) => new_name.to_uppercase(),
+			Some(CaseConversion::Capitalize) => capitalize(&new_name, false),
+			Some(CaseConversion::Title) => capitalize(&new_name, true),
+			None => new_name,
+		}
+	}
+}
+
+/// Makes the first letter uppercase, or the first letter of each word, and the others
+/// lowercase.
+fn capitalize(text: &str, words: bool) -> String {
+	let mut result = String::new();
+	let mut is_start = true;
+	for c in text.chars() {
+		if is_start && c.is_alphanumeric() {
+			result.extend(c.to_uppercase());
+			is_start = false;
+		} else {
+			result.extend(c.to_lowercase());
+			if words && !c.is_alphanumeric() && c != '\'' {
+				is_start = true;
+			}
+		}
+	}
+	result
+}
+
+fn parse_template(template: &str) -> Result<Vec<Token>, ErrorResponse> {
+	let mut tokens = Vec::new();
+	let mut text = String::new();
+	let mut rest = template;
+	while let Some(c) = rest.chars().next() {
+		if let Some(after) = rest.strip_prefix("[[").or_else(|| rest.strip_prefix("]]")) {
+			text.push(c);
+			rest = after;
+			continue;
+		}
+		if c != '[' {
+			text.push(c);
+			rest = &rest[c.len_utf8()..];
+			continue;
+		}
+		let (token, after) = rest[1..].split_once(']').ok_or_else(|| {
+			ErrorResponse::new(ErrorCode::InvalidInput, "template has an unclosed bracket")
+		})?;
+		if !text.is_empty() {
+			tokens.push(Token::Text(std::mem::take(&mut text)));
+		}
+		tokens.push(parse_token(token)?);
+		rest = after;
+	}
+	if !text.is_empty() {
+		tokens.push(Token::Text(text));
+	}
+	Ok(tokens)
+}
+
+fn parse_token(token: &str) -> Result<Token, ErrorResponse> {
+	let invalid = || {
+		ErrorResponse::new(
+			ErrorCode::InvalidInput,
+			format!("unknown template token [{}]", token),
+		)
+	};
+	Ok(match token {
+		"N" => Token::Name(None),
+		"E" => Token::Extension,
+		"P" => Token::Parent,
+		"C" => Token::Counter,
+		"Y" => Token::Date("%Y"),
+		"M" => Token::Date("%m"),
+		"D" => Token::Date("%d"),
+		"h" => Token::Date("%H"),
+		"m" => Token::Date("%M"),
+		"s" => Token::Date("%S"),
+		"YMD" => Token::Date("%Y-%m-%d"),
+		"hms" => Token::Date("%H.%M.%S"),
+		_ => {
+			// character ranges such as `N2-5`, `N2-` or `N2`
+			let range = token.strip_prefix('N').ok_or_else(invalid)?;
+			let parse = |number: &str| number.parse::<usize>().ok().filter(|number| *number > 0);
+			let (start, end) = match range.split_once('-') {
+				Some((start, "")) => (parse(start).ok_or_else(invalid)?, None),
+				Some((start, end)) => (
+					parse(start).ok_or_else(invalid)?,
+					Some(parse(end).ok_or_else(invalid)?),
+				),
+				None => {
+					let start = parse(range).ok_or_else(invalid)?;
+					(start, Some(start))
+				}
+			};
+			if end.is_some_and(|end| end < start) {
+				return Err(invalid());
+			}
+			Token::Name(Some((start, end)))
+		}
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{
+		Capabilities, JobProgress, JobResponse, JobState, PathSyntax, TransferRequest,
+		PROTOCOL_VERSION,
+	};
+	use async_trait::async_trait;
+	use parking_lot::Mutex;
+	use serde_json::json;
+
+	/// Directory whose entries are renamed at once; renaming to `fail` fails.
+	struct Directory {
+		names: Mutex<Vec<String>>,
+	}
+
+	#[async_trait(?Send)]
+	impl Backend for Directory {
+		fn capabilities(&self) -> Capabilities {
+			Capabilities {
+				protocol_version: PROTOCOL_VERSION,
+				name: "test".to_string(),
+				version: "0".to_string(),
+				requests: Vec::new(),
+				path_syntax: PathSyntax {
+					separator: "/".to_string(),
+					case_sensitive: false,
+				},
+				features: Vec::new(),
+			}
+		}
+
+		async fn rename(
+			&self,
+			request: TransferRequest,
+			_jobs: &Jobs,
+		) -> Result<JobResponse, ErrorResponse> {
+			let new_name = &request.names.unwrap_or_default()[0];
+			let mut names = self.names.lock();
+			if new_name == "fail" || names.contains(new_name) {
+				return Err(ErrorResponse::new(ErrorCode::AlreadyExists, "taken"));
+			}
+			let index = names.iter().position(|name| *name == request.keys[0]);
+			names[index.expect("renamed entry exists")] = new_name.clone();
+			Ok(JobResponse {
+				id: 0,
+				kind: JobKind::Rename,
+				state: JobState::Completed,
+				progress: JobProgress::default(),
+				error: None,
+			})
+		}
+	}
+
+	fn get_file(name: &str) -> FileInfo {
+		FileInfo {
+			key: name.to_string(),
+			name: name.to_string(),
+			..Default::default()
+		}
+	}
+
+	fn get_renamer(request: serde_json::Value) -> Renamer {
+		let mut request = request;
+		request["keys"] = json!([]);
+		Renamer::new(&serde_json::from_value(request).unwrap()).unwrap()
+	}
+
+	fn get_entry(name: &str, new_name: &str) -> RenamedEntry {
+		RenamedEntry {
+			key: name.to_string(),
+			name: new_name.to_string(),
+			error: None,
+		}
+	}
+
+	/// Applies the plan to a directory with the names; gets the names afterwards.
+	fn run_apply(
+		names: &[&str],
+		plan: &[(&str, &str)],
+	) -> (Result<(), ErrorResponse>, Vec<String>) {
+		let directory = web::Data::new(Directory {
+			names: Mutex::new(names.iter().map(|name| name.to_string()).collect()),
+		});
+		let location = Location {
+			backend: None,
+			path: Some("/directory".to_string()),
+		};
+		let side = Side::new(
+			directory.clone(),
+			web::Data::new(Jobs::default()),
+			location,
+			&reqwest::Client::new(),
+		);
+		let plan = plan
+			.iter()
+			.map(|(name, new_name)| (get_file(name), new_name.to_string()))
+			.collect();
+		let job = Job::new(1, JobKind::BatchRename);
+		let result = actix_web::rt::System::new().block_on(apply(&side, plan, false, &job));
+		let names = directory.names.lock().clone();
+		(result, names)
+	}
+
+	#[test]
+	fn parse_template_tokens() {
+		assert_eq!(
+			parse_template("[N]_[C].[E]").unwrap(),
+			[
+				Token::Name(None),
+				Token::Text("_".to_string()),
+				Token::Counter,
+				Token::Text(".".to_string()),
+				Token::Extension,
+			]
+		);
+		assert_eq!(
+			parse_template("[P] [YMD]").unwrap(),
+			[
+				Token::Parent,
+				Token::Text(" ".to_string()),
+				Token::Date("%Y-%m-%d"),
+			]
+		);
+	}
+
+	#[test]
+	fn parse_template_escapes() {
+		assert_eq!(
+			parse_template("[[[N]]]").unwrap(),
+			[
+				Token::Text("[".to_string()),
+				Token::Name(None),
+				Token::Text("]".to_string()),
+			]
+		);
+		assert_eq!(
+			parse_template("a]]b").unwrap(),
+			[Token::Text("a]b".to_string())]
+		);
+	}
+
+	#[test]
+	fn parse_template_ranges() {
+		assert_eq!(
+			parse_template("[N2-5]").unwrap(),
+			[Token::Name(Some((2, Some(5))))]
+		);
+		assert_eq!(
+			parse_template("[N3-]").unwrap(),
+			[Token::Name(Some((3, None)))]
+		);
+		assert_eq!(
+			parse_template("[N4]").unwrap(),
+			[Token::Name(Some((4, Some(4))))]
+		);
+	}
+
+	#[test]
+	fn parse_template_errors() {
+		for template in ["[N", "[X]", "[N0]", "[N5-2]", "[Nx-]", "[N2-y]"] {
+			let error = parse_template(template).unwrap_err();
+			assert_eq!(error.code, ErrorCode::InvalidInput, "{}", template);
+		}
+	}
+
+	#[test]
+	fn get_name_from_templates() {
+		let renamer = get_renamer(json!({ "name": "[P]-[N2-4]-[N5-]", "extension": "[E]x" }));
+		assert_eq!(
+			renamer.get_name(&get_file("abcdefg.txt"), 0, "dir"),
+			"dir-bcd-efg.txtx"
+		);
+		// ranges beyond the name are cut
+		assert_eq!(
+			renamer.get_name(&get_file("ab.txt"), 0, "dir"),
+			"dir-b-.txtx"
+		);
+	}
+
+	#[test]
+	fn get_name_counters() {
+		let renamer = get_renamer(json!({
+			"name": "img[C]",
+			"counterStart": 8,
+			"counterStep": 2,
+			"counterDigits": 3,
+		}));
+		assert_eq!(renamer.get_name(&get_file("a.jpg"), 0, ""), "img008.jpg");
+		assert_eq!(renamer.get_name(&get_file("b.jpg"), 2, ""), "img012.jpg");
+	}
+
+	#[test]
+	fn get_name_search_and_case() {
+		let renamer = get_renamer(json!({ "search": ".", "replace": "$", "case": "upper" }));
+		assert_eq!(renamer.get_name(&get_file("a.b.txt"), 0, ""), "A$B$TXT");
+		let renamer = get_renamer(json!({
+			"search": "(\\d+)-(\\d+)",
+			"replace": "$2-$1",
+			"regex": true,
+			"case": "title",
+		}));
+		assert_eq!(
+			renamer.get_name(&get_file("my file 1-2.TXT"), 0, ""),
+			"My File 2-1.Txt"
+		);
+	}
+
+	#[test]
+	fn get_name_keeps_names_of_directories_and_dot_files() {
+		let renamer = get_renamer(json!({ "name": "[N]_", "extension": "[E]" }));
+		let mut directory = get_file("photos.2020/");
+		directory.is_directory = true;
+		assert_eq!(renamer.get_name(&directory, 0, ""), "photos.2020_");
+		assert_eq!(renamer.get_name(&get_file(".profile"), 0, ""), ".profile_");
+	}
+
+	#[test]
+	fn find_collisions_with_entries_not_renamed() {
+		let files = ["a", "b", "README"].map(get_file);
+		let mut entries = [get_entry("a", "readme"), get_entry("b", "c")];
+		find_collisions(&mut entries, &files, false);
+		assert!(entries[0].error.is_some());
+		assert!(entries[1].error.is_none());
+
+		let mut entries = [get_entry("a", "readme"), get_entry("b", "c")];
+		find_collisions(&mut entries, &files, true);
+		assert!(entries.iter().all(|entry| entry.error.is_none()));
+	}
+
+	#[test]
+	fn find_collisions_between_new_names() {
+		let files = ["a", "b", "c"].map(get_file);
+		let mut entries = [
+			get_entry("a", "X"),
+			get_entry("b", "x"),
+			get_entry("c", "a"),
+		];
+		find_collisions(&mut entries, &files, false);
+		assert!(entries[0].error.is_some());
+		assert!(entries[1].error.is_some());
+		// the name of a renamed entry is free
+		assert!(entries[2].error.is_none());
+	}
+
+	#[test]
+	fn apply_swaps_names_through_temporary_names() {
+		let (result, names) = run_apply(&["a", "b", "c"], &[("a", "b"), ("b", "a"), ("c", "d")]);
+		assert!(result.is_ok());
+		assert_eq!(names, ["b", "a", "d"]);
+	}
+
+	#[test]
+	fn apply_compares_names_by_case() {
+		let (result, names) = run_apply(&["a", "B"], &[("a", "b"), ("B", "A")]);
+		assert!(result.is_ok());
+		assert_eq!(names, ["b", "A"]);
+	}
+
+	#[test]
+	fn apply_undoes_renames_on_failure() {
+		let (result, names) = run_apply(&["a", "b", "c"], &[("a", "b"), ("b", "a"), ("c", "fail")]);
+		assert_eq!(result.unwrap_err().code, ErrorCode::AlreadyExists);
+		assert_eq!(names, ["a", "b", "c"]);
+	}
+}
@@ -0,0 +1,390 @@
+use crate::{
+	Backend, ContentEncoding, CreateRequest, DeleteRequest, ErrorCode, ErrorResponse, Feature,
+	FileInfo, Job, JobCancelled, JobResponse, JobState, Jobs, ListRequest, ListResponse, Location,
+	ReadRequest, ReadResponse, TransferRequest, WritePart, WriteRequest, FILE_SIZE_HEADER,
+};
+use actix_web::web;
+use serde::{de::IgnoredAny, Deserialize};
+use serde_derive::Serialize;
+use std::time::Duration;
+
+/// Interval of checking whether a job started on a backend has finished.
+const JOB_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Directory on the backend receiving the request or on another backend. Keys are
+/// relative to the directory and use `/` as separator.
+pub(crate) struct Side<B: Backend> {
+	source: Source<B>,
+	path: Option<String>,
+}
+
+enum Source<B: Backend> {
+	Local(web::Data<B>, web::Data<Jobs>),
+	Remote(Remote),
+}
+
+impl<B: Backend> Side<B> {
+	pub fn new(
+		backend: web::Data<B>,
+		jobs: web::Data<Jobs>,
+		location: Location,
+		client: &reqwest::Client,
+	) -> Self {
+		let source = match location.backend {
+			Some(endpoint) => Source::Remote(Remote {
+				client: client.clone(),
+				url: format!("http://127.0.0.1:{}/", endpoint.port),
+				token: endpoint.token,
+			}),
+			None => Source::Local(backend, jobs),
+		};
+		Side {
+			source,
+			path: location.path,
+		}
+	}
+
+	/// Lists all entries of the directory with the key, without the parent entry.
+	pub async fn list(&self, key: &str) -> Result<Vec<FileInfo>, ErrorResponse> {
+		let mut files = Vec::new();
+		let mut cursor = None;
+		loop {
+			let request = ListRequest {
+				path: self.path.clone(),
+				key: Some(key.to_string()).filter(|key| !key.is_empty()),
+				cursor,
+				limit: None,
+			};
+			let response = match &self.source {
+				Source::Local(backend, _) => backend.list(request).await?,
+				Source::Remote(remote) => match remote.call(&RemoteRequest::List(request)).await? {
+					RemoteResponse::List(response) => response,
+					_ => return Err(unexpected_response()),
+				},
+			};
+			files.extend(
+				response
+					.files
+					.into_iter()
+					.filter(|file| get_name(file) != ".."),
+			);
+			match response.cursor {
+				Some(next) => cursor = Some(next),
+				None => return Ok(files),
+			}
+		}
+	}
+
+	pub async fn read(
+		&self,
+		key: &str,
+		offset: u64,
+		length: u64,
+	) -> Result<ReadResponse, ErrorResponse> {
+		let request = ReadRequest {
+			path: self.path.clone(),
+			key: Some(key.to_string()),
+			offset: Some(offset),
+			length: Some(length),
+		};
+		match &self.source {
+			Source::Local(backend, _) => backend.read(request).await,
+			Source::Remote(remote) => remote.read(request).await,
+		}
+	}
+
+	pub async fn write(
+		&self,
+		key: &str,
+		data: &[u8],
+		append: bool,
+		date: Option<i64>,
+	) -> Result<(), ErrorResponse> {
+		self.send_write(key, data, append, date, None)
+			.await
+			.map(|_| ())
+	}
+
+	/// Writes a part of the file and gets the upload the following parts belong to.
+	pub async fn write_part(
+		&self,
+		key: &str,
+		data: &[u8],
+		part: WritePart,
+	) -> Result<Option<String>, ErrorResponse> {
+		self.send_write(key, data, false, None, Some(part)).await
+	}
+
+	async fn send_write(
+		&self,
+		key: &str,
+		data: &[u8],
+		append: bool,
+		date: Option<i64>,
+		part: Option<WritePart>,
+	) -> Result<Option<String>, ErrorResponse> {
+		let request = WriteRequest {
+			path: self.path.clone(),
+			key: Some(key.to_string()),
+			content: ContentEncoding::encode(data),
+			encoding: ContentEncoding::Base64,
+			backup: false,
+			expected: None,
+			append,
+			date,
+			part,
+		};
+		match &self.source {
+			Source::Local(backend, _) => Ok(backend.write(request).await?.upload),
+			Source::Remote(remote) => match remote.call(&RemoteRequest::Write(request)).await? {
+				RemoteResponse::Write(response) => Ok(response.upload),
+				_ => Err(unexpected_response()),
+			},
+		}
+	}
+
+	/// Creates the directory with the key, including missing parents.
+	pub async fn create(&self, key: &str) -> Result<(), ErrorResponse> {
+		let request = CreateRequest {
+			path: self.path.clone(),
+			name: Some(key.to_string()),
+		};
+		match &self.source {
+			Source::Local(backend, _) => backend.create(request).await.map(|_| ()),
+			Source::Remote(remote) => match remote.call(&RemoteRequest::Create(request)).await? {
+				RemoteResponse::Create(_) => Ok(()),
+				_ => Err(unexpected_response()),
+			},
+		}
+	}
+
+	/// Moves the entry to the trash and waits until the backend has finished.
+	pub async fn delete(&self, key: &str, job: &Job) -> Result<(), ErrorResponse> {
+		let status = self.delete_job(key, false).await?;
+		self.wait(status, job).await
+	}
+
+	/// Starts deleting the entry for good without waiting; used for cleaning up.
+	pub async fn start_delete(&self, key: &str) -> Result<(), ErrorResponse> {
+		self.delete_job(key, true).await.map(|_| ())
+	}
+
+	async fn delete_job(&self, key: &str, permanent: bool) -> Result<JobStatus, ErrorResponse> {
+		let request = DeleteRequest {
+			path: self.path.clone(),
+			keys: vec![key.to_string()],
+			permanent,
+		};
+		match &self.source {
+			Source::Local(backend, jobs) => Ok(backend.delete(request, jobs).await?.into()),
+			Source::Remote(remote) => remote.job(&RemoteRequest::Delete(request)).await,
+		}
+	}
+
+	/// Renames the entry within its directory and waits until the backend has finished.
+	pub async fn rename(
+		&self,
+		key: &str,
+		name: &str,
+		overwrite: bool,
+		job: &Job,
+	) -> Result<(), ErrorResponse> {
+		let path = self.path.clone().ok_or_else(|| {
+			ErrorResponse::new(ErrorCode::InvalidInput, "directory path is required")
+		})?;
+		let destination = match key.rsplit_once('/') {
+			Some((parent, _)) => format!("{}/{}", path.trim_end_matches('/'), parent),
+			None => path,
+		};
+		let request = TransferRequest {
+			path: self.path.clone(),
+			keys: vec![key.to_string()],
+			destination,
+			names: Some(vec![name.to_string()]),
+			overwrite,
+		};
+		let status = match &self.source {
+			Source::Local(backend, jobs) => backend.rename(request, jobs).await?.into(),
+			Source::Remote(remote) => remote.job(&RemoteRequest::Rename(request)).await?,
+		};
+		self.wait(status, job).await
+	}
+
+	/// Indicates whether the backend supports the feature.
+	pub async fn has_feature(&self, feature: Feature) -> Result<bool, ErrorResponse> {
+		match &self.source {
+			Source::Local(backend, _) => Ok(backend.capabilities().features.contains(&feature)),
+			Source::Remote(remote) => match remote.call(&RemoteRequest::Capabilities {}).await? {
+				RemoteResponse::Capabilities(capabilities) => {
+					// features unknown to this version are compared by their names
+					let name = serde_json::to_value(feature)?;
+					Ok(capabilities.features.iter().any(|feature| *feature == name))
+				}
+				_ => Err(unexpected_response()),
+			},
+		}
+	}
+
+	/// Waits until a job of the backend has finished; the job is cancelled together
+	/// with the waiting one.
+	async fn wait(&self, mut status: JobStatus, job: &Job) -> Result<(), ErrorResponse> {
+		loop {
+			match status.state {
+				JobState::Completed => return Ok(()),
+				JobState::Failed | JobState::Cancelled => {
+					return Err(status.error.unwrap_or_else(|| JobCancelled.into()))
+				}
+				JobState::Running | JobState::Paused => {}
+			}
+			let id = status.id;
+			if job.is_cancelled() {
+				match &self.source {
+					Source::Local(_, jobs) => jobs.get(id).iter().for_each(|job| job.cancel()),
+					Source::Remote(remote) => {
+						remote.job(&RemoteRequest::JobCancel { id }).await?;
+					}
+				}
+				return Err(JobCancelled.into());
+			}
+			actix_web::rt::time::sleep(JOB_POLL_INTERVAL).await;
+			status = match &self.source {
+				Source::Local(_, jobs) => {
+					jobs.get(id).map(|job| job.status().into()).ok_or_else(|| {
+						ErrorResponse::new(
+							ErrorCode::NotFound,
+							format!("job {} does not exist", id),
+						)
+					})?
+				}
+				Source::Remote(remote) => remote.job(&RemoteRequest::JobStatus { id }).await?,
+			};
+		}
+	}
+}
+
+/// State of a job of a backend.
+#[derive(Deserialize)]
+struct JobStatus {
+	id: u64,
+	state: JobState,
+	error: Option<ErrorResponse>,
+}
+
+impl From<JobResponse> for JobStatus {
+	fn from(response: JobResponse) -> Self {
+		JobStatus {
+			id: response.id,
+			state: response.state,
+			error: response.error,
+		}
+	}
+}
+
+/// Client of another backend.
+struct Remote {
+	client: reqwest::Client,
+	url: String,
+	token: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+enum RemoteRequest {
+	List(ListRequest),
+	Read(ReadRequest),
+	Write(WriteRequest),
+	Create(CreateRequest),
+	Rename(TransferRequest),
+	Delete(DeleteRequest),
+	JobStatus { id: u64 },
+	JobCancel { id: u64 },
+	Capabilities {},
+}
+
+/// Responses used by the client; parts that are not used are not parsed, so newer
+/// backends are understood as well.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+enum RemoteResponse {
+	List(ListResponse),
+	Write(RemoteWrite),
+	Create(IgnoredAny),
+	Job(JobStatus),
+	Capabilities(RemoteCapabilities),
+	Error(ErrorResponse),
+}
+
+#[derive(Deserialize)]
+struct RemoteWrite {
+	upload: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct RemoteCapabilities {
+	features: Vec<String>,
+}
+
+impl Remote {
+	async fn call(&self, request: &RemoteRequest) -> Result<RemoteResponse, ErrorResponse> {
+		match self.send(request).await?.json().await? {
+			RemoteResponse::Error(err) => Err(err),
+			response => Ok(response),
+		}
+	}
+
+	async fn job(&self, request: &RemoteRequest) -> Result<JobStatus, ErrorResponse> {
+		match self.call(request).await? {
+			RemoteResponse::Job(status) => Ok(status),
+			_ => Err(unexpected_response()),
+		}
+	}
+
+	async fn read(&self, request: ReadRequest) -> Result<ReadResponse, ErrorResponse> {
+		let offset = request.offset.unwrap_or_default();
+		let response = self.send(&RemoteRequest::Read(request)).await?;
+		let size = response
+			.headers()
+			.get(FILE_SIZE_HEADER)
+			.and_then(|size| size.to_str().ok())
+			.and_then(|size| size.parse().ok())
+			.ok_or_else(|| {
+				ErrorResponse::new(ErrorCode::Connection, "backend did not send the file size")
+			})?;
+		let data = response.bytes().await?.to_vec();
+		Ok(ReadResponse { data, offset, size })
+	}
+
+	/// Sends the request; errors sent by the backend are returned as they are.
+	async fn send(&self, request: &RemoteRequest) -> Result<reqwest::Response, ErrorResponse> {
+		let mut builder = self.client.post(&self.url).json(request);
+		if let Some(token) = &self.token {
+			builder = builder.bearer_auth(token);
+		}
+		let response = builder.send().await?;
+		let status = response.status();
+		if status.is_success() {
+			return Ok(response);
+		}
+		let body = response.text().await?;
+		match serde_json::from_str(&body) {
+			Ok(RemoteResponse::Error(err)) => Err(err),
+			_ if status == reqwest::StatusCode::UNAUTHORIZED => Err(ErrorResponse::new(
+				ErrorCode::PermissionDenied,
+				"backend rejected the token",
+			)),
+			_ => Err(ErrorResponse::new(
+				ErrorCode::Connection,
+				format!("backend responded with {}", status),
+			)),
+		}
+	}
+}
+
+fn unexpected_response() -> ErrorResponse {
+	ErrorResponse::new(ErrorCode::Connection, "backend sent an unexpected response")
+}
+
+/// Gets the name of the entry, which is its key without the trailing separator.
+pub(crate) fn get_name(file: &FileInfo) -> String {
+	file.key.trim_end_matches('/').to_string()
+}
@@ -0,0 +1,570 @@
+use crate::compare::{Comparer, DATE_TOLERANCE};
+use crate::side::{get_name, Side};
+use crate::{
+	Backend, ComparisonStatus, ErrorCode, ErrorResponse, Feature, FileInfo, Job, JobKind,
+	JobResponse, JobResult, Jobs, SyncAction, SyncDirection, SyncRequest, WritePart,
+};
+use actix_web::web;
+
+/// Number of bytes copied at once. Larger files are written in chunks to a temporary
+/// file when the destination can append, so an existing file is replaced only by a
+/// complete copy.
+const CHUNK_SIZE: u64 = 1024 * 1024;
+
+/// Smallest size of parts of files written in parts; S3 requires 5 MiB for all parts
+/// but the last.
+const PART_SIZE: u64 = 8 * 1024 * 1024;
+
+/// Largest number of parts of a file, which S3 allows.
+const MAX_PARTS: u64 = 10000;
+
+/// Largest file copied to sides that can neither append nor write parts, which get
+/// the whole file in one write, so it is held in memory.
+const MAX_WHOLE_SIZE: u64 = 256 * 1024 * 1024;
+
+/// How files larger than a chunk are written to a side.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum WriteMode {
+	/// Appended in chunks to a temporary file, which then replaces the file.
+	Append,
+	/// Written in parts, which the backend joins when the last one is written.
+	Parts,
+	/// Written at once.
+	Whole,
+}
+
+/// Starts a job synchronizing two directories. The directories are compared first,
+/// then the planned actions are applied; every entry that is not identical is logged
+/// as a result with its action.
+pub(crate) fn sync<B: Backend>(
+	backend: web::Data<B>,
+	request: SyncRequest,
+	jobs: web::Data<Jobs>,
+) -> Result<JobResponse, ErrorResponse> {
+	if request.delete && request.direction == SyncDirection::Bidirectional {
+		return Err(ErrorResponse::new(
+			ErrorCode::InvalidInput,
+			"deletions are propagated only in one direction",
+		));
+	}
+	let client = reqwest::Client::new();
+	let synchronizer = Synchronizer {
+		comparer: Comparer {
+			left: Side::new(backend.clone(), jobs.clone(), request.left, &client),
+			right: Side::new(backend, jobs.clone(), request.right, &client),
+			recursive: true,
+			method: request.method,
+			date_tolerance: request.date_tolerance.unwrap_or(DATE_TOLERANCE),
+		},
+		direction: request.direction,
+		dry_run: request.dry_run,
+		delete: request.delete,
+	};
+	let job = jobs.start_local(
+		JobKind::Sync,
+		|job| async move { synchronizer.run(&job).await },
+	);
+	Ok(job.status())
+}
+
+struct Synchronizer<B: Backend> {
+	comparer: Comparer<B>,
+	direction: SyncDirection,
+	dry_run: bool,
+	delete: bool,
+}
+
+impl<B: Backend> Synchronizer<B> {
+	async fn run(&self, job: &Job) -> Result<(), ErrorResponse> {
+		// plans all actions first, so the progress has known totals
+		let mut plan = Vec::new();
+		self.comparer
+			.compare(job, |result| {
+				if let Some(action) = self.get_action(&result) {
+					plan.push((result, action));
+				}
+			})
+			.await?;
+		let bytes_total = plan
+			.iter()
+			.filter_map(|(result, action)| self.get_source(result, *action))
+			.filter(|file| !file.is_directory)
+			.map(|file| file.size)
+			.sum();
+		job.update(|progress| {
+			progress.files_total = plan.len() as u64;
+			progress.files_done = 0;
+			progress.bytes_total = bytes_total;
+			progress.bytes_done = 0;
+		});
+
+		let (left_mode, right_mode) = if self.dry_run {
+			(WriteMode::Whole, WriteMode::Whole)
+		} else {
+			(
+				get_write_mode(&self.comparer.left).await?,
+				get_write_mode(&self.comparer.right).await?,
+			)
+		};
+		for (mut result, action) in plan {
+			job.checkpoint_async().await?;
+			job.update(|progress| progress.current = Some(result.file.key.clone()));
+			if !self.dry_run && result.error.is_none() {
+				let applied = match action {
+					SyncAction::CopyToRight => self.copy(&result, true, right_mode, job).await,
+					SyncAction::CopyToLeft => self.copy(&result, false, left_mode, job).await,
+					SyncAction::DeleteRight => {
+						self.comparer.right.delete(&result.file.key, job).await
+					}
+					SyncAction::DeleteLeft => {
+						self.comparer.left.delete(&result.file.key, job).await
+					}
+					SyncAction::Skip | SyncAction::Conflict => Ok(()),
+				};
+				match applied {
+					Err(err) if err.code == ErrorCode::Cancelled => return Err(err),
+					Err(err) => result.error = Some(err),
+					Ok(()) => {}
+				}
+			}
+			result.action = Some(action);
+			job.add_result(result);
+			job.update(|progress| progress.files_done += 1);
+		}
+		Ok(())
+	}
+
+	/// Gets the action for an entry of the comparison; identical entries have none.
+	/// Entries that could not be compared are skipped.
+	fn get_action(&self, result: &JobResult) -> Option<SyncAction> {
+		let Some(comparison) = &result.comparison else {
+			return Some(SyncAction::Skip);
+		};
+		if result.error.is_some() {
+			return Some(SyncAction::Skip);
+		}
+		let is_type_mismatch = match (&comparison.left, &comparison.right) {
+			(Some(left), Some(right)) => left.is_directory != right.is_directory,
+			_ => false,
+		};
+		let action = match (self.direction, comparison.status) {
+			(_, ComparisonStatus::Identical) => return None,
+			(SyncDirection::LeftToRight, ComparisonStatus::RightOnly) if self.delete => {
+				SyncAction::DeleteRight
+			}
+			(SyncDirection::RightToLeft, ComparisonStatus::LeftOnly) if self.delete => {
+				SyncAction::DeleteLeft
+			}
+			(SyncDirection::LeftToRight, ComparisonStatus::RightOnly)
+			| (SyncDirection::RightToLeft, ComparisonStatus::LeftOnly) => SyncAction::Skip,
+			// replacing a file with a directory or the other way around deletes the entry
+			(SyncDirection::LeftToRight | SyncDirection::RightToLeft, _)
+				if is_type_mismatch && !self.delete =>
+			{
+				SyncAction::Conflict
+			}
+			(SyncDirection::LeftToRight, _) => SyncAction::CopyToRight,
+			(SyncDirection::RightToLeft, _) => SyncAction::CopyToLeft,
+			(
+				SyncDirection::Bidirectional,
+				ComparisonStatus::LeftOnly | ComparisonStatus::LeftNewer,
+			) => SyncAction::CopyToRight,
+			(
+				SyncDirection::Bidirectional,
+				ComparisonStatus::RightOnly | ComparisonStatus::RightNewer,
+			) => SyncAction::CopyToLeft,
+			(SyncDirection::Bidirectional, _) => SyncAction::Conflict,
+		};
+		Some(action)
+	}
+
+	/// Gets the entry copied by the action.
+	fn get_source<'a>(&self, result: &'a JobResult, action: SyncAction) -> Option<&'a FileInfo> {
+		let comparison = result.comparison.as_ref()?;
+		match action {
+			SyncAction::CopyToRight => comparison.left.as_ref(),
+			SyncAction::CopyToLeft => comparison.right.as_ref(),
+			_ => None,
+		}
+	}
+
+	/// Copies an entry to the other side; directories are copied with their contents,
+	/// except for links to directories.
+	async fn copy(
+		&self,
+		result: &JobResult,
+		to_right: bool,
+		mode: WriteMode,
+		job: &Job,
+	) -> Result<(), ErrorResponse> {
+		let Some(comparison) = &result.comparison else {
+			return Ok(());
+		};
+		let (from, to, source, existing) = if to_right {
+			(
+				&self.comparer.left,
+				&self.comparer.right,
+				&comparison.left,
+				&comparison.right,
+			)
+		} else {
+			(
+				&self.comparer.right,
+				&self.comparer.left,
+				&comparison.right,
+				&comparison.left,
+			)
+		};
+		let Some(source) = source else {
+			return Ok(());
+		};
+		let key = &result.file.key;
+		if existing
+			.as_ref()
+			.is_some_and(|existing| existing.is_directory != source.is_directory)
+		{
+			to.delete(key, job).await?;
+		}
+		if !source.is_directory {
+			return copy_file(from, to, key, source, mode, job).await;
+		}
+
+		let mut directories = vec![key.clone()];
+		while let Some(directory) = directories.pop() {
+			job.checkpoint_async().await?;
+			to.create(&directory).await?;
+			for file in from.list(&format!("{}/", directory)).await? {
+				let key = format!("{}/{}", directory, get_name(&file));
+				if !file.is_directory {
+					job.update(|progress| progress.bytes_total += file.size);
+					copy_file(from, to, &key, &file, mode, job).await?;
+				} else if !file.is_symlink {
+					directories.push(key);
+				}
+			}
+		}
+		Ok(())
+	}
+}
+
+async fn get_write_mode<B: Backend>(side: &Side<B>) -> Result<WriteMode, ErrorResponse> {
+	if side.has_feature(Feature::AppendWrites).await? {
+		Ok(WriteMode::Append)
+	} else if side.has_feature(Feature::MultipartWrites).await? {
+		Ok(WriteMode::Parts)
+	} else {
+		Ok(WriteMode::Whole)
+	}
+}
+
+/// Copies a file and its modification date; files are written at once to backends
+/// that can neither append nor write parts, up to a size limit.
+async fn copy_file<B: Backend>(
+	from: &Side<B>,
+	to: &Side<B>,
+	key: &str,
+	file: &FileInfo,
+	mode: WriteMode,
+	job: &Job,
+) -> Result<(), ErrorResponse> {
+	// backends without dates report zero
+	let date = Some(file.date).filter(|date| *date != 0);
+	if mode == WriteMode::Parts && file.size > CHUNK_SIZE {
+		return copy_parts(from, to, key, file.size, job).await;
+	}
+	if mode == WriteMode::Whole || file.size <= CHUNK_SIZE {
+		let too_large = || {
+			ErrorResponse::new(
+				ErrorCode::Unsupported,
+				format!(
+					"file is larger than {} MiB, which the destination can only write at once",
+					MAX_WHOLE_SIZE / 1024 / 1024
+				),
+			)
+			.with_path(key)
+		};
+		if file.size > MAX_WHOLE_SIZE {
+			return Err(too_large());
+		}
+		let mut data = Vec::new();
+		loop {
+			job.checkpoint_async().await?;
+			let chunk = from.read(key, data.len() as u64, CHUNK_SIZE).await?;
+			// the file may have grown since it was listed
+			if (data.len() + chunk.data.len()) as u64 > MAX_WHOLE_SIZE {
+				return Err(too_large());
+			}
+			data.extend_from_slice(&chunk.data);
+			job.update(|progress| progress.bytes_done += chunk.data.len() as u64);
+			if chunk.data.is_empty() || data.len() as u64 >= chunk.size {
+				break;
+			}
+		}
+		return to.write(key, &data, false, date).await;
+	}
+
+	let (parent, name) = match key.rsplit_once('/') {
+		Some((parent, name)) => (format!("{}/", parent), name),
+		None => (String::new(), key),
+	};
+	let temp_key = format!("{}.{}.xcmd-sync", parent, name);
+	let copied = async {
+		let mut offset = 0;
+		loop {
+			job.checkpoint_async().await?;
+			let chunk = from.read(key, offset, CHUNK_SIZE).await?;
+			let end = offset + chunk.data.len() as u64;
+			let is_last = chunk.data.is_empty() || end >= chunk.size;
+			let chunk_date = date.filter(|_| is_last);
+			to.write(&temp_key, &chunk.data, offset > 0, chunk_date)
+				.await?;
+			job.update(|progress| progress.bytes_done += chunk.data.len() as u64);
+			offset = end;
+			if is_last {
+				break;
+			}
+		}
+		to.rename(&temp_key, name, true, job).await
+	}
+	.await;
+	if copied.is_err() {
+		to.start_delete(&temp_key).await.ok();
+	}
+	copied
+}
+
+/// Copies a file in parts, which the destination joins when the last one is written;
+/// only a part is held in memory at a time. Backends writing parts keep their own
+/// modification dates.
+async fn copy_parts<B: Backend>(
+	from: &Side<B>,
+	to: &Side<B>,
+	key: &str,
+	size: u64,
+	job: &Job,
+) -> Result<(), ErrorResponse> {
+	let part_size = PART_SIZE.max(size.div_ceil(MAX_PARTS));
+	let mut upload = None;
+	let copied = async {
+		let mut data = Vec::new();
+		let mut offset = 0;
+		let mut number = 1;
+		loop {
+			job.checkpoint_async().await?;
+			// reads may return less than requested, so parts are filled up first
+			let chunk = from
+				.read(key, offset, part_size - data.len() as u64)
+				.await?;
+			offset += chunk.data.len() as u64;
+			let is_last = chunk.data.is_empty() || offset >= chunk.size;
+			data.extend_from_slice(&chunk.data);
+			job.update(|progress| progress.bytes_done += chunk.data.len() as u64);
+			if data.len() as u64 >= part_size || is_last {
+				let part = WritePart {
+					upload: upload.clone(),
+					number,
+					last: is_last,
+					abort: false,
+				};
+				upload = to.write_part(key, &data, part).await?;
+				data.clear();
+				number += 1;
+			}
+			if is_last {
+				return Ok(());
+			}
+		}
+	}
+	.await;
+	if let (Err(_), Some(upload)) = (&copied, upload) {
+		let part = WritePart {
+			upload: Some(upload),
+			number: 1,
+			last: false,
+			abort: true,
+		};
+		to.write_part(key, &[], part).await.ok();
+	}
+	copied
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::testing::{get_side, Files};
+	use crate::CompareMethod;
+
+	/// Synchronizes the left and right directories; gets the keys with the actions.
+	fn synchronize(
+		files: &web::Data<Files>,
+		direction: SyncDirection,
+		delete: bool,
+		dry_run: bool,
+	) -> Vec<(String, SyncAction)> {
+		let synchronizer = Synchronizer {
+			comparer: Comparer {
+				left: get_side(files, "/left"),
+				right: get_side(files, "/right"),
+				recursive: true,
+				method: CompareMethod::Date,
+				date_tolerance: DATE_TOLERANCE,
+			},
+			direction,
+			dry_run,
+			delete,
+		};
+		let job = Job::new(1, JobKind::Sync);
+		actix_web::rt::System::new()
+			.block_on(synchronizer.run(&job))
+			.unwrap();
+		job.results(0, usize::MAX)
+			.into_iter()
+			.map(|result| {
+				assert!(result.error.is_none(), "{:?}", result.error);
+				(result.file.key, result.action.unwrap())
+			})
+			.collect()
+	}
+
+	fn get_actions(actions: &[(&str, SyncAction)]) -> Vec<(String, SyncAction)> {
+		actions
+			.iter()
+			.map(|(key, action)| (key.to_string(), *action))
+			.collect()
+	}
+
+	/// Adds entries on both sides: `new` and `sub/x` only on the left, `old` only on
+	/// the right, `changed` newer on the left, `type` a file on the left and a
+	/// directory on the right, and `same` identical.
+	fn get_files() -> web::Data<Files> {
+		let files = Files::default();
+		files.add("left/same", "s", 10_000);
+		files.add("right/same", "s", 10_000);
+		files.add("left/new", "n", 10_000);
+		files.add("left/sub/x", "x", 10_000);
+		files.add("right/old", "o", 10_000);
+		files.add("left/changed", "new", 20_000);
+		files.add("right/changed", "old", 10_000);
+		files.add("left/type", "t", 10_000);
+		files.add("right/type/y", "y", 10_000);
+		web::Data::new(files)
+	}
+
+	#[test]
+	fn synchronize_to_right() {
+		use SyncAction::*;
+		let files = get_files();
+		let actions = synchronize(&files, SyncDirection::LeftToRight, false, false);
+		assert_eq!(
+			actions,
+			get_actions(&[
+				("changed", CopyToRight),
+				("new", CopyToRight),
+				("old", Skip),
+				("sub", CopyToRight),
+				("type", Conflict),
+			])
+		);
+		assert_eq!(
+			files.paths("right"),
+			["changed", "new", "old", "same", "sub/", "sub/x", "type/", "type/y"]
+		);
+		assert_eq!(files.get("right/changed").as_deref(), Some("new"));
+		// copies keep the dates, so they are identical afterwards
+		let actions = synchronize(&files, SyncDirection::LeftToRight, false, false);
+		assert_eq!(actions, get_actions(&[("old", Skip), ("type", Conflict)]));
+	}
+
+	#[test]
+	fn mirror_to_right() {
+		use SyncAction::*;
+		let files = get_files();
+		let actions = synchronize(&files, SyncDirection::LeftToRight, true, false);
+		assert_eq!(
+			actions,
+			get_actions(&[
+				("changed", CopyToRight),
+				("new", CopyToRight),
+				("old", DeleteRight),
+				("sub", CopyToRight),
+				("type", CopyToRight),
+			])
+		);
+		assert_eq!(files.paths("right"), files.paths("left"));
+		assert_eq!(files.get("right/type").as_deref(), Some("t"));
+	}
+
+	#[test]
+	fn synchronize_both_ways() {
+		use SyncAction::*;
+		let files = get_files();
+		files.add("right/conflict", "ab", 10_000);
+		files.add("left/conflict", "a", 10_000);
+		let actions = synchronize(&files, SyncDirection::Bidirectional, false, false);
+		assert_eq!(
+			actions,
+			get_actions(&[
+				("changed", CopyToRight),
+				("conflict", Conflict),
+				("new", CopyToRight),
+				("old", CopyToLeft),
+				("sub", CopyToRight),
+				("type", Conflict),
+			])
+		);
+		assert_eq!(files.get("left/old").as_deref(), Some("o"));
+		assert_eq!(files.get("right/new").as_deref(), Some("n"));
+		assert_eq!(files.get("left/conflict").as_deref(), Some("a"));
+	}
+
+	#[test]
+	fn synchronize_dry_run() {
+		let files = get_files();
+		let paths = files.paths("right");
+		let actions = synchronize(&files, SyncDirection::RightToLeft, true, true);
+		assert_eq!(
+			actions,
+			get_actions(&[
+				("changed", SyncAction::CopyToLeft),
+				("new", SyncAction::DeleteLeft),
+				("old", SyncAction::CopyToLeft),
+				("sub", SyncAction::DeleteLeft),
+				("type", SyncAction::CopyToLeft),
+			])
+		);
+		assert_eq!(files.paths("right"), paths);
+		assert!(files.get("left/new").is_some());
+	}
+
+	#[test]
+	fn copy_whole_files() {
+		let files = web::Data::new(Files::default());
+		let data = "7".repeat(CHUNK_SIZE as usize * 2 + 1);
+		files.add("left/a", &data, 10_000);
+		let (from, to) = (get_side(&files, "/left"), get_side(&files, "/right"));
+		let file = FileInfo {
+			size: data.len() as u64,
+			date: 10_000,
+			..Default::default()
+		};
+		let job = Job::new(1, JobKind::Sync);
+		let run = |file| copy_file(&from, &to, "a", file, WriteMode::Whole, &job);
+		actix_web::rt::System::new().block_on(run(&file)).unwrap();
+		assert_eq!(files.get("right/a"), Some(data.clone()));
+		assert_eq!(job.status().progress.bytes_done, data.len() as u64);
+
+		// larger files are refused instead of being read into memory
+		let file = FileInfo {
+			size: MAX_WHOLE_SIZE + 1,
+			..Default::default()
+		};
+		files.add("left/b", "b", 0);
+		let run = |file| copy_file(&from, &to, "b", file, WriteMode::Whole, &job);
+		let err = actix_web::rt::System::new()
+			.block_on(run(&file))
+			.unwrap_err();
+		assert_eq!(err.code, ErrorCode::Unsupported);
+		assert_eq!(err.path.as_deref(), Some("b"));
+		assert!(files.get("right/b").is_none());
+	}
+}
@@ -0,0 +1,223 @@
+use crate::side::Side;
+use crate::{
+	Backend, Capabilities, CreateRequest, CreateResponse, DeleteRequest, ErrorCode, ErrorResponse,
+	FileInfo, JobKind, JobProgress, JobResponse, JobState, Jobs, ListRequest, ListResponse,
+	Location, PathSyntax, ReadRequest, ReadResponse, WriteRequest, WriteResponse, PROTOCOL_VERSION,
+};
+use actix_web::web;
+use async_trait::async_trait;
+use parking_lot::Mutex;
+use std::collections::BTreeMap;
+
+/// Directory tree held in memory, by paths without leading and trailing separators.
+/// Files can only be written at once.
+#[derive(Default)]
+pub struct Files {
+	entries: Mutex<BTreeMap<String, Entry>>,
+}
+
+#[derive(Clone)]
+struct Entry {
+	/// Contents of a file; none for directories.
+	data: Option<Vec<u8>>,
+	date: i64,
+}
+
+impl Files {
+	/// Adds a file with the contents and date, and its missing parents.
+	pub fn add(&self, path: &str, contents: &str, date: i64) {
+		self.add_directory(get_parent(path));
+		let entry = Entry {
+			data: Some(contents.as_bytes().to_vec()),
+			date,
+		};
+		self.entries.lock().insert(path.to_string(), entry);
+	}
+
+	/// Adds a directory and its missing parents.
+	pub fn add_directory(&self, path: &str) {
+		let mut entries = self.entries.lock();
+		let mut path = path;
+		while !path.is_empty() {
+			entries.entry(path.to_string()).or_insert(Entry {
+				data: None,
+				date: 0,
+			});
+			path = get_parent(path);
+		}
+	}
+
+	/// Gets the contents of a file.
+	pub fn get(&self, path: &str) -> Option<String> {
+		let entries = self.entries.lock();
+		let data = entries.get(path)?.data.as_ref()?;
+		Some(String::from_utf8_lossy(data).to_string())
+	}
+
+	/// Gets the paths of the entries in the directory and its subdirectories; paths of
+	/// directories end with a separator.
+	pub fn paths(&self, directory: &str) -> Vec<String> {
+		let prefix = format!("{}/", directory);
+		let entries = self.entries.lock();
+		entries
+			.iter()
+			.filter_map(|(path, entry)| {
+				let path = path.strip_prefix(&prefix)?;
+				Some(match entry.data {
+					Some(_) => path.to_string(),
+					None => format!("{}/", path),
+				})
+			})
+			.collect()
+	}
+}
+
+/// Creates a side of the directory of the files.
+pub fn get_side(files: &web::Data<Files>, path: &str) -> Side<Files> {
+	let location = Location {
+		backend: None,
+		path: Some(path.to_string()),
+	};
+	Side::new(
+		files.clone(),
+		web::Data::new(Jobs::default()),
+		location,
+		&reqwest::Client::new(),
+	)
+}
+
+fn get_path(path: &Option<String>, key: &Option<String>) -> String {
+	[path.as_deref(), key.as_deref()]
+		.into_iter()
+		.flatten()
+		.flat_map(|part| part.split('/'))
+		.filter(|name| !name.is_empty())
+		.collect::<Vec<_>>()
+		.join("/")
+}
+
+fn get_parent(path: &str) -> &str {
+	path.rsplit_once('/').map_or("", |(parent, _)| parent)
+}
+
+fn not_found(path: &str) -> ErrorResponse {
+	ErrorResponse::new(ErrorCode::NotFound, "entry does not exist").with_path(path)
+}
+
+#[async_trait(?Send)]
+impl Backend for Files {
+	fn capabilities(&self) -> Capabilities {
+		Capabilities {
+			protocol_version: PROTOCOL_VERSION,
+			name: "test".to_string(),
+			version: "0".to_string(),
+			requests: Vec::new(),
+			path_syntax: PathSyntax {
+				separator: "/".to_string(),
+				case_sensitive: true,
+			},
+			features: Vec::new(),
+		}
+	}
+
+	async fn list(&self, request: ListRequest) -> Result<ListResponse, ErrorResponse> {
+		let path = get_path(&request.path, &request.key);
+		let entries = self.entries.lock();
+		if !path.is_empty() && entries.get(&path).is_none_or(|entry| entry.data.is_some()) {
+			return Err(not_found(&path));
+		}
+		let files = entries
+			.iter()
+			.filter(|(child, _)| get_parent(child) == path && !child.is_empty())
+			.map(|(child, entry)| {
+				let name = child.rsplit('/').next().unwrap_or_default().to_string();
+				let is_directory = entry.data.is_none();
+				FileInfo {
+					key: format!("{}{}", name, if is_directory { "/" } else { "" }),
+					name,
+					is_directory,
+					size: entry.data.as_ref().map_or(0, |data| data.len() as u64),
+					date: entry.date,
+					..Default::default()
+				}
+			})
+			.collect();
+		Ok(ListResponse {
+			path,
+			name: String::new(),
+			files,
+			cursor: None,
+			free_space: None,
+		})
+	}
+
+	async fn read(&self, request: ReadRequest) -> Result<ReadResponse, ErrorResponse> {
+		let path = get_path(&request.path, &request.key);
+		let entries = self.entries.lock();
+		let data = entries
+			.get(&path)
+			.and_then(|entry| entry.data.as_ref())
+			.ok_or_else(|| not_found(&path))?;
+		let offset = request.offset.unwrap_or(0).min(data.len() as u64) as usize;
+		let length = request.length.unwrap_or(u64::MAX);
+		let end = (offset as u64)
+			.saturating_add(length)
+			.min(data.len() as u64) as usize;
+		Ok(ReadResponse {
+			data: data[offset..end].to_vec(),
+			offset: offset as u64,
+			size: data.len() as u64,
+		})
+	}
+
+	async fn write(&self, request: WriteRequest) -> Result<WriteResponse, ErrorResponse> {
+		if request.append || request.part.is_some() {
+			return Err(ErrorResponse::unsupported());
+		}
+		let path = get_path(&request.path, &request.key);
+		let data = request.encoding.decode(request.content)?;
+		self.add_directory(get_parent(&path));
+		let entry = Entry {
+			data: Some(data),
+			date: request.date.unwrap_or_default(),
+		};
+		self.entries.lock().insert(path.clone(), entry);
+		Ok(WriteResponse {
+			path,
+			file: FileInfo::default(),
+			upload: None,
+		})
+	}
+
+	async fn create(&self, request: CreateRequest) -> Result<CreateResponse, ErrorResponse> {
+		let path = get_path(&request.path, &request.name);
+		self.add_directory(&path);
+		Ok(CreateResponse {
+			path,
+			directory: None,
+		})
+	}
+
+	async fn delete(
+		&self,
+		request: DeleteRequest,
+		_jobs: &Jobs,
+	) -> Result<JobResponse, ErrorResponse> {
+		let mut entries = self.entries.lock();
+		for key in &request.keys {
+			let path = get_path(&request.path, &Some(key.clone()));
+			if entries.remove(&path).is_none() {
+				return Err(not_found(&path));
+			}
+			let prefix = format!("{}/", path);
+			entries.retain(|child, _| !child.starts_with(&prefix));
+		}
+		Ok(JobResponse {
+			id: 0,
+			kind: JobKind::Delete,
+			state: JobState::Completed,
+			progress: JobProgress::default(),
+			error: None,
+		})
+	}
+}
@@ -0,0 +1,584 @@
+use flate2::read::MultiGzDecoder;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom};
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Instant, SystemTime};
+use xcmd_base::{
+	check_offset, ArchiveFormat, ErrorCode, ErrorContext, ErrorResponse, FileInfo, ListRequest,
+	ListResponse, ReadResponse,
+};
+use xz2::read::XzDecoder;
+use zip::result::ZipError;
+use zip::ZipArchive;
+
+pub const S_IFMT: u32 = 0o170000;
+pub const S_IFLNK: u32 = 0o120000;
+
+/// Largest number of archives whose entries are kept.
+const MAX_INDEXES: usize = 16;
+
+/// Entries of archives read lately by their paths, so compressed archives are not
+/// decompressed again to list each directory; archives that have changed are read again.
+static INDEXES: Mutex<Option<HashMap<PathBuf, Index>>> = Mutex::new(None);
+
+struct Index {
+	modified: Option<SystemTime>,
+	size: u64,
+	entries: Arc<Vec<Entry>>,
+	used: Instant,
+}
+
+/// Recognizes archives browsed like directories by their extension.
+pub fn get_format(path: &Path) -> Option<ArchiveFormat> {
+	ArchiveFormat::from_name(&path.file_name()?.to_string_lossy())
+}
+
+/// Entry of an archive; paths use `/` as separator and have no trailing separator.
+struct Entry {
+	path: String,
+	is_directory: bool,
+	is_symlink: bool,
+	size: u64,
+	date: i64,
+	/// Index of a zip entry, or offset of the data of a tar entry in the decompressed
+	/// archive; sparse tar entries have none, as their data is not stored in one piece.
+	position: Option<u64>,
+}
+
+/// Splits a path within an archive into the path of the archive and the path of
+/// the entry, which is empty for the archive itself. Paths not within an archive
+/// give `None`.
+pub fn split_path(path: &Path) -> Option<(PathBuf, String)> {
+	if path.is_dir() {
+		return None;
+	}
+	let normalized = normalize_path(path);
+	let mut names = Vec::new();
+	let mut archive = normalized.as_path();
+	loop {
+		if get_format(archive).is_some() && archive.is_file() {
+			names.reverse();
+			return Some((archive.to_path_buf(), names.join("/")));
+		}
+		names.push(archive.file_name()?.to_string_lossy().to_string());
+		archive = archive.parent()?;
+	}
+}
+
+/// Resolves parent components without the file system, which cannot enter archives.
+pub fn normalize_path(path: &Path) -> PathBuf {
+	let mut normalized = PathBuf::new();
+	for component in path.components() {
+		match component {
+			Component::ParentDir => {
+				normalized.pop();
+			}
+			Component::CurDir => {}
+			component => normalized.push(component),
+		}
+	}
+	normalized
+}
+
+/// Lists a directory of an archive. Directories missing in the archive are implied
+/// by the paths of their entries. Compressed tar archives have no index, so they are
+/// read from the start once and their entries are kept.
+pub fn list(
+	archive_path: &Path,
+	directory: &str,
+	request: &ListRequest,
+	active_key: &Option<String>,
+) -> Result<ListResponse, ErrorResponse> {
+	let full_path = get_entry_path(archive_path, directory);
+	let entries = get_entries(archive_path)?;
+	let prefix = if directory.is_empty() {
+		String::new()
+	} else {
+		format!("{}/", directory)
+	};
+	if let Some(entry) = entries.iter().find(|entry| entry.path == directory) {
+		if !entry.is_directory {
+			return Err(
+				ErrorResponse::new(ErrorCode::NotADirectory, "entry is not a directory")
+					.with_path(&full_path),
+			);
+		}
+	} else if !prefix.is_empty() && !entries.iter().any(|entry| entry.path.starts_with(&prefix)) {
+		return Err(
+			ErrorResponse::new(ErrorCode::NotFound, "entry does not exist in the archive")
+				.with_path(&full_path),
+		);
+	}
+
+	let mut children = BTreeMap::new();
+	for entry in entries.iter() {
+		let Some(rest) = entry.path.strip_prefix(&prefix) else {
+			continue;
+		};
+		match rest.split_once('/') {
+			Some((name, _)) => {
+				children
+					.entry(name.to_string())
+					.or_insert_with(|| get_file(name, None, active_key));
+			}
+			None if !rest.is_empty() => {
+				children.insert(rest.to_string(), get_file(rest, Some(entry), active_key));
+			}
+			None => {}
+		}
+	}
+
+	let (offset, limit) = request.page()?;
+	let mut files = Vec::new();
+	if offset == 0 {
+		files.push(get_file("..", None, &None));
+	}
+	let end = offset.saturating_add(limit);
+	let cursor = Some(end)
+		.filter(|end| *end < children.len())
+		.map(|end| end.to_string());
+	files.extend(children.into_values().skip(offset).take(limit));
+
+	let name = full_path
+		.file_name()
+		.map(|x| x.to_string_lossy().to_string())
+		.unwrap_or_default();
+	Ok(ListResponse {
+		path: full_path.to_string_lossy().to_string(),
+		name,
+		files,
+		cursor,
+		free_space: None,
+	})
+}
+
+/// Extracts a range of an entry. Entries of compressed tar archives are found in the
+/// kept entries, but their data can only be reached by decompressing what precedes it.
+pub fn read(
+	archive_path: &Path,
+	entry_path: &str,
+	offset: Option<u64>,
+	length: Option<u64>,
+) -> Result<ReadResponse, ErrorResponse> {
+	let full_path = get_entry_path(archive_path, entry_path);
+	let not_found = || {
+		ErrorResponse::new(ErrorCode::NotFound, "entry does not exist in the archive")
+			.with_path(&full_path)
+	};
+	let entries = get_entries(archive_path)?;
+	let directory_error = || {
+		ErrorResponse::new(ErrorCode::IsADirectory, "entry is a directory").with_path(&full_path)
+	};
+	let Some(entry) = entries.iter().find(|entry| entry.path == entry_path) else {
+		// directories may also be implied by the paths of their entries
+		let prefix = format!("{}/", entry_path);
+		return Err(
+			if entries.iter().any(|entry| entry.path.starts_with(&prefix)) {
+				directory_error()
+			} else {
+				not_found()
+			},
+		);
+	};
+	if entry.is_directory {
+		return Err(directory_error());
+	}
+
+	match (get_format(archive_path), entry.position) {
+		(Some(ArchiveFormat::Zip), Some(index)) => {
+			let mut archive = open_zip(archive_path)?;
+			let file = archive
+				.by_index(index as usize)
+				.map_err(|err| zip_error(err, archive_path))?;
+			let size = file.size();
+			read_range(file, size, offset, length).with_path(&full_path)
+		}
+		(Some(ArchiveFormat::Tar), Some(position)) => {
+			let mut file = File::open(archive_path).with_path(archive_path)?;
+			file.seek(SeekFrom::Start(position))
+				.with_path(archive_path)?;
+			let reader = BufReader::new(file).take(entry.size);
+			read_range(reader, entry.size, offset, length).with_path(&full_path)
+		}
+		(Some(format), Some(position)) => {
+			let file = BufReader::new(File::open(archive_path).with_path(archive_path)?);
+			let mut reader = decode(file, format).with_path(archive_path)?;
+			io::copy(&mut (&mut reader).take(position), &mut io::sink()).with_path(archive_path)?;
+			read_range(reader.take(entry.size), entry.size, offset, length).with_path(&full_path)
+		}
+		(Some(format), None) => {
+			let mut archive = open_tar(archive_path, format)?;
+			for tar_entry in archive.entries().with_path(archive_path)? {
+				let tar_entry = tar_entry.with_path(archive_path)?;
+				let path = tar_entry.path().with_path(archive_path)?;
+				if normalize(&path.to_string_lossy()).as_deref() == Some(entry_path) {
+					let size = tar_entry.size();
+					return read_range(tar_entry, size, offset, length).with_path(&full_path);
+				}
+			}
+			Err(not_found())
+		}
+		(None, _) => Err(not_found()),
+	}
+}
+
+fn get_entry_path(archive_path: &Path, entry_path: &str) -> PathBuf {
+	if entry_path.is_empty() {
+		archive_path.to_path_buf()
+	} else {
+		archive_path.join(entry_path)
+	}
+}
+
+fn read_range(
+	mut reader: impl Read,
+	size: u64,
+	offset: Option<u64>,
+	length: Option<u64>,
+) -> Result<ReadResponse, ErrorResponse> {
+	let offset = offset.unwrap_or(0);
+	check_offset(offset, size)?;
+	// compressed entries cannot seek, so the skipped part is decompressed as well
+	io::copy(&mut (&mut reader).take(offset), &mut io::sink())?;
+	let mut data = Vec::new();
+	reader
+		.take(length.unwrap_or(size - offset))
+		.read_to_end(&mut data)?;
+	Ok(ReadResponse { data, offset, size })
+}
+
+/// Gets the entries of the archive, which are read again when its modification date or
+/// size has changed.
+fn get_entries(archive_path: &Path) -> Result<Arc<Vec<Entry>>, ErrorResponse> {
+	let metadata = fs::metadata(archive_path).with_path(archive_path)?;
+	let (modified, size) = (metadata.modified().ok(), metadata.len());
+	if let Some(index) = lock()
+		.get_or_insert_with(HashMap::new)
+		.get_mut(archive_path)
+	{
+		if index.modified == modified && index.size == size {
+			index.used = Instant::now();
+			return Ok(index.entries.clone());
+		}
+	}
+
+	let entries = Arc::new(read_entries(archive_path)?);
+	let mut indexes = lock();
+	let indexes = indexes.get_or_insert_with(HashMap::new);
+	if indexes.len() >= MAX_INDEXES && !indexes.contains_key(archive_path) {
+		let oldest = indexes
+			.iter()
+			.min_by_key(|(_, index)| index.used)
+			.map(|(path, _)| path.clone());
+		if let Some(oldest) = oldest {
+			indexes.remove(&oldest);
+		}
+	}
+	indexes.insert(
+		archive_path.to_path_buf(),
+		Index {
+			modified,
+			size,
+			entries: entries.clone(),
+			used: Instant::now(),
+		},
+	);
+	Ok(entries)
+}
+
+fn lock() -> MutexGuard<'static, Option<HashMap<PathBuf, Index>>> {
+	INDEXES.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+fn read_entries(archive_path: &Path) -> Result<Vec<Entry>, ErrorResponse> {
+	let mut entries = Vec::new();
+	match get_format(archive_path) {
+		Some(ArchiveFormat::Zip) => {
+			let mut archive = open_zip(archive_path)?;
+			for index in 0..archive.len() {
+				let file = archive
+					.by_index_raw(index)
+					.map_err(|err| zip_error(err, archive_path))?;
+				let Some(path) = normalize(file.name()) else {
+					continue;
+				};
+				let modified = file.last_modified();
+				entries.push(Entry {
+					path,
+					is_directory: file.is_dir(),
+					is_symlink: file
+						.unix_mode()
+						.is_some_and(|mode| mode & S_IFMT == S_IFLNK),
+					size: file.size(),
+					date: get_zip_date(&modified),
+					position: Some(index as u64),
+				});
+			}
+		}
+		Some(format) => {
+			let mut archive = open_tar(archive_path, format)?;
+			for entry in archive.entries().with_path(archive_path)? {
+				let entry = entry.with_path(archive_path)?;
+				let header = entry.header();
+				let entry_type = header.entry_type();
+				if entry_type.is_pax_global_extensions() {
+					continue;
+				}
+				let path = entry.path().with_path(archive_path)?;
+				let Some(path) = normalize(&path.to_string_lossy()) else {
+					continue;
+				};
+				entries.push(Entry {
+					path,
+					is_directory: entry_type.is_dir(),
+					is_symlink: entry_type.is_symlink(),
+					size: entry.size(),
+					date: header.mtime().map_or(0, |mtime| mtime as i64 * 1000),
+					position: Some(entry.raw_file_position())
+						.filter(|_| !entry_type.is_gnu_sparse()),
+				});
+			}
+		}
+		None => {}
+	}
+	Ok(entries)
+}
+
+pub fn open_zip(path: &Path) -> Result<ZipArchive<BufReader<File>>, ErrorResponse> {
+	let file = File::open(path).with_path(path)?;
+	ZipArchive::new(BufReader::new(file)).map_err(|err| zip_error(err, path))
+}
+
+fn open_tar(
+	path: &Path,
+	format: ArchiveFormat,
+) -> Result<tar::Archive<Box<dyn Read>>, ErrorResponse> {
+	let file = BufReader::new(File::open(path).with_path(path)?);
+	let reader = decode(file, format).with_path(path)?;
+	Ok(tar::Archive::new(reader))
+}
+
+/// Decompresses a tar archive of the format.
+pub fn decode<'a>(
+	reader: impl BufRead + 'a,
+	format: ArchiveFormat,
+) -> io::Result<Box<dyn Read + 'a>> {
+	Ok(match format {
+		ArchiveFormat::TarGz => Box::new(MultiGzDecoder::new(reader)),
+		ArchiveFormat::TarXz => Box::new(XzDecoder::new(reader)),
+		ArchiveFormat::TarZst => Box::new(zstd::Decoder::with_buffer(reader)?),
+		ArchiveFormat::Tar | ArchiveFormat::Zip => Box::new(reader),
+	})
+}
+
+pub fn zip_error(error: ZipError, path: &Path) -> ErrorResponse {
+	match error {
+		ZipError::Io(err) => ErrorResponse::from(err),
+		ZipError::InvalidArchive(message) => ErrorResponse::new(
+			ErrorCode::InvalidInput,
+			format!("invalid zip archive: {}", message),
+		),
+		ZipError::UnsupportedArchive(message) => {
+			ErrorResponse::new(ErrorCode::Unsupported, message)
+		}
+		ZipError::FileNotFound => {
+			ErrorResponse::new(ErrorCode::NotFound, "entry does not exist in the archive")
+		}
+	}
+	.with_path(path)
+}
+
+/// Normalizes the path of an entry; entries outside the archive root have none.
+pub fn normalize(path: &str) -> Option<String> {
+	let mut parts = Vec::new();
+	for part in path.split(['/', '\\']) {
+		match part {
+			"" | "." => {}
+			".." => return None,
+			part => parts.push(part),
+		}
+	}
+	Some(parts.join("/")).filter(|path| !path.is_empty())
+}
+
+/// Converts the date of a zip entry, which is stored without time zone, taken as UTC.
+pub fn get_zip_date(date: &zip::DateTime) -> i64 {
+	// days since the epoch of the proleptic Gregorian calendar
+	let (year, month, day) = (date.year() as i64, date.month() as i64, date.day() as i64);
+	let year = if month <= 2 { year - 1 } else { year };
+	let era = year.div_euclid(400);
+	let year_of_era = year - era * 400;
+	let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+	let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+	let days = era * 146097 + day_of_era - 719468;
+	let seconds = date.hour() as i64 * 3600 + date.minute() as i64 * 60 + date.second() as i64;
+	(days * 86400 + seconds) * 1000
+}
+
+/// Describes an entry, or a directory implied by the paths of entries.
+fn get_file(name: &str, entry: Option<&Entry>, active_key: &Option<String>) -> FileInfo {
+	let is_directory = entry.is_none_or(|entry| entry.is_directory);
+	let (stem, extension) = match name.rsplit_once('.') {
+		Some((stem, extension)) if !is_directory && !stem.is_empty() => {
+			(stem.to_string(), extension.to_string())
+		}
+		_ => (name.to_string(), String::new()),
+	};
+	let icon = if is_directory { "folder" } else { "file" };
+	FileInfo {
+		key: format!("{}{}", name, if is_directory { "/" } else { "" }),
+		is_directory,
+		icon: icon.to_string(),
+		icon_alt: Some(icon.to_string()),
+		icon_type: "file".to_string(),
+		name: stem,
+		extension,
+		size: entry.map_or(0, |entry| entry.size),
+		date: entry.map_or(0, |entry| entry.date),
+		attributes: "-".to_string(),
+		is_active: is_directory && active_key.as_deref() == Some(name),
+		is_hidden: name != ".." && name.starts_with('.'),
+		is_symlink: entry.is_some_and(|entry| entry.is_symlink),
+		mime_type: if is_directory {
+			None
+		} else {
+			mime_guess::from_path(name).first_raw().map(String::from)
+		},
+		..Default::default()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::testing::TempDir;
+	use flate2::write::GzEncoder;
+	use flate2::Compression;
+	use std::io::Write;
+	use zip::write::FileOptions;
+	use zip::ZipWriter;
+
+	fn write_zip(path: &Path, files: &[(&str, &str)]) {
+		let mut writer = ZipWriter::new(File::create(path).unwrap());
+		for (name, contents) in files {
+			writer.start_file(*name, FileOptions::default()).unwrap();
+			writer.write_all(contents.as_bytes()).unwrap();
+		}
+		writer.finish().unwrap();
+	}
+
+	fn write_tar_gz(path: &Path, files: &[(&str, &str)]) {
+		let encoder = GzEncoder::new(File::create(path).unwrap(), Compression::default());
+		let mut builder = tar::Builder::new(encoder);
+		for (name, contents) in files {
+			let mut header = tar::Header::new_gnu();
+			header.set_size(contents.len() as u64);
+			header.set_mode(0o644);
+			header.set_mtime(1_000);
+			builder
+				.append_data(&mut header, name, contents.as_bytes())
+				.unwrap();
+		}
+		builder.into_inner().unwrap().finish().unwrap();
+	}
+
+	fn list_keys(
+		path: &Path,
+		directory: &str,
+		cursor: Option<&str>,
+	) -> (Vec<String>, Option<String>) {
+		let request = ListRequest {
+			path: None,
+			key: None,
+			cursor: cursor.map(str::to_string),
+			limit: Some(2),
+		};
+		let response = list(path, directory, &request, &None).unwrap();
+		let keys = response.files.into_iter().map(|file| file.key).collect();
+		(keys, response.cursor)
+	}
+
+	#[test]
+	fn normalize_entry_paths() {
+		assert_eq!(normalize("a//b/./c/").as_deref(), Some("a/b/c"));
+		assert_eq!(normalize("a\\b").as_deref(), Some("a/b"));
+		assert_eq!(normalize("a/../b"), None);
+		assert_eq!(normalize("./"), None);
+	}
+
+	#[test]
+	fn split_archive_paths() {
+		let dir = TempDir::new();
+		let path = dir.join("a.zip");
+		write_zip(&path, &[("x/y.txt", "y")]);
+		assert_eq!(split_path(&path), Some((path.clone(), String::new())));
+		assert_eq!(
+			split_path(&path.join("x/y.txt")),
+			Some((path.clone(), "x/y.txt".to_string()))
+		);
+		assert_eq!(split_path(&dir.join("b.zip/x")), None);
+		assert_eq!(split_path(dir.path()), None);
+	}
+
+	#[test]
+	fn list_zip_directories() {
+		let dir = TempDir::new();
+		let path = dir.join("a.zip");
+		write_zip(
+			&path,
+			&[
+				("b.txt", "b"),
+				("d/e.txt", "e"),
+				("c/", ""),
+				("a/f/g.txt", "g"),
+			],
+		);
+		// directories are implied by the paths of their entries, and pages follow
+		let (keys, cursor) = list_keys(&path, "", None);
+		assert_eq!(keys, ["../", "a/", "b.txt"]);
+		let (keys, cursor) = list_keys(&path, "", cursor.as_deref());
+		assert_eq!(keys, ["c/", "d/"]);
+		assert!(cursor.is_none());
+		assert_eq!(list_keys(&path, "a/f", None).0, ["../", "g.txt"]);
+
+		let request = ListRequest {
+			path: None,
+			key: None,
+			cursor: None,
+			limit: None,
+		};
+		let err = list(&path, "b.txt", &request, &None).unwrap_err();
+		assert_eq!(err.code, ErrorCode::NotADirectory);
+		let err = list(&path, "x", &request, &None).unwrap_err();
+		assert_eq!(err.code, ErrorCode::NotFound);
+	}
+
+	#[test]
+	fn read_compressed_entries() {
+		let dir = TempDir::new();
+		let path = dir.join("a.tar.gz");
+		write_tar_gz(&path, &[("x.txt", "first"), ("d/y.txt", "second")]);
+		let response = read(&path, "d/y.txt", Some(1), Some(3)).unwrap();
+		assert_eq!((response.data.as_slice(), response.size), (&b"eco"[..], 6));
+		assert_eq!(read(&path, "x.txt", None, None).unwrap().data, b"first");
+		let err = read(&path, "x.txt", Some(6), None).unwrap_err();
+		assert_eq!(err.code, ErrorCode::OutOfRange);
+		let err = read(&path, "d", None, None).unwrap_err();
+		assert_eq!(err.code, ErrorCode::IsADirectory);
+		let err = read(&path, "z", None, None).unwrap_err();
+		assert_eq!(err.code, ErrorCode::NotFound);
+	}
+
+	#[test]
+	fn read_changed_archives_again() {
+		let dir = TempDir::new();
+		let path = dir.join("a.zip");
+		write_zip(&path, &[("a.txt", "a")]);
+		assert_eq!(list_keys(&path, "", None).0, ["../", "a.txt"]);
+		write_zip(&path, &[("a.txt", "a"), ("b.txt", "b")]);
+		assert_eq!(list_keys(&path, "", None).0, ["../", "a.txt", "b.txt"]);
+		assert_eq!(read(&path, "b.txt", None, None).unwrap().data, b"b");
+	}
+}
@@ -0,0 +1,425 @@
+use crate::operations::get_entry_path;
+use crate::{get_local_file, get_paths};
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::Path;
+use xcmd_base::{
+	Checksum, ChecksumAlgorithm, CreateChecksumFileRequest, ErrorCode, ErrorContext, ErrorResponse,
+	FileInfo, Hasher, Job, JobResult, VerifyChecksumFileRequest,
+};
+
+const BUFFER_SIZE: usize = 1024 * 1024;
+
+/// Writes checksums of the files in the format of `sha256sum` and similar tools; an
+/// existing checksum file is replaced. The created file is the last result.
+pub fn create(request: CreateChecksumFileRequest, job: &Job) -> Result<(), ErrorResponse> {
+	let (path, _) = get_paths(&request.path, &None);
+	let name = match &request.name {
+		Some(name) => name.clone(),
+		None => get_default_name(&request.keys, request.algorithm)?,
+	};
+	let destination = get_entry_path(&path, &name)?;
+
+	// a previous checksum file among the selected files is not listed in the new one
+	let mut sources = Vec::new();
+	for key in &request.keys {
+		let source = get_entry_path(&path, key)?;
+		if source != destination {
+			sources.push((key.trim_end_matches('/'), source));
+		}
+	}
+	set_totals(sources.iter().map(|(_, source)| source.as_path()), job);
+
+	let mut content = String::new();
+	for (key, source) in &sources {
+		let value = hash_file(source, request.algorithm, job)?;
+		content.push_str(&format_line(&value, key));
+		job.add_result(JobResult {
+			file: get_file(source, key),
+			checksums: vec![Checksum {
+				algorithm: request.algorithm,
+				value,
+				expected: None,
+			}],
+			..Default::default()
+		});
+		job.update(|progress| progress.files_done += 1);
+	}
+
+	fs::write(&destination, content).with_path(&destination)?;
+	job.add_result(JobResult {
+		file: get_file(&destination, &name),
+		..Default::default()
+	});
+	Ok(())
+}
+
+/// Computes checksums of the files listed in a checksum file. Results hold both the
+/// computed and the listed checksum; files that cannot be read have an error.
+pub fn verify(request: VerifyChecksumFileRequest, job: &Job) -> Result<(), ErrorResponse> {
+	let (path, _) = get_paths(&request.path, &None);
+	let checksum_path = get_entry_path(&path, &request.key)?;
+	let content = fs::read(&checksum_path).with_path(&checksum_path)?;
+	let content = String::from_utf8_lossy(&content);
+
+	// listed names are relative to the directory of the checksum file
+	let directory = checksum_path.parent().unwrap_or(&path);
+	let prefix = match request.key.trim_end_matches('/').rsplit_once('/') {
+		Some((parent, _)) => format!("{}/", parent),
+		None => String::new(),
+	};
+	let file_name = request.key.rsplit('/').next().unwrap_or_default();
+
+	let mut entries = Vec::new();
+	for (index, line) in content.lines().enumerate() {
+		if let Some((algorithm, name, expected)) = parse_line(line, file_name) {
+			let invalid = || {
+				ErrorResponse::new(
+					ErrorCode::InvalidInput,
+					format!("invalid checksum at line {}", index + 1),
+				)
+				.with_path(&checksum_path)
+			};
+			let algorithm = algorithm.ok_or_else(invalid)?;
+			let source = get_entry_path(directory, &name);
+			entries.push((algorithm, name, expected, source));
+		}
+	}
+	set_totals(
+		entries
+			.iter()
+			.filter_map(|(_, _, _, source)| source.as_deref().ok()),
+		job,
+	);
+
+	for (algorithm, name, expected, source) in entries {
+		let key = format!("{}{}", prefix, name);
+		let result = source.and_then(|source| Ok((hash_file(&source, algorithm, job)?, source)));
+		let result = match result {
+			Ok((value, source)) => JobResult {
+				file: get_file(&source, &key),
+				checksums: vec![Checksum {
+					algorithm,
+					value,
+					expected: Some(expected),
+				}],
+				..Default::default()
+			},
+			Err(err) if err.code == ErrorCode::Cancelled => return Err(err),
+			Err(err) => JobResult {
+				file: FileInfo {
+					name: key.clone(),
+					key,
+					..Default::default()
+				},
+				checksums: vec![Checksum {
+					algorithm,
+					value: String::new(),
+					expected: Some(expected),
+				}],
+				error: Some(err),
+				..Default::default()
+			},
+		};
+		job.add_result(result);
+		job.update(|progress| progress.files_done += 1);
+	}
+	Ok(())
+}
+
+fn hash_file(
+	path: &Path,
+	algorithm: ChecksumAlgorithm,
+	job: &Job,
+) -> Result<String, ErrorResponse> {
+	job.checkpoint()?;
+	job.update(|progress| progress.current = Some(path.to_string_lossy().to_string()));
+	if path.is_dir() {
+		return Err(ErrorResponse::new(
+			ErrorCode::IsADirectory,
+			"cannot compute checksum of a directory",
+		)
+		.with_path(path));
+	}
+
+	let mut reader = File::open(path).with_path(path)?;
+	let mut hasher = Hasher::new(algorithm);
+	let mut buffer = vec![0; BUFFER_SIZE];
+	loop {
+		job.checkpoint()?;
+		let count = reader.read(&mut buffer).with_path(path)?;
+		if count == 0 {
+			break;
+		}
+		hasher.update(&buffer[..count]);
+		job.update(|progress| progress.bytes_done += count as u64);
+	}
+	Ok(hasher.finish())
+}
+
+/// Sets the totals of the progress; sizes of files that cannot be read are not known.
+fn set_totals<'a>(paths: impl Iterator<Item = &'a Path>, job: &Job) {
+	let (mut files, mut bytes) = (0, 0);
+	for path in paths {
+		files += 1;
+		bytes += fs::metadata(path)
+			.map(|metadata| metadata.len())
+			.unwrap_or(0);
+	}
+	job.update(|progress| {
+		progress.files_total = files;
+		progress.bytes_total = bytes;
+	});
+}
+
+fn get_file(path: &Path, key: &str) -> FileInfo {
+	let mut file = get_local_file(path, None, &None);
+	file.key = key.to_string();
+	file
+}
+
+/// Names the checksum file after the file, or after the algorithm for several files.
+fn get_default_name(
+	keys: &[String],
+	algorithm: ChecksumAlgorithm,
+) -> Result<String, ErrorResponse> {
+	let (extension, list_name) = match algorithm {
+		ChecksumAlgorithm::Sha256 => ("sha256", "SHA256SUMS"),
+		ChecksumAlgorithm::Sha1 => ("sha1", "SHA1SUMS"),
+		ChecksumAlgorithm::Md5 => ("md5", "MD5SUMS"),
+		ChecksumAlgorithm::Crc32 => ("crc32", "CRC32SUMS"),
+		ChecksumAlgorithm::Blake3 => ("b3", "B3SUMS"),
+	};
+	match keys {
+		[] => Err(ErrorResponse::new(
+			ErrorCode::InvalidInput,
+			"no files are selected",
+		)),
+		[key] => {
+			let name = key
+				.trim_end_matches('/')
+				.rsplit('/')
+				.next()
+				.unwrap_or_default();
+			Ok(format!("{}.{}", name, extension))
+		}
+		_ => Ok(list_name.to_string()),
+	}
+}
+
+/// Formats a line like `sha256sum`; names with line breaks or backslashes are escaped
+/// and the line is marked with a leading backslash.
+fn format_line(value: &str, name: &str) -> String {
+	if name.contains(['\\', '\n', '\r']) {
+		let name = name
+			.replace('\\', "\\\\")
+			.replace('\n', "\\n")
+			.replace('\r', "\\r");
+		format!("\\{}  {}\n", value, name)
+	} else {
+		format!("{}  {}\n", value, name)
+	}
+}
+
+/// Parses a line in the format of `sha256sum` (`<checksum>  <name>`, or `<checksum> *<name>`
+/// for binary mode) or in the BSD format (`SHA256 (<name>) = <checksum>`). Blank lines and
+/// comments are skipped. The algorithm is `None` if it cannot be determined.
+fn parse_line(line: &str, file_name: &str) -> Option<(Option<ChecksumAlgorithm>, String, String)> {
+	let line = line.trim_end_matches('\r');
+	if line.trim().is_empty() || line.starts_with('#') {
+		return None;
+	}
+	let (line, is_escaped) = match line.strip_prefix('\\') {
+		Some(line) => (line, true),
+		None => (line, false),
+	};
+
+	// names in the other format are separated by spaces, so tags are single words
+	let tagged = line
+		.split_once(" (")
+		.filter(|(tag, _)| !tag.is_empty() && !tag.contains(' '));
+	let (algorithm, name, value) = if let Some((tag, rest)) = tagged {
+		let (name, value) = rest.rsplit_once(") = ")?;
+		(get_tagged_algorithm(tag), name, value)
+	} else {
+		let (value, name) = line.split_once(' ')?;
+		let name = name.strip_prefix([' ', '*']).unwrap_or(name);
+		let algorithm = get_named_algorithm(file_name).or(get_sized_algorithm(value.len()));
+		(algorithm, name, value)
+	};
+
+	let name = name.strip_prefix("./").unwrap_or(name);
+	let name = if is_escaped {
+		unescape(name)
+	} else {
+		name.to_string()
+	};
+	let is_valid = !value.is_empty() && value.bytes().all(|byte| byte.is_ascii_hexdigit());
+	Some((
+		algorithm.filter(|_| is_valid),
+		name,
+		value.to_ascii_lowercase(),
+	))
+}
+
+fn unescape(name: &str) -> String {
+	let mut result = String::with_capacity(name.len());
+	let mut chars = name.chars();
+	while let Some(c) = chars.next() {
+		if c != '\\' {
+			result.push(c);
+			continue;
+		}
+		match chars.next() {
+			Some('n') => result.push('\n'),
+			Some('r') => result.push('\r'),
+			Some(c) => result.push(c),
+			None => result.push('\\'),
+		}
+	}
+	result
+}
+
+fn get_tagged_algorithm(tag: &str) -> Option<ChecksumAlgorithm> {
+	match tag.to_ascii_uppercase().as_str() {
+		"SHA256" => Some(ChecksumAlgorithm::Sha256),
+		"SHA1" => Some(ChecksumAlgorithm::Sha1),
+		"MD5" => Some(ChecksumAlgorithm::Md5),
+		"CRC32" => Some(ChecksumAlgorithm::Crc32),
+		"BLAKE3" => Some(ChecksumAlgorithm::Blake3),
+		_ => None,
+	}
+}
+
+/// Recognizes names like `SHA256SUMS`, `file.sha256` or `B3SUMS`.
+fn get_named_algorithm(file_name: &str) -> Option<ChecksumAlgorithm> {
+	let name = file_name.to_ascii_lowercase();
+	if name.contains("sha256") {
+		Some(ChecksumAlgorithm::Sha256)
+	} else if name.contains("sha1") {
+		Some(ChecksumAlgorithm::Sha1)
+	} else if name.contains("md5") {
+		Some(ChecksumAlgorithm::Md5)
+	} else if name.contains("crc32") {
+		Some(ChecksumAlgorithm::Crc32)
+	} else if name.contains("blake3") || name.starts_with("b3sums") || name.ends_with(".b3") {
+		Some(ChecksumAlgorithm::Blake3)
+	} else {
+		None
+	}
+}
+
+/// Guesses the algorithm from the number of digits; 64 digits are taken as SHA-256.
+fn get_sized_algorithm(digits: usize) -> Option<ChecksumAlgorithm> {
+	match digits {
+		64 => Some(ChecksumAlgorithm::Sha256),
+		40 => Some(ChecksumAlgorithm::Sha1),
+		32 => Some(ChecksumAlgorithm::Md5),
+		8 => Some(ChecksumAlgorithm::Crc32),
+		_ => None,
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const SHA256: &str = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+	const MD5: &str = "d41d8cd98f00b204e9800998ecf8427e";
+
+	fn parse(line: &str) -> Option<(Option<ChecksumAlgorithm>, String, String)> {
+		parse_line(line, "CHECKSUMS")
+	}
+
+	#[test]
+	fn parse_line_gnu() {
+		assert_eq!(
+			parse(&format!("{}  file name.txt", SHA256)),
+			Some((
+				Some(ChecksumAlgorithm::Sha256),
+				"file name.txt".to_string(),
+				SHA256.to_string()
+			))
+		);
+		// binary mode, relative names and line breaks of windows
+		assert_eq!(
+			parse(&format!("{} *./dir/file.bin\r", MD5)),
+			Some((
+				Some(ChecksumAlgorithm::Md5),
+				"dir/file.bin".to_string(),
+				MD5.to_string()
+			))
+		);
+	}
+
+	#[test]
+	fn parse_line_bsd() {
+		assert_eq!(
+			parse(&format!("MD5 (a (1)) = {}", MD5)),
+			Some((
+				Some(ChecksumAlgorithm::Md5),
+				"a (1)".to_string(),
+				MD5.to_string()
+			))
+		);
+		assert_eq!(
+			parse(&format!("sha256 (x) = {}", SHA256.to_ascii_uppercase())),
+			Some((
+				Some(ChecksumAlgorithm::Sha256),
+				"x".to_string(),
+				SHA256.to_string()
+			))
+		);
+		assert_eq!(
+			parse("WHIRLPOOL (x) = 00"),
+			Some((None, "x".to_string(), "00".to_string()))
+		);
+	}
+
+	#[test]
+	fn parse_line_escaped() {
+		let line = format_line(MD5, "a\\b\nc");
+		assert_eq!(line, format!("\\{}  a\\\\b\\nc\n", MD5));
+		assert_eq!(
+			parse(line.trim_end_matches('\n')),
+			Some((
+				Some(ChecksumAlgorithm::Md5),
+				"a\\b\nc".to_string(),
+				MD5.to_string()
+			))
+		);
+		assert_eq!(
+			parse(&format!("\\MD5 (a\\nb) = {}", MD5)),
+			Some((
+				Some(ChecksumAlgorithm::Md5),
+				"a\nb".to_string(),
+				MD5.to_string()
+			))
+		);
+	}
+
+	#[test]
+	fn parse_line_algorithms() {
+		// the name of the checksum file wins over the number of digits
+		assert_eq!(
+			parse_line(&format!("{}  x", MD5), "file.sha1").map(|(algorithm, ..)| algorithm),
+			Some(Some(ChecksumAlgorithm::Sha1))
+		);
+		assert_eq!(
+			parse("12345678  x").map(|(algorithm, ..)| algorithm),
+			Some(Some(ChecksumAlgorithm::Crc32))
+		);
+		assert_eq!(parse("123  x").map(|(algorithm, ..)| algorithm), Some(None));
+		assert_eq!(
+			parse(&format!("{}  x", MD5.replace('d', "g"))).map(|(algorithm, ..)| algorithm),
+			Some(None)
+		);
+	}
+
+	#[test]
+	fn parse_line_skips_comments() {
+		assert_eq!(parse("# comment"), None);
+		assert_eq!(parse("  "), None);
+		assert_eq!(parse("no-separator"), None);
+	}
+}
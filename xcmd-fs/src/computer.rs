@@ -0,0 +1,307 @@
+use crate::{get_local_file, split_virtual_path};
+use std::path::{Component, Path, PathBuf};
+use urlencoding::{decode_binary, encode};
+use xcmd_base::{get_listing_page, ErrorCode, ErrorResponse, FileInfo, ListRequest, ListResponse};
+
+/// Path of the computer root, which lists mounts, the home directory and user
+/// directories. Keys of its entries are their escaped paths, so listing an entry
+/// lists the directory on the file system.
+pub const COMPUTER_PATH: &str = "computer:";
+
+/// File systems that do not hold files of the user.
+#[cfg(not(target_os = "windows"))]
+const VIRTUAL_FILE_SYSTEMS: &[&str] = &[
+	"autofs",
+	"binfmt_misc",
+	"bpf",
+	"cgroup",
+	"cgroup2",
+	"configfs",
+	"debugfs",
+	"devpts",
+	"devtmpfs",
+	"efivarfs",
+	"fusectl",
+	"hugetlbfs",
+	"mqueue",
+	"nsfs",
+	"proc",
+	"pstore",
+	"rpc_pipefs",
+	"securityfs",
+	"selinuxfs",
+	"sysfs",
+	"tracefs",
+];
+
+/// Directories holding mounts of the system rather than of the user.
+#[cfg(not(target_os = "windows"))]
+const SYSTEM_DIRS: &[&str] = &["/dev", "/proc", "/run", "/snap", "/sys", "/var/lib"];
+
+pub struct Mount {
+	pub path: PathBuf,
+	/// Type of the file system, such as `ext4`.
+	pub file_system: Option<String>,
+}
+
+/// Space of a file system in bytes.
+pub struct Space {
+	pub total: u64,
+	/// Space available to the user, which excludes space reserved for the system.
+	pub free: u64,
+	pub used: u64,
+}
+
+/// Gets the path within the computer root if the path is in it, empty for the root
+/// itself.
+pub fn split_path(path: &Path) -> Option<PathBuf> {
+	split_virtual_path(path, COMPUTER_PATH)
+}
+
+/// Gets the path on the file system of a path within the computer root.
+pub fn resolve(relative: &Path) -> Result<PathBuf, ErrorResponse> {
+	let mut components = relative.components();
+	match components.next() {
+		Some(Component::Normal(key)) => {
+			let key = key.to_string_lossy();
+			let path = String::from_utf8_lossy(&decode_binary(key.as_bytes())).into_owned();
+			Ok(Path::new(&path).join(components.as_path()))
+		}
+		_ => Err(ErrorResponse::new(
+			ErrorCode::InvalidInput,
+			"path is the computer root itself",
+		)),
+	}
+}
+
+/// Lists the home directory, user directories and mounts.
+pub fn list(request: &ListRequest) -> Result<ListResponse, ErrorResponse> {
+	let mounts = get_mounts();
+
+	let mut entries = Vec::new();
+	let home = dirs::home_dir();
+	if let Some(home) = &home {
+		entries.push((home.clone(), "Home".to_string()));
+	}
+	let user_dirs = [
+		dirs::desktop_dir(),
+		dirs::document_dir(),
+		dirs::download_dir(),
+		dirs::audio_dir(),
+		dirs::picture_dir(),
+		dirs::video_dir(),
+	];
+	// user directories that are not configured fall back to the home directory
+	for dir in user_dirs.into_iter().flatten() {
+		if Some(&dir) != home.as_ref() && dir.is_dir() {
+			let name = dir
+				.file_name()
+				.map(|name| name.to_string_lossy().to_string())
+				.unwrap_or_default();
+			entries.push((dir, name));
+		}
+	}
+	for mount in mounts.iter().filter(|mount| is_visible(mount)) {
+		// mounts over the same path are listed once
+		if entries.iter().all(|(path, _)| path != &mount.path) {
+			entries.push((mount.path.clone(), mount.path.to_string_lossy().to_string()));
+		}
+	}
+
+	let page = get_listing_page(Path::new(COMPUTER_PATH), request, || Ok(entries))?;
+	let files = page
+		.entries
+		.iter()
+		.map(|(path, name)| get_entry_file(path, name, &mounts))
+		.collect();
+	Ok(ListResponse {
+		path: COMPUTER_PATH.to_string(),
+		name: "Computer".to_string(),
+		files,
+		cursor: page.cursor,
+		free_space: None,
+	})
+}
+
+fn get_entry_file(path: &Path, name: &str, mounts: &[Mount]) -> FileInfo {
+	let mut file = get_local_file(path, Some(name.to_string()), &None);
+	file.key = format!("{}/", encode(&path.to_string_lossy()));
+	file.is_hidden = false;
+	// the entry is on the file system of the innermost mount containing it
+	file.file_system = mounts
+		.iter()
+		.filter(|mount| path.starts_with(&mount.path))
+		.max_by_key(|mount| mount.path.components().count())
+		.and_then(|mount| mount.file_system.clone());
+	if let Some(space) = get_space(path) {
+		file.total_space = Some(space.total);
+		file.free_space = Some(space.free);
+		file.used_space = Some(space.used);
+	}
+	file
+}
+
+#[cfg(not(target_os = "windows"))]
+fn is_visible(mount: &Mount) -> bool {
+	let is_virtual = mount
+		.file_system
+		.as_deref()
+		.is_some_and(|file_system| VIRTUAL_FILE_SYSTEMS.contains(&file_system));
+	// removable media are mounted in `/run/media` by some systems
+	let is_system = SYSTEM_DIRS.iter().any(|dir| mount.path.starts_with(dir))
+		&& !mount.path.starts_with("/run/media");
+	!is_virtual && !is_system && mount.path.is_dir()
+}
+
+#[cfg(target_os = "windows")]
+fn is_visible(_mount: &Mount) -> bool {
+	true
+}
+
+/// Gets the mounts listed in `/proc/self/mountinfo`, in the order they were mounted.
+#[cfg(not(target_os = "windows"))]
+pub fn get_mounts() -> Vec<Mount> {
+	let Ok(content) = std::fs::read_to_string("/proc/self/mountinfo") else {
+		return Vec::new();
+	};
+	content
+		.lines()
+		.filter_map(|line| {
+			let mut fields = line.split(' ');
+			let path = fields.nth(4)?;
+			// optional fields end with a separator, which the file system type follows
+			let file_system = fields.skip_while(|field| *field != "-").nth(1)?;
+			Some(Mount {
+				path: unescape_mount_path(path),
+				file_system: Some(file_system.to_string()),
+			})
+		})
+		.collect()
+}
+
+/// Gets the roots of the drives.
+#[cfg(target_os = "windows")]
+pub fn get_mounts() -> Vec<Mount> {
+	('A'..='Z')
+		.map(|letter| PathBuf::from(format!("{}:\\", letter)))
+		.filter(|path| path.is_dir())
+		.map(|path| Mount {
+			path,
+			file_system: None,
+		})
+		.collect()
+}
+
+/// Replaces octal escapes such as `\040` for spaces in mount paths.
+#[cfg(not(target_os = "windows"))]
+fn unescape_mount_path(path: &str) -> PathBuf {
+	use std::ffi::OsString;
+	use std::os::unix::ffi::OsStringExt;
+
+	let bytes = path.as_bytes();
+	let mut unescaped = Vec::with_capacity(bytes.len());
+	let mut index = 0;
+	while index < bytes.len() {
+		let code = bytes
+			.get(index + 1..index + 4)
+			.filter(|_| bytes[index] == b'\\')
+			.and_then(|digits| u8::from_str_radix(std::str::from_utf8(digits).ok()?, 8).ok());
+		match code {
+			Some(code) => {
+				unescaped.push(code);
+				index += 4;
+			}
+			None => {
+				unescaped.push(bytes[index]);
+				index += 1;
+			}
+		}
+	}
+	PathBuf::from(OsString::from_vec(unescaped))
+}
+
+/// Gets the space of the file system containing the path.
+#[cfg(not(target_os = "windows"))]
+pub fn get_space(path: &Path) -> Option<Space> {
+	use std::ffi::CString;
+	use std::mem::MaybeUninit;
+	use std::os::unix::ffi::OsStrExt;
+
+	let path = CString::new(path.as_os_str().as_bytes()).ok()?;
+	let mut stat = MaybeUninit::<libc::statvfs>::uninit();
+	// SAFETY: the path is terminated and the structure is written by a successful call
+	let stat = unsafe {
+		if libc::statvfs(path.as_ptr(), stat.as_mut_ptr()) != 0 {
+			return None;
+		}
+		stat.assume_init()
+	};
+	let unit = stat.f_frsize as u64;
+	Some(Space {
+		total: stat.f_blocks as u64 * unit,
+		free: stat.f_bavail as u64 * unit,
+		used: stat.f_blocks.saturating_sub(stat.f_bfree) as u64 * unit,
+	})
+}
+
+#[cfg(target_os = "windows")]
+pub fn get_space(_path: &Path) -> Option<Space> {
+	None
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn resolve_computer_paths() {
+		let key = encode("/home/a b").to_string();
+		let path = Path::new(COMPUTER_PATH).join(&key).join("c");
+		let relative = split_path(&path).unwrap();
+		assert_eq!(resolve(&relative).unwrap(), Path::new("/home/a b/c"));
+		assert_eq!(split_path(Path::new(COMPUTER_PATH)), Some(PathBuf::new()));
+		assert_eq!(split_path(Path::new("/home")), None);
+		let err = resolve(Path::new("")).unwrap_err();
+		assert_eq!(err.code, ErrorCode::InvalidInput);
+	}
+
+	#[test]
+	fn list_computer_root() {
+		let request = ListRequest {
+			path: Some(COMPUTER_PATH.to_string()),
+			key: None,
+			cursor: None,
+			limit: None,
+		};
+		let response = list(&request).unwrap();
+		assert_eq!(response.path, COMPUTER_PATH);
+		assert!(!response.files.is_empty());
+		// keys of entries are the escaped paths of directories
+		for file in &response.files {
+			let key = file.key.strip_suffix('/').unwrap();
+			let path = resolve(Path::new(key)).unwrap();
+			assert!(path.is_dir() && file.is_directory, "{}", path.display());
+			assert!(file.total_space.is_some());
+		}
+	}
+
+	#[cfg(not(target_os = "windows"))]
+	#[test]
+	fn list_visible_mounts() {
+		assert_eq!(
+			unescape_mount_path(r"/media/a\040b\\"),
+			Path::new(r"/media/a b\\")
+		);
+		let mount = |path: &str, file_system: &str| Mount {
+			path: PathBuf::from(path),
+			file_system: Some(file_system.to_string()),
+		};
+		assert!(is_visible(&mount("/", "ext4")));
+		assert!(!is_visible(&mount("/", "proc")));
+		assert!(!is_visible(&mount("/sys/fs/cgroup", "ext4")));
+		// the root of the file system is always mounted
+		assert!(get_mounts()
+			.iter()
+			.any(|mount| mount.path == Path::new("/")));
+	}
+}
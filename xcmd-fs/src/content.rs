@@ -0,0 +1,229 @@
+use chardetng::EncodingDetector;
+use encoding_rs::Encoding;
+use encoding_rs_io::DecodeReaderBytesBuilder;
+use regex::{Regex, RegexBuilder};
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Seek};
+use std::path::Path;
+use xcmd_base::{ContentPattern, ErrorCode, ErrorResponse, Job, TextMatch};
+
+/// Size of the beginning of a file used to detect its encoding.
+const SAMPLE_SIZE: usize = 8 * 1024;
+
+/// Number of lines read between checks for cancellation.
+const CANCEL_CHECK_LINES: usize = 1024;
+
+pub struct ContentMatcher {
+	regex: Regex,
+	context: usize,
+	include_binary: bool,
+}
+
+impl ContentMatcher {
+	pub fn new(content: &ContentPattern) -> Result<Self, ErrorResponse> {
+		let pattern = if content.is_regex {
+			content.pattern.clone()
+		} else {
+			regex::escape(&content.pattern)
+		};
+		let regex = RegexBuilder::new(&pattern)
+			.case_insensitive(!content.case_sensitive)
+			.build()
+			.map_err(|err| {
+				ErrorResponse::new(
+					ErrorCode::InvalidInput,
+					format!("invalid pattern '{}': {}", content.pattern, err),
+				)
+			})?;
+		Ok(ContentMatcher {
+			regex,
+			context: content.context,
+			include_binary: content.include_binary,
+		})
+	}
+
+	/// Finds the matching lines of a file; binary files have no matches unless
+	/// these are included. Reading stops early when the job is cancelled.
+	pub fn find(&self, path: &Path, job: &Job) -> io::Result<Vec<TextMatch>> {
+		let mut file = File::open(path)?;
+		let size = file.metadata()?.len();
+
+		let mut sample = Vec::with_capacity(SAMPLE_SIZE);
+		(&mut file)
+			.take(SAMPLE_SIZE as u64)
+			.read_to_end(&mut sample)?;
+		let Some(encoding) = detect_encoding(&sample, self.include_binary) else {
+			return Ok(Vec::new());
+		};
+		file.rewind()?;
+
+		// decodes the contents, a byte order mark takes precedence over the detected encoding
+		let reader = DecodeReaderBytesBuilder::new()
+			.encoding(Some(encoding))
+			.bom_override(true)
+			.build(file);
+
+		let mut matches = Vec::<TextMatch>::new();
+		let mut before = VecDeque::<String>::with_capacity(self.context + 1);
+		for (index, line) in BufReader::new(reader).lines().enumerate() {
+			if index % CANCEL_CHECK_LINES == 0 && job.is_cancelled() {
+				break;
+			}
+			let line = line?;
+			let line_number = index as u64 + 1;
+			let is_match = self.regex.is_match(&line);
+			let line = TextMatch::shorten(line);
+
+			// appends the line to the trailing context of preceding matches
+			for text_match in matches.iter_mut().rev().take_while(|text_match| {
+				text_match.line_number + self.context as u64 >= line_number
+			}) {
+				text_match.after.push(line.clone());
+			}
+
+			if is_match {
+				matches.push(TextMatch {
+					line_number,
+					line: line.clone(),
+					before: before.iter().cloned().collect(),
+					after: Vec::new(),
+				});
+			}
+
+			if self.context > 0 {
+				before.push_back(line);
+				if before.len() > self.context {
+					before.pop_front();
+				}
+			}
+		}
+
+		job.update(|progress| progress.bytes_done += size);
+		Ok(matches)
+	}
+}
+
+/// Detects the encoding from a byte order mark or the beginning of the contents.
+/// Contents with null bytes and without a byte order mark are binary.
+fn detect_encoding(sample: &[u8], include_binary: bool) -> Option<&'static Encoding> {
+	if let Some((encoding, _)) = Encoding::for_bom(sample) {
+		return Some(encoding);
+	}
+	if sample.contains(&0) && !include_binary {
+		return None;
+	}
+	let mut detector = EncodingDetector::new();
+	detector.feed(sample, sample.len() < SAMPLE_SIZE);
+	Some(detector.guess(None, true))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::testing::TempDir;
+	use std::fs;
+	use xcmd_base::JobKind;
+
+	fn find_lines(path: &Path, content: ContentPattern) -> Vec<(u64, String)> {
+		let job = Job::new(0, JobKind::Search);
+		let matcher = ContentMatcher::new(&content).unwrap();
+		let matches = matcher.find(path, &job).unwrap();
+		matches
+			.into_iter()
+			.map(|text_match| (text_match.line_number, text_match.line))
+			.collect()
+	}
+
+	fn pattern(pattern: &str) -> ContentPattern {
+		ContentPattern {
+			pattern: pattern.to_string(),
+			is_regex: false,
+			case_sensitive: false,
+			context: 0,
+			include_binary: false,
+		}
+	}
+
+	#[test]
+	fn find_matching_lines() {
+		let dir = TempDir::new();
+		let path = dir.write("a", "one\nTwo (2)\nthree\ntwo\n");
+		let lines = find_lines(&path, pattern("two"));
+		assert_eq!(lines, [(2, "Two (2)".to_string()), (4, "two".to_string())]);
+		let case_sensitive = ContentPattern {
+			case_sensitive: true,
+			..pattern("two")
+		};
+		assert_eq!(find_lines(&path, case_sensitive), [(4, "two".to_string())]);
+		// literal patterns are not regular expressions
+		assert_eq!(find_lines(&path, pattern("(2)")).len(), 1);
+		let regex = ContentPattern {
+			is_regex: true,
+			..pattern("^t")
+		};
+		assert_eq!(find_lines(&path, regex).len(), 3);
+		let err = ContentMatcher::new(&ContentPattern {
+			is_regex: true,
+			..pattern("(")
+		});
+		assert_eq!(err.err().unwrap().code, ErrorCode::InvalidInput);
+	}
+
+	#[test]
+	fn find_lines_with_context() {
+		let dir = TempDir::new();
+		let path = dir.write("a", "1\n2 x\n3\n4 x\n5\n6\n");
+		let job = Job::new(0, JobKind::Search);
+		let content = ContentPattern {
+			context: 1,
+			..pattern("x")
+		};
+		let matches = ContentMatcher::new(&content)
+			.unwrap()
+			.find(&path, &job)
+			.unwrap();
+		let context = matches
+			.iter()
+			.map(|text_match| (text_match.before.clone(), text_match.after.clone()))
+			.collect::<Vec<_>>();
+		// context lines may be matching lines too
+		assert_eq!(
+			context,
+			[
+				(vec!["1".to_string()], vec!["3".to_string()]),
+				(vec!["3".to_string()], vec!["5".to_string()]),
+			]
+		);
+		assert_eq!(job.status().progress.bytes_done, 16);
+	}
+
+	#[test]
+	fn find_in_encoded_and_binary_files() {
+		let dir = TempDir::new();
+		let mut utf16 = vec![0xff, 0xfe];
+		utf16.extend("héllo\n".encode_utf16().flat_map(|unit| unit.to_le_bytes()));
+		let path = dir.join("utf16");
+		fs::write(&path, utf16).unwrap();
+		assert_eq!(find_lines(&path, pattern("héllo")).len(), 1);
+
+		let path = dir.join("binary");
+		fs::write(&path, b"\0\x01match\n").unwrap();
+		assert!(find_lines(&path, pattern("match")).is_empty());
+		let include_binary = ContentPattern {
+			include_binary: true,
+			..pattern("match")
+		};
+		assert_eq!(find_lines(&path, include_binary).len(), 1);
+	}
+
+	#[test]
+	fn shorten_long_lines() {
+		let dir = TempDir::new();
+		let line = "é".repeat(600);
+		let path = dir.write("a", &line);
+		let lines = find_lines(&path, pattern("é"));
+		assert_eq!(lines[0].1, format!("{}…", "é".repeat(500)));
+		assert_eq!(TextMatch::shorten("short".to_string()), "short");
+	}
+}
@@ -0,0 +1,755 @@
+use crate::operations::{copy_entry, copy_link, remove_entry};
+use crate::{get_date, get_local_file, get_system_time};
+use serde_derive::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs::{self, Metadata, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, MutexGuard};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::trace;
+use xcmd_base::{ErrorCode, ErrorContext, ErrorResponse, Job, JobResult, UndoRequest};
+
+/// Number of operations that can be undone; entries trashed by older operations are
+/// deleted.
+const MAX_OPERATIONS: usize = 100;
+
+/// Guards the journal file; operations of different jobs are recorded concurrently.
+static LOCK: Mutex<()> = Mutex::new(());
+
+/// Last operation id, so ids stay unique when operations start at the same time.
+static LAST_ID: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum OperationKind {
+	Create,
+	Write,
+	Copy,
+	Rename,
+	Delete,
+}
+
+/// Operation that changed files, with the changes needed for reverting it.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Operation {
+	/// Unique id, also the name of the directory holding its trashed entries.
+	id: u64,
+	kind: OperationKind,
+	/// Date the operation was completed in milliseconds since the epoch.
+	completed: i64,
+	changes: Vec<Change>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+enum Change {
+	/// Entry created by the operation; reverted by deleting it.
+	Created { path: PathBuf, stamp: Option<Stamp> },
+	/// Entry moved by the operation; reverted by moving it back.
+	Moved {
+		from: PathBuf,
+		to: PathBuf,
+		stamp: Option<Stamp>,
+	},
+	/// Entry deleted or replaced by the operation, kept in the trash of the journal
+	/// or in the trash of the desktop with an info file.
+	Trashed {
+		path: PathBuf,
+		trash: PathBuf,
+		info: Option<PathBuf>,
+	},
+	/// File appended to by the operation; reverted by truncating it.
+	Appended {
+		path: PathBuf,
+		size: u64,
+		date: i64,
+		stamp: Option<Stamp>,
+	},
+	/// Entry deleted for good, which cannot be restored.
+	Deleted { path: PathBuf },
+}
+
+/// State of an entry after the operation, so later changes are noticed.
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Stamp {
+	size: u64,
+	date: i64,
+	is_directory: bool,
+}
+
+impl Stamp {
+	fn get(path: &Path) -> Option<Stamp> {
+		fs::symlink_metadata(path)
+			.ok()
+			.map(|metadata| Stamp::of(&metadata))
+	}
+
+	fn of(metadata: &Metadata) -> Stamp {
+		// directories change with their contents, which are checked on their own
+		if metadata.is_dir() {
+			Stamp {
+				size: 0,
+				date: 0,
+				is_directory: true,
+			}
+		} else {
+			Stamp {
+				size: metadata.len(),
+				date: get_date(metadata),
+				is_directory: false,
+			}
+		}
+	}
+}
+
+/// Collects the changes of an operation; they are added to the journal when the
+/// recorder is dropped, so operations failing halfway are recorded as well.
+pub struct Recorder {
+	operation: Option<Operation>,
+	/// State of the appended file before the change, for continuing the operation
+	/// that wrote the file before.
+	previous: Option<Stamp>,
+}
+
+impl Recorder {
+	pub fn new(kind: OperationKind) -> Self {
+		let now = SystemTime::now()
+			.duration_since(UNIX_EPOCH)
+			.map(|duration| duration.as_nanos() as u64)
+			.unwrap_or_default();
+		let next = |last: u64| now.max(last + 1);
+		let id = LAST_ID
+			.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |last| {
+				Some(next(last))
+			})
+			.map_or(now, next);
+		Recorder {
+			operation: Some(Operation {
+				id,
+				kind,
+				completed: 0,
+				changes: Vec::new(),
+			}),
+			previous: None,
+		}
+	}
+
+	/// Creates a recorder that records nothing, for changes that are part of another
+	/// recorded change.
+	pub fn disabled() -> Self {
+		Recorder {
+			operation: None,
+			previous: None,
+		}
+	}
+
+	/// Creates a recorder of appending to the file, which creates it if it is missing.
+	/// Appending to a file left as the last write operation left it continues that
+	/// operation, so a file written in chunks is reverted at once.
+	pub fn append(path: &Path) -> Self {
+		let mut recorder = Recorder::new(OperationKind::Write);
+		match fs::symlink_metadata(path) {
+			Ok(metadata) => {
+				recorder.add(|| Change::Appended {
+					path: path.to_path_buf(),
+					size: metadata.len(),
+					date: get_date(&metadata),
+					stamp: None,
+				});
+				recorder.previous = Some(Stamp::of(&metadata));
+			}
+			Err(_) => recorder.created(path),
+		}
+		recorder
+	}
+
+	pub fn created(&mut self, path: &Path) {
+		self.add(|| Change::Created {
+			path: path.to_path_buf(),
+			stamp: None,
+		});
+	}
+
+	pub fn moved(&mut self, from: &Path, to: &Path) {
+		self.add(|| Change::Moved {
+			from: from.to_path_buf(),
+			to: to.to_path_buf(),
+			stamp: None,
+		});
+	}
+
+	/// Moves the file or link to the trash of the journal, or copies it there when the
+	/// trash is on another file system, leaving it for the caller to replace. Returns
+	/// false if it could not be kept, as there is no trash.
+	pub fn trash(&mut self, path: &Path) -> io::Result<bool> {
+		let Some(trash) = self.get_trash_path() else {
+			self.deleted(path);
+			return Ok(false);
+		};
+		match fs::rename(path, &trash) {
+			Ok(()) => {}
+			Err(err) if err.kind() == io::ErrorKind::CrossesDevices => {
+				if fs::symlink_metadata(path)?.file_type().is_symlink() {
+					copy_link(path, &trash)?;
+				} else {
+					fs::copy(path, &trash)?;
+				}
+			}
+			Err(err) => return Err(err),
+		}
+		self.add(|| Change::Trashed {
+			path: path.to_path_buf(),
+			trash,
+			info: None,
+		});
+		Ok(true)
+	}
+
+	/// Keeps the contents of a file that is about to be replaced, as a hard link in
+	/// the trash or as a copy if linking is not possible. The file has to be replaced
+	/// by moving another one over it, as writing it in place changes the link as well.
+	pub fn keep(&mut self, path: &Path) {
+		let Some(trash) = self.get_trash_path() else {
+			return;
+		};
+		let kept = fs::hard_link(path, &trash).or_else(|_| fs::copy(path, &trash).map(|_| ()));
+		match kept {
+			Ok(()) => self.add(|| Change::Trashed {
+				path: path.to_path_buf(),
+				trash,
+				info: None,
+			}),
+			Err(err) => {
+				trace!("journal cannot keep {:?}: {}", path, err);
+				self.deleted(path);
+			}
+		}
+	}
+
+	/// Records moving the entry to the trash of the desktop.
+	pub fn trashed(&mut self, path: &Path, trash: &Path, info: &Path) {
+		self.add(|| Change::Trashed {
+			path: path.to_path_buf(),
+			trash: trash.to_path_buf(),
+			info: Some(info.to_path_buf()),
+		});
+	}
+
+	pub fn deleted(&mut self, path: &Path) {
+		self.add(|| Change::Deleted {
+			path: path.to_path_buf(),
+		});
+	}
+
+	fn add(&mut self, change: impl FnOnce() -> Change) {
+		if let Some(operation) = &mut self.operation {
+			operation.changes.push(change());
+		}
+	}
+
+	/// Gets a free path in the trash, creating the directory of the operation.
+	fn get_trash_path(&self) -> Option<PathBuf> {
+		let operation = self.operation.as_ref()?;
+		let dir = get_trash_dir(operation.id)?;
+		if let Err(err) = fs::create_dir_all(&dir) {
+			trace!("journal cannot create {:?}: {}", dir, err);
+			return None;
+		}
+		Some(dir.join(operation.changes.len().to_string()))
+	}
+}
+
+impl Drop for Recorder {
+	fn drop(&mut self) {
+		let Some(mut operation) = self.operation.take() else {
+			return;
+		};
+		if operation.changes.is_empty() {
+			return;
+		}
+		for change in &mut operation.changes {
+			match change {
+				Change::Created { path, stamp }
+				| Change::Moved {
+					to: path, stamp, ..
+				}
+				| Change::Appended { path, stamp, .. } => *stamp = Stamp::get(path),
+				Change::Trashed { .. } | Change::Deleted { .. } => {}
+			}
+		}
+		operation.completed = get_now();
+		let saved = match self.previous {
+			Some(previous) => continue_write(&operation, previous),
+			None => save(&operation),
+		};
+		if let Err(err) = saved {
+			trace!("journal cannot record {:?}: {}", operation.kind, err);
+		}
+	}
+}
+
+/// Reverts the most recent operations, the last one first.
+pub fn undo(request: UndoRequest, job: &Job) -> Result<(), ErrorResponse> {
+	let _guard = lock();
+	let mut operations = load().map_err(ErrorResponse::from)?;
+	let count = request.count.unwrap_or(1);
+	if operations.is_empty() {
+		return Err(ErrorResponse::new(
+			ErrorCode::NotFound,
+			"there are no operations to undo",
+		));
+	}
+	if count > operations.len() {
+		return Err(ErrorResponse::new(
+			ErrorCode::InvalidInput,
+			format!("only {} operations can be undone", operations.len()),
+		));
+	}
+	job.update(|progress| progress.files_total = count as u64);
+
+	for done in 0..count {
+		job.checkpoint()?;
+		let Some(operation) = operations.pop() else {
+			break;
+		};
+		// nothing is reverted if any entry was changed since
+		check(&operation)?;
+		for change in operation.changes.iter().rev() {
+			let (path, error) = match revert(change, job) {
+				Ok(None) => continue,
+				Ok(Some(path)) => (path, None),
+				Err(err) => (change.path(), Some(err)),
+			};
+			job.add_result(JobResult {
+				file: get_local_file(path, None, &None),
+				error,
+				..Default::default()
+			});
+		}
+		// entries that could not be restored stay in the trash
+		if let Some(dir) = get_trash_dir(operation.id) {
+			fs::remove_dir(dir).ok();
+		}
+		store(&operations).map_err(ErrorResponse::from)?;
+		job.update(|progress| progress.files_done = done as u64 + 1);
+	}
+	Ok(())
+}
+
+/// Checks that the changes of the operation can be reverted.
+fn check(operation: &Operation) -> Result<(), ErrorResponse> {
+	// paths freed and taken by reverting the later changes first
+	let mut freed = HashSet::new();
+	let mut taken = HashSet::new();
+	let is_free = |path: &PathBuf, freed: &HashSet<&PathBuf>, taken: &HashSet<&PathBuf>| {
+		!taken.contains(path) && (freed.contains(path) || fs::symlink_metadata(path).is_err())
+	};
+
+	for change in operation.changes.iter().rev() {
+		match change {
+			Change::Created { path, stamp } => {
+				check_stamp(path, stamp)?;
+				if stamp.is_some_and(|stamp| stamp.is_directory) {
+					check_contents(path, operation.completed)?;
+				}
+				taken.remove(path);
+				freed.insert(path);
+			}
+			Change::Moved { from, to, stamp } => {
+				check_stamp(to, stamp)?;
+				if !is_free(from, &freed, &taken) {
+					return Err(ErrorResponse::new(
+						ErrorCode::AlreadyExists,
+						"another entry was created where the entry was moved from",
+					)
+					.with_path(from));
+				}
+				taken.remove(to);
+				freed.insert(to);
+				freed.remove(from);
+				taken.insert(from);
+			}
+			Change::Trashed { path, trash, .. } => {
+				if fs::symlink_metadata(trash).is_err() {
+					return Err(ErrorResponse::new(
+						ErrorCode::NotFound,
+						"deleted entry is missing from the trash",
+					)
+					.with_path(path));
+				}
+				if !is_free(path, &freed, &taken) {
+					return Err(ErrorResponse::new(
+						ErrorCode::AlreadyExists,
+						"another entry was created where the deleted entry was",
+					)
+					.with_path(path));
+				}
+				freed.remove(path);
+				taken.insert(path);
+			}
+			Change::Appended { path, stamp, .. } => check_stamp(path, stamp)?,
+			Change::Deleted { .. } => {}
+		}
+	}
+	Ok(())
+}
+
+fn check_stamp(path: &Path, stamp: &Option<Stamp>) -> Result<(), ErrorResponse> {
+	if Stamp::get(path) == *stamp {
+		Ok(())
+	} else {
+		Err(changed_error(path))
+	}
+}
+
+/// Checks that no entry in the created directory was changed after the operation.
+fn check_contents(path: &Path, completed: i64) -> Result<(), ErrorResponse> {
+	for entry in fs::read_dir(path).with_path(path)? {
+		let entry_path = entry.with_path(path)?.path();
+		let metadata = fs::symlink_metadata(&entry_path).with_path(&entry_path)?;
+		if metadata.is_dir() {
+			check_contents(&entry_path, completed)?;
+		} else if get_change_date(&metadata) > completed {
+			return Err(changed_error(&entry_path));
+		}
+	}
+	Ok(())
+}
+
+fn changed_error(path: &Path) -> ErrorResponse {
+	ErrorResponse::new(
+		ErrorCode::Conflict,
+		"entry was changed since the operation, so it cannot be undone",
+	)
+	.with_path(path)
+}
+
+/// Reverts a change; gets the path of the entry if it was restored.
+fn revert<'a>(change: &'a Change, job: &Job) -> Result<Option<&'a Path>, ErrorResponse> {
+	match change {
+		Change::Created { path, .. } => {
+			match fs::symlink_metadata(path) {
+				Ok(metadata) if metadata.is_dir() => fs::remove_dir_all(path).with_path(path)?,
+				Ok(_) => fs::remove_file(path).with_path(path)?,
+				Err(_) => {}
+			}
+			Ok(None)
+		}
+		Change::Moved { from, to, .. } => {
+			move_entry(to, from, job)?;
+			Ok(Some(from))
+		}
+		Change::Trashed { path, trash, info } => {
+			move_entry(trash, path, job)?;
+			if let Some(info) = info {
+				fs::remove_file(info).ok();
+			}
+			Ok(Some(path))
+		}
+		Change::Appended {
+			path, size, date, ..
+		} => {
+			let file = OpenOptions::new().write(true).open(path).with_path(path)?;
+			file.set_len(*size).with_path(path)?;
+			file.set_modified(get_system_time(*date)).with_path(path)?;
+			Ok(Some(path))
+		}
+		Change::Deleted { path } => Err(ErrorResponse::new(
+			ErrorCode::NotFound,
+			"entry was deleted permanently, so it cannot be restored",
+		)
+		.with_path(path)),
+	}
+}
+
+/// Moves an entry back, also from another file system, such as one of the trash.
+fn move_entry(from: &Path, to: &Path, job: &Job) -> Result<(), ErrorResponse> {
+	match fs::rename(from, to) {
+		Ok(()) => Ok(()),
+		Err(err) if err.kind() == io::ErrorKind::CrossesDevices => {
+			copy_entry(from, to, false, job, &mut Recorder::disabled())?;
+			remove_entry(from, job)
+		}
+		Err(err) => Err(ErrorResponse::from(err).with_path(from)),
+	}
+}
+
+impl Change {
+	/// Indicates whether the change affected the entry at the path.
+	fn touches(&self, path: &Path) -> bool {
+		match self {
+			Change::Moved { from, to, .. } => from == path || to == path,
+			_ => self.path() == path,
+		}
+	}
+
+	fn path(&self) -> &Path {
+		match self {
+			Change::Created { path, .. }
+			| Change::Moved { from: path, .. }
+			| Change::Trashed { path, .. }
+			| Change::Appended { path, .. }
+			| Change::Deleted { path } => path,
+		}
+	}
+}
+
+fn lock() -> MutexGuard<'static, ()> {
+	LOCK.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+fn get_journal_dir() -> Option<PathBuf> {
+	dirs::data_dir().map(|dir| dir.join("xcmd"))
+}
+
+fn get_trash_dir(id: u64) -> Option<PathBuf> {
+	get_journal_dir().map(|dir| dir.join("trash").join(id.to_string()))
+}
+
+fn get_journal_path() -> io::Result<PathBuf> {
+	get_journal_dir()
+		.map(|dir| dir.join("journal.jsonl"))
+		.ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "data directory is unknown"))
+}
+
+/// Adds the operation to the journal, dropping the oldest ones beyond the limit.
+fn save(operation: &Operation) -> io::Result<()> {
+	let _guard = lock();
+	let path = get_journal_path()?;
+	if let Some(dir) = path.parent() {
+		fs::create_dir_all(dir)?;
+	}
+	let mut file = OpenOptions::new().append(true).create(true).open(&path)?;
+	writeln!(file, "{}", serde_json::to_string(operation)?)?;
+	drop(file);
+
+	let mut operations = load()?;
+	if operations.len() > MAX_OPERATIONS {
+		let dropped = operations
+			.drain(..operations.len() - MAX_OPERATIONS)
+			.collect::<Vec<_>>();
+		store(&operations)?;
+		for operation in dropped {
+			if let Some(dir) = get_trash_dir(operation.id) {
+				fs::remove_dir_all(dir).ok();
+			}
+		}
+	}
+	Ok(())
+}
+
+/// Adds the change of appending to a file to the latest operation changing the file
+/// if that was a write that left the file in the previous state; adds the operation
+/// otherwise.
+fn continue_write(operation: &Operation, previous: Stamp) -> io::Result<()> {
+	let [Change::Appended {
+		path,
+		stamp: appended,
+		..
+	}] = &operation.changes[..]
+	else {
+		return save(operation);
+	};
+	{
+		let _guard = lock();
+		let mut operations = load()?;
+		let last = operations
+			.iter_mut()
+			.rev()
+			.find(|last| last.changes.iter().any(|change| change.touches(path)));
+		if let Some(last) = last.filter(|last| matches!(last.kind, OperationKind::Write)) {
+			if let Some(
+				Change::Created {
+					path: written,
+					stamp,
+				}
+				| Change::Appended {
+					path: written,
+					stamp,
+					..
+				},
+			) = last.changes.last_mut()
+			{
+				if written == path && *stamp == Some(previous) {
+					*stamp = *appended;
+					last.completed = operation.completed;
+					return store(&operations);
+				}
+			}
+		}
+	}
+	save(operation)
+}
+
+/// Reads the operations of the journal, the oldest first.
+fn load() -> io::Result<Vec<Operation>> {
+	let file = match fs::File::open(get_journal_path()?) {
+		Ok(file) => file,
+		Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+		Err(err) => return Err(err),
+	};
+	let mut operations = Vec::new();
+	for line in BufReader::new(file).lines() {
+		match serde_json::from_str(&line?) {
+			Ok(operation) => operations.push(operation),
+			Err(err) => trace!("journal line skipped: {}", err),
+		}
+	}
+	Ok(operations)
+}
+
+/// Replaces the journal with the operations.
+fn store(operations: &[Operation]) -> io::Result<()> {
+	let path = get_journal_path()?;
+	let temp_path = path.with_file_name(format!(".journal.{}.tmp", process::id()));
+	let mut content = String::new();
+	for operation in operations {
+		content.push_str(&serde_json::to_string(operation)?);
+		content.push('\n');
+	}
+	fs::write(&temp_path, content)?;
+	fs::rename(&temp_path, &path)
+}
+
+fn get_now() -> i64 {
+	SystemTime::now()
+		.duration_since(UNIX_EPOCH)
+		.map(|duration| duration.as_millis() as i64)
+		.unwrap_or_default()
+}
+
+/// Gets the date the entry was last changed, including renames and links, which
+/// cannot be set to an earlier date.
+#[cfg(not(target_os = "windows"))]
+fn get_change_date(metadata: &Metadata) -> i64 {
+	use std::os::unix::fs::MetadataExt;
+	metadata.ctime() * 1000 + metadata.ctime_nsec() / 1_000_000
+}
+
+#[cfg(target_os = "windows")]
+fn get_change_date(metadata: &Metadata) -> i64 {
+	get_date(metadata)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::testing::TempDir;
+	use serde_json::json;
+	use xcmd_base::{JobKind, WriteRequest};
+
+	fn write(dir: &TempDir, name: &str, content: &str, backup: bool) {
+		let request: WriteRequest = serde_json::from_value(json!({
+			"path": dir.path(),
+			"key": name,
+			"content": content,
+			"backup": backup,
+		}))
+		.unwrap();
+		crate::write(request).unwrap();
+	}
+
+	fn append(dir: &TempDir, name: &str, content: &str) {
+		let request: WriteRequest = serde_json::from_value(json!({
+			"path": dir.path(),
+			"key": name,
+			"content": content,
+			"append": true,
+		}))
+		.unwrap();
+		crate::write(request).unwrap();
+	}
+
+	fn undo_last() -> Result<(), ErrorResponse> {
+		undo(UndoRequest { count: None }, &Job::new(0, JobKind::Undo))
+	}
+
+	#[test]
+	fn undo_write_with_backup() {
+		let dir = TempDir::new();
+		write(&dir, "a", "1", false);
+		write(&dir, "a", "2", true);
+		write(&dir, "a", "3", true);
+		assert_eq!(dir.read("a~"), "2");
+
+		undo_last().unwrap();
+		assert_eq!(dir.read("a"), "2");
+		assert_eq!(dir.read("a~"), "1");
+		undo_last().unwrap();
+		assert_eq!(dir.read("a"), "1");
+		assert!(!dir.join("a~").exists());
+		undo_last().unwrap();
+		assert!(!dir.join("a").exists());
+		assert_eq!(undo_last().unwrap_err().code, ErrorCode::NotFound);
+	}
+
+	#[test]
+	fn undo_appends_at_once() {
+		let dir = TempDir::new();
+		append(&dir, "a", "1");
+		append(&dir, "a", "2");
+		dir.write("b", "1");
+		append(&dir, "b", "2");
+		append(&dir, "b", "3");
+		assert_eq!(dir.read("b"), "123");
+
+		// the chunks appended to a file are one operation
+		undo_last().unwrap();
+		assert_eq!(dir.read("b"), "1");
+		undo_last().unwrap();
+		assert!(!dir.join("a").exists());
+		assert_eq!(undo_last().unwrap_err().code, ErrorCode::NotFound);
+	}
+
+	#[test]
+	fn undo_several_operations() {
+		let dir = TempDir::new();
+		write(&dir, "a", "1", false);
+		write(&dir, "b", "1", false);
+		write(&dir, "c", "1", false);
+		let undo_count = |count| {
+			undo(
+				UndoRequest { count: Some(count) },
+				&Job::new(0, JobKind::Undo),
+			)
+		};
+		assert_eq!(undo_count(4).unwrap_err().code, ErrorCode::InvalidInput);
+		undo_count(2).unwrap();
+		assert!(dir.join("a").exists());
+		assert!(!dir.join("b").exists());
+		assert!(!dir.join("c").exists());
+	}
+
+	#[test]
+	fn undo_keeps_changed_entries() {
+		let dir = TempDir::new();
+		write(&dir, "a", "1", false);
+		dir.write("a", "changed");
+		let err = undo_last().unwrap_err();
+		assert_eq!(err.code, ErrorCode::Conflict);
+		assert_eq!(dir.read("a"), "changed");
+	}
+
+	#[test]
+	fn undo_moves_unless_taken() {
+		let dir = TempDir::new();
+		let from = dir.write("a", "1");
+		let to = dir.join("b");
+		fs::rename(&from, &to).unwrap();
+		Recorder::new(OperationKind::Rename).moved(&from, &to);
+		dir.write("a", "new");
+		let err = undo_last().unwrap_err();
+		assert_eq!(err.code, ErrorCode::AlreadyExists);
+		assert_eq!(dir.read("b"), "1");
+
+		fs::remove_file(&from).unwrap();
+		undo_last().unwrap();
+		assert_eq!(dir.read("a"), "1");
+		assert!(!to.exists());
+	}
+}
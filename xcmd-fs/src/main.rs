@@ -1,20 +1,46 @@
-use actix_web::body::to_bytes;
-use actix_web::{get, post, web, App, HttpResponse, HttpServer};
+mod archive;
+mod checksums;
+mod computer;
+mod content;
+mod journal;
+mod operations;
+mod pack;
+mod search;
+#[cfg(test)]
+mod testing;
+mod thumbnails;
+mod trash;
+mod unpack;
+mod watch;
+
+use actix_web::web;
+use async_trait::async_trait;
+use journal::{OperationKind, Recorder};
 use rust_embed::RustEmbed;
-use serde::Deserialize;
 use std::error::Error;
-use std::fs::Permissions;
-use std::fs::{self, create_dir_all};
+use std::fs::{self, create_dir_all, File, OpenOptions};
+use std::fs::{Metadata, Permissions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
 #[cfg(not(target_os = "windows"))]
-use std::os::unix::fs::PermissionsExt;
-use std::path::{Component, Path, PathBuf};
-use std::time::UNIX_EPOCH;
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+#[cfg(target_os = "windows")]
+use std::os::windows::fs::MetadataExt;
+use std::path::{Component, Path, PathBuf, MAIN_SEPARATOR_STR};
+use std::process;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use tracing::trace;
-use tracing_actix_web::TracingLogger;
 use urlencoding::encode;
+#[cfg(not(target_os = "windows"))]
+use uzers::{Groups, Users, UsersCache};
 use xcmd_base::{
-	get_port, init_telemetry, post_startup, CreateRequest, CreateResponse, FileInfo, ListRequest,
-	ListResponse, Middleware, ReadRequest, Request, Response,
+	check_offset, get_listing_page, get_timestamp, init_telemetry, serve, Backend, Capabilities,
+	CreateChecksumFileRequest, CreateLinkRequest, CreateLinkResponse, CreateRequest,
+	CreateResponse, DeleteRequest, DirectorySizeRequest, ErrorCode, ErrorContext, ErrorResponse,
+	Feature, FileInfo, Icon, JobKind, JobResponse, Jobs, LinkKind, ListRequest, ListResponse,
+	PackRequest, PathSyntax, ReadRequest, ReadResponse, RequestKind, RestoreRequest, SearchRequest,
+	StatRequest, StatResponse, TransferRequest, UndoRequest, UnpackRequest,
+	VerifyChecksumFileRequest, WatchRequest, WatchStream, WriteRequest, WriteResponse,
+	PROTOCOL_VERSION,
 };
 
 #[cfg(target_os = "windows")]
@@ -22,6 +48,14 @@ const DEFAULT_PATH: &str = "c:/";
 #[cfg(not(target_os = "windows"))]
 const DEFAULT_PATH: &str = "/";
 
+/// Start of PNG files; system icons may be SVG files as well.
+const PNG_SIGNATURE: &[u8] = b"\x89PNG";
+
+#[cfg(not(target_os = "windows"))]
+thread_local! {
+	static USERS: UsersCache = UsersCache::new();
+}
+
 pub const FMT: u32 = 0b1111_000_000_000_000;
 pub const FIFO: u32 = 0b0001_000_000_000_000;
 pub const FCHR: u32 = 0b0010_000_000_000_000;
@@ -51,24 +85,222 @@ pub const OR: u32 = 0b_000_000_100;
 pub const OW: u32 = 0b_000_000_010;
 pub const OX: u32 = 0b_000_000_001;
 
-#[post("/")]
-async fn enact(request: web::Json<Request>) -> Result<HttpResponse, Box<dyn Error>> {
-	match request.into_inner() {
-		Request::List(request) => {
-			let response = list_files(request)?;
-			let body = serde_json::to_string(&Response::List(response))?;
-			Ok(HttpResponse::Ok().body(body))
+struct FsBackend;
+
+#[async_trait(?Send)]
+impl Backend for FsBackend {
+	fn capabilities(&self) -> Capabilities {
+		Capabilities {
+			protocol_version: PROTOCOL_VERSION,
+			name: env!("CARGO_PKG_NAME").to_string(),
+			version: env!("CARGO_PKG_VERSION").to_string(),
+			requests: vec![
+				RequestKind::List,
+				RequestKind::Create,
+				RequestKind::CreateLink,
+				RequestKind::Read,
+				RequestKind::Write,
+				RequestKind::Copy,
+				RequestKind::Rename,
+				RequestKind::Delete,
+				RequestKind::Stat,
+				RequestKind::JobStatus,
+				RequestKind::JobCancel,
+				RequestKind::JobPause,
+				RequestKind::JobResume,
+				RequestKind::JobResults,
+				RequestKind::JobRemove,
+				RequestKind::Search,
+				RequestKind::DirectorySize,
+				RequestKind::Checksum,
+				RequestKind::CreateChecksumFile,
+				RequestKind::VerifyChecksumFile,
+				RequestKind::CompareDirectories,
+				RequestKind::Sync,
+				RequestKind::Pack,
+				RequestKind::Unpack,
+				RequestKind::BatchRename,
+				RequestKind::Undo,
+				RequestKind::Restore,
+				RequestKind::EmptyTrash,
+				RequestKind::Capabilities,
+			],
+			path_syntax: PathSyntax {
+				separator: MAIN_SEPARATOR_STR.to_string(),
+				case_sensitive: !cfg!(any(target_os = "windows", target_os = "macos")),
+			},
+			features: vec![
+				Feature::RangedReads,
+				Feature::Watch,
+				Feature::PagedListing,
+				Feature::ContentSearch,
+				Feature::AppendWrites,
+				#[cfg(not(target_os = "windows"))]
+				Feature::Trash,
+				Feature::ComputerRoot,
+				Feature::Thumbnails,
+			],
 		}
-		Request::Create(request) => {
-			let response = create_directory(request)?;
-			let body = serde_json::to_string(&Response::Create(response))?;
-			Ok(HttpResponse::Ok().body(body))
+	}
+
+	async fn list(&self, request: ListRequest) -> Result<ListResponse, ErrorResponse> {
+		list_files(request)
+	}
+
+	async fn read(&self, request: ReadRequest) -> Result<ReadResponse, ErrorResponse> {
+		read(request)
+	}
+
+	async fn write(&self, request: WriteRequest) -> Result<WriteResponse, ErrorResponse> {
+		write(request)
+	}
+
+	async fn create(&self, request: CreateRequest) -> Result<CreateResponse, ErrorResponse> {
+		create_directory(request)
+	}
+
+	async fn create_link(
+		&self,
+		request: CreateLinkRequest,
+	) -> Result<CreateLinkResponse, ErrorResponse> {
+		create_link(request)
+	}
+
+	async fn copy(
+		&self,
+		request: TransferRequest,
+		jobs: &Jobs,
+	) -> Result<JobResponse, ErrorResponse> {
+		let job = jobs.start(JobKind::Copy, move |job| operations::copy(request, job));
+		Ok(job.status())
+	}
+
+	async fn rename(
+		&self,
+		request: TransferRequest,
+		jobs: &Jobs,
+	) -> Result<JobResponse, ErrorResponse> {
+		let job = jobs.start(JobKind::Rename, move |job| operations::rename(request, job));
+		Ok(job.status())
+	}
+
+	async fn delete(
+		&self,
+		request: DeleteRequest,
+		jobs: &Jobs,
+	) -> Result<JobResponse, ErrorResponse> {
+		let job = jobs.start(JobKind::Delete, move |job| operations::delete(request, job));
+		Ok(job.status())
+	}
+
+	async fn search(
+		&self,
+		request: SearchRequest,
+		jobs: &Jobs,
+	) -> Result<JobResponse, ErrorResponse> {
+		let job = jobs.start(JobKind::Search, move |job| search::search(request, job));
+		Ok(job.status())
+	}
+
+	async fn directory_size(
+		&self,
+		request: DirectorySizeRequest,
+		jobs: &Jobs,
+	) -> Result<JobResponse, ErrorResponse> {
+		let job = jobs.start(JobKind::DirectorySize, move |job| {
+			operations::directory_size(request, job)
+		});
+		Ok(job.status())
+	}
+
+	async fn create_checksum_file(
+		&self,
+		request: CreateChecksumFileRequest,
+		jobs: &Jobs,
+	) -> Result<JobResponse, ErrorResponse> {
+		let job = jobs.start(JobKind::CreateChecksumFile, move |job| {
+			checksums::create(request, job)
+		});
+		Ok(job.status())
+	}
+
+	async fn verify_checksum_file(
+		&self,
+		request: VerifyChecksumFileRequest,
+		jobs: &Jobs,
+	) -> Result<JobResponse, ErrorResponse> {
+		let job = jobs.start(JobKind::VerifyChecksumFile, move |job| {
+			checksums::verify(request, job)
+		});
+		Ok(job.status())
+	}
+
+	async fn pack(&self, request: PackRequest, jobs: &Jobs) -> Result<JobResponse, ErrorResponse> {
+		let job = jobs.start(JobKind::Pack, move |job| pack::pack(request, job));
+		Ok(job.status())
+	}
+
+	async fn unpack(
+		&self,
+		request: UnpackRequest,
+		jobs: &Jobs,
+	) -> Result<JobResponse, ErrorResponse> {
+		let job = jobs.start(JobKind::Unpack, move |job| unpack::unpack(request, job));
+		Ok(job.status())
+	}
+
+	async fn undo(&self, request: UndoRequest, jobs: &Jobs) -> Result<JobResponse, ErrorResponse> {
+		let job = jobs.start(JobKind::Undo, move |job| journal::undo(request, job));
+		Ok(job.status())
+	}
+
+	async fn restore(
+		&self,
+		request: RestoreRequest,
+		jobs: &Jobs,
+	) -> Result<JobResponse, ErrorResponse> {
+		let job = jobs.start(JobKind::Restore, move |job| trash::restore(request, job));
+		Ok(job.status())
+	}
+
+	async fn empty_trash(&self, jobs: &Jobs) -> Result<JobResponse, ErrorResponse> {
+		let job = jobs.start(JobKind::EmptyTrash, trash::empty);
+		Ok(job.status())
+	}
+
+	async fn stat(&self, request: StatRequest) -> Result<StatResponse, ErrorResponse> {
+		stat(request)
+	}
+
+	fn watch(&self, request: WatchRequest) -> Result<WatchStream, ErrorResponse> {
+		watch::watch(request)
+	}
+
+	fn icon(&self, name: &str, path: Option<&str>) -> Result<Option<Icon>, ErrorResponse> {
+		// files without an icon in the system theme get the bundled one
+		if let Some(path) = path {
+			match systemicons::get_icon(path, 16, name == "folder") {
+				Ok(bytes) if bytes.starts_with(PNG_SIGNATURE) => return Ok(Some(Icon::png(bytes))),
+				Ok(bytes) => return Ok(Some(Icon::svg(bytes))),
+				Err(err) => trace!("no system icon for {}: {}", path, err.message),
+			}
 		}
-		Request::Read(request) => {
-			let response = read(request)?;
-			Ok(HttpResponse::Ok().body(response))
+		let asset_name = format!("{}.svg", name);
+		Ok(Asset::get(&asset_name).map(|image| Icon::svg(image.data.to_vec())))
+	}
+
+	async fn thumbnail(&self, path: &str, size: u32) -> Result<Option<Icon>, ErrorResponse> {
+		let mut path = PathBuf::from(path);
+		if let Some(relative) = trash::split_path(&path) {
+			path = trash::resolve(&relative)?;
+		} else if let Some(relative) = computer::split_path(&path) {
+			path = computer::resolve(&relative)?;
 		}
-		_ => Ok(HttpResponse::NotFound().body("".to_string())),
+		// decoding large images would hold up other requests
+		let thumbnail = web::block(move || thumbnails::get_thumbnail(&path, size))
+			.await
+			.map_err(|err| ErrorResponse::new(ErrorCode::Internal, err.to_string()))??;
+		Ok(thumbnail.map(Icon::png))
 	}
 }
 
@@ -76,58 +308,20 @@ async fn enact(request: web::Json<Request>) -> Result<HttpResponse, Box<dyn Erro
 #[folder = "res/"]
 struct Asset;
 
-#[derive(Deserialize)]
-struct IconQuery {
-	path: Option<String>,
-}
-
-#[get("/icons/{name}")]
-async fn icon(
-	name: web::Path<String>,
-	query: web::Query<IconQuery>,
-) -> Result<HttpResponse, Box<dyn Error>> {
-	if let Some(path) = &query.path {
-		let bytes = systemicons::get_icon(&path, 16, name.to_string() == "folder")
-			.map_err(|err| err.message)?;
-		return Ok(HttpResponse::Ok()
-			.content_type("image/png")
-			.append_header(("Cache-Control", "public, max-age=86400"))
-			.body(bytes));
-	}
-	let asset_name = format!("{}.svg", name);
-	if let Some(image) = Asset::get(&asset_name) {
-		let bytes = to_bytes(image.data.to_vec()).await?;
-		Ok(HttpResponse::Ok()
-			.content_type("image/svg+xml")
-			.append_header(("Cache-Control", "public, max-age=86400"))
-			.body(bytes))
-	} else {
-		Ok(HttpResponse::NotFound().body("".to_string()))
-	}
-}
-
 #[actix_web::main]
 async fn main() -> Result<(), Box<dyn Error>> {
 	init_telemetry("xcmd_fs");
-	let port = get_port()?;
-
-	let server = HttpServer::new(|| {
-		App::new()
-			.wrap(Middleware::cors())
-			.wrap(Middleware::token_auth())
-			.wrap(TracingLogger::default())
-			.service(icon)
-			.service(enact)
-	})
-	.bind(("127.0.0.1", port))?
-	// TODO: use TLS when tauri adds support to trust self-signed certificates
-	// .bind_rustls_021(format!("127.0.0.1:{}", port), load_rustls_config()?)?
-	.run();
-
-	post_startup(&server, port);
-
-	server.await?;
-	Ok(())
+	serve(FsBackend).await
+}
+
+/// Gets the path within a virtual location such as `trash:` if the path is in it,
+/// empty for the location itself.
+fn split_virtual_path(path: &Path, root: &str) -> Option<PathBuf> {
+	let mut components = path.components();
+	match components.next() {
+		Some(Component::Normal(name)) if name == root => Some(components.as_path().to_path_buf()),
+		_ => None,
+	}
 }
 
 fn get_paths(path: &Option<String>, key: &Option<String>) -> (PathBuf, PathBuf) {
@@ -148,7 +342,7 @@ fn get_paths(path: &Option<String>, key: &Option<String>) -> (PathBuf, PathBuf)
 	(path, full_path)
 }
 
-fn list_files(request: ListRequest) -> Result<ListResponse, Box<dyn Error>> {
+fn list_files(request: ListRequest) -> Result<ListResponse, ErrorResponse> {
 	trace!("request = {:?}", &request);
 
 	// vector for result with list of files
@@ -156,7 +350,39 @@ fn list_files(request: ListRequest) -> Result<ListResponse, Box<dyn Error>> {
 
 	let (path, full_path) = get_paths(&request.path, &request.key);
 
-	let full_path_canonicalized = fs::canonicalize(full_path)?;
+	// active name is 'c' for case when {path: 'a/b/c', key: '..'}
+	let active_key = if let Some(ref key) = request.key {
+		if key == "../" {
+			path.file_name().map(|x| x.to_string_lossy().to_string())
+		} else {
+			None
+		}
+	} else {
+		None
+	};
+
+	if trash::split_path(&path).is_some() {
+		return trash::list(&full_path, &request, &active_key);
+	}
+	// entries of the computer root are listed as the directories they point to
+	let full_path = match computer::split_path(&full_path) {
+		Some(relative) if relative.as_os_str().is_empty() => return computer::list(&request),
+		Some(relative) => computer::resolve(&relative)?,
+		None => full_path,
+	};
+
+	// archives and their directories are listed like directories; the parent of an
+	// archive is resolved without the file system
+	let full_path = if archive::split_path(&path).is_some() {
+		archive::normalize_path(&full_path)
+	} else {
+		full_path
+	};
+	if let Some((archive_path, directory)) = archive::split_path(&full_path) {
+		return archive::list(&archive_path, &directory, &request, &active_key);
+	}
+
+	let full_path_canonicalized = fs::canonicalize(&full_path).with_path(&full_path)?;
 	let full_path_str = full_path_canonicalized.to_string_lossy();
 	let full_path = Path::new(trim_long_path_prefix(&full_path_str));
 
@@ -171,46 +397,72 @@ fn list_files(request: ListRequest) -> Result<ListResponse, Box<dyn Error>> {
 			}
 		});
 
-	// gets the parent path item and adds it as the first file
-	if let Some(parent_path) = full_path.parent() {
-		files.push(get_local_file(parent_path, Some("..".to_string()), &None));
-	}
-
-	// active name is 'c' for case when {path: 'a/b/c', key: '..'}
-	let active_key = if let Some(ref key) = request.key {
-		if key == "../" {
-			path.file_name().map(|x| x.to_string_lossy().to_string())
-		} else {
-			None
-		}
-	} else {
-		None
-	};
-
-	// appends the files in the full_path directory
-	if let Ok(read_dir) = fs::read_dir(&full_path) {
-		for child_path in read_dir {
-			match child_path {
-				Ok(dir_entry) => files.push(get_local_file(&dir_entry.path(), None, &active_key)),
-				Err(err) => eprintln!("Error: {}", err),
-			}
+	// gets the parent path item and adds it as the first file of the first page
+	if request.cursor.is_none() {
+		if let Some(parent_path) = full_path.parent() {
+			files.push(get_local_file(parent_path, Some("..".to_string()), &None));
 		}
 	}
 
+	// appends a page of files in the full_path directory; entries deleted since the
+	// first page are left out
+	let page = get_listing_page(full_path, &request, || read_entry_paths(full_path))?;
+	files.extend(
+		page.entries
+			.iter()
+			.filter(|path| fs::symlink_metadata(path).is_ok())
+			.map(|path| get_local_file(path, None, &active_key)),
+	);
+	let cursor = page.cursor;
+
 	// normalizes the full_path directory
-	let path = trim_long_path_prefix(&fs::canonicalize(full_path)?.to_string_lossy()).to_string();
+	let path = trim_long_path_prefix(
+		&fs::canonicalize(full_path)
+			.with_path(full_path)?
+			.to_string_lossy(),
+	)
+	.to_string();
 
-	let response = ListResponse { path, name, files };
+	let response = ListResponse {
+		free_space: computer::get_space(Path::new(&path)).map(|space| space.free),
+		path,
+		name,
+		files,
+		cursor,
+	};
 	// trace!("response = {:?}", &response);
 	Ok(response)
 }
 
-fn create_directory(request: CreateRequest) -> Result<CreateResponse, Box<dyn Error>> {
+/// Gets the paths of the entries of the directory.
+fn read_entry_paths(path: &Path) -> Result<Vec<PathBuf>, ErrorResponse> {
+	let read_dir = fs::read_dir(path).with_path(path)?;
+	Ok(read_dir
+		.filter_map(|entry| match entry {
+			Ok(entry) => Some(entry.path()),
+			Err(err) => {
+				eprintln!("Error: {}", err);
+				None
+			}
+		})
+		.collect())
+}
+
+fn create_directory(request: CreateRequest) -> Result<CreateResponse, ErrorResponse> {
 	trace!("request = {:?}", &request);
 
 	// gets the path, for instance `a/b/c`; falls back to `c:/` if not provided
 	let (dir_path, file_path) = get_paths(&request.path, &request.name);
-	create_dir_all(&file_path)?;
+	// the top-most missing directory is recorded, so undoing removes all created ones
+	let created = file_path
+		.ancestors()
+		.take_while(|path| fs::symlink_metadata(path).is_err())
+		.last()
+		.map(Path::to_path_buf);
+	create_dir_all(&file_path).with_path(&file_path)?;
+	if let Some(created) = created {
+		Recorder::new(OperationKind::Create).created(&created);
+	}
 
 	let directory = get_local_file(dir_path.as_path(), request.name, &None);
 	let path = file_path
@@ -226,15 +478,248 @@ fn create_directory(request: CreateRequest) -> Result<CreateResponse, Box<dyn Er
 	Ok(response)
 }
 
-fn read(request: ReadRequest) -> Result<Vec<u8>, Box<dyn Error>> {
-	let (_path, full_path) = get_paths(&request.path, &request.key);
+fn create_link(request: CreateLinkRequest) -> Result<CreateLinkResponse, ErrorResponse> {
+	trace!("request = {:?}", &request);
+
+	let (path, _) = get_paths(&request.path, &None);
+	let link_path = operations::get_entry_path(&path, &request.name)?;
+	if request.target.is_empty() {
+		return Err(ErrorResponse::new(
+			ErrorCode::InvalidInput,
+			"link target is missing",
+		));
+	}
+	let target = Path::new(&request.target);
+
+	match request.kind {
+		// relative targets are kept, so the link stays valid when its directory is moved
+		LinkKind::Symbolic => create_symlink(target, &link_path),
+		LinkKind::Hard => fs::hard_link(path.join(target), &link_path),
+	}
+	.with_path(&link_path)?;
+	Recorder::new(OperationKind::Create).created(&link_path);
+
+	let path = path.to_string_lossy().to_string();
+	let file = get_local_file(&link_path, None, &None);
+	Ok(CreateLinkResponse { path, file })
+}
+
+#[cfg(not(target_os = "windows"))]
+fn create_symlink(target: &Path, link_path: &Path) -> io::Result<()> {
+	std::os::unix::fs::symlink(target, link_path)
+}
+
+#[cfg(target_os = "windows")]
+fn create_symlink(target: &Path, link_path: &Path) -> io::Result<()> {
+	// links to directories are a different kind of link on windows
+	let is_dir = link_path
+		.parent()
+		.map(|parent| parent.join(target).is_dir())
+		.unwrap_or_default();
+	if is_dir {
+		std::os::windows::fs::symlink_dir(target, link_path)
+	} else {
+		std::os::windows::fs::symlink_file(target, link_path)
+	}
+}
+
+fn read(request: ReadRequest) -> Result<ReadResponse, ErrorResponse> {
+	let (_path, mut full_path) = get_paths(&request.path, &request.key);
+	if let Some(relative) = trash::split_path(&full_path) {
+		full_path = trash::resolve(&relative)?;
+	}
 
-	let full_path_canonicalized = fs::canonicalize(full_path)?;
+	// entries of archives are extracted; archives themselves are read as files
+	if let Some((archive_path, entry_path)) = archive::split_path(&full_path) {
+		if !entry_path.is_empty() {
+			return archive::read(&archive_path, &entry_path, request.offset, request.length);
+		}
+	}
+
+	let full_path_canonicalized = fs::canonicalize(&full_path).with_path(&full_path)?;
 	let full_path_str = full_path_canonicalized.to_string_lossy();
 	let full_path = Path::new(trim_long_path_prefix(&full_path_str));
 
-	// Ok(full_path.as_os_str().to_string_lossy().as_bytes().to_vec())
-	Ok(std::fs::read(full_path)?)
+	let mut file = File::open(full_path).with_path(full_path)?;
+	let size = file.metadata().with_path(full_path)?.len();
+	let offset = request.offset.unwrap_or(0);
+	check_offset(offset, size).with_path(full_path)?;
+	file.seek(SeekFrom::Start(offset)).with_path(full_path)?;
+
+	let mut data = Vec::new();
+	if let Some(length) = request.length {
+		file.take(length)
+			.read_to_end(&mut data)
+			.with_path(full_path)?;
+	} else {
+		// special files may report no size, so these are read to their end
+		file.read_to_end(&mut data).with_path(full_path)?;
+	}
+
+	let size = size.max(offset + data.len() as u64);
+	Ok(ReadResponse { data, offset, size })
+}
+
+fn stat(request: StatRequest) -> Result<StatResponse, ErrorResponse> {
+	trace!("request = {:?}", &request);
+
+	let (_path, mut full_path) = get_paths(&request.path, &request.key);
+	if let Some(relative) = trash::split_path(&full_path) {
+		full_path = trash::resolve(&relative)?;
+	}
+	fs::symlink_metadata(&full_path).with_path(&full_path)?;
+
+	let path = full_path
+		.parent()
+		.map(|x| x.to_string_lossy().to_string())
+		.unwrap_or_default();
+	let file = get_local_file(&full_path, None, &None);
+
+	Ok(StatResponse { path, file })
+}
+
+fn write(request: WriteRequest) -> Result<WriteResponse, ErrorResponse> {
+	trace!("request = {:?}", &request);
+
+	let (_path, file_path) = get_paths(&request.path, &request.key);
+	let file_name = file_path
+		.file_name()
+		.map(|x| x.to_string_lossy().to_string())
+		.ok_or_else(|| ErrorResponse::new(ErrorCode::InvalidInput, "file name is missing"))?;
+	// files are written in parts by appending instead
+	if request.part.is_some() {
+		return Err(ErrorResponse::unsupported());
+	}
+
+	if request.append {
+		let content = request.encoding.decode(request.content)?;
+		let _recorder = Recorder::append(&file_path);
+		append_file(&file_path, &content, request.date).with_path(&file_path)?;
+		return Ok(WriteResponse {
+			path: get_parent(&file_path),
+			file: get_local_file(&file_path, None, &None),
+			upload: None,
+		});
+	}
+
+	let mut recorder = Recorder::new(OperationKind::Write);
+	let metadata = match fs::metadata(&file_path) {
+		Ok(metadata) => Some(metadata),
+		Err(err) if err.kind() == io::ErrorKind::NotFound => None,
+		Err(err) => return Err(ErrorResponse::from(err).with_path(&file_path)),
+	};
+
+	// fails if the file was changed since the client has seen it
+	if let Some(expected) = &request.expected {
+		let unchanged = match &metadata {
+			Some(metadata) => {
+				expected.date.is_none_or(|date| date == get_date(metadata))
+					&& expected.size.is_none_or(|size| size == metadata.len())
+			}
+			None => expected.date.is_none() && expected.size.is_none(),
+		};
+		if !unchanged {
+			return Err(ErrorResponse::new(
+				ErrorCode::Conflict,
+				"file was changed by another process",
+			)
+			.with_path(&file_path));
+		}
+	}
+
+	// writes the contents to a temporary sibling, so the file is never seen half-written
+	let content = request.encoding.decode(request.content)?;
+	let temp_path = file_path.with_file_name(format!(".{}.{}.tmp", file_name, process::id()));
+	if let Err(err) = write_temp_file(&temp_path, &content, &metadata, request.date) {
+		fs::remove_file(&temp_path).ok();
+		return Err(ErrorResponse::from(err).with_path(&temp_path));
+	}
+
+	if request.backup && metadata.is_some() {
+		// the backup is moved over the old one as well, as the journal keeps a link to
+		// the old one, which must not be written in place
+		let backup_path = file_path.with_file_name(format!("{}~", file_name));
+		let backup_temp_path =
+			file_path.with_file_name(format!(".{}~.{}.tmp", file_name, process::id()));
+		if let Err(err) = fs::copy(&file_path, &backup_temp_path) {
+			fs::remove_file(&backup_temp_path).ok();
+			fs::remove_file(&temp_path).ok();
+			return Err(ErrorResponse::from(err).with_path(&backup_path));
+		}
+		if fs::symlink_metadata(&backup_path).is_ok() {
+			recorder.keep(&backup_path);
+		}
+		if let Err(err) = fs::rename(&backup_temp_path, &backup_path) {
+			fs::remove_file(&backup_temp_path).ok();
+			fs::remove_file(&temp_path).ok();
+			return Err(ErrorResponse::from(err).with_path(&backup_path));
+		}
+		recorder.created(&backup_path);
+	}
+
+	// the replaced file is kept, as the new one is moved over it
+	if metadata.is_some() {
+		recorder.keep(&file_path);
+	}
+	if let Err(err) = fs::rename(&temp_path, &file_path) {
+		fs::remove_file(&temp_path).ok();
+		return Err(ErrorResponse::from(err).with_path(&file_path));
+	}
+	recorder.created(&file_path);
+
+	let path = get_parent(&file_path);
+	let file = get_local_file(&file_path, None, &None);
+
+	let response = WriteResponse {
+		path,
+		file,
+		upload: None,
+	};
+	// trace!("response = {:?}", &response);
+	Ok(response)
+}
+
+fn write_temp_file(
+	path: &Path,
+	content: &[u8],
+	metadata: &Option<Metadata>,
+	date: Option<i64>,
+) -> io::Result<()> {
+	let mut file = File::create(path)?;
+	file.write_all(content)?;
+	// keeps the permissions of the replaced file
+	if let Some(metadata) = metadata {
+		file.set_permissions(metadata.permissions())?;
+	}
+	if let Some(date) = date {
+		file.set_modified(get_system_time(date))?;
+	}
+	file.sync_all()
+}
+
+fn append_file(path: &Path, content: &[u8], date: Option<i64>) -> io::Result<()> {
+	let mut file = OpenOptions::new().append(true).create(true).open(path)?;
+	file.write_all(content)?;
+	if let Some(date) = date {
+		file.set_modified(get_system_time(date))?;
+	}
+	file.sync_all()
+}
+
+/// Converts a date in milliseconds since the epoch.
+fn get_system_time(date: i64) -> SystemTime {
+	let offset = Duration::from_millis(date.unsigned_abs());
+	if date < 0 {
+		UNIX_EPOCH - offset
+	} else {
+		UNIX_EPOCH + offset
+	}
+}
+
+fn get_parent(path: &Path) -> String {
+	path.parent()
+		.map(|x| x.to_string_lossy().to_string())
+		.unwrap_or_default()
 }
 
 #[cfg(target_os = "windows")]
@@ -319,19 +804,27 @@ fn permissions_to_string(permissions: &Permissions) -> String {
 fn get_local_file(path: &Path, name: Option<String>, active_key: &Option<String>) -> FileInfo {
 	let path = Path::new(path);
 	// let full_path = trim_long_path_prefix(&path.to_string_lossy().into_owned()).to_owned();
-	let metadata = path.metadata();
+	// describes links themselves; only whether it is a directory is taken from the
+	// target, so links to directories can be entered
+	let metadata = fs::symlink_metadata(path);
 	let mut size = 0;
 	let mut is_dir = false;
+	let mut is_symlink = false;
+	let mut is_broken = false;
 	let mut date = 0;
 	let mut attributes: String = "-".to_string();
-	if let Ok(metadata) = metadata {
+	if let Ok(metadata) = &metadata {
 		size = metadata.len();
-		is_dir = metadata.file_type().is_dir();
-		date = metadata
-			.modified()
-			.map(|t| t.duration_since(UNIX_EPOCH).unwrap_or_default())
-			.unwrap_or_default()
-			.as_millis();
+		is_symlink = metadata.file_type().is_symlink();
+		if is_symlink {
+			match path.metadata() {
+				Ok(target_metadata) => is_dir = target_metadata.is_dir(),
+				Err(_) => is_broken = true,
+			}
+		} else {
+			is_dir = metadata.is_dir();
+		}
+		date = get_date(metadata);
 		attributes = permissions_to_string(&metadata.permissions());
 	};
 	let (key, name, extension) = if let Some(name) = name {
@@ -355,7 +848,8 @@ fn get_local_file(path: &Path, name: Option<String>, active_key: &Option<String>
 				.unwrap_or_else(|| String::from("")),
 		)
 	};
-	FileInfo {
+	let is_archive = !is_dir && archive::get_format(path).is_some();
+	let mut file = FileInfo {
 		key: format!("{}{}", key, if is_dir { "/" } else { "" }),
 		is_directory: is_dir,
 		icon: format!(
@@ -371,13 +865,321 @@ fn get_local_file(path: &Path, name: Option<String>, active_key: &Option<String>
 		date,
 		attributes,
 		is_active: if let Some(active_key) = &active_key {
-			is_dir && active_key == &key
+			(is_dir || is_archive) && active_key == &key
 		} else {
 			false
 		},
+		is_hidden: key != ".." && key.starts_with('.'),
+		is_archive,
+		is_symlink,
+		is_broken,
+		link_target: if is_symlink {
+			fs::read_link(path)
+				.ok()
+				.map(|x| x.to_string_lossy().into_owned())
+		} else {
+			None
+		},
+		mime_type: if is_dir {
+			None
+		} else {
+			mime_guess::from_path(path).first_raw().map(String::from)
+		},
+		..Default::default()
+	};
+	if let Ok(metadata) = &metadata {
+		file.created = metadata.created().ok().map(get_timestamp);
+		file.accessed = metadata.accessed().ok().map(get_timestamp);
+		add_platform_details(&mut file, metadata);
 	}
+	file
+}
+
+#[cfg(target_os = "windows")]
+fn add_platform_details(file: &mut FileInfo, metadata: &Metadata) {
+	const FILE_ATTRIBUTE_HIDDEN: u32 = 0x2;
+	file.is_hidden = metadata.file_attributes() & FILE_ATTRIBUTE_HIDDEN != 0;
+}
+
+#[cfg(not(target_os = "windows"))]
+fn add_platform_details(file: &mut FileInfo, metadata: &Metadata) {
+	file.uid = Some(metadata.uid());
+	file.gid = Some(metadata.gid());
+	file.inode = Some(metadata.ino());
+	file.device = Some(metadata.dev());
+	file.links = Some(metadata.nlink());
+	// names are cached, as a listing mostly has a few distinct owners
+	USERS.with(|users| {
+		file.owner = users
+			.get_user_by_uid(metadata.uid())
+			.map(|user| user.name().to_string_lossy().into_owned());
+		file.group = users
+			.get_group_by_gid(metadata.gid())
+			.map(|group| group.name().to_string_lossy().into_owned());
+	});
+}
+
+fn get_date(metadata: &Metadata) -> i64 {
+	metadata.modified().map(get_timestamp).unwrap_or_default()
 }
 
 fn trim_long_path_prefix(path: &str) -> &str {
 	path.strip_prefix("\\\\?\\").unwrap_or(path)
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::testing::TempDir;
+	use serde_json::json;
+
+	fn write_request(dir: &TempDir, name: &str, request: serde_json::Value) -> WriteRequest {
+		let mut request = request;
+		request["path"] = json!(dir.path());
+		request["key"] = json!(name);
+		serde_json::from_value(request).unwrap()
+	}
+
+	fn get_names(dir: &TempDir) -> Vec<String> {
+		let mut names = fs::read_dir(dir.path())
+			.unwrap()
+			.map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+			.filter(|name| name != "data")
+			.collect::<Vec<_>>();
+		names.sort();
+		names
+	}
+
+	#[test]
+	fn write_replaces_files() {
+		let dir = TempDir::new();
+		let path = dir.write("a", "old contents");
+		#[cfg(not(target_os = "windows"))]
+		fs::set_permissions(&path, Permissions::from_mode(0o640)).unwrap();
+		let request = write_request(&dir, "a", json!({ "content": "new", "date": 1_000_000 }));
+		let response = write(request).unwrap();
+		assert_eq!(dir.read("a"), "new");
+		assert_eq!((response.file.size, response.file.date), (3, 1_000_000));
+		// the temporary file was moved over the file, which keeps its permissions
+		assert_eq!(get_names(&dir), ["a"]);
+		#[cfg(not(target_os = "windows"))]
+		assert_eq!(
+			fs::metadata(&path).unwrap().permissions().mode() & 0o777,
+			0o640
+		);
+	}
+
+	#[test]
+	fn write_backups() {
+		let dir = TempDir::new();
+		write(write_request(
+			&dir,
+			"a",
+			json!({ "content": "1", "backup": true }),
+		))
+		.unwrap();
+		// new files have nothing to back up
+		assert_eq!(get_names(&dir), ["a"]);
+		write(write_request(
+			&dir,
+			"a",
+			json!({ "content": "2", "backup": true }),
+		))
+		.unwrap();
+		write(write_request(
+			&dir,
+			"a",
+			json!({ "content": "3", "backup": true }),
+		))
+		.unwrap();
+		assert_eq!(dir.read("a"), "3");
+		assert_eq!(dir.read("a~"), "2");
+		assert_eq!(get_names(&dir), ["a", "a~"]);
+	}
+
+	#[test]
+	fn write_preconditions() {
+		let dir = TempDir::new();
+		let path = dir.write("a", "old");
+		let date = get_date(&fs::metadata(&path).unwrap());
+		let write_expecting = |expected| {
+			write(write_request(
+				&dir,
+				"a",
+				json!({ "content": "new", "expected": expected }),
+			))
+		};
+		for expected in [json!({ "size": 4 }), json!({ "date": date - 1, "size": 3 })] {
+			let err = write_expecting(expected).unwrap_err();
+			assert_eq!(err.code, ErrorCode::Conflict);
+			assert_eq!(dir.read("a"), "old");
+		}
+		write_expecting(json!({ "date": date, "size": 3 })).unwrap();
+		assert_eq!(dir.read("a"), "new");
+
+		// missing files are expected without date and size
+		fs::remove_file(&path).unwrap();
+		let err = write_expecting(json!({ "size": 3 })).unwrap_err();
+		assert_eq!(err.code, ErrorCode::Conflict);
+		assert!(!path.exists());
+		write_expecting(json!({})).unwrap();
+		assert_eq!(dir.read("a"), "new");
+	}
+
+	#[test]
+	fn write_appending() {
+		let dir = TempDir::new();
+		let append = |content| json!({ "content": content, "append": true });
+		write(write_request(&dir, "a", append("ab"))).unwrap();
+		write(write_request(&dir, "a", append("c"))).unwrap();
+		assert_eq!(dir.read("a"), "abc");
+		let part = json!({ "content": "d", "part": { "number": 1, "last": true } });
+		let err = write(write_request(&dir, "a", part)).unwrap_err();
+		assert_eq!(err.code, ErrorCode::Unsupported);
+	}
+
+	#[test]
+	fn list_files_in_pages() {
+		let dir = TempDir::new();
+		for name in ["a", "b", "c", "d", "e"] {
+			dir.write(&format!("list/{}", name), "");
+		}
+		let list_page = |cursor| {
+			let request = ListRequest {
+				path: Some(dir.join("list").to_string_lossy().to_string()),
+				key: None,
+				cursor,
+				limit: Some(2),
+			};
+			let response = list_files(request).unwrap();
+			let keys = response.files.into_iter().map(|file| file.key);
+			(keys.collect::<Vec<_>>(), response.cursor)
+		};
+		let (mut keys, mut cursor) = list_page(None);
+		assert_eq!(keys.len(), 3);
+		assert_eq!(keys.remove(0), "../");
+		// later pages are taken from the entries seen for the first page, without the
+		// deleted ones
+		let deleted = ["a", "b", "c", "d", "e"]
+			.into_iter()
+			.find(|name| !keys.iter().any(|key| key == name))
+			.unwrap();
+		fs::remove_file(dir.join("list").join(deleted)).unwrap();
+		dir.write("list/f", "");
+		while let Some(next) = cursor {
+			let page;
+			(page, cursor) = list_page(Some(next));
+			assert!(page.len() <= 2);
+			keys.extend(page);
+		}
+		keys.sort();
+		let mut expected = vec!["a", "b", "c", "d", "e"];
+		expected.retain(|name| *name != deleted);
+		assert_eq!(keys, expected);
+	}
+
+	#[test]
+	fn capabilities_name_requests() {
+		let capabilities = FsBackend.capabilities();
+		assert_eq!(capabilities.protocol_version, PROTOCOL_VERSION);
+		assert!(capabilities.requests.contains(&RequestKind::Capabilities));
+		// every request listed is one the server parses
+		for kind in capabilities.requests {
+			let name = serde_json::to_value(kind).unwrap();
+			let name = name.as_str().unwrap();
+			let err = serde_json::from_value::<xcmd_base::Request>(json!({ name: {} }))
+				.err()
+				.map(|err| err.to_string())
+				.unwrap_or_default();
+			assert!(!err.contains("unknown variant"), "{}", name);
+		}
+	}
+
+	fn stat_entry(dir: &TempDir, name: &str) -> Result<FileInfo, ErrorResponse> {
+		let request = StatRequest {
+			path: Some(dir.path().to_string_lossy().to_string()),
+			key: Some(name.to_string()),
+		};
+		stat(request).map(|response| response.file)
+	}
+
+	#[test]
+	fn stat_entries() {
+		let dir = TempDir::new();
+		dir.write("a.txt", "abc");
+		fs::create_dir(dir.join("d")).unwrap();
+		let file = stat_entry(&dir, "a.txt").unwrap();
+		assert_eq!((file.key.as_str(), file.name.as_str()), ("a.txt", "a"));
+		assert_eq!((file.extension.as_str(), file.size), ("txt", 3));
+		assert_eq!(file.mime_type.as_deref(), Some("text/plain"));
+		assert!(file.date > 0 && file.accessed.is_some());
+		let directory = stat_entry(&dir, "d").unwrap();
+		assert_eq!(directory.key, "d/");
+		assert!(directory.is_directory && directory.mime_type.is_none());
+		let err = stat_entry(&dir, "missing").unwrap_err();
+		assert_eq!(err.code, ErrorCode::NotFound);
+	}
+
+	#[cfg(not(target_os = "windows"))]
+	#[test]
+	fn stat_owners_and_links() {
+		let dir = TempDir::new();
+		let path = dir.write("a", "");
+		fs::hard_link(&path, dir.join("b")).unwrap();
+		let file = stat_entry(&dir, "a").unwrap();
+		let metadata = fs::metadata(&path).unwrap();
+		assert_eq!(file.uid, Some(metadata.uid()));
+		assert_eq!(file.gid, Some(metadata.gid()));
+		assert!(file.owner.is_some());
+		assert_eq!(file.links, Some(2));
+		// hard links are the same file
+		let link = stat_entry(&dir, "b").unwrap();
+		assert_eq!((link.inode, link.device), (file.inode, file.device));
+	}
+
+	fn link_request(dir: &TempDir, name: &str, target: &str, kind: LinkKind) -> CreateLinkRequest {
+		CreateLinkRequest {
+			path: Some(dir.path().to_string_lossy().to_string()),
+			name: name.to_string(),
+			target: target.to_string(),
+			kind,
+		}
+	}
+
+	#[cfg(not(target_os = "windows"))]
+	#[test]
+	fn create_symlinks() {
+		let dir = TempDir::new();
+		dir.write("d/a.txt", "abc");
+		let response = create_link(link_request(&dir, "l", "d", LinkKind::Symbolic)).unwrap();
+		// links to directories are listed as directories
+		assert_eq!(response.file.key, "l/");
+		assert!(response.file.is_directory && response.file.is_symlink);
+		assert_eq!(response.file.link_target.as_deref(), Some("d"));
+		// relative targets are kept as they are
+		assert_eq!(fs::read_link(dir.join("l")).unwrap(), Path::new("d"));
+		assert_eq!(dir.read("l/a.txt"), "abc");
+
+		let response =
+			create_link(link_request(&dir, "broken", "missing", LinkKind::Symbolic)).unwrap();
+		assert!(response.file.is_symlink && response.file.is_broken);
+		assert!(!response.file.is_directory);
+
+		let err = create_link(link_request(&dir, "l", "d", LinkKind::Symbolic)).unwrap_err();
+		assert_eq!(err.code, ErrorCode::AlreadyExists);
+		let err = create_link(link_request(&dir, "empty", "", LinkKind::Symbolic)).unwrap_err();
+		assert_eq!(err.code, ErrorCode::InvalidInput);
+	}
+
+	#[cfg(not(target_os = "windows"))]
+	#[test]
+	fn create_hard_links() {
+		let dir = TempDir::new();
+		dir.write("a", "abc");
+		let response = create_link(link_request(&dir, "b", "a", LinkKind::Hard)).unwrap();
+		assert!(!response.file.is_symlink);
+		assert_eq!((response.file.size, response.file.links), (3, Some(2)));
+		dir.write("a", "new");
+		assert_eq!(dir.read("b"), "new");
+	}
+}
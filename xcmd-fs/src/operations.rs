@@ -0,0 +1,680 @@
+use crate::journal::{OperationKind, Recorder};
+use crate::trash;
+use crate::{get_local_file, get_paths};
+#[cfg(not(target_os = "windows"))]
+use std::collections::HashSet;
+use std::fs::{self, File, Metadata};
+use std::io::{self, Read, Write};
+#[cfg(not(target_os = "windows"))]
+use std::os::unix::fs::MetadataExt;
+use std::path::{Component, Path, PathBuf};
+use tracing::trace;
+use xcmd_base::{
+	DeleteRequest, DirectorySizeRequest, ErrorCode, ErrorContext, ErrorResponse, Job,
+	TransferRequest,
+};
+
+type JobResult<T> = Result<T, ErrorResponse>;
+
+const BUFFER_SIZE: usize = 1024 * 1024;
+
+pub fn copy(request: TransferRequest, job: &Job) -> JobResult<()> {
+	let transfers = get_transfers(&request)?;
+
+	for (source, destination) in &transfers {
+		let (resolved_source, resolved_destination) =
+			(resolve_parent(source), resolve_parent(destination));
+		let message = if resolved_destination == resolved_source {
+			"source and destination are the same"
+		} else if resolved_destination.starts_with(&resolved_source) {
+			"cannot copy a directory into itself"
+		} else {
+			continue;
+		};
+		return Err(ErrorResponse::new(ErrorCode::InvalidInput, message).with_path(source));
+	}
+
+	let (files, bytes) = measure_all(transfers.iter().map(|(source, _)| source), job)?;
+	job.update(|progress| {
+		progress.files_total = files;
+		progress.bytes_total = bytes;
+	});
+
+	let mut recorder = Recorder::new(OperationKind::Copy);
+	for (source, destination) in &transfers {
+		copy_entry(source, destination, request.overwrite, job, &mut recorder)?;
+	}
+	Ok(())
+}
+
+/// Resolves links and relative components in the parent of the path, so paths of
+/// the same entry compare equal; the entry itself may be missing or a link. Paths
+/// whose parent is missing are left as they are.
+fn resolve_parent(path: &Path) -> PathBuf {
+	let resolved = match (path.parent(), path.file_name()) {
+		(Some(parent), Some(name)) => parent.canonicalize().map(|parent| parent.join(name)),
+		_ => path.canonicalize(),
+	};
+	resolved.unwrap_or_else(|_| path.to_path_buf())
+}
+
+pub fn rename(request: TransferRequest, job: &Job) -> JobResult<()> {
+	let transfers = get_transfers(&request)?;
+	job.update(|progress| progress.files_total = transfers.len() as u64);
+
+	let mut recorder = Recorder::new(OperationKind::Rename);
+	for (done, (source, destination)) in transfers.iter().enumerate() {
+		job.checkpoint()?;
+		job.update(|progress| progress.current = Some(source.to_string_lossy().to_string()));
+		check_overwrite(destination, request.overwrite)?;
+		trash_replaced(destination, &mut recorder, job)?;
+
+		match fs::rename(source, destination) {
+			Ok(()) => {}
+			Err(err) if err.kind() == io::ErrorKind::CrossesDevices => {
+				// moves between file systems by copying and deleting the source
+				let mut disabled = Recorder::disabled();
+				copy_entry(source, destination, request.overwrite, job, &mut disabled)?;
+				remove_entry(source, job)?;
+			}
+			Err(err) => return Err(ErrorResponse::from(err).with_path(source)),
+		}
+		recorder.moved(source, destination);
+		job.update(|progress| progress.files_done = done as u64 + 1);
+	}
+	Ok(())
+}
+
+pub fn delete(request: DeleteRequest, job: &Job) -> JobResult<()> {
+	let (path, _) = get_paths(&request.path, &None);
+	if let Some(relative) = trash::split_path(&path) {
+		return trash::delete(&relative, &request.keys, job);
+	}
+	let sources = request
+		.keys
+		.iter()
+		.map(|key| get_entry_path(&path, key))
+		.collect::<JobResult<Vec<_>>>()?;
+
+	let counts = sources
+		.iter()
+		.map(|source| measure_all(std::iter::once(source), job).map(|(files, _)| files))
+		.collect::<JobResult<Vec<_>>>()?;
+	job.update(|progress| progress.files_total = counts.iter().sum());
+
+	// where there is no trash, such as on windows, moving to it fails rather than
+	// deleting for good
+	let mut recorder = Recorder::new(OperationKind::Delete);
+	for (source, files) in sources.iter().zip(counts) {
+		job.checkpoint()?;
+		job.update(|progress| progress.current = Some(source.to_string_lossy().to_string()));
+		if request.permanent {
+			remove_entry(source, job)?;
+			recorder.deleted(source);
+		} else {
+			let (file, info) = trash::trash(source)?;
+			recorder.trashed(source, &file, &info);
+			job.update(|progress| progress.files_done += files);
+		}
+	}
+	Ok(())
+}
+
+pub fn directory_size(request: DirectorySizeRequest, job: &Job) -> JobResult<()> {
+	let (path, _) = get_paths(&request.path, &None);
+	let sources = request
+		.keys
+		.iter()
+		.map(|key| get_entry_path(&path, key))
+		.collect::<JobResult<Vec<_>>>()?;
+
+	// hard links are counted once in each entry; the progress is the grand total of the
+	// entries, so it counts links found in several entries once
+	let mut grand_total = Totals::default();
+	for source in &sources {
+		let mut totals = Totals::default();
+		sum_entry(source, &mut totals, &mut grand_total, job)?;
+
+		let mut file = get_local_file(source, None, &None);
+		file.size = totals.size;
+		file.allocated_size = Some(totals.allocated_size);
+		file.file_count = Some(totals.files);
+		// the measured directory itself is not counted
+		file.directory_count = Some(totals.directories.saturating_sub(1));
+		job.add_result(xcmd_base::JobResult {
+			file,
+			..Default::default()
+		});
+	}
+	Ok(())
+}
+
+#[derive(Default)]
+struct Totals {
+	size: u64,
+	allocated_size: u64,
+	files: u64,
+	directories: u64,
+	#[cfg(not(target_os = "windows"))]
+	inodes: HashSet<(u64, u64)>,
+}
+
+impl Totals {
+	fn add_file(&mut self, metadata: &Metadata) {
+		self.size += metadata.len();
+		self.allocated_size += get_allocated_size(metadata);
+		self.files += 1;
+	}
+}
+
+/// Adds sizes of the entry to the totals of the measured entry and the grand total;
+/// subdirectories that cannot be read are skipped.
+fn sum_entry(
+	path: &Path,
+	totals: &mut Totals,
+	grand_total: &mut Totals,
+	job: &Job,
+) -> JobResult<()> {
+	job.checkpoint()?;
+	let metadata = fs::symlink_metadata(path).with_path(path)?;
+
+	if metadata.is_dir() {
+		totals.directories += 1;
+		job.update(|progress| progress.current = Some(path.to_string_lossy().to_string()));
+		for entry in fs::read_dir(path).with_path(path)? {
+			let entry_path = entry.with_path(path)?.path();
+			match sum_entry(&entry_path, totals, grand_total, job) {
+				Err(err) if err.code == ErrorCode::Cancelled => return Err(err),
+				Err(err) => trace!("directory size error: {}", err),
+				Ok(()) => {}
+			}
+		}
+	} else {
+		if is_first_link(&metadata, totals) {
+			totals.add_file(&metadata);
+		}
+		if is_first_link(&metadata, grand_total) {
+			grand_total.add_file(&metadata);
+			job.update(|progress| {
+				progress.files_done += 1;
+				progress.bytes_done += metadata.len();
+			});
+		}
+	}
+	Ok(())
+}
+
+#[cfg(not(target_os = "windows"))]
+fn is_first_link(metadata: &Metadata, totals: &mut Totals) -> bool {
+	metadata.nlink() <= 1 || totals.inodes.insert((metadata.dev(), metadata.ino()))
+}
+
+#[cfg(target_os = "windows")]
+fn is_first_link(_metadata: &Metadata, _totals: &mut Totals) -> bool {
+	true
+}
+
+#[cfg(not(target_os = "windows"))]
+fn get_allocated_size(metadata: &Metadata) -> u64 {
+	// blocks are counted in units of 512 bytes, regardless of the block size
+	metadata.blocks() * 512
+}
+
+#[cfg(target_os = "windows")]
+fn get_allocated_size(metadata: &Metadata) -> u64 {
+	metadata.len()
+}
+
+/// Pairs each source entry with its destination path.
+fn get_transfers(request: &TransferRequest) -> JobResult<Vec<(PathBuf, PathBuf)>> {
+	let (path, _) = get_paths(&request.path, &None);
+	let destination = Path::new(&request.destination);
+
+	if let Some(names) = &request.names {
+		if names.len() != request.keys.len() {
+			return Err(ErrorResponse::new(
+				ErrorCode::InvalidInput,
+				"number of names does not match number of keys",
+			));
+		}
+	}
+
+	request
+		.keys
+		.iter()
+		.enumerate()
+		.map(|(index, key)| {
+			let source = get_entry_path(&path, key)?;
+			let name = match &request.names {
+				Some(names) => get_entry_path(destination, &names[index])?,
+				None => destination.join(source.file_name().unwrap_or_default()),
+			};
+			Ok((source, name))
+		})
+		.collect()
+}
+
+/// Joins the key to the path; keys pointing outside of the directory are rejected.
+pub fn get_entry_path(path: &Path, key: &str) -> JobResult<PathBuf> {
+	let key = key.trim_end_matches('/');
+	let is_valid = !key.is_empty()
+		&& Path::new(key)
+			.components()
+			.all(|component| matches!(component, Component::Normal(_)));
+	if is_valid {
+		Ok(path.join(key))
+	} else {
+		Err(ErrorResponse::new(
+			ErrorCode::InvalidInput,
+			format!("invalid key '{}'", key),
+		))
+	}
+}
+
+/// Counts files and bytes in the entries, so the progress has known totals.
+fn measure_all<'a>(paths: impl Iterator<Item = &'a PathBuf>, job: &Job) -> JobResult<(u64, u64)> {
+	let mut totals = (0, 0);
+	for path in paths {
+		measure(path, &mut totals, job)?;
+	}
+	Ok(totals)
+}
+
+fn measure(path: &Path, totals: &mut (u64, u64), job: &Job) -> JobResult<()> {
+	job.checkpoint()?;
+	let metadata = fs::symlink_metadata(path).with_path(path)?;
+	if metadata.is_dir() {
+		for entry in fs::read_dir(path).with_path(path)? {
+			measure(&entry.with_path(path)?.path(), totals, job)?;
+		}
+	} else {
+		totals.0 += 1;
+		totals.1 += metadata.len();
+	}
+	Ok(())
+}
+
+pub fn check_overwrite(destination: &Path, overwrite: bool) -> JobResult<()> {
+	if !overwrite && fs::symlink_metadata(destination).is_ok() {
+		Err(
+			ErrorResponse::new(ErrorCode::AlreadyExists, "destination already exists")
+				.with_path(destination),
+		)
+	} else {
+		Ok(())
+	}
+}
+
+/// Copies the entry; new entries are recorded, except for the contents of new
+/// directories, which are reverted with the directory.
+pub fn copy_entry(
+	source: &Path,
+	destination: &Path,
+	overwrite: bool,
+	job: &Job,
+	recorder: &mut Recorder,
+) -> JobResult<()> {
+	job.checkpoint()?;
+	let metadata = fs::symlink_metadata(source).with_path(source)?;
+	job.update(|progress| progress.current = Some(source.to_string_lossy().to_string()));
+
+	if metadata.is_dir() {
+		// copying into an existing directory merges the contents
+		let mut disabled = Recorder::disabled();
+		let recorder = if destination.is_dir() {
+			recorder
+		} else {
+			fs::create_dir(destination).with_path(destination)?;
+			recorder.created(destination);
+			&mut disabled
+		};
+		for entry in fs::read_dir(source).with_path(source)? {
+			let entry = entry.with_path(source)?;
+			copy_entry(
+				&entry.path(),
+				&destination.join(entry.file_name()),
+				overwrite,
+				job,
+				recorder,
+			)?;
+		}
+		fs::set_permissions(destination, metadata.permissions()).with_path(destination)?;
+	} else {
+		check_overwrite(destination, overwrite)?;
+		trash_replaced(destination, recorder, job)?;
+		recorder.created(destination);
+		if metadata.file_type().is_symlink() {
+			copy_link(source, destination).with_path(destination)?;
+		} else if let Err(err) = copy_file(source, destination, &metadata, job) {
+			// does not leave partially copied files behind
+			fs::remove_file(destination).ok();
+			return Err(err);
+		}
+		job.update(|progress| progress.files_done += 1);
+	}
+	Ok(())
+}
+
+fn copy_file(source: &Path, destination: &Path, metadata: &Metadata, job: &Job) -> JobResult<()> {
+	let mut reader = File::open(source).with_path(source)?;
+	let mut writer = File::create(destination).with_path(destination)?;
+	let mut buffer = vec![0; BUFFER_SIZE];
+	loop {
+		job.checkpoint()?;
+		let count = reader.read(&mut buffer).with_path(source)?;
+		if count == 0 {
+			break;
+		}
+		writer.write_all(&buffer[..count]).with_path(destination)?;
+		job.update(|progress| progress.bytes_done += count as u64);
+	}
+	writer
+		.set_permissions(metadata.permissions())
+		.with_path(destination)?;
+	if let Ok(modified) = metadata.modified() {
+		writer.set_modified(modified).with_path(destination)?;
+	}
+	Ok(())
+}
+
+#[cfg(not(target_os = "windows"))]
+pub fn copy_link(source: &Path, destination: &Path) -> io::Result<()> {
+	let target = fs::read_link(source)?;
+	if fs::symlink_metadata(destination).is_ok() {
+		fs::remove_file(destination)?;
+	}
+	std::os::unix::fs::symlink(target, destination)
+}
+
+#[cfg(target_os = "windows")]
+pub fn copy_link(source: &Path, destination: &Path) -> io::Result<()> {
+	fs::copy(source, destination).map(|_| ())
+}
+
+/// Moves a file or link that is about to be replaced to the trash of the journal;
+/// directories are merged rather than replaced. Entries that cannot be kept are
+/// reported as results, as they cannot be restored.
+fn trash_replaced(destination: &Path, recorder: &mut Recorder, job: &Job) -> JobResult<()> {
+	match fs::symlink_metadata(destination) {
+		Ok(metadata) if !metadata.is_dir() => {
+			if !recorder.trash(destination).with_path(destination)? {
+				job.add_result(xcmd_base::JobResult {
+					file: get_local_file(destination, None, &None),
+					error: Some(
+						ErrorResponse::new(
+							ErrorCode::Io,
+							"replaced entry was not kept, so it cannot be restored",
+						)
+						.with_path(destination),
+					),
+					..Default::default()
+				});
+			}
+			Ok(())
+		}
+		_ => Ok(()),
+	}
+}
+
+pub fn remove_entry(path: &Path, job: &Job) -> JobResult<()> {
+	job.checkpoint()?;
+	let metadata = fs::symlink_metadata(path).with_path(path)?;
+	job.update(|progress| progress.current = Some(path.to_string_lossy().to_string()));
+
+	if metadata.is_dir() {
+		for entry in fs::read_dir(path).with_path(path)? {
+			remove_entry(&entry.with_path(path)?.path(), job)?;
+		}
+		fs::remove_dir(path).with_path(path)?;
+	} else {
+		fs::remove_file(path).with_path(path)?;
+		job.update(|progress| progress.files_done += 1);
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::journal;
+	use crate::testing::TempDir;
+	use std::{env, process};
+	use xcmd_base::{JobKind, UndoRequest};
+
+	fn transfer(
+		dir: &TempDir,
+		keys: &[&str],
+		destination: &str,
+		overwrite: bool,
+	) -> TransferRequest {
+		TransferRequest {
+			path: Some(dir.path().to_string_lossy().to_string()),
+			keys: keys.iter().map(|key| key.to_string()).collect(),
+			destination: dir.join(destination).to_string_lossy().to_string(),
+			names: None,
+			overwrite,
+		}
+	}
+
+	fn undo_last() -> Result<(), ErrorResponse> {
+		journal::undo(UndoRequest { count: None }, &Job::new(0, JobKind::Undo))
+	}
+
+	/// Gets a directory on another file system than the one of the test, if there is one.
+	#[cfg(not(target_os = "windows"))]
+	fn get_other_device_dir(dir: &TempDir) -> Option<PathBuf> {
+		let shm = Path::new("/dev/shm");
+		let device = |path: &Path| fs::metadata(path).ok().map(|metadata| metadata.dev());
+		(device(shm)? != device(dir.path())?)
+			.then(|| shm.join(format!("xcmd-fs-test-{}", process::id())))
+	}
+
+	fn get_message(result: JobResult<()>) -> String {
+		result.unwrap_err().message
+	}
+
+	#[cfg(not(target_os = "windows"))]
+	#[test]
+	fn directory_size_of_hard_links() {
+		let dir = TempDir::new();
+		dir.write("a/x", "xxx");
+		dir.write("b/y", "yy");
+		fs::hard_link(dir.join("a/x"), dir.join("a/x2")).unwrap();
+		fs::hard_link(dir.join("a/x"), dir.join("b/x")).unwrap();
+		let request = DirectorySizeRequest {
+			path: Some(dir.path().to_string_lossy().to_string()),
+			keys: vec!["a".to_string(), "b".to_string()],
+		};
+		let job = Job::new(0, JobKind::DirectorySize);
+		directory_size(request, &job).unwrap();
+		let sizes = job
+			.results(0, usize::MAX)
+			.iter()
+			.map(|result| (result.file.size, result.file.file_count))
+			.collect::<Vec<_>>();
+		// links are counted once in each entry, also when counted in another one
+		assert_eq!(sizes, [(3, Some(1)), (5, Some(2))]);
+		let progress = job.status().progress;
+		assert_eq!((progress.files_done, progress.bytes_done), (2, 5));
+	}
+
+	#[test]
+	fn copy_files_and_directories() {
+		let dir = TempDir::new();
+		dir.write("a/x", "x");
+		dir.write("a/sub/y", "y");
+		dir.write("b/a/z", "z");
+		let job = Job::new(0, JobKind::Copy);
+		copy(transfer(&dir, &["a"], "b", false), &job).unwrap();
+		// directories are merged
+		assert_eq!(dir.read("b/a/x"), "x");
+		assert_eq!(dir.read("b/a/sub/y"), "y");
+		assert_eq!(dir.read("b/a/z"), "z");
+		assert_eq!(dir.read("a/x"), "x");
+		let progress = job.status().progress;
+		assert_eq!((progress.files_done, progress.bytes_done), (2, 2));
+	}
+
+	#[test]
+	fn copy_overwrite() {
+		let dir = TempDir::new();
+		dir.write("a/x", "new");
+		dir.write("b/x", "old");
+		let job = Job::new(0, JobKind::Copy);
+		let err = copy(transfer(&dir, &["a/x"], "b", false), &job).unwrap_err();
+		assert_eq!(err.code, ErrorCode::AlreadyExists);
+		assert_eq!(dir.read("b/x"), "old");
+
+		copy(transfer(&dir, &["a/x"], "b", true), &job).unwrap();
+		assert_eq!(dir.read("b/x"), "new");
+		// the replaced file is kept by the journal
+		undo_last().unwrap();
+		assert_eq!(dir.read("b/x"), "old");
+	}
+
+	#[test]
+	fn copy_onto_itself() {
+		let dir = TempDir::new();
+		dir.write("a/x", "x");
+		let job = Job::new(0, JobKind::Copy);
+		assert_eq!(
+			get_message(copy(transfer(&dir, &["a/x"], "a", true), &job)),
+			"source and destination are the same"
+		);
+		assert_eq!(
+			get_message(copy(transfer(&dir, &["a"], "a/sub", false), &job)),
+			"cannot copy a directory into itself"
+		);
+		assert_eq!(
+			get_message(copy(transfer(&dir, &["a"], "a/../a/sub", false), &job)),
+			"cannot copy a directory into itself"
+		);
+		// names sharing a prefix are different entries
+		fs::create_dir(dir.join("ab")).unwrap();
+		copy(transfer(&dir, &["a"], "ab", false), &job).unwrap();
+		assert_eq!(dir.read("ab/a/x"), "x");
+		assert_eq!(dir.read("a/x"), "x");
+	}
+
+	#[test]
+	#[cfg(not(target_os = "windows"))]
+	fn copy_into_itself_through_link() {
+		let dir = TempDir::new();
+		dir.write("a/x", "x");
+		std::os::unix::fs::symlink(dir.join("a"), dir.join("link")).unwrap();
+		let job = Job::new(0, JobKind::Copy);
+		assert_eq!(
+			get_message(copy(transfer(&dir, &["a"], "link", false), &job)),
+			"cannot copy a directory into itself"
+		);
+		assert!(!dir.join("a/a").exists());
+	}
+
+	#[test]
+	fn rename_entries() {
+		let dir = TempDir::new();
+		dir.write("a/x", "x");
+		dir.write("b/x", "old");
+		let job = Job::new(0, JobKind::Rename);
+		let mut request = transfer(&dir, &["a/x"], "b", false);
+		let err = rename(request, &job).unwrap_err();
+		assert_eq!(err.code, ErrorCode::AlreadyExists);
+
+		request = transfer(&dir, &["a/x"], "b", false);
+		request.names = Some(vec!["y".to_string()]);
+		rename(request, &job).unwrap();
+		assert!(!dir.join("a/x").exists());
+		assert_eq!(dir.read("b/y"), "x");
+		undo_last().unwrap();
+		assert_eq!(dir.read("a/x"), "x");
+		assert!(!dir.join("b/y").exists());
+	}
+
+	#[test]
+	#[cfg(not(target_os = "windows"))]
+	fn rename_to_other_device() {
+		let dir = TempDir::new();
+		let Some(other_dir) = get_other_device_dir(&dir) else {
+			return;
+		};
+		fs::create_dir_all(&other_dir).unwrap();
+		dir.write("a/x", "x");
+		dir.write("a/sub/y", "y");
+		let job = Job::new(0, JobKind::Rename);
+		let mut request = transfer(&dir, &["a"], "", false);
+		request.destination = other_dir.to_string_lossy().to_string();
+		let renamed = rename(request, &job);
+		let moved = fs::read_to_string(other_dir.join("a/sub/y"));
+		let undone = undo_last();
+		fs::remove_dir_all(&other_dir).ok();
+
+		renamed.unwrap();
+		assert_eq!(moved.unwrap(), "y");
+		undone.unwrap();
+		assert_eq!(dir.read("a/x"), "x");
+		assert_eq!(dir.read("a/sub/y"), "y");
+	}
+
+	#[test]
+	fn delete_permanently() {
+		let dir = TempDir::new();
+		dir.write("a/x", "x");
+		dir.write("b", "b");
+		let request = DeleteRequest {
+			path: Some(dir.path().to_string_lossy().to_string()),
+			keys: vec!["a".to_string(), "b".to_string()],
+			permanent: true,
+		};
+		let job = Job::new(0, JobKind::Delete);
+		delete(request, &job).unwrap();
+		assert!(!dir.join("a").exists());
+		assert!(!dir.join("b").exists());
+		assert_eq!(job.status().progress.files_done, 2);
+		// entries deleted for good are reported as not restored
+		let undo_job = Job::new(0, JobKind::Undo);
+		journal::undo(UndoRequest { count: None }, &undo_job).unwrap();
+		let results = undo_job.results(0, 10);
+		assert_eq!(results.len(), 2);
+		assert!(results
+			.iter()
+			.all(|result| result.error.as_ref().unwrap().code == ErrorCode::NotFound));
+	}
+
+	#[test]
+	#[cfg(not(target_os = "windows"))]
+	fn overwrite_keeps_replaced_file_on_other_device() {
+		let dir = TempDir::new();
+		let Some(data_dir) = get_other_device_dir(&dir) else {
+			return;
+		};
+		env::set_var("XDG_DATA_HOME", &data_dir);
+		dir.write("a/x", "new");
+		dir.write("b/x", "old");
+		let job = Job::new(0, JobKind::Copy);
+		copy(transfer(&dir, &["a/x"], "b", true), &job).unwrap();
+		assert_eq!(dir.read("b/x"), "new");
+		assert!(job.results(0, 10).is_empty());
+
+		undo_last().unwrap();
+		fs::remove_dir_all(&data_dir).ok();
+		assert_eq!(dir.read("b/x"), "old");
+	}
+
+	#[test]
+	fn overwrite_reports_replaced_file_not_kept() {
+		let dir = TempDir::new();
+		// the data directory cannot be created within a file
+		env::set_var("XDG_DATA_HOME", dir.write("data-file", "").join("data"));
+		dir.write("a/x", "new");
+		dir.write("b/x", "old");
+		let job = Job::new(0, JobKind::Copy);
+		copy(transfer(&dir, &["a/x"], "b", true), &job).unwrap();
+		assert_eq!(dir.read("b/x"), "new");
+		let results = job.results(0, 10);
+		assert_eq!(results.len(), 1);
+		assert_eq!(
+			results[0].error.as_ref().and_then(|err| err.path.clone()),
+			Some(dir.join("b/x").to_string_lossy().to_string())
+		);
+	}
+}
@@ -0,0 +1,555 @@
+use crate::operations::{check_overwrite, get_entry_path};
+use crate::{get_local_file, get_paths};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use std::fs::{self, File, Metadata};
+use std::io::{self, BufWriter, Read, Write};
+#[cfg(not(target_os = "windows"))]
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::{SystemTime, UNIX_EPOCH};
+use xcmd_base::{
+	ArchiveFormat, ErrorCode, ErrorContext, ErrorResponse, Job, JobResult, PackRequest,
+};
+use xz2::write::XzEncoder;
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+/// Packs the entries into an archive. The archive is written to a temporary file
+/// first, so an incomplete archive is never left behind; it is the result of the job.
+pub fn pack(request: PackRequest, job: &Job) -> Result<(), ErrorResponse> {
+	let (path, _) = get_paths(&request.path, &None);
+	let destination = get_entry_path(&path, &request.name)?;
+	let format = request
+		.format
+		.or_else(|| ArchiveFormat::from_name(&request.name))
+		.ok_or_else(|| {
+			ErrorResponse::new(ErrorCode::InvalidInput, "archive format is not recognized")
+		})?;
+	check_level(format, request.level)?;
+	check_overwrite(&destination, request.overwrite)?;
+	let exclude = get_patterns(&request.exclude)?;
+
+	// collects the entries first, so the progress has known totals
+	let mut entries = Vec::new();
+	for key in &request.keys {
+		let source = get_entry_path(&path, key)?;
+		let name = key.trim_end_matches('/').replace('\\', "/");
+		collect(&source, name, &destination, &exclude, &mut entries, job)?;
+	}
+	let files = entries.iter().filter(|entry| entry.metadata.is_file());
+	let (files_total, bytes_total) = files.fold((0, 0), |(count, size), entry| {
+		(count + 1, size + entry.metadata.len())
+	});
+	job.update(|progress| {
+		progress.files_total = files_total;
+		progress.bytes_total = bytes_total;
+	});
+
+	let packer = Packer {
+		request: &request,
+		time: SystemTime::now(),
+		job,
+	};
+	let file_name = destination
+		.file_name()
+		.map(|x| x.to_string_lossy().to_string())
+		.unwrap_or_default();
+	let temp_path = destination.with_file_name(format!(".{}.{}.tmp", file_name, process::id()));
+	let written = packer
+		.write(&temp_path, format, &entries)
+		.and_then(|()| fs::rename(&temp_path, &destination).with_path(&destination));
+	if let Err(err) = written {
+		fs::remove_file(&temp_path).ok();
+		return Err(err);
+	}
+
+	job.add_result(JobResult {
+		file: get_local_file(&destination, None, &None),
+		..Default::default()
+	});
+	Ok(())
+}
+
+struct Entry {
+	path: PathBuf,
+	/// Path within the archive, with `/` as separator.
+	name: String,
+	metadata: Metadata,
+}
+
+/// Collects the entry and the contents of directories in a stable order; links are
+/// not followed.
+fn collect(
+	path: &Path,
+	name: String,
+	destination: &Path,
+	exclude: &GlobSet,
+	entries: &mut Vec<Entry>,
+	job: &Job,
+) -> Result<(), ErrorResponse> {
+	job.checkpoint()?;
+	let file_name = name.rsplit('/').next().unwrap_or_default();
+	if path == destination || exclude.is_match(file_name) || exclude.is_match(&name) {
+		return Ok(());
+	}
+	let metadata = fs::symlink_metadata(path).with_path(path)?;
+	let is_dir = metadata.is_dir();
+	entries.push(Entry {
+		path: path.to_path_buf(),
+		name: name.clone(),
+		metadata,
+	});
+
+	if is_dir {
+		job.update(|progress| progress.current = Some(path.to_string_lossy().to_string()));
+		let mut children = Vec::new();
+		for entry in fs::read_dir(path).with_path(path)? {
+			children.push(entry.with_path(path)?.file_name());
+		}
+		children.sort();
+		for child in children {
+			let child_name = format!("{}/{}", name, child.to_string_lossy());
+			collect(
+				&path.join(child),
+				child_name,
+				destination,
+				exclude,
+				entries,
+				job,
+			)?;
+		}
+	}
+	Ok(())
+}
+
+struct Packer<'a> {
+	request: &'a PackRequest,
+	/// Time of packing, stored when dates are not preserved.
+	time: SystemTime,
+	job: &'a Job,
+}
+
+impl Packer<'_> {
+	fn write(
+		&self,
+		path: &Path,
+		format: ArchiveFormat,
+		entries: &[Entry],
+	) -> Result<(), ErrorResponse> {
+		let writer = BufWriter::new(File::create(path).with_path(path)?);
+		let writer = match format {
+			ArchiveFormat::Zip => {
+				let mut zip = ZipWriter::new(writer);
+				for entry in entries {
+					self.add_zip_entry(&mut zip, entry)?;
+				}
+				zip.finish().map_err(io::Error::from).with_path(path)?
+			}
+			_ => {
+				let encoder = Encoder::new(writer, format, self.request.level).with_path(path)?;
+				let mut builder = tar::Builder::new(encoder);
+				for entry in entries {
+					self.add_tar_entry(&mut builder, entry)?;
+				}
+				builder
+					.into_inner()
+					.and_then(Encoder::finish)
+					.with_path(path)?
+			}
+		};
+		writer
+			.into_inner()
+			.map_err(|err| err.into_error())
+			.and_then(|file| file.sync_all())
+			.with_path(path)
+	}
+
+	fn add_tar_entry<W: Write>(
+		&self,
+		builder: &mut tar::Builder<W>,
+		entry: &Entry,
+	) -> Result<(), ErrorResponse> {
+		self.start_entry(entry)?;
+		let mut header = tar::Header::new_gnu();
+		header.set_metadata(&entry.metadata);
+		header.set_mode(self.get_mode(&entry.metadata));
+		header.set_mtime(self.get_seconds(&entry.metadata));
+
+		let file_type = entry.metadata.file_type();
+		let added = if file_type.is_symlink() {
+			let target = fs::read_link(&entry.path).with_path(&entry.path)?;
+			header.set_entry_type(tar::EntryType::Symlink);
+			header.set_size(0);
+			builder.append_link(&mut header, &entry.name, target)
+		} else if file_type.is_dir() {
+			builder.append_data(&mut header, format!("{}/", entry.name), io::empty())
+		} else if file_type.is_file() {
+			let file = File::open(&entry.path).with_path(&entry.path)?;
+			builder.append_data(&mut header, &entry.name, self.read(file))
+		} else {
+			// devices, pipes and sockets cannot be restored from an archive
+			return Ok(());
+		};
+		self.finish_entry(entry, added)
+	}
+
+	fn add_zip_entry<W: Write + io::Seek>(
+		&self,
+		zip: &mut ZipWriter<W>,
+		entry: &Entry,
+	) -> Result<(), ErrorResponse> {
+		self.start_entry(entry)?;
+		let level = self.request.level;
+		let options = FileOptions::default()
+			.compression_method(if level == Some(0) {
+				CompressionMethod::Stored
+			} else {
+				CompressionMethod::Deflated
+			})
+			.compression_level(level.filter(|level| *level > 0).map(|level| level as i32))
+			.unix_permissions(self.get_mode(&entry.metadata))
+			.last_modified_time(get_zip_date(self.get_seconds(&entry.metadata)))
+			.large_file(entry.metadata.len() >= u32::MAX as u64);
+
+		let file_type = entry.metadata.file_type();
+		let added = if file_type.is_symlink() {
+			let target = fs::read_link(&entry.path).with_path(&entry.path)?;
+			zip.add_symlink(&entry.name, target.to_string_lossy(), options)
+				.map_err(io::Error::from)
+		} else if file_type.is_dir() {
+			zip.add_directory(&entry.name, options)
+				.map_err(io::Error::from)
+		} else if file_type.is_file() {
+			let file = File::open(&entry.path).with_path(&entry.path)?;
+			zip.start_file(&entry.name, options)
+				.map_err(io::Error::from)
+				.and_then(|()| io::copy(&mut self.read(file), zip).map(|_| ()))
+		} else {
+			return Ok(());
+		};
+		self.finish_entry(entry, added)
+	}
+
+	fn start_entry(&self, entry: &Entry) -> Result<(), ErrorResponse> {
+		self.job.checkpoint()?;
+		self.job
+			.update(|progress| progress.current = Some(entry.path.to_string_lossy().to_string()));
+		Ok(())
+	}
+
+	/// Counts a packed file; reading fails when the job is cancelled, which is
+	/// reported as cancellation.
+	fn finish_entry(&self, entry: &Entry, added: io::Result<()>) -> Result<(), ErrorResponse> {
+		if let Err(err) = added {
+			self.job.checkpoint()?;
+			return Err(ErrorResponse::from(err).with_path(&entry.path));
+		}
+		if entry.metadata.is_file() {
+			self.job.update(|progress| progress.files_done += 1);
+		}
+		Ok(())
+	}
+
+	fn read<R: Read>(&self, reader: R) -> ProgressReader<'_, R> {
+		ProgressReader {
+			reader,
+			job: self.job,
+		}
+	}
+
+	fn get_mode(&self, metadata: &Metadata) -> u32 {
+		let mode = get_permissions(metadata);
+		if self.request.preserve_permissions {
+			mode
+		} else if metadata.is_dir() || mode & 0o111 != 0 {
+			0o755
+		} else {
+			0o644
+		}
+	}
+
+	fn get_seconds(&self, metadata: &Metadata) -> u64 {
+		let time = if self.request.preserve_dates {
+			metadata.modified().unwrap_or(self.time)
+		} else {
+			self.time
+		};
+		time.duration_since(UNIX_EPOCH)
+			.map_or(0, |duration| duration.as_secs())
+	}
+}
+
+#[cfg(not(target_os = "windows"))]
+fn get_permissions(metadata: &Metadata) -> u32 {
+	metadata.permissions().mode() & 0o7777
+}
+
+#[cfg(target_os = "windows")]
+fn get_permissions(metadata: &Metadata) -> u32 {
+	match (metadata.is_dir(), metadata.permissions().readonly()) {
+		(true, _) => 0o755,
+		(false, true) => 0o444,
+		(false, false) => 0o644,
+	}
+}
+
+/// Reads a file while updating the progress; reading fails when the job is cancelled.
+pub struct ProgressReader<'a, R> {
+	pub reader: R,
+	pub job: &'a Job,
+}
+
+impl<R: Read> Read for ProgressReader<'_, R> {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		if self.job.checkpoint().is_err() {
+			return Err(io::Error::other("job was cancelled"));
+		}
+		let count = self.reader.read(buf)?;
+		self.job
+			.update(|progress| progress.bytes_done += count as u64);
+		Ok(count)
+	}
+}
+
+/// Compression of tar archives.
+enum Encoder<W: Write> {
+	None(W),
+	Gz(GzEncoder<W>),
+	Xz(XzEncoder<W>),
+	Zst(zstd::Encoder<'static, W>),
+}
+
+impl<W: Write> Encoder<W> {
+	fn new(writer: W, format: ArchiveFormat, level: Option<u32>) -> io::Result<Self> {
+		Ok(match format {
+			ArchiveFormat::TarGz => Encoder::Gz(GzEncoder::new(
+				writer,
+				level.map_or(Compression::default(), Compression::new),
+			)),
+			ArchiveFormat::TarXz => Encoder::Xz(XzEncoder::new(writer, level.unwrap_or(6))),
+			// level 0 is the default level of zstd
+			ArchiveFormat::TarZst => {
+				Encoder::Zst(zstd::Encoder::new(writer, level.unwrap_or(0) as i32)?)
+			}
+			ArchiveFormat::Tar | ArchiveFormat::Zip => Encoder::None(writer),
+		})
+	}
+
+	fn finish(self) -> io::Result<W> {
+		match self {
+			Encoder::None(writer) => Ok(writer),
+			Encoder::Gz(encoder) => encoder.finish(),
+			Encoder::Xz(encoder) => encoder.finish(),
+			Encoder::Zst(encoder) => encoder.finish(),
+		}
+	}
+}
+
+impl<W: Write> Write for Encoder<W> {
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		match self {
+			Encoder::None(writer) => writer.write(buf),
+			Encoder::Gz(encoder) => encoder.write(buf),
+			Encoder::Xz(encoder) => encoder.write(buf),
+			Encoder::Zst(encoder) => encoder.write(buf),
+		}
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		match self {
+			Encoder::None(writer) => writer.flush(),
+			Encoder::Gz(encoder) => encoder.flush(),
+			Encoder::Xz(encoder) => encoder.flush(),
+			Encoder::Zst(encoder) => encoder.flush(),
+		}
+	}
+}
+
+fn check_level(format: ArchiveFormat, level: Option<u32>) -> Result<(), ErrorResponse> {
+	let range = match format {
+		ArchiveFormat::TarZst => 1..=22,
+		_ => 0..=9,
+	};
+	match level {
+		Some(level) if !range.contains(&level) => Err(ErrorResponse::new(
+			ErrorCode::InvalidInput,
+			format!(
+				"compression level must be between {} and {}",
+				range.start(),
+				range.end()
+			),
+		)),
+		_ => Ok(()),
+	}
+}
+
+fn get_patterns(patterns: &[String]) -> Result<GlobSet, ErrorResponse> {
+	let mut builder = GlobSetBuilder::new();
+	for pattern in patterns {
+		let glob = Glob::new(pattern).map_err(|err| {
+			ErrorResponse::new(ErrorCode::InvalidInput, format!("invalid pattern: {}", err))
+		})?;
+		builder.add(glob);
+	}
+	builder.build().map_err(|err| {
+		ErrorResponse::new(ErrorCode::InvalidInput, format!("invalid pattern: {}", err))
+	})
+}
+
+/// Converts seconds since the epoch to the date of a zip entry, which is stored
+/// without time zone; dates out of the range of zip files are stored as 1980-01-01.
+fn get_zip_date(seconds: u64) -> zip::DateTime {
+	let days = (seconds / 86400) as i64;
+	let time = seconds % 86400;
+	// civil date of the proleptic Gregorian calendar
+	let days = days + 719468;
+	let era = days.div_euclid(146097);
+	let day_of_era = days - era * 146097;
+	let year_of_era =
+		(day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+	let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+	let month = (5 * day_of_year + 2) / 153;
+	let day = day_of_year - (153 * month + 2) / 5 + 1;
+	let month = if month < 10 { month + 3 } else { month - 9 };
+	let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+	zip::DateTime::from_date_and_time(
+		year.clamp(0, u16::MAX as i64) as u16,
+		month as u8,
+		day as u8,
+		(time / 3600) as u8,
+		(time / 60 % 60) as u8,
+		(time % 60) as u8,
+	)
+	.unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::testing::TempDir;
+	use flate2::read::GzDecoder;
+	use serde_json::json;
+	use xcmd_base::JobKind;
+	use zip::ZipArchive;
+
+	fn pack_request(dir: &TempDir, request: serde_json::Value) -> Result<Job, ErrorResponse> {
+		let mut request = request;
+		request["path"] = json!(dir.path());
+		let job = Job::new(0, JobKind::Pack);
+		pack(serde_json::from_value(request).unwrap(), &job)?;
+		Ok(job)
+	}
+
+	#[test]
+	fn pack_zip_archives() {
+		let dir = TempDir::new();
+		dir.write("d/a.txt", "abc");
+		dir.write("d/e/b.log", "b");
+		dir.write("c", "cc");
+		let request = json!({ "keys": ["d/", "c"], "name": "x.zip", "exclude": ["*.log"] });
+		let job = pack_request(&dir, request).unwrap();
+		let results = job.results(0, usize::MAX);
+		assert_eq!(results[0].file.key, "x.zip");
+		let progress = job.status().progress;
+		assert_eq!((progress.files_done, progress.bytes_done), (2, 5));
+
+		let mut zip = ZipArchive::new(File::open(dir.join("x.zip")).unwrap()).unwrap();
+		let names = zip.file_names().collect::<Vec<_>>();
+		let mut names = names.into_iter().map(str::to_string).collect::<Vec<_>>();
+		names.sort();
+		assert_eq!(names, ["c", "d/", "d/a.txt", "d/e/"]);
+		let mut contents = String::new();
+		zip.by_name("d/a.txt")
+			.unwrap()
+			.read_to_string(&mut contents)
+			.unwrap();
+		assert_eq!(contents, "abc");
+		assert_eq!(zip.by_name("c").unwrap().unix_mode(), Some(0o100644));
+		// only the archive is left in the directory
+		assert!(!fs::read_dir(dir.path()).unwrap().any(|entry| entry
+			.unwrap()
+			.file_name()
+			.to_string_lossy()
+			.ends_with(".tmp")));
+	}
+
+	#[test]
+	fn pack_tar_archives() {
+		let dir = TempDir::new();
+		let path = dir.write("d/a", "abc");
+		#[cfg(not(target_os = "windows"))]
+		fs::set_permissions(&path, fs::Permissions::from_mode(0o600)).unwrap();
+		File::options()
+			.write(true)
+			.open(&path)
+			.unwrap()
+			.set_modified(UNIX_EPOCH + std::time::Duration::from_secs(1_000_000))
+			.unwrap();
+		let request = json!({
+			"keys": ["d"],
+			"name": "x.tgz",
+			"preservePermissions": true,
+			"preserveDates": true,
+		});
+		pack_request(&dir, request).unwrap();
+
+		let file = File::open(dir.join("x.tgz")).unwrap();
+		let mut archive = tar::Archive::new(GzDecoder::new(file));
+		let mut entries = Vec::new();
+		for entry in archive.entries().unwrap() {
+			let mut entry = entry.unwrap();
+			let mut contents = String::new();
+			entry.read_to_string(&mut contents).unwrap();
+			let header = entry.header();
+			let name = entry.path().unwrap().to_string_lossy().to_string();
+			entries.push((
+				name,
+				contents,
+				header.mtime().unwrap(),
+				header.mode().unwrap(),
+			));
+		}
+		assert_eq!(entries.len(), 2);
+		assert_eq!(entries[0].0, "d/");
+		let (name, contents, date, _mode) = &entries[1];
+		assert_eq!(
+			(name.as_str(), contents.as_str(), *date),
+			("d/a", "abc", 1_000_000)
+		);
+		#[cfg(not(target_os = "windows"))]
+		assert_eq!(*_mode, 0o600);
+	}
+
+	#[test]
+	fn pack_invalid_requests() {
+		let dir = TempDir::new();
+		dir.write("a", "");
+		dir.write("x.zip", "");
+		let err = pack_request(&dir, json!({ "keys": ["a"], "name": "x.rar" }))
+			.err()
+			.unwrap();
+		assert_eq!(err.code, ErrorCode::InvalidInput);
+		let request = json!({ "keys": ["a"], "name": "x.tar.zst", "level": 0 });
+		let err = pack_request(&dir, request).err().unwrap();
+		assert_eq!(err.code, ErrorCode::InvalidInput);
+		let err = pack_request(&dir, json!({ "keys": ["a"], "name": "x.zip" }))
+			.err()
+			.unwrap();
+		assert_eq!(err.code, ErrorCode::AlreadyExists);
+		let request = json!({ "keys": ["a"], "name": "x.zip", "overwrite": true });
+		pack_request(&dir, request).unwrap();
+	}
+
+	#[test]
+	fn convert_zip_dates() {
+		let date = get_zip_date(951_827_696);
+		let parts = (date.year(), date.month(), date.day());
+		assert_eq!(parts, (2000, 2, 29));
+		assert_eq!((date.hour(), date.minute(), date.second()), (12, 34, 56));
+		// dates before 1980 cannot be stored
+		assert_eq!(get_zip_date(0).year(), 1980);
+	}
+}
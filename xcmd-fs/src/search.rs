@@ -0,0 +1,258 @@
+use crate::content::ContentMatcher;
+use crate::{get_local_file, get_paths};
+use globset::{GlobBuilder, GlobMatcher};
+use ignore::{DirEntry, WalkBuilder};
+use regex::{Regex, RegexBuilder};
+use std::path::Path;
+use tracing::trace;
+use xcmd_base::{
+	get_timestamp, EntryType, ErrorCode, ErrorResponse, Job, JobResult, PatternSyntax,
+	SearchRequest,
+};
+
+pub fn search(request: SearchRequest, job: &Job) -> Result<(), ErrorResponse> {
+	let (root, _) = get_paths(&request.path, &None);
+	let pattern = match &request.pattern {
+		Some(pattern) => Some(NamePattern::new(pattern, &request)?),
+		None => None,
+	};
+	let content = match &request.content {
+		Some(content) => Some(ContentMatcher::new(content)?),
+		None => None,
+	};
+
+	let walker = WalkBuilder::new(&root)
+		.standard_filters(false)
+		.git_ignore(request.respect_gitignore)
+		.git_global(request.respect_gitignore)
+		.git_exclude(request.respect_gitignore)
+		.parents(request.respect_gitignore)
+		.max_depth(request.max_depth)
+		.build();
+
+	// the root itself has depth 0 and is never a result
+	let min_depth = request.min_depth.unwrap_or(1).max(1);
+	let limit = request.limit.unwrap_or(usize::MAX);
+	let mut found = 0;
+
+	for entry in walker {
+		job.checkpoint()?;
+		let entry = match entry {
+			Ok(entry) => entry,
+			Err(err) => {
+				// unreadable directories are skipped, the rest of the tree is still searched
+				trace!("search error: {}", err);
+				continue;
+			}
+		};
+		job.update(|progress| {
+			progress.files_done += 1;
+			if entry
+				.file_type()
+				.is_some_and(|file_type| file_type.is_dir())
+			{
+				progress.current = Some(entry.path().to_string_lossy().to_string());
+			}
+		});
+
+		if entry.depth() < min_depth || !is_match(&entry, pattern.as_ref(), &request) {
+			continue;
+		}
+
+		// content searches find files with at least one matching line
+		let matches = match &content {
+			Some(content) => {
+				if !entry
+					.file_type()
+					.is_some_and(|file_type| file_type.is_file())
+				{
+					continue;
+				}
+				match content.find(entry.path(), job) {
+					Ok(matches) if !matches.is_empty() => matches,
+					Ok(_) => continue,
+					Err(err) => {
+						trace!("search error: {}: {}", entry.path().display(), err);
+						continue;
+					}
+				}
+			}
+			None => Vec::new(),
+		};
+
+		let mut file = get_local_file(entry.path(), None, &None);
+		file.key = format!(
+			"{}{}",
+			get_relative_key(&root, entry.path()),
+			if file.is_directory { "/" } else { "" }
+		);
+		job.add_result(JobResult {
+			file,
+			matches,
+			..Default::default()
+		});
+
+		found += 1;
+		if found == limit {
+			break;
+		}
+	}
+	Ok(())
+}
+
+enum NamePattern {
+	Glob(GlobMatcher),
+	Regex(Regex),
+}
+
+impl NamePattern {
+	fn new(pattern: &str, request: &SearchRequest) -> Result<Self, ErrorResponse> {
+		let invalid = |err: String| {
+			ErrorResponse::new(
+				ErrorCode::InvalidInput,
+				format!("invalid pattern '{}': {}", pattern, err),
+			)
+		};
+		match request.syntax {
+			PatternSyntax::Glob => GlobBuilder::new(pattern)
+				.case_insensitive(!request.case_sensitive)
+				.build()
+				.map(|glob| NamePattern::Glob(glob.compile_matcher()))
+				.map_err(|err| invalid(err.to_string())),
+			PatternSyntax::Regex => RegexBuilder::new(pattern)
+				.case_insensitive(!request.case_sensitive)
+				.build()
+				.map(NamePattern::Regex)
+				.map_err(|err| invalid(err.to_string())),
+		}
+	}
+
+	/// Globs match whole names, regular expressions match any part of a name.
+	fn is_match(&self, name: &str) -> bool {
+		match self {
+			NamePattern::Glob(glob) => glob.is_match(name),
+			NamePattern::Regex(regex) => regex.is_match(name),
+		}
+	}
+}
+
+fn is_match(entry: &DirEntry, pattern: Option<&NamePattern>, request: &SearchRequest) -> bool {
+	if let Some(pattern) = pattern {
+		if !pattern.is_match(&entry.file_name().to_string_lossy()) {
+			return false;
+		}
+	}
+
+	if let Some(entry_type) = request.entry_type {
+		let is_type = entry.file_type().is_some_and(|file_type| match entry_type {
+			EntryType::File => file_type.is_file(),
+			EntryType::Directory => file_type.is_dir(),
+			EntryType::Symlink => file_type.is_symlink(),
+		});
+		if !is_type {
+			return false;
+		}
+	}
+
+	let has_metadata_filters = request.min_size.is_some()
+		|| request.max_size.is_some()
+		|| request.modified_after.is_some()
+		|| request.modified_before.is_some();
+	if has_metadata_filters {
+		let Ok(metadata) = entry.metadata() else {
+			return false;
+		};
+		let size = metadata.len();
+		let date = metadata.modified().map(get_timestamp).unwrap_or_default();
+		return request.min_size.is_none_or(|min_size| size >= min_size)
+			&& request.max_size.is_none_or(|max_size| size <= max_size)
+			&& request.modified_after.is_none_or(|after| date >= after)
+			&& request.modified_before.is_none_or(|before| date <= before);
+	}
+	true
+}
+
+/// Joins the components of the path below the root with "/", like keys of a listing.
+fn get_relative_key(root: &Path, path: &Path) -> String {
+	path.strip_prefix(root)
+		.unwrap_or(path)
+		.components()
+		.map(|component| component.as_os_str().to_string_lossy())
+		.collect::<Vec<_>>()
+		.join("/")
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::testing::TempDir;
+	use serde_json::json;
+	use std::fs;
+	use xcmd_base::JobKind;
+
+	fn search_keys(
+		dir: &TempDir,
+		request: serde_json::Value,
+	) -> Result<Vec<String>, ErrorResponse> {
+		let mut request = request;
+		request["path"] = json!(dir.join("s"));
+		let job = Job::new(0, JobKind::Search);
+		search(serde_json::from_value(request).unwrap(), &job)?;
+		let mut keys = job
+			.results(0, usize::MAX)
+			.into_iter()
+			.map(|result| result.file.key)
+			.collect::<Vec<_>>();
+		keys.sort();
+		Ok(keys)
+	}
+
+	#[test]
+	fn search_names() {
+		let dir = TempDir::new();
+		dir.write("s/a.txt", "");
+		dir.write("s/sub/B.TXT", "");
+		dir.write("s/sub/b.rs", "");
+		let keys = search_keys(&dir, json!({ "pattern": "*.txt" })).unwrap();
+		assert_eq!(keys, ["a.txt", "sub/B.TXT"]);
+		let keys = search_keys(&dir, json!({ "pattern": "*.txt", "caseSensitive": true }));
+		assert_eq!(keys.unwrap(), ["a.txt"]);
+		// regular expressions match parts of names
+		let keys = search_keys(&dir, json!({ "pattern": "b", "syntax": "regex" })).unwrap();
+		assert_eq!(keys, ["sub/", "sub/B.TXT", "sub/b.rs"]);
+		let err = search_keys(&dir, json!({ "pattern": "(", "syntax": "regex" })).unwrap_err();
+		assert_eq!(err.code, ErrorCode::InvalidInput);
+	}
+
+	#[test]
+	fn search_with_filters() {
+		let dir = TempDir::new();
+		dir.write("s/a", "aaa");
+		dir.write("s/d/b", "b");
+		dir.write("s/d/e/c", "cc");
+		let keys = search_keys(&dir, json!({ "entryType": "directory" })).unwrap();
+		assert_eq!(keys, ["d/", "d/e/"]);
+		let keys = search_keys(&dir, json!({ "entryType": "file", "minSize": 2 })).unwrap();
+		assert_eq!(keys, ["a", "d/e/c"]);
+		let keys = search_keys(&dir, json!({ "minDepth": 2, "maxDepth": 2 })).unwrap();
+		assert_eq!(keys, ["d/b", "d/e/"]);
+		let keys = search_keys(&dir, json!({ "entryType": "file", "limit": 2 })).unwrap();
+		assert_eq!(keys.len(), 2);
+	}
+
+	#[test]
+	fn search_respecting_gitignore() {
+		let dir = TempDir::new();
+		fs::create_dir_all(dir.join("s/.git")).unwrap();
+		dir.write("s/.gitignore", "*.log\n");
+		dir.write("s/a.log", "");
+		dir.write("s/b", "");
+		let request = json!({ "entryType": "file", "respectGitignore": true });
+		assert_eq!(search_keys(&dir, request).unwrap(), [".gitignore", "b"]);
+		let request = json!({ "entryType": "file" });
+		assert_eq!(
+			search_keys(&dir, request).unwrap(),
+			[".gitignore", "a.log", "b"]
+		);
+	}
+}
@@ -0,0 +1,60 @@
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, MutexGuard};
+use std::{env, fs, process};
+
+/// Serializes tests changing files, as they share the journal and the trash in the
+/// data directory.
+static LOCK: Mutex<()> = Mutex::new(());
+
+static LAST_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Directory of a test, removed when dropped. The data directory holding the journal
+/// and the trash is redirected into it, so every test starts without operations, and
+/// so is the cache directory holding thumbnails.
+pub struct TempDir {
+	path: PathBuf,
+	_guard: MutexGuard<'static, ()>,
+}
+
+impl TempDir {
+	pub fn new() -> Self {
+		let guard = LOCK.lock().unwrap_or_else(|err| err.into_inner());
+		let id = LAST_ID.fetch_add(1, Ordering::Relaxed);
+		let path = env::temp_dir().join(format!("xcmd-fs-test-{}-{}", process::id(), id));
+		fs::create_dir_all(path.join("data")).unwrap();
+		env::set_var("XDG_DATA_HOME", path.join("data"));
+		env::set_var("XDG_CACHE_HOME", path.join("cache"));
+		TempDir {
+			path,
+			_guard: guard,
+		}
+	}
+
+	pub fn path(&self) -> &Path {
+		&self.path
+	}
+
+	pub fn join(&self, name: &str) -> PathBuf {
+		self.path.join(name)
+	}
+
+	/// Creates a file with the contents, and its missing parents.
+	pub fn write(&self, name: &str, contents: &str) -> PathBuf {
+		let path = self.join(name);
+		fs::create_dir_all(path.parent().unwrap()).unwrap();
+		fs::write(&path, contents).unwrap();
+		path
+	}
+
+	/// Gets the contents of a file.
+	pub fn read(&self, name: &str) -> String {
+		fs::read_to_string(self.join(name)).unwrap()
+	}
+}
+
+impl Drop for TempDir {
+	fn drop(&mut self) {
+		fs::remove_dir_all(&self.path).ok();
+	}
+}
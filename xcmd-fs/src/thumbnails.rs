@@ -0,0 +1,333 @@
+use image::{DynamicImage, ImageError, ImageFormat};
+use md5::{Digest, Md5};
+use std::fs::{self, OpenOptions};
+use std::io::{self, Cursor, Write};
+use std::path::Path;
+use std::process;
+use std::time::UNIX_EPOCH;
+use tracing::trace;
+use xcmd_base::{ErrorCode, ErrorContext, ErrorResponse};
+
+/// Sizes of the cache directories of the freedesktop thumbnail specification;
+/// thumbnails are scaled down from the smallest one that is large enough.
+const SIZES: &[(u32, &str)] = &[
+	(128, "normal"),
+	(256, "large"),
+	(512, "x-large"),
+	(1024, "xx-large"),
+];
+
+const FORMATS: &[ImageFormat] = &[
+	ImageFormat::Png,
+	ImageFormat::Jpeg,
+	ImageFormat::Gif,
+	ImageFormat::WebP,
+	ImageFormat::Bmp,
+];
+
+/// Directory of images that could not be decoded, within the `fail` directory.
+const FAIL_DIR: &str = "xcmd";
+
+/// Identifies the version of a file a thumbnail was made from.
+struct Stamp {
+	uri: String,
+	/// Modification date in seconds since the epoch.
+	date: u64,
+	size: u64,
+}
+
+/// Gets a PNG preview of the image fitting in a square of the size; files that are
+/// not images have none. Previews are cached in the thumbnail directories shared
+/// with other applications.
+pub fn get_thumbnail(path: &Path, size: u32) -> Result<Option<Vec<u8>>, ErrorResponse> {
+	let (max_size, _) = SIZES[SIZES.len() - 1];
+	if size == 0 || size > max_size {
+		return Err(ErrorResponse::new(
+			ErrorCode::InvalidInput,
+			format!("thumbnail size must be between 1 and {}", max_size),
+		));
+	}
+	if !ImageFormat::from_path(path).is_ok_and(|format| FORMATS.contains(&format)) {
+		return Ok(None);
+	}
+	let path = fs::canonicalize(path).with_path(path)?;
+	let metadata = fs::metadata(&path).with_path(&path)?;
+	if !metadata.is_file() {
+		return Ok(None);
+	}
+	let stamp = Stamp {
+		uri: get_uri(&path),
+		date: metadata
+			.modified()
+			.ok()
+			.and_then(|date| date.duration_since(UNIX_EPOCH).ok())
+			.map(|duration| duration.as_secs())
+			.unwrap_or_default(),
+		size: metadata.len(),
+	};
+	let name = format!("{:x}.png", Md5::digest(stamp.uri.as_bytes()));
+	let (cache_size, cache_name) = SIZES
+		.iter()
+		.copied()
+		.find(|(cache_size, _)| *cache_size >= size)
+		.unwrap_or(SIZES[SIZES.len() - 1]);
+	// thumbnails of thumbnails are not cached
+	let cache_dir = dirs::cache_dir()
+		.map(|dir| dir.join("thumbnails"))
+		.filter(|dir| !path.starts_with(dir));
+	let cache_path = cache_dir
+		.as_ref()
+		.map(|dir| dir.join(cache_name).join(&name));
+	let fail_path = cache_dir.map(|dir| dir.join("fail").join(FAIL_DIR).join(&name));
+
+	let data = match cache_path
+		.as_ref()
+		.and_then(|path| read_cached(path, &stamp))
+	{
+		Some(data) => data,
+		None => {
+			if fail_path
+				.as_ref()
+				.is_some_and(|path| read_cached(path, &stamp).is_some())
+			{
+				return Err(decode_error(&path, "image could not be decoded before"));
+			}
+			let image = match decode(&path) {
+				Ok(image) => image,
+				Err(err) => {
+					// an empty image records the failure, so the file is not decoded again
+					if let Some(fail_path) = &fail_path {
+						let empty = DynamicImage::new_rgba8(1, 1);
+						encode(&empty, Some(&stamp))
+							.and_then(|data| store(fail_path, &data))
+							.ok();
+					}
+					return Err(err);
+				}
+			};
+			// images are not scaled up
+			let image = if image.width() > cache_size || image.height() > cache_size {
+				image.thumbnail(cache_size, cache_size)
+			} else {
+				image
+			};
+			let data = encode(&image, Some(&stamp))?;
+			if let Some(cache_path) = &cache_path {
+				if let Err(err) = store(cache_path, &data) {
+					trace!("thumbnail is not cached: {}", err);
+				}
+			}
+			data
+		}
+	};
+	if size == cache_size {
+		return Ok(Some(data));
+	}
+
+	let image = image::load_from_memory_with_format(&data, ImageFormat::Png)
+		.map_err(|err| decode_error(&path, err))?;
+	if image.width() <= size && image.height() <= size {
+		return Ok(Some(data));
+	}
+	encode(&image.thumbnail(size, size), None).map(Some)
+}
+
+fn decode(path: &Path) -> Result<DynamicImage, ErrorResponse> {
+	image::io::Reader::open(path)
+		.with_path(path)?
+		.with_guessed_format()
+		.with_path(path)?
+		.decode()
+		.map_err(|err| match err {
+			ImageError::IoError(err) => ErrorResponse::from(err).with_path(path),
+			err => decode_error(path, err),
+		})
+}
+
+fn decode_error(path: &Path, message: impl ToString) -> ErrorResponse {
+	ErrorResponse::new(ErrorCode::InvalidInput, message.to_string()).with_path(path)
+}
+
+/// Gets the cached thumbnail if it was made from the same version of the file.
+fn read_cached(path: &Path, stamp: &Stamp) -> Option<Vec<u8>> {
+	let data = fs::read(path).ok()?;
+	let reader = png::Decoder::new(Cursor::new(&data)).read_info().ok()?;
+	let texts = &reader.info().uncompressed_latin1_text;
+	let get_text = |keyword: &str| {
+		texts
+			.iter()
+			.find(|chunk| chunk.keyword == keyword)
+			.map(|chunk| chunk.text.as_str())
+	};
+	let is_current = get_text("Thumb::URI") == Some(stamp.uri.as_str())
+		&& get_text("Thumb::MTime") == Some(stamp.date.to_string().as_str())
+		&& get_text("Thumb::Size").is_none_or(|size| size == stamp.size.to_string());
+	is_current.then_some(data)
+}
+
+/// Encodes the image as PNG, with the attributes of the thumbnail specification if
+/// it is cached.
+fn encode(image: &DynamicImage, stamp: Option<&Stamp>) -> Result<Vec<u8>, ErrorResponse> {
+	let image = image.to_rgba8();
+	let mut data = Vec::new();
+	let mut encoder = png::Encoder::new(&mut data, image.width(), image.height());
+	encoder.set_color(png::ColorType::Rgba);
+	encoder.set_depth(png::BitDepth::Eight);
+	let encode = || {
+		if let Some(stamp) = stamp {
+			encoder.add_text_chunk("Thumb::URI".to_string(), stamp.uri.clone())?;
+			encoder.add_text_chunk("Thumb::MTime".to_string(), stamp.date.to_string())?;
+			encoder.add_text_chunk("Thumb::Size".to_string(), stamp.size.to_string())?;
+			encoder.add_text_chunk("Software".to_string(), "xcmd".to_string())?;
+		}
+		let mut writer = encoder.write_header()?;
+		writer.write_image_data(&image)?;
+		writer.finish()
+	};
+	encode().map_err(|err| ErrorResponse::new(ErrorCode::Internal, err.to_string()))?;
+	Ok(data)
+}
+
+/// Writes the thumbnail to a temporary sibling first, so other applications never
+/// read it half-written; thumbnails are private to the user.
+fn store(path: &Path, data: &[u8]) -> Result<(), ErrorResponse> {
+	let dir = path.parent().unwrap_or(Path::new("."));
+	create_private_dir(dir).with_path(dir)?;
+	let name = path.file_name().unwrap_or_default().to_string_lossy();
+	let temp_path = dir.join(format!(".{}.{}.tmp", name, process::id()));
+	let written = create_private_file(&temp_path)
+		.and_then(|mut file| file.write_all(data))
+		.and_then(|_| fs::rename(&temp_path, path));
+	if let Err(err) = written {
+		fs::remove_file(&temp_path).ok();
+		return Err(ErrorResponse::from(err).with_path(path));
+	}
+	Ok(())
+}
+
+#[cfg(not(target_os = "windows"))]
+fn create_private_dir(path: &Path) -> io::Result<()> {
+	use std::os::unix::fs::DirBuilderExt;
+	fs::DirBuilder::new()
+		.recursive(true)
+		.mode(0o700)
+		.create(path)
+}
+
+#[cfg(target_os = "windows")]
+fn create_private_dir(path: &Path) -> io::Result<()> {
+	fs::create_dir_all(path)
+}
+
+#[cfg(not(target_os = "windows"))]
+fn create_private_file(path: &Path) -> io::Result<fs::File> {
+	use std::os::unix::fs::OpenOptionsExt;
+	OpenOptions::new()
+		.write(true)
+		.create(true)
+		.truncate(true)
+		.mode(0o600)
+		.open(path)
+}
+
+#[cfg(target_os = "windows")]
+fn create_private_file(path: &Path) -> io::Result<fs::File> {
+	OpenOptions::new()
+		.write(true)
+		.create(true)
+		.truncate(true)
+		.open(path)
+}
+
+/// Gets the URI of the file, escaped like GLib does, as the cache names are hashes
+/// of URIs.
+fn get_uri(path: &Path) -> String {
+	let path = path.to_string_lossy().replace('\\', "/");
+	let mut uri = String::from("file://");
+	if !path.starts_with('/') {
+		uri.push('/');
+	}
+	for byte in path.bytes() {
+		if byte.is_ascii_alphanumeric() || b"!$&'()*+,-./:=@_~".contains(&byte) {
+			uri.push(byte as char);
+		} else {
+			uri.push_str(&format!("%{:02X}", byte));
+		}
+	}
+	uri
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::testing::TempDir;
+	use image::RgbImage;
+
+	fn write_image(dir: &TempDir, name: &str, width: u32, height: u32) -> std::path::PathBuf {
+		let path = dir.join(name);
+		RgbImage::new(width, height).save(&path).unwrap();
+		path
+	}
+
+	fn get_dimensions(data: &[u8]) -> (u32, u32) {
+		let image = image::load_from_memory_with_format(data, ImageFormat::Png).unwrap();
+		(image.width(), image.height())
+	}
+
+	#[test]
+	fn cache_thumbnails() {
+		let dir = TempDir::new();
+		let path = write_image(&dir, "a b.png", 400, 200);
+		let data = get_thumbnail(&path, 256).unwrap().unwrap();
+		assert_eq!(get_dimensions(&data), (256, 128));
+		let uri = get_uri(&fs::canonicalize(&path).unwrap());
+		assert!(uri.starts_with("file:///") && uri.ends_with("/a%20b.png"));
+		let name = format!("{:x}.png", Md5::digest(uri.as_bytes()));
+		let cache_path = dir.join("cache/thumbnails/large").join(name);
+		assert_eq!(fs::read(&cache_path).unwrap(), data);
+
+		// smaller sizes are scaled down from the cached thumbnail
+		let data = get_thumbnail(&path, 100).unwrap().unwrap();
+		assert_eq!(get_dimensions(&data), (100, 50));
+		// images are not scaled up
+		let data = get_thumbnail(&path, 1024).unwrap().unwrap();
+		assert_eq!(get_dimensions(&data), (400, 200));
+	}
+
+	#[test]
+	fn refresh_changed_images() {
+		let dir = TempDir::new();
+		let path = write_image(&dir, "a.png", 300, 300);
+		let data = get_thumbnail(&path, 128).unwrap().unwrap();
+		assert_eq!(get_dimensions(&data), (128, 128));
+		write_image(&dir, "a.png", 300, 150);
+		// thumbnails are stamped with dates in seconds
+		let date = fs::metadata(&path).unwrap().modified().unwrap();
+		let file = OpenOptions::new().write(true).open(&path).unwrap();
+		file.set_modified(date + std::time::Duration::from_secs(2))
+			.unwrap();
+		let data = get_thumbnail(&path, 128).unwrap().unwrap();
+		assert_eq!(get_dimensions(&data), (128, 64));
+	}
+
+	#[test]
+	fn thumbnails_of_other_files() {
+		let dir = TempDir::new();
+		let path = dir.write("a.txt", "text");
+		assert_eq!(get_thumbnail(&path, 128).unwrap(), None);
+		let path = dir.write("b.png", "not an image");
+		let err = get_thumbnail(&path, 128).unwrap_err();
+		assert_eq!(err.code, ErrorCode::InvalidInput);
+		// the failure is recorded, so the file is not decoded again
+		assert_eq!(
+			fs::read_dir(dir.join("cache/thumbnails/fail/xcmd"))
+				.unwrap()
+				.count(),
+			1
+		);
+		let err = get_thumbnail(&path, 128).unwrap_err();
+		assert_eq!(err.code, ErrorCode::InvalidInput);
+		let err = get_thumbnail(&path, 2048).unwrap_err();
+		assert_eq!(err.code, ErrorCode::InvalidInput);
+	}
+}
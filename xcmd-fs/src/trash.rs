@@ -0,0 +1,591 @@
+use crate::archive::normalize_path;
+use crate::computer::get_mounts;
+use crate::journal::Recorder;
+use crate::operations::{copy_entry, get_entry_path, remove_entry};
+use crate::{get_local_file, split_virtual_path};
+use chrono::{Local, NaiveDateTime, TimeZone};
+use std::cmp::Reverse;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Component, Path, PathBuf};
+use urlencoding::{decode_binary, encode};
+use xcmd_base::{
+	get_listing_page, ErrorCode, ErrorContext, ErrorResponse, FileInfo, Job, JobResult,
+	ListRequest, ListResponse, RestoreRequest,
+};
+
+/// Path of the trash, which is listed like a directory. Entries of the trash have
+/// their original names; their keys identify them across all trash directories.
+pub const TRASH_PATH: &str = "trash:";
+
+const DATE_FORMAT: &str = "%Y-%m-%dT%H:%M:%S";
+
+/// Trash directory of the freedesktop trash specification, with the `files` and
+/// `info` subdirectories.
+struct TrashDir {
+	path: PathBuf,
+	/// Directory the original paths are relative to, for trash directories at the
+	/// top of a mount.
+	top: Option<PathBuf>,
+}
+
+/// Entry of the trash.
+#[derive(Clone)]
+struct Item {
+	/// Path of the entry in the `files` directory.
+	file: PathBuf,
+	/// Path of the `.trashinfo` file.
+	info: PathBuf,
+	original_path: PathBuf,
+	deletion_date: Option<i64>,
+}
+
+/// Gets the path within the trash if the path is in the trash, empty for the trash
+/// itself.
+pub fn split_path(path: &Path) -> Option<PathBuf> {
+	split_virtual_path(path, TRASH_PATH)
+}
+
+/// Moves the entry to the trash; gets the paths of the entry in the trash and of its
+/// info file.
+pub fn trash(path: &Path) -> Result<(PathBuf, PathBuf), ErrorResponse> {
+	let name = path
+		.file_name()
+		.ok_or_else(|| ErrorResponse::new(ErrorCode::InvalidInput, "entry has no name"))?;
+	let parent = path.parent().unwrap_or(Path::new("/"));
+	let original_path = fs::canonicalize(parent).with_path(parent)?.join(name);
+	let dir = find_dir(&original_path)?;
+	let relative_path = match &dir.top {
+		Some(top) => original_path.strip_prefix(top).unwrap_or(&original_path),
+		None => &original_path,
+	};
+	let content = format!(
+		"[Trash Info]\nPath={}\nDeletionDate={}\n",
+		encode_path(relative_path),
+		Local::now().format(DATE_FORMAT)
+	);
+
+	// the info file is created first, which reserves the name in the trash
+	let name = name.to_string_lossy();
+	let mut index = 1;
+	loop {
+		let name = match index {
+			1 => name.to_string(),
+			_ => format!("{}.{}", name, index),
+		};
+		index += 1;
+		let file = dir.path.join("files").join(&name);
+		let info = dir.path.join("info").join(format!("{}.trashinfo", name));
+		let mut writer = match OpenOptions::new().write(true).create_new(true).open(&info) {
+			Ok(writer) => writer,
+			Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+			Err(err) => return Err(ErrorResponse::from(err).with_path(&info)),
+		};
+		// entries without info files are left behind by other programs
+		if fs::symlink_metadata(&file).is_ok() {
+			fs::remove_file(&info).ok();
+			continue;
+		}
+		let moved = writer
+			.write_all(content.as_bytes())
+			.and_then(|_| writer.sync_all())
+			.with_path(&info)
+			.and_then(|_| fs::rename(path, &file).with_path(path));
+		if let Err(err) = moved {
+			fs::remove_file(&info).ok();
+			return Err(err);
+		}
+		return Ok((file, info));
+	}
+}
+
+/// Lists the trash or a directory within it.
+pub fn list(
+	path: &Path,
+	request: &ListRequest,
+	active_key: &Option<String>,
+) -> Result<ListResponse, ErrorResponse> {
+	let relative = split_path(&normalize_path(path)).unwrap_or_default();
+	let mut files = Vec::new();
+
+	if relative.as_os_str().is_empty() {
+		let page = get_listing_page(Path::new(TRASH_PATH), request, || {
+			let mut items = get_dirs().iter().flat_map(read_items).collect::<Vec<_>>();
+			// the most recently deleted entries first
+			items.sort_by_key(|item| Reverse(item.deletion_date));
+			Ok(items)
+		})?;
+		files.extend(
+			page.entries
+				.iter()
+				.filter(|item| fs::symlink_metadata(&item.file).is_ok())
+				.map(|item| get_item_file(item, active_key)),
+		);
+		return Ok(ListResponse {
+			path: TRASH_PATH.to_string(),
+			name: "Trash".to_string(),
+			files,
+			cursor: page.cursor,
+			free_space: None,
+		});
+	}
+
+	let full_path = resolve(&relative)?;
+	if request.cursor.is_none() {
+		if let Some(parent_path) = full_path.parent() {
+			files.push(get_local_file(parent_path, Some("..".to_string()), &None));
+		}
+	}
+	let page = get_listing_page(&full_path, request, || {
+		let read_dir = fs::read_dir(&full_path).with_path(&full_path)?;
+		read_dir
+			.map(|entry| entry.map(|entry| entry.path()).with_path(&full_path))
+			.collect()
+	})?;
+	files.extend(
+		page.entries
+			.iter()
+			.filter(|path| fs::symlink_metadata(path).is_ok())
+			.map(|path| get_local_file(path, None, active_key)),
+	);
+	let cursor = page.cursor;
+	Ok(ListResponse {
+		path: Path::new(TRASH_PATH)
+			.join(&relative)
+			.to_string_lossy()
+			.to_string(),
+		name: full_path
+			.file_name()
+			.map(|name| name.to_string_lossy().to_string())
+			.unwrap_or_default(),
+		files,
+		cursor,
+		free_space: None,
+	})
+}
+
+/// Gets the path of an entry within the trash on the file system.
+pub fn resolve(relative: &Path) -> Result<PathBuf, ErrorResponse> {
+	let mut components = relative.components();
+	let key = match components.next() {
+		Some(Component::Normal(key)) => key.to_string_lossy(),
+		_ => {
+			return Err(ErrorResponse::new(
+				ErrorCode::InvalidInput,
+				"path is the trash itself",
+			))
+		}
+	};
+	let item = find_item(&key)?;
+	let rest = components.as_path();
+	if rest
+		.components()
+		.any(|component| !matches!(component, Component::Normal(_)))
+	{
+		return Err(ErrorResponse::new(
+			ErrorCode::InvalidInput,
+			"path points outside of the trash",
+		));
+	}
+	Ok(item.file.join(rest))
+}
+
+/// Deletes entries of the trash, or entries of a directory within it, for good.
+pub fn delete(relative: &Path, keys: &[String], job: &Job) -> Result<(), ErrorResponse> {
+	job.update(|progress| progress.files_total = keys.len() as u64);
+	if !relative.as_os_str().is_empty() {
+		let path = resolve(relative)?;
+		for key in keys {
+			remove_entry(&get_entry_path(&path, key)?, job)?;
+		}
+		return Ok(());
+	}
+	for key in keys {
+		let item = find_item(key.trim_end_matches('/'))?;
+		remove_entry(&item.file, job)?;
+		fs::remove_file(&item.info).with_path(&item.info)?;
+	}
+	Ok(())
+}
+
+/// Moves entries of the trash back to where they were deleted from; entries whose
+/// original path is taken are results with an error.
+pub fn restore(request: RestoreRequest, job: &Job) -> Result<(), ErrorResponse> {
+	job.update(|progress| progress.files_total = request.keys.len() as u64);
+	for (done, key) in request.keys.iter().enumerate() {
+		job.checkpoint()?;
+		let item = find_item(key.trim_end_matches('/'))?;
+		let path = &item.original_path;
+		job.update(|progress| progress.current = Some(path.to_string_lossy().to_string()));
+		let error = match restore_item(&item, job) {
+			Ok(()) => None,
+			Err(err) if err.code == ErrorCode::Cancelled => return Err(err),
+			Err(err) => Some(err),
+		};
+		job.add_result(JobResult {
+			file: get_local_file(path, None, &None),
+			error,
+			..Default::default()
+		});
+		job.update(|progress| progress.files_done = done as u64 + 1);
+	}
+	Ok(())
+}
+
+fn restore_item(item: &Item, job: &Job) -> Result<(), ErrorResponse> {
+	let path = &item.original_path;
+	if fs::symlink_metadata(path).is_ok() {
+		return Err(ErrorResponse::new(
+			ErrorCode::AlreadyExists,
+			"another entry exists where the entry was deleted from",
+		)
+		.with_path(path));
+	}
+	if let Some(parent) = path.parent() {
+		fs::create_dir_all(parent).with_path(parent)?;
+	}
+	match fs::rename(&item.file, path) {
+		Ok(()) => {}
+		Err(err) if err.kind() == io::ErrorKind::CrossesDevices => {
+			copy_entry(&item.file, path, false, job, &mut Recorder::disabled())?;
+			remove_entry(&item.file, job)?;
+		}
+		Err(err) => return Err(ErrorResponse::from(err).with_path(path)),
+	}
+	fs::remove_file(&item.info).with_path(&item.info)
+}
+
+/// Deletes all entries of all trash directories for good.
+pub fn empty(job: &Job) -> Result<(), ErrorResponse> {
+	for dir in get_dirs() {
+		for name in ["files", "info"] {
+			let path = dir.path.join(name);
+			let read_dir = match fs::read_dir(&path) {
+				Ok(read_dir) => read_dir,
+				Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+				Err(err) => return Err(ErrorResponse::from(err).with_path(&path)),
+			};
+			for entry in read_dir {
+				remove_entry(&entry.with_path(&path)?.path(), job)?;
+			}
+		}
+		// the cache of directory sizes describes the deleted entries
+		fs::remove_file(dir.path.join("directorysizes")).ok();
+	}
+	Ok(())
+}
+
+fn get_item_file(item: &Item, active_key: &Option<String>) -> FileInfo {
+	let mut file = get_local_file(&item.file, None, &None);
+	let name = item
+		.original_path
+		.file_name()
+		.map(|name| name.to_string_lossy().to_string())
+		.unwrap_or_default();
+	let key = encode(&item.file.to_string_lossy()).into_owned();
+	(file.name, file.extension) = match name.rsplit_once('.') {
+		Some((stem, extension)) if !file.is_directory && !stem.is_empty() => {
+			(stem.to_string(), extension.to_string())
+		}
+		_ => (name.clone(), String::new()),
+	};
+	file.is_active = file.is_directory && active_key.as_ref() == Some(&key);
+	file.is_hidden = name.starts_with('.');
+	file.key = if file.is_directory {
+		format!("{}/", key)
+	} else {
+		key
+	};
+	file.original_path = Some(item.original_path.to_string_lossy().to_string());
+	file.deletion_date = item.deletion_date;
+	file
+}
+
+/// Finds the entry with the key in the trash directories.
+fn find_item(key: &str) -> Result<Item, ErrorResponse> {
+	let file = PathBuf::from(String::from_utf8_lossy(&decode_binary(key.as_bytes())).as_ref());
+	let not_found =
+		|| ErrorResponse::new(ErrorCode::NotFound, "entry is not in the trash").with_path(&file);
+	let name = file.file_name().ok_or_else(not_found)?;
+	let dir = get_dirs()
+		.into_iter()
+		.find(|dir| file.parent() == Some(dir.path.join("files").as_path()))
+		.ok_or_else(not_found)?;
+	let info = dir
+		.path
+		.join("info")
+		.join(format!("{}.trashinfo", name.to_string_lossy()));
+	read_item(&dir, &info).ok_or_else(not_found)
+}
+
+fn read_items(dir: &TrashDir) -> Vec<Item> {
+	let Ok(read_dir) = fs::read_dir(dir.path.join("info")) else {
+		return Vec::new();
+	};
+	read_dir
+		.filter_map(|entry| read_item(dir, &entry.ok()?.path()))
+		.collect()
+}
+
+/// Reads the info file of an entry; entries missing in `files` are skipped.
+fn read_item(dir: &TrashDir, info: &Path) -> Option<Item> {
+	let name = info.file_name()?.to_string_lossy();
+	let file = dir
+		.path
+		.join("files")
+		.join(name.strip_suffix(".trashinfo")?);
+	fs::symlink_metadata(&file).ok()?;
+
+	let content = fs::read_to_string(info).ok()?;
+	let mut lines = content
+		.lines()
+		.skip_while(|line| line.trim() != "[Trash Info]");
+	lines.next()?;
+	let mut original_path = None;
+	let mut deletion_date = None;
+	for line in lines.take_while(|line| !line.starts_with('[')) {
+		match line.split_once('=') {
+			Some(("Path", value)) => {
+				let path = String::from_utf8_lossy(&decode_binary(value.as_bytes())).into_owned();
+				original_path = Some(match &dir.top {
+					Some(top) => top.join(path),
+					None => PathBuf::from(path),
+				});
+			}
+			Some(("DeletionDate", value)) => {
+				deletion_date = NaiveDateTime::parse_from_str(value, DATE_FORMAT)
+					.ok()
+					.and_then(|date| Local.from_local_datetime(&date).earliest())
+					.map(|date| date.timestamp_millis());
+			}
+			_ => {}
+		}
+	}
+	Some(Item {
+		file,
+		info: info.to_path_buf(),
+		original_path: original_path?,
+		deletion_date,
+	})
+}
+
+/// Escapes the path as in URLs, keeping the separators.
+fn encode_path(path: &Path) -> String {
+	path.to_string_lossy()
+		.split('/')
+		.map(encode)
+		.collect::<Vec<_>>()
+		.join("/")
+}
+
+fn get_home_dir() -> Option<PathBuf> {
+	dirs::data_dir().map(|dir| dir.join("Trash"))
+}
+
+/// Gets the trash directories that exist: the one in the home directory and those
+/// at the top of mounts.
+#[cfg(not(target_os = "windows"))]
+fn get_dirs() -> Vec<TrashDir> {
+	let uid = uzers::get_current_uid();
+	let mut dirs = get_home_dir()
+		.map(|path| TrashDir { path, top: None })
+		.into_iter()
+		.collect::<Vec<_>>();
+	for top in get_mounts().into_iter().map(|mount| mount.path) {
+		for path in [
+			top.join(".Trash").join(uid.to_string()),
+			top.join(format!(".Trash-{}", uid)),
+		] {
+			if path.is_dir() && dirs.iter().all(|dir| dir.path != path) {
+				dirs.push(TrashDir {
+					path,
+					top: Some(top.clone()),
+				});
+			}
+		}
+	}
+	dirs
+}
+
+#[cfg(target_os = "windows")]
+fn get_dirs() -> Vec<TrashDir> {
+	Vec::new()
+}
+
+/// Finds the trash directory for the entry: the one in the home directory for
+/// entries on the same device, otherwise one at the top of the mount.
+#[cfg(not(target_os = "windows"))]
+fn find_dir(path: &Path) -> Result<TrashDir, ErrorResponse> {
+	use std::os::unix::fs::MetadataExt;
+
+	let device = fs::symlink_metadata(path).with_path(path)?.dev();
+	if let Some(home) = get_home_dir() {
+		let is_same_device = create_dirs(&home).is_ok()
+			&& fs::metadata(&home).is_ok_and(|metadata| metadata.dev() == device);
+		if is_same_device {
+			return Ok(TrashDir {
+				path: home,
+				top: None,
+			});
+		}
+	}
+
+	let top = path
+		.ancestors()
+		.skip(1)
+		.take_while(|ancestor| {
+			fs::metadata(ancestor).is_ok_and(|metadata| metadata.dev() == device)
+		})
+		.last()
+		.unwrap_or(path)
+		.to_path_buf();
+	let uid = uzers::get_current_uid();
+	// a shared trash directory must be sticky and must not be a link
+	let shared = top.join(".Trash");
+	let is_shared_valid = fs::symlink_metadata(&shared)
+		.is_ok_and(|metadata| metadata.is_dir() && metadata.mode() & 0o1000 != 0);
+	let mut candidates = vec![top.join(format!(".Trash-{}", uid))];
+	if is_shared_valid {
+		candidates.insert(0, shared.join(uid.to_string()));
+	}
+	for candidate in candidates {
+		if create_dirs(&candidate).is_ok() {
+			return Ok(TrashDir {
+				path: candidate,
+				top: Some(top),
+			});
+		}
+	}
+	Err(ErrorResponse::new(
+		ErrorCode::Unsupported,
+		"entry cannot be moved to the trash, but it can be deleted permanently",
+	)
+	.with_path(path))
+}
+
+#[cfg(target_os = "windows")]
+fn find_dir(path: &Path) -> Result<TrashDir, ErrorResponse> {
+	Err(ErrorResponse::new(ErrorCode::Unsupported, "trash is not supported").with_path(path))
+}
+
+#[cfg(not(target_os = "windows"))]
+fn create_dirs(path: &Path) -> io::Result<()> {
+	use std::os::unix::fs::DirBuilderExt;
+
+	let mut builder = fs::DirBuilder::new();
+	builder.recursive(true).mode(0o700);
+	builder.create(path.join("files"))?;
+	builder.create(path.join("info"))
+}
+
+#[cfg(all(test, not(target_os = "windows")))]
+mod tests {
+	use super::*;
+	use crate::testing::TempDir;
+	use xcmd_base::JobKind;
+
+	fn list_trash() -> Vec<FileInfo> {
+		let request = ListRequest {
+			path: Some(TRASH_PATH.to_string()),
+			key: None,
+			cursor: None,
+			limit: None,
+		};
+		list(Path::new(TRASH_PATH), &request, &None).unwrap().files
+	}
+
+	#[test]
+	fn trash_writes_info_files() {
+		let dir = TempDir::new();
+		let path = dir.write("a b%.txt", "1");
+		let (file, info) = trash(&path).unwrap();
+		assert!(!path.exists());
+		let trash_dir = dir.join("data/Trash");
+		assert_eq!(file, trash_dir.join("files/a b%.txt"));
+		assert_eq!(info, trash_dir.join("info/a b%.txt.trashinfo"));
+		let content = fs::read_to_string(&info).unwrap();
+		let original_path = fs::canonicalize(dir.path()).unwrap().join("a b%.txt");
+		let lines = content.lines().collect::<Vec<_>>();
+		assert_eq!(
+			lines[..2],
+			[
+				"[Trash Info]",
+				&format!("Path={}", encode_path(&original_path))
+			]
+		);
+		assert!(lines[1].ends_with("/a%20b%25.txt"));
+		let date = lines[2].strip_prefix("DeletionDate=").unwrap();
+		assert!(NaiveDateTime::parse_from_str(date, DATE_FORMAT).is_ok());
+
+		// names of entries deleted before are not reused
+		let path = dir.write("a b%.txt", "2");
+		let (file, info) = trash(&path).unwrap();
+		assert_eq!(file, trash_dir.join("files/a b%.txt.2"));
+		assert_eq!(info, trash_dir.join("info/a b%.txt.2.trashinfo"));
+	}
+
+	#[test]
+	fn list_trashed_entries() {
+		let dir = TempDir::new();
+		trash(&dir.write("a.txt", "1")).unwrap();
+		let trash_dir = dir.join("data/Trash");
+		// entries without info files and info files without entries are left out
+		dir.write("data/Trash/files/orphan", "");
+		dir.write(
+			"data/Trash/info/missing.trashinfo",
+			"[Trash Info]\nPath=/missing\n",
+		);
+		let files = list_trash();
+		assert_eq!(files.len(), 1);
+		let file = &files[0];
+		assert_eq!((file.name.as_str(), file.extension.as_str()), ("a", "txt"));
+		let original_path = fs::canonicalize(dir.path()).unwrap().join("a.txt");
+		assert_eq!(file.original_path.as_deref(), original_path.to_str());
+		let now = Local::now().timestamp_millis();
+		assert!(file
+			.deletion_date
+			.is_some_and(|date| (now - date).abs() < 60_000));
+		assert_eq!(
+			resolve(Path::new(&file.key)).unwrap(),
+			trash_dir.join("files/a.txt")
+		);
+	}
+
+	#[test]
+	fn restore_trashed_entries() {
+		let dir = TempDir::new();
+		trash(&dir.write("a", "1")).unwrap();
+		trash(&dir.write("b", "2")).unwrap();
+		dir.write("b", "new");
+		let mut keys = list_trash()
+			.into_iter()
+			.map(|file| file.key)
+			.collect::<Vec<_>>();
+		keys.sort();
+		let job = Job::new(0, JobKind::Restore);
+		restore(RestoreRequest { keys }, &job).unwrap();
+		assert_eq!(dir.read("a"), "1");
+		// entries whose original path is taken stay in the trash
+		let results = job.results(0, usize::MAX);
+		assert!(results[0].error.is_none());
+		let error = results[1].error.as_ref().unwrap();
+		assert_eq!(error.code, ErrorCode::AlreadyExists);
+		assert_eq!(dir.read("b"), "new");
+		assert_eq!(list_trash().len(), 1);
+		assert!(!dir.join("data/Trash/info/a.trashinfo").exists());
+	}
+
+	#[test]
+	fn delete_trashed_entries() {
+		let dir = TempDir::new();
+		trash(&dir.write("a", "1")).unwrap();
+		let keys = list_trash()
+			.into_iter()
+			.map(|file| file.key)
+			.collect::<Vec<_>>();
+		delete(Path::new(""), &keys, &Job::new(0, JobKind::Delete)).unwrap();
+		assert!(list_trash().is_empty());
+		assert!(!dir.join("data/Trash/files/a").exists());
+		assert!(!dir.join("data/Trash/info/a.trashinfo").exists());
+	}
+}
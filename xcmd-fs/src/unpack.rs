@@ -0,0 +1,624 @@
+use crate::archive::{self, get_zip_date, zip_error, S_IFLNK, S_IFMT};
+use crate::pack::ProgressReader;
+use crate::{create_symlink, get_local_file, get_paths};
+use bzip2::read::MultiBzDecoder;
+use flate2::read::MultiGzDecoder;
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use xcmd_base::{
+	ArchiveFormat, ConflictPolicy, ErrorCode, ErrorContext, ErrorResponse, FileInfo, Job,
+	JobResult, UnpackRequest,
+};
+use xz2::read::XzDecoder;
+
+/// Compression of a file compressed on its own.
+#[derive(Clone, Copy)]
+enum Compression {
+	Gz,
+	Xz,
+	Zst,
+	Bz2,
+}
+
+/// Extracts entries of an archive, or a file compressed on its own, into a directory.
+/// Entries with absolute paths or parent components are rejected, as are entries
+/// reaching outside the destination through links; they are results with an error.
+pub fn unpack(request: UnpackRequest, job: &Job) -> Result<(), ErrorResponse> {
+	let (path, _) = get_paths(&request.path, &None);
+	let destination = PathBuf::from(&request.destination);
+	fs::create_dir_all(&destination).with_path(&destination)?;
+	let mut unpacker = Unpacker {
+		destination: destination.canonicalize().with_path(&destination)?,
+		conflict: request.conflict,
+		job,
+		renamed: Vec::new(),
+		skipped: Vec::new(),
+	};
+
+	if let Some(compression) = get_compression(&path) {
+		return unpacker.decompress(&path, compression);
+	}
+	let (archive_path, directory) = archive::split_path(&path).ok_or_else(|| {
+		ErrorResponse::new(ErrorCode::InvalidInput, "file is not an archive").with_path(&path)
+	})?;
+	let selection = Selection {
+		prefix: if directory.is_empty() {
+			String::new()
+		} else {
+			format!("{}/", directory)
+		},
+		keys: request
+			.keys
+			.iter()
+			.map(|key| key.trim_end_matches('/').to_string())
+			.collect(),
+	};
+	match archive::get_format(&archive_path) {
+		Some(ArchiveFormat::Zip) => unpacker.unpack_zip(&archive_path, &selection),
+		Some(format) => unpacker.unpack_tar(&archive_path, format, &selection),
+		None => Err(
+			ErrorResponse::new(ErrorCode::InvalidInput, "file is not an archive")
+				.with_path(&archive_path),
+		),
+	}
+}
+
+/// Entries selected within a directory of the archive.
+struct Selection {
+	/// Path of the directory with a trailing separator, empty for the archive root.
+	prefix: String,
+	keys: Vec<String>,
+}
+
+impl Selection {
+	/// Gets the path of a selected entry relative to the directory.
+	fn get_name<'a>(&self, path: &'a str) -> Option<&'a str> {
+		let name = path.strip_prefix(&self.prefix)?;
+		let is_selected = self.keys.is_empty()
+			|| self.keys.iter().any(|key| {
+				name.strip_prefix(key.as_str())
+					.is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+			});
+		Some(name).filter(|name| is_selected && !name.is_empty())
+	}
+
+	fn is_all(&self) -> bool {
+		self.prefix.is_empty() && self.keys.is_empty()
+	}
+}
+
+enum EntryKind {
+	Directory,
+	File,
+	Symlink(PathBuf),
+	/// Hard link to an entry extracted before, with its path relative to the directory.
+	HardLink(String),
+	/// Devices and pipes, which are not extracted.
+	Other,
+}
+
+struct Unpacker<'a> {
+	destination: PathBuf,
+	conflict: ConflictPolicy,
+	job: &'a Job,
+	/// Directories extracted with another name, with the paths they were extracted to.
+	renamed: Vec<(String, PathBuf)>,
+	/// Directories left out because of a conflict.
+	skipped: Vec<String>,
+}
+
+impl Unpacker<'_> {
+	fn unpack_zip(
+		&mut self,
+		archive_path: &Path,
+		selection: &Selection,
+	) -> Result<(), ErrorResponse> {
+		let mut archive = archive::open_zip(archive_path)?;
+
+		// sizes are known from the central directory, so the progress has totals
+		let mut names = Vec::new();
+		let (mut files_total, mut bytes_total) = (0, 0);
+		for index in 0..archive.len() {
+			let file = archive
+				.by_index_raw(index)
+				.map_err(|err| zip_error(err, archive_path))?;
+			let name = self.select(file.name(), selection);
+			if name.is_some() && !file.is_dir() {
+				files_total += 1;
+				bytes_total += file.size();
+			}
+			names.push(name);
+		}
+		self.job.update(|progress| {
+			progress.files_total = files_total;
+			progress.bytes_total = bytes_total;
+		});
+
+		let job = self.job;
+		for (index, name) in names.into_iter().enumerate() {
+			let Some(name) = name else {
+				continue;
+			};
+			job.checkpoint()?;
+			let mut file = archive
+				.by_index(index)
+				.map_err(|err| zip_error(err, archive_path))?;
+			let mode = file.unix_mode();
+			let kind = if file.is_dir() {
+				EntryKind::Directory
+			} else if mode.is_some_and(|mode| mode & S_IFMT == S_IFLNK) {
+				let mut target = String::new();
+				file.read_to_string(&mut target).with_path(archive_path)?;
+				EntryKind::Symlink(PathBuf::from(target))
+			} else {
+				EntryKind::File
+			};
+			let date = get_zip_date(&file.last_modified()).max(0) as u64;
+			let date = UNIX_EPOCH + Duration::from_millis(date);
+			let mut reader = ProgressReader { reader: file, job };
+			self.extract(&name, kind, mode, Some(date), &mut reader)?;
+		}
+		Ok(())
+	}
+
+	/// Extracts a tar archive in one pass; as compressed archives have no index, the
+	/// progress is the part of the archive file read.
+	fn unpack_tar(
+		&mut self,
+		archive_path: &Path,
+		format: ArchiveFormat,
+		selection: &Selection,
+	) -> Result<(), ErrorResponse> {
+		let file = File::open(archive_path).with_path(archive_path)?;
+		let size = file.metadata().with_path(archive_path)?.len();
+		self.job.update(|progress| progress.bytes_total = size);
+		let job = self.job;
+		let reader = BufReader::new(ProgressReader { reader: file, job });
+		let mut archive =
+			tar::Archive::new(archive::decode(reader, format).with_path(archive_path)?);
+
+		let entries = archive
+			.entries()
+			.map_err(|err| self.read_error(err, archive_path))?;
+		for entry in entries {
+			let mut entry = entry.map_err(|err| self.read_error(err, archive_path))?;
+			let path = String::from_utf8_lossy(&entry.path_bytes()).to_string();
+			let header = entry.header();
+			let entry_type = header.entry_type();
+			if entry_type.is_pax_global_extensions() {
+				continue;
+			}
+			let Some(name) = self.select(&path, selection) else {
+				continue;
+			};
+			let mode = header.mode().ok();
+			let date = header
+				.mtime()
+				.ok()
+				.map(|mtime| UNIX_EPOCH + Duration::from_secs(mtime));
+			let kind = if entry_type.is_dir() {
+				EntryKind::Directory
+			} else if entry_type.is_file() || entry_type.is_contiguous() {
+				EntryKind::File
+			} else if entry_type.is_symlink() || entry_type.is_hard_link() {
+				let target = entry
+					.link_name_bytes()
+					.map(|target| String::from_utf8_lossy(&target).to_string())
+					.unwrap_or_default();
+				if entry_type.is_symlink() {
+					EntryKind::Symlink(PathBuf::from(target))
+				} else {
+					let source = archive::normalize(&target);
+					match source
+						.as_deref()
+						.and_then(|source| selection.get_name(source))
+					{
+						Some(source) if !is_unsafe(&target) => {
+							EntryKind::HardLink(source.to_string())
+						}
+						_ => {
+							self.add_error(
+								&name,
+								ErrorResponse::new(
+									ErrorCode::InvalidInput,
+									"link target is not extracted",
+								),
+							);
+							continue;
+						}
+					}
+				}
+			} else {
+				EntryKind::Other
+			};
+			self.extract(&name, kind, mode, date, &mut entry)?;
+		}
+		Ok(())
+	}
+
+	/// Decompresses a file next to the name without the extension, like `gunzip`.
+	fn decompress(&mut self, path: &Path, compression: Compression) -> Result<(), ErrorResponse> {
+		let name = path
+			.file_stem()
+			.map(|x| x.to_string_lossy().to_string())
+			.unwrap_or_default();
+		let file = File::open(path).with_path(path)?;
+		let metadata = file.metadata().with_path(path)?;
+		self.job.update(|progress| {
+			progress.files_total = 1;
+			progress.bytes_total = metadata.len();
+		});
+
+		let job = self.job;
+		let reader = BufReader::new(ProgressReader { reader: file, job });
+		let mut reader: Box<dyn Read> = match compression {
+			Compression::Gz => Box::new(MultiGzDecoder::new(reader)),
+			Compression::Xz => Box::new(XzDecoder::new_multi_decoder(reader)),
+			Compression::Zst => Box::new(zstd::Decoder::with_buffer(reader).with_path(path)?),
+			Compression::Bz2 => Box::new(MultiBzDecoder::new(reader)),
+		};
+		self.extract(
+			&name,
+			EntryKind::File,
+			None,
+			metadata.modified().ok(),
+			&mut reader,
+		)
+	}
+
+	/// Gets the path of an entry relative to the destination, or `None` if it is not
+	/// selected or is rejected.
+	fn select(&self, path: &str, selection: &Selection) -> Option<String> {
+		let normalized = archive::normalize(path);
+		let is_selected = match &normalized {
+			Some(normalized) => selection.get_name(normalized).is_some(),
+			None => selection.is_all(),
+		};
+		if !is_selected {
+			return None;
+		}
+		if is_unsafe(path) {
+			self.add_error(
+				path,
+				ErrorResponse::new(ErrorCode::InvalidInput, "entry is outside the destination"),
+			);
+			return None;
+		}
+		selection.get_name(&normalized?).map(String::from)
+	}
+
+	fn extract(
+		&mut self,
+		name: &str,
+		kind: EntryKind,
+		mode: Option<u32>,
+		date: Option<SystemTime>,
+		reader: &mut dyn Read,
+	) -> Result<(), ErrorResponse> {
+		self.job.checkpoint()?;
+		let is_skipped = self
+			.skipped
+			.iter()
+			.any(|directory| is_within(name, directory));
+		if is_skipped || matches!(kind, EntryKind::Other) {
+			return Ok(());
+		}
+		// directories implied by the paths of entries may conflict with files as well
+		for (index, _) in name.match_indices('/') {
+			let directory = &name[..index];
+			let path = self.get_path(directory);
+			if path.exists() && !path.is_dir() {
+				match self.resolve_conflict(directory, &EntryKind::Directory, &path)? {
+					Some(target) => {
+						fs::create_dir(&target).with_path(&target)?;
+						if target != path {
+							self.add_file(&target);
+						}
+					}
+					None => return Ok(()),
+				}
+			}
+		}
+		let path = self.get_path(name);
+		self.job
+			.update(|progress| progress.current = Some(path.to_string_lossy().to_string()));
+
+		// parents may be links placed by earlier entries
+		if let Some(parent) = path.parent() {
+			fs::create_dir_all(parent).with_path(parent)?;
+			if !parent
+				.canonicalize()
+				.with_path(parent)?
+				.starts_with(&self.destination)
+			{
+				self.add_error(
+					name,
+					ErrorResponse::new(ErrorCode::InvalidInput, "entry is outside the destination"),
+				);
+				return Ok(());
+			}
+		}
+		let Some(target) = self.resolve_conflict(name, &kind, &path)? else {
+			return Ok(());
+		};
+
+		match kind {
+			EntryKind::Directory => {
+				if !target.is_dir() {
+					fs::create_dir(&target).with_path(&target)?;
+				}
+			}
+			EntryKind::File => {
+				self.write_file(&target, reader, mode, date)?;
+				self.job.update(|progress| progress.files_done += 1);
+			}
+			EntryKind::Symlink(link_target) => {
+				create_symlink(&link_target, &target).with_path(&target)?;
+			}
+			EntryKind::HardLink(source) => {
+				let source = self.get_path(&source);
+				let is_inside = source
+					.canonicalize()
+					.is_ok_and(|source| source.starts_with(&self.destination));
+				if !is_inside {
+					self.add_error(
+						name,
+						ErrorResponse::new(ErrorCode::InvalidInput, "link target is not extracted"),
+					);
+					return Ok(());
+				}
+				fs::hard_link(&source, &target).with_path(&target)?;
+			}
+			EntryKind::Other => {}
+		}
+
+		if target != path {
+			self.add_file(&target);
+		}
+		Ok(())
+	}
+
+	/// Gets the path an entry is extracted to, within renamed directories.
+	fn get_path(&self, name: &str) -> PathBuf {
+		for (directory, path) in &self.renamed {
+			if name == directory {
+				return path.clone();
+			}
+			if let Some(rest) = name.strip_prefix(&format!("{}/", directory)) {
+				return path.join(rest);
+			}
+		}
+		self.destination.join(name)
+	}
+
+	/// Gets the path the entry is extracted to after the conflict policy is applied,
+	/// or `None` if it is skipped.
+	fn resolve_conflict(
+		&mut self,
+		name: &str,
+		kind: &EntryKind,
+		path: &Path,
+	) -> Result<Option<PathBuf>, ErrorResponse> {
+		let Ok(metadata) = fs::symlink_metadata(path) else {
+			return Ok(Some(path.to_path_buf()));
+		};
+		let is_directory = matches!(kind, EntryKind::Directory);
+		if is_directory && metadata.is_dir() {
+			return Ok(Some(path.to_path_buf()));
+		}
+		match self.conflict {
+			ConflictPolicy::Overwrite if metadata.is_dir() => {
+				self.add_error(
+					name,
+					ErrorResponse::new(ErrorCode::IsADirectory, "destination is a directory")
+						.with_path(path),
+				);
+				Ok(None)
+			}
+			ConflictPolicy::Overwrite => {
+				fs::remove_file(path).with_path(path)?;
+				Ok(Some(path.to_path_buf()))
+			}
+			ConflictPolicy::Skip => {
+				if is_directory {
+					self.skipped.push(name.to_string());
+				}
+				self.add_error(
+					name,
+					ErrorResponse::new(ErrorCode::AlreadyExists, "destination already exists")
+						.with_path(path),
+				);
+				Ok(None)
+			}
+			ConflictPolicy::Rename => {
+				let renamed = get_free_path(path);
+				if is_directory {
+					self.renamed.push((name.to_string(), renamed.clone()));
+				}
+				Ok(Some(renamed))
+			}
+		}
+	}
+
+	/// Writes a file; a partially written file is removed.
+	fn write_file(
+		&self,
+		path: &Path,
+		reader: &mut dyn Read,
+		mode: Option<u32>,
+		date: Option<SystemTime>,
+	) -> Result<(), ErrorResponse> {
+		let written = File::create(path).and_then(|mut file| {
+			io::copy(reader, &mut file)?;
+			if let Some(date) = date {
+				file.set_modified(date)?;
+			}
+			Ok(())
+		});
+		if let Err(err) = written {
+			fs::remove_file(path).ok();
+			return Err(self.read_error(err, path));
+		}
+		set_mode(path, mode).with_path(path)
+	}
+
+	/// Reading fails when the job is cancelled, which is reported as cancellation.
+	fn read_error(&self, err: io::Error, path: &Path) -> ErrorResponse {
+		match self.job.checkpoint() {
+			Err(cancelled) => cancelled.into(),
+			Ok(()) => ErrorResponse::from(err).with_path(path),
+		}
+	}
+
+	/// Adds an entry extracted with another name as a result.
+	fn add_file(&self, path: &Path) {
+		let mut file = get_local_file(path, None, &None);
+		file.key = self.get_key(path);
+		self.job.add_result(JobResult {
+			file,
+			..Default::default()
+		});
+	}
+
+	fn add_error(&self, name: &str, error: ErrorResponse) {
+		self.job.add_result(JobResult {
+			file: FileInfo {
+				key: name.to_string(),
+				name: name.rsplit('/').next().unwrap_or(name).to_string(),
+				attributes: "-".to_string(),
+				..Default::default()
+			},
+			error: Some(error),
+			..Default::default()
+		});
+	}
+
+	/// Gets the key of an extracted entry, relative to the destination.
+	fn get_key(&self, path: &Path) -> String {
+		path.strip_prefix(&self.destination)
+			.unwrap_or(path)
+			.to_string_lossy()
+			.replace('\\', "/")
+	}
+}
+
+/// Indicates whether the path of an entry is absolute or has parent components, which
+/// could place it outside the destination.
+fn is_unsafe(path: &str) -> bool {
+	let bytes = path.as_bytes();
+	path.starts_with(['/', '\\'])
+		|| (bytes.len() >= 2 && bytes[0].is_ascii_alphabetic() && bytes[1] == b':')
+		|| path.split(['/', '\\']).any(|part| part == "..")
+}
+
+fn is_within(name: &str, directory: &str) -> bool {
+	name.strip_prefix(directory)
+		.is_some_and(|rest| rest.starts_with('/'))
+}
+
+/// Recognizes files compressed on their own, which are not tar archives.
+fn get_compression(path: &Path) -> Option<Compression> {
+	if archive::get_format(path).is_some() || !path.is_file() {
+		return None;
+	}
+	let extension = path.extension()?.to_string_lossy().to_ascii_lowercase();
+	match extension.as_str() {
+		"gz" => Some(Compression::Gz),
+		"xz" => Some(Compression::Xz),
+		"zst" => Some(Compression::Zst),
+		"bz2" => Some(Compression::Bz2),
+		_ => None,
+	}
+}
+
+/// Gets a name that does not exist yet by appending a number, such as `name (2).txt`.
+fn get_free_path(path: &Path) -> PathBuf {
+	let name = path
+		.file_name()
+		.map(|x| x.to_string_lossy().to_string())
+		.unwrap_or_default();
+	let (stem, extension) = match name.rsplit_once('.') {
+		Some((stem, extension)) if !stem.is_empty() => (stem, format!(".{}", extension)),
+		_ => (name.as_str(), String::new()),
+	};
+	(2..)
+		.map(|number| path.with_file_name(format!("{} ({}){}", stem, number, extension)))
+		.find(|path| fs::symlink_metadata(path).is_err())
+		.unwrap_or_default()
+}
+
+#[cfg(not(target_os = "windows"))]
+fn set_mode(path: &Path, mode: Option<u32>) -> io::Result<()> {
+	use std::os::unix::fs::PermissionsExt;
+	match mode {
+		// special bits such as set-user-ID are not restored
+		Some(mode) => fs::set_permissions(path, fs::Permissions::from_mode(mode & 0o777)),
+		None => Ok(()),
+	}
+}
+
+#[cfg(target_os = "windows")]
+fn set_mode(_path: &Path, _mode: Option<u32>) -> io::Result<()> {
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn select(prefix: &str, keys: &[&str]) -> Selection {
+		Selection {
+			prefix: prefix.to_string(),
+			keys: keys.iter().map(|key| key.to_string()).collect(),
+		}
+	}
+
+	#[test]
+	fn is_unsafe_paths() {
+		for path in [
+			"/etc/passwd",
+			"\\windows",
+			"C:\\x",
+			"c:x",
+			"..",
+			"a/../b",
+			"a\\..\\b",
+		] {
+			assert!(is_unsafe(path), "{}", path);
+		}
+		for path in ["a/b", "a..b/c", "...", "a/..b", "1:2"] {
+			assert!(!is_unsafe(path), "{}", path);
+		}
+	}
+
+	#[test]
+	fn get_name_of_all_entries() {
+		let selection = select("", &[]);
+		assert!(selection.is_all());
+		assert_eq!(selection.get_name("a/b.txt"), Some("a/b.txt"));
+		assert_eq!(selection.get_name(""), None);
+	}
+
+	#[test]
+	fn get_name_within_directory() {
+		let selection = select("dir/", &[]);
+		assert!(!selection.is_all());
+		assert_eq!(selection.get_name("dir/a/b.txt"), Some("a/b.txt"));
+		assert_eq!(selection.get_name("dir/"), None);
+		assert_eq!(selection.get_name("other/a"), None);
+		assert_eq!(selection.get_name("directory/a"), None);
+	}
+
+	#[test]
+	fn get_name_of_selected_keys() {
+		let selection = select("dir/", &["a", "c.txt"]);
+		assert_eq!(selection.get_name("dir/a"), Some("a"));
+		assert_eq!(selection.get_name("dir/a/b.txt"), Some("a/b.txt"));
+		assert_eq!(selection.get_name("dir/c.txt"), Some("c.txt"));
+		// keys are whole names, not prefixes
+		assert_eq!(selection.get_name("dir/ab"), None);
+		assert_eq!(selection.get_name("dir/c.txt.bak"), None);
+	}
+}
@@ -0,0 +1,252 @@
+use crate::{get_local_file, get_paths, trim_long_path_prefix};
+use futures_util::{stream, StreamExt};
+use notify_debouncer_full::notify::event::{ModifyKind, RemoveKind, RenameMode};
+use notify_debouncer_full::notify::{self, Event, EventKind, RecursiveMode, Watcher};
+use notify_debouncer_full::{new_debouncer, DebounceEventResult};
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use xcmd_base::{
+	ErrorCode, ErrorContext, ErrorResponse, WatchEvent, WatchEventKind, WatchRequest, WatchStream,
+};
+
+const DEBOUNCE_TIMEOUT: Duration = Duration::from_millis(200);
+
+pub fn watch(request: WatchRequest) -> Result<WatchStream, ErrorResponse> {
+	let (_path, full_path) = get_paths(&request.path, &request.key);
+	let full_path_canonicalized = fs::canonicalize(&full_path).with_path(&full_path)?;
+	let full_path_str = full_path_canonicalized.to_string_lossy();
+	let full_path = Path::new(trim_long_path_prefix(&full_path_str));
+
+	// collects events for a short time, so bursts of writes are reported once and
+	// both halves of a rename are paired
+	let (sender, receiver) = mpsc::unbounded_channel();
+	let mut debouncer = new_debouncer(DEBOUNCE_TIMEOUT, None, move |result| {
+		sender.send(result).ok();
+	})
+	.map_err(notify_error)?;
+	debouncer
+		.watcher()
+		.watch(full_path, RecursiveMode::NonRecursive)
+		.map_err(notify_error)?;
+	debouncer
+		.cache()
+		.add_root(full_path, RecursiveMode::NonRecursive);
+
+	// the stream owns the watcher, so a disconnected client stops watching
+	let events = stream::unfold(
+		(debouncer, receiver),
+		|(debouncer, mut receiver)| async move {
+			let result = receiver.recv().await?;
+			Some((result, (debouncer, receiver)))
+		},
+	)
+	.flat_map(|result| stream::iter(get_watch_events(result)));
+	Ok(Box::pin(events))
+}
+
+fn get_watch_events(result: DebounceEventResult) -> Vec<Result<WatchEvent, ErrorResponse>> {
+	match result {
+		Ok(events) => events.iter().flat_map(|event| get_events(event)).collect(),
+		Err(errors) => errors
+			.into_iter()
+			.map(|err| Err(notify_error(err)))
+			.collect(),
+	}
+}
+
+fn get_events(event: &Event) -> Vec<Result<WatchEvent, ErrorResponse>> {
+	match event.kind {
+		EventKind::Create(_) => get_changes(WatchEventKind::Created, event),
+		EventKind::Modify(ModifyKind::Name(RenameMode::Both)) => match event.paths.as_slice() {
+			[from, to] => {
+				let mut change = get_change(WatchEventKind::Renamed, to);
+				// keys of directories end with a separator, also the previous one
+				let is_directory = change.file.as_ref().is_some_and(|file| file.is_directory);
+				change.old_key = get_name(from)
+					.map(|name| format!("{}{}", name, if is_directory { "/" } else { "" }));
+				vec![Ok(change)]
+			}
+			_ => vec![],
+		},
+		// entries moved from or into other directories
+		EventKind::Modify(ModifyKind::Name(_)) => event
+			.paths
+			.iter()
+			.map(|path| {
+				if fs::symlink_metadata(path).is_ok() {
+					Ok(get_change(WatchEventKind::Created, path))
+				} else {
+					Ok(get_removal(path, false))
+				}
+			})
+			.collect(),
+		EventKind::Modify(_) => get_changes(WatchEventKind::Modified, event),
+		EventKind::Remove(kind) => event
+			.paths
+			.iter()
+			.map(|path| Ok(get_removal(path, kind == RemoveKind::Folder)))
+			.collect(),
+		EventKind::Access(_) | EventKind::Any | EventKind::Other => vec![],
+	}
+}
+
+fn get_changes(kind: WatchEventKind, event: &Event) -> Vec<Result<WatchEvent, ErrorResponse>> {
+	event
+		.paths
+		.iter()
+		.map(|path| Ok(get_change(kind, path)))
+		.collect()
+}
+
+fn get_change(kind: WatchEventKind, path: &Path) -> WatchEvent {
+	let file = get_local_file(path, None, &None);
+	WatchEvent {
+		kind,
+		path: get_parent(path),
+		key: file.key.clone(),
+		old_key: None,
+		file: Some(file),
+	}
+}
+
+/// Gets the event of a removed entry; keys of directories are only known when
+/// the watcher reports the entry type.
+fn get_removal(path: &Path, is_directory: bool) -> WatchEvent {
+	let name = get_name(path).unwrap_or_default();
+	WatchEvent {
+		kind: WatchEventKind::Removed,
+		path: get_parent(path),
+		key: format!("{}{}", name, if is_directory { "/" } else { "" }),
+		old_key: None,
+		file: None,
+	}
+}
+
+fn get_parent(path: &Path) -> String {
+	path.parent()
+		.map(|x| x.to_string_lossy().to_string())
+		.unwrap_or_default()
+}
+
+fn get_name(path: &Path) -> Option<String> {
+	path.file_name().map(|x| x.to_string_lossy().to_string())
+}
+
+fn notify_error(error: notify::Error) -> ErrorResponse {
+	let response = match error.kind {
+		notify::ErrorKind::Io(err) => ErrorResponse::from(err),
+		notify::ErrorKind::PathNotFound | notify::ErrorKind::WatchNotFound => {
+			ErrorResponse::new(ErrorCode::NotFound, "path does not exist")
+		}
+		notify::ErrorKind::MaxFilesWatch => {
+			ErrorResponse::new(ErrorCode::Io, "limit of watched directories was reached")
+		}
+		notify::ErrorKind::Generic(message) => ErrorResponse::new(ErrorCode::Io, message),
+		notify::ErrorKind::InvalidConfig(_) => {
+			ErrorResponse::new(ErrorCode::Internal, "invalid watcher configuration")
+		}
+	};
+	match error.paths.first() {
+		Some(path) => response.with_path(path),
+		None => response,
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::testing::TempDir;
+	use notify::event::{AccessKind, CreateKind};
+
+	fn get_keys(event: Event) -> Vec<(WatchEventKind, String, Option<String>)> {
+		get_events(&event)
+			.into_iter()
+			.map(|change| {
+				let change = change.unwrap();
+				(change.kind, change.key, change.old_key)
+			})
+			.collect()
+	}
+
+	fn rename_event(mode: RenameMode) -> Event {
+		Event::new(EventKind::Modify(ModifyKind::Name(mode)))
+	}
+
+	#[test]
+	fn get_events_of_renames() {
+		let dir = TempDir::new();
+		let file = dir.write("new.txt", "");
+		fs::create_dir(dir.join("new-dir")).unwrap();
+		let both = rename_event(RenameMode::Both);
+		assert_eq!(
+			get_keys(
+				both.clone()
+					.add_path(dir.join("old.txt"))
+					.add_path(file.clone())
+			),
+			[(
+				WatchEventKind::Renamed,
+				"new.txt".to_string(),
+				Some("old.txt".to_string())
+			)]
+		);
+		assert_eq!(
+			get_keys(
+				both.add_path(dir.join("old-dir"))
+					.add_path(dir.join("new-dir"))
+			),
+			[(
+				WatchEventKind::Renamed,
+				"new-dir/".to_string(),
+				Some("old-dir/".to_string())
+			)]
+		);
+		let change = get_events(&rename_event(RenameMode::Both).add_path(file.clone()))
+			.into_iter()
+			.next();
+		assert!(change.is_none());
+	}
+
+	#[test]
+	fn get_events_of_moves() {
+		let dir = TempDir::new();
+		let file = dir.write("in.txt", "");
+		// halves of renames of entries moved into or out of the directory
+		assert_eq!(
+			get_keys(rename_event(RenameMode::To).add_path(file)),
+			[(WatchEventKind::Created, "in.txt".to_string(), None)]
+		);
+		assert_eq!(
+			get_keys(rename_event(RenameMode::From).add_path(dir.join("out.txt"))),
+			[(WatchEventKind::Removed, "out.txt".to_string(), None)]
+		);
+	}
+
+	#[test]
+	fn get_events_of_removals() {
+		let dir = TempDir::new();
+		let removed = |kind| {
+			let event = Event::new(EventKind::Remove(kind)).add_path(dir.join("x"));
+			get_keys(event)[0].1.clone()
+		};
+		assert_eq!(removed(RemoveKind::Folder), "x/");
+		assert_eq!(removed(RemoveKind::File), "x");
+		assert_eq!(removed(RemoveKind::Any), "x");
+	}
+
+	#[test]
+	fn get_events_of_changes() {
+		let dir = TempDir::new();
+		let file = dir.write("a.txt", "a");
+		let created = Event::new(EventKind::Create(CreateKind::File)).add_path(file.clone());
+		let changes = get_events(&created);
+		let change = changes[0].as_ref().unwrap();
+		assert_eq!(change.kind, WatchEventKind::Created);
+		assert_eq!(change.path, dir.path().to_string_lossy());
+		assert_eq!(change.file.as_ref().map(|file| file.size), Some(1));
+		let accessed = Event::new(EventKind::Access(AccessKind::Read)).add_path(file);
+		assert!(get_events(&accessed).is_empty());
+	}
+}
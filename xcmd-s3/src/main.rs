@@ -1,5 +1,12 @@
-use actix_web::{body::to_bytes, get, post, web, App, HttpResponse, HttpServer};
-use aws_sdk_s3::{config::Credentials, types::EncodingType};
+mod operations;
+
+use async_trait::async_trait;
+use aws_sdk_s3::{
+	config::Credentials,
+	error::{ProvideErrorMetadata, SdkError},
+	primitives::DateTime,
+	types::EncodingType,
+};
 use aws_types::region::Region;
 use rust_embed::RustEmbed;
 use std::{
@@ -8,70 +15,138 @@ use std::{
 	error::Error,
 	iter::once,
 	ops::Deref,
-	path::Component,
+	path::{Component, Path},
 };
 use tracing::trace;
-use tracing_actix_web::TracingLogger;
 use xcmd_base::{
-	get_port, init_telemetry, post_startup, FileInfo, ListRequest, ListResponse, Middleware,
-	Request, Response,
+	init_telemetry, serve, Backend, Capabilities, CreateRequest, CreateResponse, DeleteRequest,
+	ErrorCode, ErrorResponse, Feature, FileInfo, Icon, JobKind, JobResponse, Jobs, ListRequest,
+	ListResponse, PathSyntax, ReadRequest, ReadResponse, RequestKind, StatRequest, StatResponse,
+	WriteRequest, WriteResponse, PROTOCOL_VERSION,
 };
 
-#[post("/")]
-async fn enact(request: web::Json<Request>) -> Result<HttpResponse, Box<dyn Error>> {
-	match request.into_inner() {
-		Request::List(request) => {
-			let response = list_files(request).await?;
-			let body = serde_json::to_string(&Response::List(response))?;
-			Ok(HttpResponse::Ok().body(body))
+struct S3Backend;
+
+#[async_trait(?Send)]
+impl Backend for S3Backend {
+	fn capabilities(&self) -> Capabilities {
+		Capabilities {
+			protocol_version: PROTOCOL_VERSION,
+			name: env!("CARGO_PKG_NAME").to_string(),
+			version: env!("CARGO_PKG_VERSION").to_string(),
+			requests: vec![
+				RequestKind::List,
+				RequestKind::Create,
+				RequestKind::Read,
+				RequestKind::Write,
+				RequestKind::Delete,
+				RequestKind::Stat,
+				RequestKind::JobStatus,
+				RequestKind::JobCancel,
+				RequestKind::JobPause,
+				RequestKind::JobResume,
+				RequestKind::JobResults,
+				RequestKind::JobRemove,
+				RequestKind::CompareDirectories,
+				RequestKind::Sync,
+				RequestKind::Capabilities,
+			],
+			path_syntax: PathSyntax {
+				separator: "/".to_string(),
+				case_sensitive: true,
+			},
+			features: vec![
+				Feature::RangedReads,
+				Feature::PagedListing,
+				Feature::MultipartWrites,
+			],
 		}
-		_ => Ok(HttpResponse::NotFound().body("".to_string())),
+	}
+
+	async fn list(&self, request: ListRequest) -> Result<ListResponse, ErrorResponse> {
+		list_files(request).await
+	}
+
+	async fn read(&self, request: ReadRequest) -> Result<ReadResponse, ErrorResponse> {
+		operations::read(request).await
+	}
+
+	async fn write(&self, request: WriteRequest) -> Result<WriteResponse, ErrorResponse> {
+		operations::write(request).await
+	}
+
+	async fn create(&self, request: CreateRequest) -> Result<CreateResponse, ErrorResponse> {
+		operations::create_directory(request).await
+	}
+
+	async fn delete(
+		&self,
+		request: DeleteRequest,
+		jobs: &Jobs,
+	) -> Result<JobResponse, ErrorResponse> {
+		let job = jobs.start_local(JobKind::Delete, |job| async move {
+			operations::delete(request, &job).await
+		});
+		Ok(job.status())
+	}
+
+	async fn stat(&self, request: StatRequest) -> Result<StatResponse, ErrorResponse> {
+		operations::stat(request).await
+	}
+
+	fn icon(&self, name: &str, _path: Option<&str>) -> Result<Option<Icon>, ErrorResponse> {
+		let asset_name = format!("{}.svg", name);
+		Ok(Asset::get(&asset_name).map(|image| Icon::svg(image.data.to_vec())))
 	}
 }
 
+/// Maps errors of the AWS SDK onto error codes; service errors are recognized by their S3 code.
+fn s3_error<E: ProvideErrorMetadata, R>(error: SdkError<E, R>, path: &str) -> ErrorResponse {
+	let code = match &error {
+		SdkError::TimeoutError(_) => ErrorCode::Timeout,
+		SdkError::DispatchFailure(_) | SdkError::ResponseError(_) => ErrorCode::Connection,
+		SdkError::ServiceError(_) => match error.code() {
+			Some(
+				"AccessDenied"
+				| "AllAccessDisabled"
+				| "InvalidAccessKeyId"
+				| "SignatureDoesNotMatch"
+				| "ExpiredToken",
+			) => ErrorCode::PermissionDenied,
+			Some("NoSuchBucket" | "NoSuchKey" | "NoSuchUpload" | "NotFound") => ErrorCode::NotFound,
+			Some("BucketAlreadyExists" | "BucketAlreadyOwnedByYou") => ErrorCode::AlreadyExists,
+			Some("InvalidBucketName" | "KeyTooLongError" | "InvalidArgument") => {
+				ErrorCode::InvalidInput
+			}
+			Some("PreconditionFailed") => ErrorCode::Conflict,
+			Some("RequestTimeout") => ErrorCode::Timeout,
+			Some("SlowDown" | "ServiceUnavailable" | "InternalError") => {
+				return ErrorResponse::new(ErrorCode::Io, error.to_string())
+					.with_path(path)
+					.with_retryable(true);
+			}
+			_ => ErrorCode::Io,
+		},
+		_ => ErrorCode::Internal,
+	};
+	let message = error
+		.message()
+		.map(|x| x.to_string())
+		.unwrap_or_else(|| error.to_string());
+	ErrorResponse::new(code, message).with_path(path)
+}
+
 #[derive(RustEmbed)]
 #[folder = "res/"]
 struct Asset;
 
-#[get("/icons/{name}")]
-async fn icon(name: web::Path<String>) -> Result<HttpResponse, Box<dyn Error>> {
-	let asset_name = format!("{}.svg", name);
-	if let Some(image) = Asset::get(&asset_name) {
-		let bytes = to_bytes(image.data.to_vec()).await?;
-		Ok(HttpResponse::Ok()
-			.content_type("image/svg+xml")
-			.append_header(("Cache-Control", "public, max-age=86400"))
-			.body(bytes))
-	} else {
-		Ok(HttpResponse::NotFound().body("".to_string()))
-	}
-}
-
 #[actix_web::main]
 async fn main() -> Result<(), Box<dyn Error>> {
 	init_telemetry("xcmd_s3");
-	let port = get_port()?;
-
-	let server = HttpServer::new(|| {
-		App::new()
-			.wrap(Middleware::cors())
-			.wrap(Middleware::token_auth())
-			.wrap(TracingLogger::default())
-			.service(icon)
-			.service(enact)
-	})
-	.bind(("127.0.0.1", port))?
-	.run();
-
-	post_startup(&server, port);
-
-	server.await?;
-	Ok(())
+	serve(S3Backend).await
 }
 
-async fn list_files(request: ListRequest) -> Result<ListResponse, Box<dyn Error>> {
-	trace!("request = {:?}", &request);
-
+async fn get_client() -> aws_sdk_s3::Client {
 	// create config for AWS SDK
 	let config = aws_config::from_env()
 		.credentials_provider(Credentials::from_keys(
@@ -84,6 +159,56 @@ async fn list_files(request: ListRequest) -> Result<ListResponse, Box<dyn Error>
 		))
 		.load()
 		.await;
+	aws_sdk_s3::Client::new(&config)
+}
+
+/// Gets the bucket name and the object key of an entry; the first component of the
+/// path is the bucket.
+fn get_object_path(
+	path: &Option<String>,
+	key: &Option<String>,
+) -> Result<(String, String), ErrorResponse> {
+	let mut full_path = Path::new(path.as_deref().unwrap_or("/")).to_path_buf();
+	if let Some(key) = key {
+		full_path.push(key);
+	}
+	let mut parts = Vec::new();
+	for component in full_path.components() {
+		match component {
+			Component::RootDir | Component::CurDir => {}
+			Component::ParentDir => {
+				parts.pop();
+			}
+			Component::Normal(part) => parts.push(part.to_string_lossy().to_string()),
+			_ => parts.clear(),
+		}
+	}
+	if parts.len() < 2 {
+		return Err(ErrorResponse::new(
+			ErrorCode::InvalidInput,
+			"path does not name an object within a bucket",
+		)
+		.with_path(&full_path));
+	}
+	let bucket = parts.remove(0);
+	Ok((bucket, parts.join("/")))
+}
+
+/// Decodes a key listed with the URL encoding type, which encodes spaces as `+`.
+fn decode_key(key: &str) -> String {
+	let key = key.replace('+', " ");
+	match urlencoding::decode(&key) {
+		Ok(key) => key.into_owned(),
+		Err(_) => key,
+	}
+}
+
+fn get_date(date: Option<&DateTime>) -> i64 {
+	date.and_then(|date| date.to_millis().ok()).unwrap_or(0)
+}
+
+async fn list_files(request: ListRequest) -> Result<ListResponse, ErrorResponse> {
+	trace!("request = {:?}", &request);
 
 	// gets the path
 	let path = if let Some(ref path) = request.path {
@@ -175,42 +300,57 @@ async fn list_files(request: ListRequest) -> Result<ListResponse, Box<dyn Error>
 	};
 
 	let mut files = Vec::<FileInfo>::new();
-	let client = aws_sdk_s3::Client::new(&config);
+	let client = get_client().await;
+
+	let mut cursor = None;
 
 	// if path has a bucket name, objects within the bucket will be listed (otherwise, bucket names will be listed)
 	if let Some(bucket_name) = bucket_name {
 		// within a bucket, there is always a parent directory that contains all buckets
-		files.push(FileInfo {
-			key: "../".to_string(),
-			name: "..".to_string(),
-			size: 0,
-			attributes: "-".to_string(),
-			date: 0,
-			extension: "".to_string(),
-			icon: "region".to_string(),
-			icon_alt: None,
-			icon_type: "".to_string(),
-			is_directory: true,
-			is_active: false,
-		});
+		// (shown on the first page only)
+		if request.cursor.is_none() {
+			files.push(FileInfo {
+				key: "../".to_string(),
+				name: "..".to_string(),
+				size: 0,
+				attributes: "-".to_string(),
+				date: 0,
+				extension: "".to_string(),
+				icon: "region".to_string(),
+				icon_alt: None,
+				icon_type: "".to_string(),
+				is_directory: true,
+				is_active: false,
+				..Default::default()
+			});
+		}
 
+		// pages are mapped onto continuation tokens of the listing
 		let resp = client
 			.list_objects_v2()
 			.bucket(bucket_name.deref())
 			.delimiter("/")
 			.prefix(&path)
 			.encoding_type(EncodingType::Url)
+			.set_continuation_token(request.cursor.clone())
+			.set_max_keys(
+				request
+					.limit
+					.map(|limit| limit.min(i32::MAX as usize) as i32),
+			)
 			.send()
-			.await?;
+			.await
+			.map_err(|err| s3_error(err, &full_path.to_string_lossy()))?;
+		cursor = resp.next_continuation_token().map(|x| x.to_string());
 
 		// get directories
 		if let Some(common_prefixes) = resp.common_prefixes() {
 			for common_prefix in common_prefixes {
 				let key = common_prefix
 					.prefix()
-					.map(|x| &x[path.len()..])
-					.unwrap_or_default()
-					.to_string();
+					.map(decode_key)
+					.map(|x| x[path.len()..].to_string())
+					.unwrap_or_default();
 				let name = key.clone();
 				let is_active = active_key.as_ref().map(|x| x == &key).unwrap_or(false);
 				files.push(FileInfo {
@@ -225,6 +365,7 @@ async fn list_files(request: ListRequest) -> Result<ListResponse, Box<dyn Error>
 					icon_type: "".to_string(),
 					is_directory: true,
 					is_active,
+					..Default::default()
 				});
 			}
 		}
@@ -234,30 +375,35 @@ async fn list_files(request: ListRequest) -> Result<ListResponse, Box<dyn Error>
 		for object in objects {
 			let key = object
 				.key()
-				.map(|x| &x[path.len()..])
-				.unwrap_or_default()
-				.to_string();
+				.map(decode_key)
+				.map(|x| x[path.len()..].to_string())
+				.unwrap_or_default();
 			let name = key.clone();
 			if !name.is_empty() {
 				let is_active = active_key.as_ref().map(|x| x == &key).unwrap_or(false);
 				files.push(FileInfo {
 					key,
 					name,
-					size: 0,
+					size: object.size().max(0) as u64,
 					attributes: "-".to_string(),
-					date: 0,
+					date: get_date(object.last_modified()),
 					extension: "".to_string(),
 					icon: "object".to_string(),
 					icon_alt: None,
 					icon_type: "".to_string(),
-					is_directory: true,
+					is_directory: false,
 					is_active,
+					..Default::default()
 				});
 			}
 		}
 	} else {
 		// if bucket name is not specified in the path
-		let resp = client.list_buckets().send().await?;
+		let resp = client
+			.list_buckets()
+			.send()
+			.await
+			.map_err(|err| s3_error(err, "/"))?;
 		let buckets = resp.buckets().unwrap_or_default();
 
 		for bucket in buckets {
@@ -276,6 +422,7 @@ async fn list_files(request: ListRequest) -> Result<ListResponse, Box<dyn Error>
 				icon_type: "".to_string(),
 				is_directory: true,
 				is_active,
+				..Default::default()
 			});
 		}
 	}
@@ -305,5 +452,7 @@ async fn list_files(request: ListRequest) -> Result<ListResponse, Box<dyn Error>
 		path: absolute_path,
 		name,
 		files,
+		cursor,
+		free_space: None,
 	})
 }
@@ -0,0 +1,425 @@
+use crate::{decode_key, get_client, get_date, get_object_path, s3_error};
+use aws_sdk_s3::{
+	primitives::{ByteStream, DateTime},
+	types::{CompletedMultipartUpload, CompletedPart, EncodingType},
+	Client,
+};
+use xcmd_base::{
+	check_offset, CreateRequest, CreateResponse, DeleteRequest, ErrorCode, ErrorContext,
+	ErrorResponse, FileInfo, Job, ReadRequest, ReadResponse, StatRequest, StatResponse, WritePart,
+	WriteRequest, WriteResponse,
+};
+
+/// Largest number of parts of a multipart upload.
+const MAX_PARTS: u32 = 10000;
+
+pub async fn read(request: ReadRequest) -> Result<ReadResponse, ErrorResponse> {
+	let (bucket, key) = get_object_path(&request.path, &request.key)?;
+	let client = get_client().await;
+	let full_path = format!("/{}/{}", bucket, key);
+
+	// ranges beyond the end are rejected, so the size is needed first
+	let head = client
+		.head_object()
+		.bucket(&bucket)
+		.key(&key)
+		.send()
+		.await
+		.map_err(|err| s3_error(err, &full_path))?;
+	let size = head.content_length().max(0) as u64;
+	let offset = request.offset.unwrap_or(0);
+	check_offset(offset, size).with_path(&full_path)?;
+	let end = request
+		.length
+		.map_or(size, |length| size.min(offset.saturating_add(length)));
+	if end <= offset {
+		return Ok(ReadResponse {
+			data: Vec::new(),
+			offset,
+			size,
+		});
+	}
+
+	let object = client
+		.get_object()
+		.bucket(&bucket)
+		.key(&key)
+		.range(format!("bytes={}-{}", offset, end - 1))
+		.send()
+		.await
+		.map_err(|err| s3_error(err, &full_path))?;
+	let data = object
+		.body
+		.collect()
+		.await
+		.map_err(|err| ErrorResponse::new(ErrorCode::Connection, err.to_string()))?
+		.into_bytes()
+		.to_vec();
+	Ok(ReadResponse { data, offset, size })
+}
+
+/// Gets an object, or a prefix when no object has the key.
+pub async fn stat(request: StatRequest) -> Result<StatResponse, ErrorResponse> {
+	let (bucket, key) = get_object_path(&request.path, &request.key)?;
+	let client = get_client().await;
+	let full_path = format!("/{}/{}", bucket, key);
+	let (parent, name) = split_path(&full_path);
+
+	match client.head_object().bucket(&bucket).key(&key).send().await {
+		Ok(head) => {
+			return Ok(StatResponse {
+				path: parent,
+				file: get_object(name, head.content_length(), head.last_modified()),
+			})
+		}
+		Err(err) => {
+			let err = s3_error(err, &full_path);
+			if err.code != ErrorCode::NotFound {
+				return Err(err);
+			}
+		}
+	}
+
+	let listing = client
+		.list_objects_v2()
+		.bucket(&bucket)
+		.prefix(format!("{}/", key))
+		.max_keys(1)
+		.send()
+		.await
+		.map_err(|err| s3_error(err, &full_path))?;
+	if listing.key_count() == 0 {
+		return Err(
+			ErrorResponse::new(ErrorCode::NotFound, "object does not exist").with_path(&full_path),
+		);
+	}
+	Ok(StatResponse {
+		path: parent,
+		file: get_prefix(name),
+	})
+}
+
+/// Uploads the object at once, or a part of it with a multipart upload. S3 sets
+/// the modification date itself and cannot append, so these options are not
+/// supported.
+pub async fn write(mut request: WriteRequest) -> Result<WriteResponse, ErrorResponse> {
+	if request.append {
+		return Err(ErrorResponse::unsupported());
+	}
+	let (bucket, key) = get_object_path(&request.path, &request.key)?;
+	let client = get_client().await;
+	let full_path = format!("/{}/{}", bucket, key);
+	if let Some(part) = request.part.take() {
+		let content = request.encoding.decode(request.content)?;
+		return write_part(&client, &bucket, &key, part, content).await;
+	}
+
+	if request.expected.is_some() || request.backup {
+		let head = match client.head_object().bucket(&bucket).key(&key).send().await {
+			Ok(head) => Some(head),
+			Err(err) => {
+				let err = s3_error(err, &full_path);
+				if err.code != ErrorCode::NotFound {
+					return Err(err);
+				}
+				None
+			}
+		};
+
+		// fails if the object was changed since the client has seen it
+		if let Some(expected) = &request.expected {
+			let unchanged = match &head {
+				Some(head) => {
+					expected
+						.date
+						.is_none_or(|date| date == get_date(head.last_modified()))
+						&& expected
+							.size
+							.is_none_or(|size| size as i64 == head.content_length())
+				}
+				None => expected.date.is_none() && expected.size.is_none(),
+			};
+			if !unchanged {
+				return Err(ErrorResponse::new(
+					ErrorCode::Conflict,
+					"file was changed by another process",
+				)
+				.with_path(&full_path));
+			}
+		}
+
+		if request.backup && head.is_some() {
+			client
+				.copy_object()
+				.bucket(&bucket)
+				.copy_source(format!("{}/{}", bucket, urlencoding::encode(&key)))
+				.key(format!("{}~", key))
+				.send()
+				.await
+				.map_err(|err| s3_error(err, &full_path))?;
+		}
+	}
+
+	let content = request.encoding.decode(request.content)?;
+	let size = content.len() as i64;
+	client
+		.put_object()
+		.bucket(&bucket)
+		.key(&key)
+		.body(ByteStream::from(content))
+		.send()
+		.await
+		.map_err(|err| s3_error(err, &full_path))?;
+
+	let (path, name) = split_path(&full_path);
+	Ok(WriteResponse {
+		path,
+		file: get_object(name, size, None),
+		upload: None,
+	})
+}
+
+/// Uploads a part of an object; the object is created from all parts when the last
+/// one is uploaded. Parts but the last must have at least 5 MiB.
+async fn write_part(
+	client: &Client,
+	bucket: &str,
+	key: &str,
+	part: WritePart,
+	content: Vec<u8>,
+) -> Result<WriteResponse, ErrorResponse> {
+	let full_path = format!("/{}/{}", bucket, key);
+	if part.number == 0 || part.number > MAX_PARTS {
+		return Err(ErrorResponse::new(
+			ErrorCode::InvalidInput,
+			format!("part number must be between 1 and {}", MAX_PARTS),
+		)
+		.with_path(&full_path));
+	}
+	let (path, name) = split_path(&full_path);
+	let is_started = part.upload.is_none();
+	let upload = match part.upload {
+		Some(upload) => upload,
+		None if part.abort => {
+			return Err(ErrorResponse::new(
+				ErrorCode::InvalidInput,
+				"upload to abort is missing",
+			))
+		}
+		None => client
+			.create_multipart_upload()
+			.bucket(bucket)
+			.key(key)
+			.send()
+			.await
+			.map_err(|err| s3_error(err, &full_path))?
+			.upload_id()
+			.map(|upload| upload.to_string())
+			.ok_or_else(|| {
+				ErrorResponse::new(ErrorCode::Connection, "S3 did not return an upload ID")
+			})?,
+	};
+	if part.abort {
+		client
+			.abort_multipart_upload()
+			.bucket(bucket)
+			.key(key)
+			.upload_id(&upload)
+			.send()
+			.await
+			.map_err(|err| s3_error(err, &full_path))?;
+		return Ok(WriteResponse {
+			path,
+			file: get_object(name, 0, None),
+			upload: None,
+		});
+	}
+
+	let uploaded = async {
+		client
+			.upload_part()
+			.bucket(bucket)
+			.key(key)
+			.upload_id(&upload)
+			.part_number(part.number as i32)
+			.body(ByteStream::from(content))
+			.send()
+			.await
+			.map_err(|err| s3_error(err, &full_path))?;
+		if part.last {
+			complete_upload(client, bucket, key, &upload).await
+		} else {
+			Ok(0)
+		}
+	}
+	.await;
+	// an upload started here is unknown to the client, so it cannot abort it
+	if uploaded.is_err() && is_started {
+		client
+			.abort_multipart_upload()
+			.bucket(bucket)
+			.key(key)
+			.upload_id(&upload)
+			.send()
+			.await
+			.ok();
+	}
+	let size = uploaded?;
+	Ok(WriteResponse {
+		path,
+		file: get_object(name, size, None),
+		upload: Some(upload).filter(|_| !part.last),
+	})
+}
+
+/// Creates the object from the uploaded parts and gets its size.
+async fn complete_upload(
+	client: &Client,
+	bucket: &str,
+	key: &str,
+	upload: &str,
+) -> Result<i64, ErrorResponse> {
+	let full_path = format!("/{}/{}", bucket, key);
+	let mut parts = Vec::new();
+	let mut size = 0;
+	let mut marker = None;
+	loop {
+		let listing = client
+			.list_parts()
+			.bucket(bucket)
+			.key(key)
+			.upload_id(upload)
+			.set_part_number_marker(marker)
+			.send()
+			.await
+			.map_err(|err| s3_error(err, &full_path))?;
+		for part in listing.parts().unwrap_or_default() {
+			size += part.size();
+			parts.push(
+				CompletedPart::builder()
+					.part_number(part.part_number())
+					.set_e_tag(part.e_tag().map(|tag| tag.to_string()))
+					.build(),
+			);
+		}
+		marker = listing.next_part_number_marker().map(|x| x.to_string());
+		if !listing.is_truncated() || marker.is_none() {
+			break;
+		}
+	}
+	client
+		.complete_multipart_upload()
+		.bucket(bucket)
+		.key(key)
+		.upload_id(upload)
+		.multipart_upload(
+			CompletedMultipartUpload::builder()
+				.set_parts(Some(parts))
+				.build(),
+		)
+		.send()
+		.await
+		.map_err(|err| s3_error(err, &full_path))?;
+	Ok(size)
+}
+
+/// Creates an empty object ending with the separator, which is shown as a directory.
+pub async fn create_directory(request: CreateRequest) -> Result<CreateResponse, ErrorResponse> {
+	let (bucket, key) = get_object_path(&request.path, &request.name)?;
+	let client = get_client().await;
+	client
+		.put_object()
+		.bucket(&bucket)
+		.key(format!("{}/", key))
+		.body(ByteStream::from(Vec::new()))
+		.send()
+		.await
+		.map_err(|err| s3_error(err, &format!("/{}/{}", bucket, key)))?;
+
+	let (_, name) = split_path(&key);
+	Ok(CreateResponse {
+		path: name.clone(),
+		directory: Some(get_prefix(name)),
+	})
+}
+
+/// Deletes the objects with the keys and all objects below them.
+pub async fn delete(request: DeleteRequest, job: &Job) -> Result<(), ErrorResponse> {
+	let client = get_client().await;
+	let mut objects = Vec::new();
+	for key in &request.keys {
+		job.checkpoint_async().await?;
+		let (bucket, key) = get_object_path(&request.path, &Some(key.clone()))?;
+		let prefix = format!("{}/", key.trim_end_matches('/'));
+		let mut cursor = None;
+		loop {
+			let listing = client
+				.list_objects_v2()
+				.bucket(&bucket)
+				.prefix(&prefix)
+				.encoding_type(EncodingType::Url)
+				.set_continuation_token(cursor)
+				.send()
+				.await
+				.map_err(|err| s3_error(err, &format!("/{}/{}", bucket, prefix)))?;
+			for object in listing.contents().unwrap_or_default() {
+				if let Some(key) = object.key() {
+					objects.push((bucket.clone(), decode_key(key)));
+				}
+			}
+			cursor = listing.next_continuation_token().map(|x| x.to_string());
+			if cursor.is_none() {
+				break;
+			}
+		}
+		// deleting a missing object succeeds, so the key itself is deleted in any case
+		objects.push((bucket, key.trim_end_matches('/').to_string()));
+	}
+	job.update(|progress| progress.files_total = objects.len() as u64);
+
+	for (bucket, key) in objects {
+		job.checkpoint_async().await?;
+		let full_path = format!("/{}/{}", bucket, key);
+		job.update(|progress| progress.current = Some(full_path.clone()));
+		client
+			.delete_object()
+			.bucket(&bucket)
+			.key(&key)
+			.send()
+			.await
+			.map_err(|err| s3_error(err, &full_path))?;
+		job.update(|progress| progress.files_done += 1);
+	}
+	Ok(())
+}
+
+/// Splits the path of an object into the path of its parent and its name.
+fn split_path(full_path: &str) -> (String, String) {
+	match full_path.rsplit_once('/') {
+		Some((parent, name)) => (parent.to_string(), name.to_string()),
+		None => (String::new(), full_path.to_string()),
+	}
+}
+
+fn get_object(name: String, size: i64, date: Option<&DateTime>) -> FileInfo {
+	FileInfo {
+		key: name.clone(),
+		name,
+		size: size.max(0) as u64,
+		attributes: "-".to_string(),
+		date: get_date(date),
+		icon: "object".to_string(),
+		..Default::default()
+	}
+}
+
+fn get_prefix(name: String) -> FileInfo {
+	FileInfo {
+		key: format!("{}/", name),
+		name,
+		attributes: "-".to_string(),
+		icon: "bucket".to_string(),
+		is_directory: true,
+		..Default::default()
+	}
+}
@@ -1,126 +1,267 @@
-use actix_web::body::to_bytes;
-use actix_web::{get, post, web, App, HttpResponse, HttpServer};
+mod operations;
+
+use async_trait::async_trait;
 use rust_embed::RustEmbed;
 use ssh2::{FileStat, Session, Sftp};
 use std::env;
 use std::error::Error;
+use std::io::{self, Read};
 use std::net::TcpStream;
-use std::sync::Arc;
-use tracing_actix_web::TracingLogger;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
 use xcmd_base::{
-	get_port, init_telemetry, post_startup, FileInfo, ListRequest, ListResponse, Middleware,
-	Request, Response,
+	get_listing_page, init_telemetry, serve, Backend, Capabilities, CreateRequest, CreateResponse,
+	DeleteRequest, EntryType, ErrorCode, ErrorResponse, Feature, FileInfo, Icon, Job, JobKind,
+	JobResponse, JobResult, Jobs, ListRequest, ListResponse, PathSyntax, PatternSyntax,
+	ReadRequest, ReadResponse, RequestKind, SearchRequest, StatRequest, StatResponse, TextMatch,
+	TransferRequest, WriteRequest, WriteResponse, PROTOCOL_VERSION,
 };
 
-#[post("/")]
-async fn enact(
-	request: web::Json<Request>,
-	sftp: web::Data<Arc<Sftp>>,
-) -> Result<HttpResponse, Box<dyn Error>> {
-	match request.into_inner() {
-		Request::List(request) => {
-			let response = list_files(request, sftp)?;
-			let body = serde_json::to_string(&Response::List(response))?;
-			Ok(HttpResponse::Ok().body(body))
+const LIBSSH2_ERROR_SOCKET_SEND: i32 = -7;
+const LIBSSH2_ERROR_TIMEOUT: i32 = -9;
+const LIBSSH2_ERROR_SOCKET_DISCONNECT: i32 = -13;
+const LIBSSH2_ERROR_SOCKET_TIMEOUT: i32 = -30;
+const LIBSSH2_ERROR_EAGAIN: i32 = -37;
+const LIBSSH2_ERROR_SOCKET_RECV: i32 = -43;
+
+const LIBSSH2_FX_NO_SUCH_FILE: i32 = 2;
+const LIBSSH2_FX_PERMISSION_DENIED: i32 = 3;
+const LIBSSH2_FX_NO_CONNECTION: i32 = 6;
+const LIBSSH2_FX_CONNECTION_LOST: i32 = 7;
+const LIBSSH2_FX_OP_UNSUPPORTED: i32 = 8;
+const LIBSSH2_FX_NO_SUCH_PATH: i32 = 10;
+const LIBSSH2_FX_FILE_ALREADY_EXISTS: i32 = 11;
+const LIBSSH2_FX_WRITE_PROTECT: i32 = 12;
+const LIBSSH2_FX_NO_SPACE_ON_FILESYSTEM: i32 = 14;
+const LIBSSH2_FX_QUOTA_EXCEEDED: i32 = 15;
+const LIBSSH2_FX_LOCK_CONFLICT: i32 = 17;
+const LIBSSH2_FX_DIR_NOT_EMPTY: i32 = 18;
+const LIBSSH2_FX_NOT_A_DIRECTORY: i32 = 19;
+const LIBSSH2_FX_INVALID_FILENAME: i32 = 20;
+
+/// Interval of checking for output of commands and for cancellation.
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+struct SshBackend {
+	session: Session,
+	sftp: Sftp,
+}
+
+#[async_trait(?Send)]
+impl Backend for SshBackend {
+	fn capabilities(&self) -> Capabilities {
+		Capabilities {
+			protocol_version: PROTOCOL_VERSION,
+			name: env!("CARGO_PKG_NAME").to_string(),
+			version: env!("CARGO_PKG_VERSION").to_string(),
+			requests: vec![
+				RequestKind::List,
+				RequestKind::Create,
+				RequestKind::Read,
+				RequestKind::Write,
+				RequestKind::Rename,
+				RequestKind::Delete,
+				RequestKind::Stat,
+				RequestKind::Search,
+				RequestKind::JobStatus,
+				RequestKind::JobCancel,
+				RequestKind::JobPause,
+				RequestKind::JobResume,
+				RequestKind::JobResults,
+				RequestKind::JobRemove,
+				RequestKind::CompareDirectories,
+				RequestKind::Sync,
+				RequestKind::Capabilities,
+			],
+			path_syntax: PathSyntax {
+				separator: "/".to_string(),
+				case_sensitive: true,
+			},
+			features: vec![
+				Feature::RangedReads,
+				Feature::PagedListing,
+				Feature::ContentSearch,
+				Feature::AppendWrites,
+			],
 		}
-		_ => Ok(HttpResponse::NotFound().body("".to_string())),
 	}
+
+	async fn list(&self, request: ListRequest) -> Result<ListResponse, ErrorResponse> {
+		list_files(request, &self.sftp)
+	}
+
+	async fn read(&self, request: ReadRequest) -> Result<ReadResponse, ErrorResponse> {
+		operations::read(request, &self.sftp)
+	}
+
+	async fn write(&self, request: WriteRequest) -> Result<WriteResponse, ErrorResponse> {
+		operations::write(request, &self.sftp)
+	}
+
+	async fn create(&self, request: CreateRequest) -> Result<CreateResponse, ErrorResponse> {
+		operations::create_directory(request, &self.sftp)
+	}
+
+	async fn rename(
+		&self,
+		request: TransferRequest,
+		jobs: &Jobs,
+	) -> Result<JobResponse, ErrorResponse> {
+		let session = self.session.clone();
+		let job = jobs.start(JobKind::Rename, move |job| {
+			let sftp = session
+				.sftp()
+				.map_err(|err| sftp_error(err, Path::new("/")))?;
+			operations::rename(request, &sftp, job)
+		});
+		Ok(job.status())
+	}
+
+	async fn delete(
+		&self,
+		request: DeleteRequest,
+		jobs: &Jobs,
+	) -> Result<JobResponse, ErrorResponse> {
+		let session = self.session.clone();
+		let job = jobs.start(JobKind::Delete, move |job| {
+			let sftp = session
+				.sftp()
+				.map_err(|err| sftp_error(err, Path::new("/")))?;
+			operations::delete(request, &sftp, job)
+		});
+		Ok(job.status())
+	}
+
+	async fn stat(&self, request: StatRequest) -> Result<StatResponse, ErrorResponse> {
+		operations::stat(request, &self.sftp)
+	}
+
+	async fn search(
+		&self,
+		request: SearchRequest,
+		jobs: &Jobs,
+	) -> Result<JobResponse, ErrorResponse> {
+		let command = get_grep_command(&request)?;
+		let session = self.session.clone();
+		let job = jobs.start(JobKind::Search, move |job| {
+			grep(&session, &command, request, job)
+		});
+		Ok(job.status())
+	}
+
+	fn icon(&self, name: &str, _path: Option<&str>) -> Result<Option<Icon>, ErrorResponse> {
+		let asset_name = format!("{}.svg", name);
+		Ok(Asset::get(&asset_name).map(|image| Icon::svg(image.data.to_vec())))
+	}
+}
+
+/// Maps errors of the SSH session and the SFTP subsystem onto error codes.
+fn sftp_error(error: ssh2::Error, path: &Path) -> ErrorResponse {
+	let code = match error.code() {
+		ssh2::ErrorCode::SFTP(LIBSSH2_FX_NO_SUCH_FILE | LIBSSH2_FX_NO_SUCH_PATH) => {
+			ErrorCode::NotFound
+		}
+		ssh2::ErrorCode::SFTP(LIBSSH2_FX_PERMISSION_DENIED | LIBSSH2_FX_WRITE_PROTECT) => {
+			ErrorCode::PermissionDenied
+		}
+		ssh2::ErrorCode::SFTP(LIBSSH2_FX_FILE_ALREADY_EXISTS) => ErrorCode::AlreadyExists,
+		ssh2::ErrorCode::SFTP(LIBSSH2_FX_DIR_NOT_EMPTY) => ErrorCode::DirectoryNotEmpty,
+		ssh2::ErrorCode::SFTP(LIBSSH2_FX_NOT_A_DIRECTORY) => ErrorCode::NotADirectory,
+		ssh2::ErrorCode::SFTP(LIBSSH2_FX_INVALID_FILENAME) => ErrorCode::InvalidInput,
+		ssh2::ErrorCode::SFTP(LIBSSH2_FX_OP_UNSUPPORTED) => ErrorCode::Unsupported,
+		ssh2::ErrorCode::SFTP(LIBSSH2_FX_NO_SPACE_ON_FILESYSTEM | LIBSSH2_FX_QUOTA_EXCEEDED) => {
+			ErrorCode::StorageFull
+		}
+		ssh2::ErrorCode::SFTP(LIBSSH2_FX_LOCK_CONFLICT) => ErrorCode::Conflict,
+		ssh2::ErrorCode::SFTP(LIBSSH2_FX_NO_CONNECTION | LIBSSH2_FX_CONNECTION_LOST)
+		| ssh2::ErrorCode::Session(
+			LIBSSH2_ERROR_SOCKET_SEND | LIBSSH2_ERROR_SOCKET_RECV | LIBSSH2_ERROR_SOCKET_DISCONNECT,
+		) => ErrorCode::Connection,
+		ssh2::ErrorCode::Session(LIBSSH2_ERROR_TIMEOUT | LIBSSH2_ERROR_SOCKET_TIMEOUT) => {
+			ErrorCode::Timeout
+		}
+		ssh2::ErrorCode::Session(LIBSSH2_ERROR_EAGAIN) => {
+			return ErrorResponse::new(ErrorCode::Io, error.message())
+				.with_path(path)
+				.with_retryable(true);
+		}
+		_ => ErrorCode::Io,
+	};
+	ErrorResponse::new(code, error.message()).with_path(path)
 }
 
 #[derive(RustEmbed)]
 #[folder = "res/"]
 struct Asset;
 
-#[get("/icons/{name}")]
-async fn icon(name: web::Path<String>) -> Result<HttpResponse, Box<dyn Error>> {
-	let asset_name = format!("{}.svg", name);
-	if let Some(image) = Asset::get(&asset_name) {
-		let bytes = to_bytes(image.data.to_vec()).await?;
-		Ok(HttpResponse::Ok()
-			.content_type("image/svg+xml")
-			.append_header(("Cache-Control", "public, max-age=86400"))
-			.body(bytes))
-	} else {
-		Ok(HttpResponse::NotFound().body("".to_string()))
-	}
-}
-
 #[actix_web::main]
 async fn main() -> Result<(), Box<dyn Error>> {
 	init_telemetry("xcmd_ssh");
-	let port = get_port()?;
 
 	env::set_var("RUST_LOG", "debug");
 	env::set_var("RUST_BACKTRACE", "1");
 	env_logger::init();
 
+	let session = connect()?;
+	let sftp = session.sftp()?;
+
+	serve(SshBackend { session, sftp }).await
+}
+
+/// Opens an authenticated session with the server of the environment.
+fn connect() -> Result<Session, Box<dyn Error>> {
 	let tcp = TcpStream::connect(env::var("SSH_HOST").unwrap_or_default())?;
 	let mut session = Session::new()?;
 	session.set_tcp_stream(tcp);
 	session.handshake()?;
 
-	session
-		.userauth_password(
-			&env::var("SSH_USER").unwrap_or_default(),
-			&env::var("SSH_PASSWORD").unwrap_or_default(),
-		)
-		.unwrap();
-	assert!(session.authenticated());
-
-	let sftp = Arc::new(session.sftp()?);
-
-	let server = HttpServer::new(move || {
-		App::new()
-			.app_data(web::Data::new(sftp.clone()))
-			.wrap(Middleware::cors())
-			.wrap(Middleware::token_auth())
-			.wrap(TracingLogger::default())
-			.service(icon)
-			.service(enact)
-	})
-	.bind(("127.0.0.1", port))?
-	.run();
-
-	post_startup(&server, port);
-
-	server.await?;
-	Ok(())
+	session.userauth_password(
+		&env::var("SSH_USER").unwrap_or_default(),
+		&env::var("SSH_PASSWORD").unwrap_or_default(),
+	)?;
+	if !session.authenticated() {
+		return Err("server did not authenticate the user".into());
+	}
+	Ok(session)
 }
 
-fn list_files(
-	request: ListRequest,
-	sftp: web::Data<Arc<Sftp>>,
-) -> Result<ListResponse, Box<dyn Error>> {
+fn list_files(request: ListRequest, sftp: &Sftp) -> Result<ListResponse, ErrorResponse> {
 	let mut files = Vec::<FileInfo>::new();
-	let path = if let Some(path) = request.path {
-		std::path::Path::new(&path).to_path_buf()
-	} else {
-		std::path::Path::new("/").to_path_buf()
-	};
-	let full_path = if let Some(key) = request.key {
-		path.join(key)
-	} else {
-		path
-	};
+	let full_path = get_path(&request.path, &request.key);
 	let name = full_path
 		.file_name()
 		.map(|x| x.to_string_lossy().to_string())
 		.unwrap_or_else(|| "/".to_string());
-	if let Some(parent_path) = full_path.parent() {
-		files.push(get_local_file(
-			&sftp,
-			parent_path,
-			None,
-			Some(String::from("..")),
-		)?);
-	}
-	if let Ok(read_dir) = sftp.readdir(&full_path) {
-		for (child_path, stat) in read_dir {
-			files.push(get_local_file(&sftp, &child_path, Some(stat), None)?);
+	if request.cursor.is_none() {
+		if let Some(parent_path) = full_path.parent() {
+			files.push(get_local_file(
+				sftp,
+				parent_path,
+				None,
+				Some(String::from("..")),
+			)?);
 		}
 	}
+
+	// the directory is read once for the first page, later pages are taken from its entries
+	let page = get_listing_page(&full_path, &request, || {
+		sftp.readdir(&full_path)
+			.map_err(|err| sftp_error(err, &full_path))
+	})?;
+	for (child_path, stat) in page.entries {
+		files.push(get_local_file(sftp, &child_path, Some(stat), None)?);
+	}
+
 	let path = full_path.to_string_lossy().to_string();
-	Ok(ListResponse { path, name, files })
+	Ok(ListResponse {
+		path,
+		name,
+		files,
+		cursor: page.cursor,
+		free_space: None,
+	})
 }
 
 fn get_local_file(
@@ -128,13 +269,13 @@ fn get_local_file(
 	path: &std::path::Path,
 	stat: Option<FileStat>,
 	name: Option<String>,
-) -> Result<FileInfo, Box<dyn Error>> {
+) -> Result<FileInfo, ErrorResponse> {
 	let path = std::path::Path::new(path);
 	// let full_path = &path.to_string_lossy().into_owned();
 	let stat = if let Some(stat) = stat {
 		stat
 	} else {
-		sftp.stat(path)?
+		sftp.stat(path).map_err(|err| sftp_error(err, path))?
 	};
 	let size = stat.size.unwrap_or(0);
 	let is_dir = stat.is_dir();
@@ -156,8 +297,12 @@ fn get_local_file(
 				.unwrap_or_else(|| String::from("")),
 		)
 	};
+	let key = match path.file_name() {
+		Some(file_name) if !is_dir => file_name.to_string_lossy().into_owned(),
+		_ => format!("{}/", name),
+	};
 	Ok(FileInfo {
-		key: format!("{}{}", name, if is_dir { "/" } else { "" }),
+		key,
 		is_directory: is_dir,
 		icon: (if is_dir { "folder" } else { "file" }).to_string(), // full_path,
 		icon_alt: None,
@@ -165,8 +310,269 @@ fn get_local_file(
 		name,
 		extension,
 		size,
-		date: 0,
+		date: operations::get_date(&stat),
 		attributes: "-".to_string(),
 		is_active: false,
+		..Default::default()
+	})
+}
+
+/// Gets the path of an entry of a directory; the root directory is the default.
+fn get_path(path: &Option<String>, key: &Option<String>) -> PathBuf {
+	let path = Path::new(path.as_deref().unwrap_or("/"));
+	match key {
+		Some(key) => path.join(key),
+		None => path.to_path_buf(),
+	}
+}
+
+fn get_parent(path: &Path) -> String {
+	path.parent()
+		.map(|x| x.to_string_lossy().to_string())
+		.unwrap_or_default()
+}
+
+/// Builds the `grep` command running the content search on the server. Only the
+/// filters `grep` understands are supported.
+fn get_grep_command(request: &SearchRequest) -> Result<String, ErrorResponse> {
+	let unsupported = |filter: &str| {
+		ErrorResponse::new(
+			ErrorCode::Unsupported,
+			format!("{} is not supported by this backend", filter),
+		)
+	};
+	let Some(content) = &request.content else {
+		return Err(unsupported("searching without content pattern"));
+	};
+	if request.syntax == PatternSyntax::Regex {
+		return Err(unsupported("regular expression name pattern"));
+	}
+	if request
+		.entry_type
+		.is_some_and(|entry_type| entry_type != EntryType::File)
+		|| request.min_size.is_some()
+		|| request.max_size.is_some()
+		|| request.modified_after.is_some()
+		|| request.modified_before.is_some()
+		|| request.min_depth.is_some()
+		|| request.max_depth.is_some()
+		|| request.respect_gitignore
+	{
+		return Err(unsupported("filter"));
+	}
+
+	// -Z separates file names with a null byte, so names may contain any other character
+	let mut command = String::from("grep -r -n -s -Z");
+	command.push_str(if content.include_binary { " -a" } else { " -I" });
+	command.push_str(if content.is_regex { " -E" } else { " -F" });
+	if !content.case_sensitive {
+		command.push_str(" -i");
+	}
+	if content.context > 0 {
+		command.push_str(&format!(" -C {}", content.context));
+	}
+	if let Some(pattern) = &request.pattern {
+		command.push_str(&format!(" --include={}", shell_quote(pattern)));
+	}
+	let path = request.path.as_deref().unwrap_or("/");
+	command.push_str(&format!(
+		" -e {} -- {}",
+		shell_quote(&content.pattern),
+		shell_quote(path)
+	));
+	Ok(command)
+}
+
+fn shell_quote(value: &str) -> String {
+	format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+/// Runs `grep` over an exec channel and adds the matches of each file as a result.
+/// The command runs in a session of its own, which is read without blocking, so
+/// other requests are not held up and cancelling takes effect while it is quiet.
+fn grep(
+	session: &Session,
+	command: &str,
+	request: SearchRequest,
+	job: &Job,
+) -> Result<(), ErrorResponse> {
+	let root = request.path.as_deref().unwrap_or("/");
+	let root_path = Path::new(root);
+	let context = request
+		.content
+		.as_ref()
+		.map_or(0, |content| content.context);
+	let limit = request.limit.unwrap_or(usize::MAX);
+	// matching files are looked up in the shared session
+	let sftp = session.sftp().map_err(|err| sftp_error(err, root_path))?;
+	let grep_session = connect().map_err(|err| {
+		ErrorResponse::new(ErrorCode::Connection, err.to_string()).with_path(root_path)
+	})?;
+	let mut channel = grep_session
+		.channel_session()
+		.map_err(|err| sftp_error(err, root_path))?;
+	channel
+		.exec(command)
+		.map_err(|err| sftp_error(err, root_path))?;
+	grep_session.set_blocking(false);
+
+	let mut found = 0;
+	let mut file_lines = FileLines::default();
+	// gets whether the limit of results is reached
+	let mut add_line = |line: &[u8]| {
+		let Some(output) = parse_grep_line(line) else {
+			return false;
+		};
+		if file_lines.path.as_deref() != Some(output.path.as_str()) {
+			if let Some(result) = file_lines.take_result(&sftp, root_path, context) {
+				job.add_result(result);
+				found += 1;
+				if found == limit {
+					return true;
+				}
+			}
+			job.update(|progress| {
+				progress.files_done += 1;
+				progress.current = Some(output.path.clone());
+			});
+			file_lines.path = Some(output.path.clone());
+		}
+		file_lines.lines.push(output);
+		false
+	};
+
+	// both streams are drained, as the server stops sending when either is full
+	let mut output = Vec::new();
+	let mut errors = Vec::new();
+	let mut buffer = [0; 32 * 1024];
+	loop {
+		job.checkpoint()?;
+		let read = read_available(&mut channel, &mut buffer, &mut output)
+			.and_then(|read| {
+				let read_errors = read_available(&mut channel.stderr(), &mut buffer, &mut errors)?;
+				Ok(read + read_errors)
+			})
+			.map_err(|err| ErrorResponse::from(err).with_path(root_path))?;
+		while let Some(end) = output.iter().position(|byte| *byte == b'\n') {
+			let line = output.drain(..=end).collect::<Vec<_>>();
+			if add_line(&line[..end]) {
+				// the remaining output is discarded with the session
+				return Ok(());
+			}
+		}
+		if read == 0 {
+			if channel.eof() {
+				break;
+			}
+			thread::sleep(POLL_INTERVAL);
+		}
+	}
+	if !output.is_empty() && add_line(&output) {
+		return Ok(());
+	}
+	if let Some(result) = file_lines.take_result(&sftp, root_path, context) {
+		job.add_result(result);
+		found += 1;
+	}
+
+	// exit status 1 means no matches, 2 an error; errors of single files are
+	// suppressed, so these are only reported when nothing was found
+	grep_session.set_blocking(true);
+	channel.wait_close().ok();
+	if channel.exit_status().unwrap_or(0) > 1 && found == 0 {
+		let message = String::from_utf8_lossy(&errors);
+		return Err(
+			ErrorResponse::new(ErrorCode::Io, format!("grep failed: {}", message.trim()))
+				.with_path(root_path),
+		);
+	}
+	Ok(())
+}
+
+/// Appends the data available from a stream of a channel that does not block; gets
+/// the number of bytes read.
+fn read_available(
+	stream: &mut impl Read,
+	buffer: &mut [u8],
+	data: &mut Vec<u8>,
+) -> io::Result<usize> {
+	match stream.read(buffer) {
+		Ok(read) => {
+			data.extend_from_slice(&buffer[..read]);
+			Ok(read)
+		}
+		Err(err) if err.kind() == io::ErrorKind::WouldBlock => Ok(0),
+		Err(err) => Err(err),
+	}
+}
+
+struct GrepLine {
+	path: String,
+	line_number: u64,
+	is_match: bool,
+	text: String,
+}
+
+/// Parses a line of `grep -n -Z` output, `path\0number:text` for matching lines and
+/// `path\0number-text` for context lines.
+fn parse_grep_line(line: &[u8]) -> Option<GrepLine> {
+	let separator = line.iter().position(|byte| *byte == 0)?;
+	let path = String::from_utf8_lossy(&line[..separator]).into_owned();
+	let rest = String::from_utf8_lossy(&line[separator + 1..]).into_owned();
+	let digits = rest.find(|c: char| !c.is_ascii_digit())?;
+	let line_number = rest[..digits].parse().ok()?;
+	let is_match = rest[digits..].starts_with(':');
+	let text = rest[digits + 1..].trim_end_matches('\r').to_string();
+	Some(GrepLine {
+		path,
+		line_number,
+		is_match,
+		text: TextMatch::shorten(text),
 	})
 }
+
+/// Output lines of the file being read.
+#[derive(Default)]
+struct FileLines {
+	path: Option<String>,
+	lines: Vec<GrepLine>,
+}
+
+impl FileLines {
+	/// Gets the result of the file with its matches and their context lines.
+	fn take_result(&mut self, sftp: &Sftp, root: &Path, context: usize) -> Option<JobResult> {
+		let path = self.path.take()?;
+		let lines = std::mem::take(&mut self.lines);
+		let context = context as u64;
+		let get_lines = |range: RangeInclusive<u64>| {
+			lines
+				.iter()
+				.filter(|line| range.contains(&line.line_number))
+				.map(|line| line.text.clone())
+				.collect()
+		};
+		let matches = lines
+			.iter()
+			.filter(|line| line.is_match)
+			.map(|line| TextMatch {
+				line_number: line.line_number,
+				line: line.text.clone(),
+				before: get_lines(line.line_number.saturating_sub(context)..=line.line_number - 1),
+				after: get_lines(line.line_number + 1..=line.line_number + context),
+			})
+			.collect::<Vec<_>>();
+		if matches.is_empty() {
+			return None;
+		}
+
+		let full_path = Path::new(&path);
+		let mut file = get_local_file(sftp, full_path, None, None).ok()?;
+		let relative_path = full_path.strip_prefix(root).unwrap_or(full_path);
+		file.key = relative_path.to_string_lossy().to_string();
+		Some(JobResult {
+			file,
+			matches,
+			..Default::default()
+		})
+	}
+}
@@ -0,0 +1,318 @@
+use crate::{get_local_file, get_parent, get_path, sftp_error, LIBSSH2_FX_NO_SUCH_FILE};
+use ssh2::{FileStat, OpenFlags, OpenType, RenameFlags, Sftp};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use xcmd_base::{
+	check_offset, CreateRequest, CreateResponse, DeleteRequest, ErrorCode, ErrorContext,
+	ErrorResponse, Job, ReadRequest, ReadResponse, StatRequest, StatResponse, TransferRequest,
+	WriteRequest, WriteResponse,
+};
+
+/// Permissions of created files and directories, before the umask of the server.
+const FILE_MODE: i32 = 0o666;
+const DIRECTORY_MODE: i32 = 0o777;
+
+pub fn read(request: ReadRequest, sftp: &Sftp) -> Result<ReadResponse, ErrorResponse> {
+	let path = get_path(&request.path, &request.key);
+	let mut file = sftp.open(&path).map_err(|err| sftp_error(err, &path))?;
+	let size = file
+		.stat()
+		.map_err(|err| sftp_error(err, &path))?
+		.size
+		.unwrap_or(0);
+	let offset = request.offset.unwrap_or(0);
+	check_offset(offset, size).with_path(&path)?;
+	file.seek(SeekFrom::Start(offset))
+		.map_err(|err| ErrorResponse::from(err).with_path(&path))?;
+
+	let mut data = Vec::new();
+	let read = match request.length {
+		Some(length) => file.take(length).read_to_end(&mut data),
+		None => file.read_to_end(&mut data),
+	};
+	read.map_err(|err| ErrorResponse::from(err).with_path(&path))?;
+
+	let size = size.max(offset + data.len() as u64);
+	Ok(ReadResponse { data, offset, size })
+}
+
+pub fn stat(request: StatRequest, sftp: &Sftp) -> Result<StatResponse, ErrorResponse> {
+	let path = get_path(&request.path, &request.key);
+	let file = get_local_file(sftp, &path, None, None)?;
+	Ok(StatResponse {
+		path: get_parent(&path),
+		file,
+	})
+}
+
+/// Writes the file like the local backend: the contents go to a temporary sibling
+/// which then replaces the file.
+pub fn write(request: WriteRequest, sftp: &Sftp) -> Result<WriteResponse, ErrorResponse> {
+	let path = get_path(&request.path, &request.key);
+	let file_name = path
+		.file_name()
+		.map(|x| x.to_string_lossy().to_string())
+		.ok_or_else(|| ErrorResponse::new(ErrorCode::InvalidInput, "file name is missing"))?;
+	if request.part.is_some() {
+		return Err(ErrorResponse::unsupported());
+	}
+	let content = request.encoding.decode(request.content)?;
+
+	if request.append {
+		append_file(sftp, &path, &content, request.date)?;
+		return Ok(WriteResponse {
+			path: get_parent(&path),
+			file: get_local_file(sftp, &path, None, None)?,
+			upload: None,
+		});
+	}
+
+	let stat = match sftp.stat(&path) {
+		Ok(stat) => Some(stat),
+		Err(err) if is_not_found(&err) => None,
+		Err(err) => return Err(sftp_error(err, &path)),
+	};
+
+	// fails if the file was changed since the client has seen it
+	if let Some(expected) = &request.expected {
+		let unchanged = match &stat {
+			Some(stat) => {
+				expected.date.is_none_or(|date| date == get_date(stat))
+					&& expected.size.is_none_or(|size| Some(size) == stat.size)
+			}
+			None => expected.date.is_none() && expected.size.is_none(),
+		};
+		if !unchanged {
+			return Err(ErrorResponse::new(
+				ErrorCode::Conflict,
+				"file was changed by another process",
+			)
+			.with_path(&path));
+		}
+	}
+
+	let temp_path = path.with_file_name(format!(".{}.{}.tmp", file_name, process::id()));
+	let written = write_temp_file(sftp, &temp_path, &content, &stat, request.date)
+		.and_then(|()| {
+			if request.backup && stat.is_some() {
+				let backup_path = path.with_file_name(format!("{}~", file_name));
+				replace(sftp, &path, &backup_path)
+			} else {
+				Ok(())
+			}
+		})
+		.and_then(|()| replace(sftp, &temp_path, &path));
+	if let Err(err) = written {
+		sftp.unlink(&temp_path).ok();
+		return Err(err);
+	}
+
+	Ok(WriteResponse {
+		path: get_parent(&path),
+		file: get_local_file(sftp, &path, None, None)?,
+		upload: None,
+	})
+}
+
+fn write_temp_file(
+	sftp: &Sftp,
+	path: &Path,
+	content: &[u8],
+	stat: &Option<FileStat>,
+	date: Option<i64>,
+) -> Result<(), ErrorResponse> {
+	let mut file = sftp.create(path).map_err(|err| sftp_error(err, path))?;
+	file.write_all(content)
+		.map_err(|err| ErrorResponse::from(err).with_path(path))?;
+	// keeps the permissions of the replaced file
+	if let Some(perm) = stat.as_ref().and_then(|stat| stat.perm) {
+		file.setstat(FileStat {
+			perm: Some(perm),
+			..empty_stat()
+		})
+		.map_err(|err| sftp_error(err, path))?;
+	}
+	drop(file);
+	set_date(sftp, path, date)
+}
+
+fn append_file(
+	sftp: &Sftp,
+	path: &Path,
+	content: &[u8],
+	date: Option<i64>,
+) -> Result<(), ErrorResponse> {
+	let flags = OpenFlags::WRITE | OpenFlags::APPEND | OpenFlags::CREATE;
+	let mut file = sftp
+		.open_mode(path, flags, FILE_MODE, OpenType::File)
+		.map_err(|err| sftp_error(err, path))?;
+	// servers ignoring the append flag write at the given offset
+	let size = file
+		.stat()
+		.map_err(|err| sftp_error(err, path))?
+		.size
+		.unwrap_or(0);
+	file.seek(SeekFrom::Start(size))
+		.and_then(|_| file.write_all(content))
+		.map_err(|err| ErrorResponse::from(err).with_path(path))?;
+	drop(file);
+	set_date(sftp, path, date)
+}
+
+fn set_date(sftp: &Sftp, path: &Path, date: Option<i64>) -> Result<(), ErrorResponse> {
+	let Some(date) = date else {
+		return Ok(());
+	};
+	let seconds = (date / 1000).max(0) as u64;
+	sftp.setstat(
+		path,
+		FileStat {
+			atime: Some(seconds),
+			mtime: Some(seconds),
+			..empty_stat()
+		},
+	)
+	.map_err(|err| sftp_error(err, path))
+}
+
+/// Creates the directory with missing parents.
+pub fn create_directory(
+	request: CreateRequest,
+	sftp: &Sftp,
+) -> Result<CreateResponse, ErrorResponse> {
+	let path = get_path(&request.path, &request.name);
+	let mut missing = Vec::new();
+	let mut ancestor = path.as_path();
+	loop {
+		match sftp.stat(ancestor) {
+			Ok(stat) if stat.is_dir() => break,
+			Ok(_) => {
+				return Err(
+					ErrorResponse::new(ErrorCode::NotADirectory, "path is not a directory")
+						.with_path(ancestor),
+				)
+			}
+			Err(err) if is_not_found(&err) => missing.push(ancestor.to_path_buf()),
+			Err(err) => return Err(sftp_error(err, ancestor)),
+		}
+		match ancestor.parent() {
+			Some(parent) => ancestor = parent,
+			None => break,
+		}
+	}
+	for directory in missing.iter().rev() {
+		sftp.mkdir(directory, DIRECTORY_MODE)
+			.map_err(|err| sftp_error(err, directory))?;
+	}
+
+	let directory = get_local_file(sftp, &path, None, None)?;
+	let name = path
+		.file_name()
+		.map(|x| x.to_string_lossy().to_string())
+		.unwrap_or_default();
+	Ok(CreateResponse {
+		path: name,
+		directory: Some(directory),
+	})
+}
+
+pub fn rename(request: TransferRequest, sftp: &Sftp, job: &Job) -> Result<(), ErrorResponse> {
+	if request
+		.names
+		.as_ref()
+		.is_some_and(|names| names.len() != request.keys.len())
+	{
+		return Err(ErrorResponse::new(
+			ErrorCode::InvalidInput,
+			"number of names does not match the number of keys",
+		));
+	}
+	job.update(|progress| progress.files_total = request.keys.len() as u64);
+
+	let destination = Path::new(&request.destination);
+	for (index, key) in request.keys.iter().enumerate() {
+		job.checkpoint()?;
+		let source = get_path(&request.path, &Some(key.clone()));
+		job.update(|progress| progress.current = Some(source.to_string_lossy().to_string()));
+		let name = match &request.names {
+			Some(names) => PathBuf::from(&names[index]),
+			None => source
+				.file_name()
+				.map(PathBuf::from)
+				.ok_or_else(|| ErrorResponse::new(ErrorCode::InvalidInput, "key is empty"))?,
+		};
+		let target = destination.join(name);
+		if sftp.lstat(&target).is_ok() && !request.overwrite {
+			return Err(
+				ErrorResponse::new(ErrorCode::AlreadyExists, "destination already exists")
+					.with_path(&target),
+			);
+		}
+		replace(sftp, &source, &target)?;
+		job.update(|progress| progress.files_done = index as u64 + 1);
+	}
+	Ok(())
+}
+
+/// Renames an entry, replacing an existing file. Servers of the SFTP version 3 do not
+/// replace files, so the file is removed first if the rename fails.
+fn replace(sftp: &Sftp, source: &Path, destination: &Path) -> Result<(), ErrorResponse> {
+	let flags = Some(RenameFlags::OVERWRITE | RenameFlags::ATOMIC | RenameFlags::NATIVE);
+	let err = match sftp.rename(source, destination, flags) {
+		Ok(()) => return Ok(()),
+		Err(err) => err,
+	};
+	match sftp.lstat(destination) {
+		Ok(stat) if !stat.is_dir() => {
+			sftp.unlink(destination)
+				.map_err(|err| sftp_error(err, destination))?;
+			sftp.rename(source, destination, flags)
+				.map_err(|err| sftp_error(err, destination))
+		}
+		_ => Err(sftp_error(err, destination)),
+	}
+}
+
+pub fn delete(request: DeleteRequest, sftp: &Sftp, job: &Job) -> Result<(), ErrorResponse> {
+	job.update(|progress| progress.files_total = request.keys.len() as u64);
+	for (index, key) in request.keys.iter().enumerate() {
+		let path = get_path(&request.path, &Some(key.clone()));
+		remove_entry(sftp, &path, job)?;
+		job.update(|progress| progress.files_done = index as u64 + 1);
+	}
+	Ok(())
+}
+
+/// Removes a file, or a directory with its contents; links are removed, not followed.
+fn remove_entry(sftp: &Sftp, path: &Path, job: &Job) -> Result<(), ErrorResponse> {
+	job.checkpoint()?;
+	job.update(|progress| progress.current = Some(path.to_string_lossy().to_string()));
+	let stat = sftp.lstat(path).map_err(|err| sftp_error(err, path))?;
+	if !stat.is_dir() {
+		return sftp.unlink(path).map_err(|err| sftp_error(err, path));
+	}
+	for (child, _) in sftp.readdir(path).map_err(|err| sftp_error(err, path))? {
+		remove_entry(sftp, &child, job)?;
+	}
+	sftp.rmdir(path).map_err(|err| sftp_error(err, path))
+}
+
+fn is_not_found(err: &ssh2::Error) -> bool {
+	err.code() == ssh2::ErrorCode::SFTP(LIBSSH2_FX_NO_SUCH_FILE)
+}
+
+pub fn get_date(stat: &FileStat) -> i64 {
+	stat.mtime.map_or(0, |mtime| mtime as i64 * 1000)
+}
+
+fn empty_stat() -> FileStat {
+	FileStat {
+		size: None,
+		uid: None,
+		gid: None,
+		perm: None,
+		atime: None,
+		mtime: None,
+	}
+}